@@ -44,3 +44,55 @@ pub fn count_stmts(_args: TokenStream, input: TokenStream) -> TokenStream {
     println!("count_stmts of {}: {}", ident, len);
     item.into_token_stream().into()
 }
+
+/// Mark a function as a fault site of `unishyper::libs::fault_inject`, checked on entry.
+///
+/// `#[fault_site("fs::open")]` injects panics, page faults and latency,
+/// `#[fault_site("fs::open", io_error, ret = "-1")]` also returns `ret` on injected I/O errors,
+/// and `alloc_failure` on injected allocation failures.
+#[proc_macro_attribute]
+pub fn fault_site(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(args as syn::AttributeArgs);
+    let mut item: syn::Item = syn::parse(input).unwrap();
+    let fn_item = match &mut item {
+        syn::Item::Fn(fn_item) => fn_item,
+        _ => panic!("This attribute only targets function"),
+    };
+
+    let mut id = None;
+    let mut kind = None;
+    let mut ret = None;
+    for arg in args {
+        match arg {
+            syn::NestedMeta::Lit(syn::Lit::Str(lit)) => id = Some(lit.value()),
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("io_error") => {
+                kind = Some(quote!(IoError))
+            }
+            syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("alloc_failure") => {
+                kind = Some(quote!(AllocFailure))
+            }
+            syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("ret") => {
+                match nv.lit {
+                    syn::Lit::Str(lit) => ret = Some(lit.parse::<syn::Expr>().unwrap()),
+                    _ => panic!("ret must be a string of the returned expression"),
+                }
+            }
+            _ => panic!("unknown argument of fault_site"),
+        }
+    }
+    let id = id.expect("fault_site requires an ID");
+
+    let check: syn::Stmt = match (kind, ret) {
+        (Some(kind), Some(ret)) => syn::parse(
+            quote!(if crate::inject_fault!(#id, crate::libs::fault_inject::SiteKind::#kind) {
+                return #ret;
+            })
+            .into(),
+        )
+        .unwrap(),
+        (None, None) => syn::parse(quote!(crate::inject_fault!(#id);).into()).unwrap(),
+        _ => panic!("fault_site requires both an error kind and ret, or neither"),
+    };
+    fn_item.block.stmts.insert(0, check);
+    item.into_token_stream().into()
+}
@@ -3,12 +3,7 @@ cfg_if::cfg_if!(
         mod mpk;
         pub use mpk::*;
     } else {
-        /// Get current PKRU register value.
-        pub fn rdpkru() -> u32 {
-            0
-        }
-
-        /// Set current PKRU register value.
-        pub fn wrpkru(_val: u32) {}
+        mod view;
+        pub use view::*;
     }
 );
@@ -38,3 +38,20 @@ pub fn wrpkru(val: u32) {
         );
     }
 }
+
+/// Disable interrupts, return the original RFLAGS for `irq_restore`.
+pub(crate) fn irq_save() -> u64 {
+    let flags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", "cli", out(reg) flags);
+    }
+    flags
+}
+
+/// Enable interrupts again if they were enabled in `flags`.
+pub(crate) fn irq_restore(flags: u64) {
+    // IF flag is bit 9 of RFLAGS.
+    if flags & (1 << 9) != 0 {
+        unsafe { asm!("sti") };
+    }
+}
@@ -0,0 +1,49 @@
+//! Zones isolated by per-zone page-table views, for architectures without protection keys.
+//!
+//! Each zone owns a view of the address space, which only maps the pages the zone's keys
+//! are allowed to access, see `ZoneKeys`. The view of `ZONE_ID_PRIVILEGED` is the full address space.
+//!
+//! The kernel implements the views on its page table and registers them by `register_view_ops`,
+//! before that, current context is always treated as privileged.
+
+use spin::Once;
+
+use crate::{ZoneId, ZONE_ID_PRIVILEGED};
+
+/// Page-table view operations provided by the kernel.
+pub struct ViewOps {
+    /// Get the zone whose view is active on current core.
+    pub current: fn() -> ZoneId,
+    /// Activate the view of target zone on current core.
+    pub switch: fn(ZoneId),
+}
+
+static VIEW_OPS: Once<ViewOps> = Once::new();
+
+/// Register the page-table view operations, only the first call takes effect.
+pub fn register_view_ops(ops: ViewOps) {
+    VIEW_OPS.call_once(|| ops);
+}
+
+pub(crate) fn current_view() -> ZoneId {
+    match VIEW_OPS.get() {
+        Some(ops) => (ops.current)(),
+        None => ZONE_ID_PRIVILEGED,
+    }
+}
+
+pub(crate) fn switch_view(zone_id: ZoneId) {
+    if let Some(ops) = VIEW_OPS.get() {
+        if (ops.current)() != zone_id {
+            (ops.switch)(zone_id);
+        }
+    }
+}
+
+/// Get current PKRU register value.
+pub fn rdpkru() -> u32 {
+    0
+}
+
+/// Set current PKRU register value.
+pub fn wrpkru(_val: u32) {}
@@ -3,10 +3,17 @@
 #[allow(unused)]
 mod pkey;
 
-pub use pkey::{ZoneKeys, ZONE_ID_SHARED};
+pub use pkey::{ZoneKeys, PKRU_PRIVILEGED, ZONE_ID_PRIVILEGED, ZONE_ID_SHARED, ZONE_NUM};
+#[cfg(target_arch = "x86_64")]
+pub use pkey::MAX_GRANTS;
 
 pub type ZoneId = usize;
 
+#[cfg(target_arch = "x86_64")]
+mod vkey;
+#[cfg(target_arch = "x86_64")]
+pub use vkey::{register_key_ops, tag_key, zone_key, KeyOps, PKey, PKEY_EVICTED};
+
 mod arch;
 pub use arch::*;
 
@@ -19,48 +26,169 @@ pub use arch::*;
 //     0
 // }
 
+/// Descriptor of a static declared by `#[zone_protected::zone_data("name")]`,
+/// gathered in section `.zone_data_desc` for the kernel to tag its pages at boot.
+#[repr(C)]
+pub struct ZoneDataDesc {
+    /// Name of the zone owning the static.
+    pub name: &'static str,
+    pub start: *const u8,
+    pub size: usize,
+}
+
+// Descriptors are immutable, the pointer is only used as an address.
+unsafe impl Sync for ZoneDataDesc {}
+
+/// Descriptor of an entry point declared by `#[zone_protected::zone_entry]`,
+/// gathered in section `.zone_entry_desc` for the kernel to check zone calls against.
+#[repr(C)]
+pub struct ZoneEntryDesc {
+    /// Name of the entry point.
+    pub name: &'static str,
+    /// Address of the function run in the target zone.
+    pub entry: *const (),
+}
+
+// Descriptors are immutable, the pointer is only used as an address.
+unsafe impl Sync for ZoneEntryDesc {}
+
 pub fn zone_init() {
-    #[cfg(target_arch = "x86_64")]
     pkey::zone_init();
 }
 
-pub fn zone_alloc() -> Option<ZoneId> {
+/// Allocate a new zone, fails explicitly when all zone ids are taken.
+pub fn zone_alloc() -> Result<ZoneId, &'static str> {
+    pkey::zone_alloc()
+}
+
+/// Whether the zone is allocated.
+pub fn zone_allocated(zone_id: ZoneId) -> bool {
+    pkey::zone_allocated(zone_id)
+}
+
+/// Add a user to the zone, e.g. a thread inheriting its parent's zone.
+pub fn zone_get(zone_id: ZoneId) {
+    pkey::zone_get(zone_id);
+}
+
+/// Drop a user of the zone, return true if it was the last one and the zone can be freed.
+pub fn zone_put(zone_id: ZoneId) -> bool {
+    pkey::zone_put(zone_id)
+}
+
+pub fn zone_free(zone_id: ZoneId) {
+    pkey::zone_free(zone_id);
+}
+
+/// Allocated zones besides the privileged and shared ones, with their number of users.
+pub fn zone_users() -> impl Iterator<Item = (ZoneId, usize)> {
+    pkey::zone_users()
+}
+
+/// Grant `zone_id` access to pages of `target`, read-only unless `write`.
+///
+/// The grant is part of the zone's keys, see `ZoneKeys::from`, and takes effect
+/// the next time a thread enters the zone, e.g. when it's scheduled.
+/// Fails on architectures isolating zones by page-table views.
+pub fn zone_grant(zone_id: ZoneId, target: ZoneId, write: bool) -> Result<(), &'static str> {
+    pkey::zone_grant(zone_id, target, write)
+}
+
+/// Revoke the access of `zone_id` to `target`, fails if it's not granted.
+pub fn zone_revoke(zone_id: ZoneId, target: ZoneId) -> Result<(), &'static str> {
+    pkey::zone_revoke(zone_id, target)
+}
+
+/// Zones `zone_id` is granted access to, and whether it may write them.
+pub fn zone_grants(zone_id: ZoneId) -> impl Iterator<Item = (ZoneId, bool)> {
+    pkey::zone_grants(zone_id)
+}
+
+/// Switch to the privileged zone keys, return the zone left for `switch_from_privilege`.
+///
+/// On x86_64 it's the zone owning the key enabled in PKRU,
+/// elsewhere it's the zone whose page-table view was active.
+pub fn switch_to_privilege() -> ZoneId {
     #[cfg(target_arch = "x86_64")]
     {
-        pkey::zone_alloc()
+        pkey::switch_to_privilege()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        let ori_zone = arch::current_view();
+        arch::switch_view(ZONE_ID_PRIVILEGED);
+        ori_zone
     }
+}
+
+/// Return to the zone left by `switch_to_privilege`.
+///
+/// The zone may have lost its key meanwhile, so it's entered again rather than restoring the old PKRU.
+pub fn switch_from_privilege(zone_id: ZoneId) {
+    switch_to_zone(zone_id);
+}
+
+/// Enter target zone, dropping access to all other zones it's not allowed to access.
+pub fn switch_to_zone(zone_id: ZoneId) {
+    #[cfg(target_arch = "x86_64")]
+    pkey::switch_to_zone(zone_id);
     #[cfg(not(target_arch = "x86_64"))]
-    None
+    arch::switch_view(zone_id);
 }
 
-pub fn zone_free(_zone_id: ZoneId) {
+/// Get the zone current context runs in.
+pub fn current_zone() -> ZoneId {
     #[cfg(target_arch = "x86_64")]
     {
-        pkey::zone_free(_zone_id);
+        pkey::zone_of_pkru(rdpkru())
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        arch::current_view()
     }
 }
 
-pub fn switch_to_privilege() -> ZoneId {
+/// Whether current context holds the privileged zone keys, i.e. can access every zone.
+///
+/// Always true before zones are set up.
+pub fn is_privileged() -> bool {
     #[cfg(target_arch = "x86_64")]
     {
-        pkey::switch_to_privilege() as ZoneId
+        rdpkru() == PKRU_PRIVILEGED
     }
     #[cfg(not(target_arch = "x86_64"))]
-    0
+    {
+        arch::current_view() == ZONE_ID_PRIVILEGED
+    }
 }
 
-pub fn switch_from_privilege(_zone_id: ZoneId) {
-    #[cfg(target_arch = "x86_64")]
-    pkey::switch_from_privilege(_zone_id as u32);
+/// Holds the privileged zone keys until dropped, then returns to the zone it was entered from.
+///
+/// The zone is restored on early returns and unwinding as well, see `#[privileged_func]`.
+#[must_use]
+pub struct PrivilegeGuard {
+    ori_zone: ZoneId,
+}
+
+impl PrivilegeGuard {
+    pub fn enter() -> Self {
+        Self {
+            ori_zone: switch_to_privilege(),
+        }
+    }
+}
+
+impl Drop for PrivilegeGuard {
+    fn drop(&mut self) {
+        switch_from_privilege(self.ori_zone);
+    }
 }
 
 pub fn protected_function_wrapper<F>(f: F)
 where
     F: FnOnce() -> (),
 {
-    let ori_zone = switch_to_privilege();
+    let _guard = PrivilegeGuard::enter();
 
     f();
-
-    switch_from_privilege(ori_zone);
 }
@@ -1,3 +1,5 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use spin::Mutex;
 use spin::Lazy;
 
@@ -9,8 +11,21 @@ use super::ZoneId;
 pub const ZONE_ID_PRIVILEGED: ZoneId = 0x0;
 pub const ZONE_ID_SHARED: ZoneId = 0xf;
 
+/// Number of logical zones.
+///
+/// On x86_64 zones are mapped onto the 16 protection keys on demand, see `vkey`,
+/// page table entries keep the zone id in their software-available bits 52-58.
+/// Elsewhere each zone owns a page-table view, so there are as many zones as views.
+#[cfg(target_arch = "x86_64")]
+pub const ZONE_NUM: usize = 64;
+#[cfg(not(target_arch = "x86_64"))]
+pub const ZONE_NUM: usize = 16;
+
+// Grants of a zone are kept as a bitmap of zone ids.
+const _: () = assert!(ZONE_NUM <= u64::BITS as usize);
+
 // By default, only shared zone(with pkey 15) can be accessed.
-pub const PKRU_DEFAULT: u32 = 0x3fff;
+pub const PKRU_DEFAULT: u32 = 0x3fff_ffff;
 pub const PKRU_PRIVILEGED: u32 = 0x0;
 
 // https://man7.org/linux/man-pages/man7/pkeys.7.html
@@ -25,16 +40,55 @@ pub const PKRU_PRIVILEGED: u32 = 0x0;
 //     ReadWrite = 0b00,
 // }
 
-use super::{wrpkru, rdpkru};
+#[cfg(target_arch = "x86_64")]
+use super::{irq_restore, irq_save, rdpkru, vkey, wrpkru};
 
-pub fn switch_to_privilege() -> u32 {
+/// Switch to the privileged PKRU, return the zone whose key was enabled.
+#[cfg(target_arch = "x86_64")]
+pub fn switch_to_privilege() -> ZoneId {
     let ori_pkru = rdpkru();
     wrpkru(PKRU_PRIVILEGED);
-    ori_pkru
+    zone_of_pkru(ori_pkru)
+}
+
+/// Load the PKRU of `zone_id`, binding keys to it and the zones it's granted first if they hold none.
+#[cfg(target_arch = "x86_64")]
+pub fn switch_to_zone(zone_id: ZoneId) {
+    if zone_id == ZONE_ID_PRIVILEGED {
+        wrpkru(PKRU_PRIVILEGED);
+        return;
+    }
+    // The keys must not be evicted before they're loaded.
+    // The zone itself is activated last, so it's the most recently used one,
+    // grants are limited to `MAX_GRANTS` so activating them never evicts each other.
+    let flags = irq_save();
+    for (target, _) in zone_grants(zone_id) {
+        vkey::activate(target);
+    }
+    vkey::activate(zone_id);
+    wrpkru(ZoneKeys::from(zone_id).as_pkru());
+    irq_restore(flags);
 }
 
-pub fn switch_from_privilege(ori_pkru: u32) {
-    wrpkru(ori_pkru);
+/// Get the zone whose keys are loaded in `pkru`.
+///
+/// With grants several keys may be enabled, the zone is the owner of an enabled key
+/// whose keys match `pkru`. Zones granted write access to each other are told apart by key order.
+#[cfg(target_arch = "x86_64")]
+pub fn zone_of_pkru(pkru: u32) -> ZoneId {
+    if pkru == PKRU_PRIVILEGED {
+        return ZONE_ID_PRIVILEGED;
+    }
+    let mut owners = (ZONE_ID_PRIVILEGED + 1..ZONE_ID_SHARED)
+        .filter(|key| pkru & (0b11 << (key * 2)) == 0)
+        .filter_map(vkey::key_owner);
+    let first = owners.next();
+    first
+        .into_iter()
+        .chain(owners)
+        .find(|zone_id| ZoneKeys::from(*zone_id).as_pkru() == pkru)
+        .or(first)
+        .unwrap_or(ZONE_ID_SHARED)
 }
 
 // The PKRU register (protection-key rights for user pages) is a 32-bit register with the following format:
@@ -99,47 +153,232 @@ bitflags! {
     }
 }
 
-static GLOBAL_ZONES: Lazy<Mutex<Bitmap<16>>> = Lazy::new(|| Mutex::new(Bitmap::<16>::new()));
-
 impl ZoneKeys {
     pub fn as_pkru(&self) -> u32 {
         self.bits
     }
+
+    /// Keys enabling hardware key `key` besides the shared one.
+    pub fn of_key(key: usize) -> Self {
+        if key >= ZONE_ID_SHARED {
+            return Self { bits: PKRU_DEFAULT };
+        }
+        Self {
+            bits: PKRU_DEFAULT & !(0b11 << (key * 2)),
+        }
+    }
+
+    /// Enable reading pages tagged with hardware key `key`, and writing them if `write`.
+    pub fn enable(&mut self, key: usize, write: bool) {
+        let bits = if write { 0b11 } else { 0b01 };
+        self.bits &= !(bits << (key * 2));
+    }
+
+    /// Whether pages of `zone_id` are readable with these keys.
+    pub fn readable(&self, zone_id: ZoneId) -> bool {
+        self.bits & (0b01 << (zone_id * 2)) == 0
+    }
+
+    /// Whether pages of `zone_id` are writable with these keys.
+    pub fn writable(&self, zone_id: ZoneId) -> bool {
+        self.bits & (0b11 << (zone_id * 2)) == 0
+    }
+}
+
+/// Get the hardware key of `zone_id`, each zone is its own key where keys are not virtualized.
+fn key_of(zone_id: ZoneId) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        vkey::zone_key(zone_id)
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        Some(zone_id)
+    }
 }
 
 impl From<ZoneId> for ZoneKeys {
+    /// Keys of `zone_id` and the zones it's granted, zones holding no hardware key
+    /// can only access the shared zone.
     fn from(zone_id: ZoneId) -> Self {
-        if zone_id >= ZONE_ID_SHARED {
-            return Self { bits: PKRU_DEFAULT };
+        let mut keys = match key_of(zone_id) {
+            Some(key) => Self::of_key(key),
+            None => Self { bits: PKRU_DEFAULT },
+        };
+        for (target, write) in zone_grants(zone_id) {
+            if let Some(key) = key_of(target) {
+                keys.enable(key, write);
+            }
         }
-        let mut pkru = PKRU_DEFAULT;
-        pkru &= !(1 << (zone_id * 2));
-        pkru &= !(1 << ((zone_id * 2) + 1));
+        keys
+    }
+}
+
+/// Most zones one zone can be granted, the rest of the 14 allocatable keys.
+#[cfg(target_arch = "x86_64")]
+pub const MAX_GRANTS: usize = vkey::PKEY_NUM - 3;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_GRANT: AtomicU64 = AtomicU64::new(0);
+/// Zones each zone is granted to read, bit i for zone i, read lock-free on zone switches.
+static GRANT_READ: [AtomicU64; ZONE_NUM] = [NO_GRANT; ZONE_NUM];
+/// Zones each zone is granted to write, a subset of the readable ones.
+static GRANT_WRITE: [AtomicU64; ZONE_NUM] = [NO_GRANT; ZONE_NUM];
+
+/// Zones `zone_id` is granted access to, and whether it's granted to write them.
+pub fn zone_grants(zone_id: ZoneId) -> impl Iterator<Item = (ZoneId, bool)> {
+    let load = |grants: &[AtomicU64; ZONE_NUM]| {
+        grants
+            .get(zone_id)
+            .map_or(0, |grant| grant.load(Ordering::Acquire))
+    };
+    let (read, write) = (load(&GRANT_READ), load(&GRANT_WRITE));
+    (0..ZONE_NUM)
+        .filter(move |target| read & (1 << target) != 0)
+        .map(move |target| (target, write & (1 << target) != 0))
+}
+
+/// Grant `zone_id` access to pages of `target`, read-only unless `write`.
+///
+/// Only supported with protection keys, page-table views don't know which zone a mapping belongs to
+/// once it's mapped, so they can't be rebuilt.
+pub fn zone_grant(zone_id: ZoneId, target: ZoneId, write: bool) -> Result<(), &'static str> {
+    if !cfg!(target_arch = "x86_64") {
+        return Err("zone grants are not supported by page-table views");
+    }
+    if is_fixed(zone_id) || is_fixed(target) || zone_id == target {
+        return Err("invalid zone grant");
+    }
+    if !zone_allocated(zone_id) || !zone_allocated(target) {
+        return Err("zone not allocated");
+    }
+    let bit = 1 << target;
+    let read = GRANT_READ[zone_id].load(Ordering::Acquire);
+    #[cfg(target_arch = "x86_64")]
+    if read & bit == 0 && read.count_ones() as usize >= MAX_GRANTS {
+        return Err("too many zone grants");
+    }
+    if write {
+        GRANT_WRITE[zone_id].fetch_or(bit, Ordering::AcqRel);
+    } else {
+        GRANT_WRITE[zone_id].fetch_and(!bit, Ordering::AcqRel);
+    }
+    GRANT_READ[zone_id].fetch_or(bit, Ordering::AcqRel);
+    Ok(())
+}
 
-        Self { bits: pkru }
+/// Revoke the access of `zone_id` to `target`.
+pub fn zone_revoke(zone_id: ZoneId, target: ZoneId) -> Result<(), &'static str> {
+    if zone_id >= ZONE_NUM || target >= ZONE_NUM {
+        return Err("invalid zone id");
     }
+    let bit = 1 << target;
+    if GRANT_READ[zone_id].fetch_and(!bit, Ordering::AcqRel) & bit == 0 {
+        return Err("zone not granted");
+    }
+    GRANT_WRITE[zone_id].fetch_and(!bit, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Drop grants of and to a freed zone.
+fn clear_grants(zone_id: ZoneId) {
+    GRANT_READ[zone_id].store(0, Ordering::Release);
+    GRANT_WRITE[zone_id].store(0, Ordering::Release);
+    let bit = 1 << zone_id;
+    for grants in GRANT_READ.iter().chain(GRANT_WRITE.iter()) {
+        grants.fetch_and(!bit, Ordering::AcqRel);
+    }
+}
+
+struct Zones {
+    allocated: Bitmap<ZONE_NUM>,
+    /// Number of threads running in each zone.
+    users: [usize; ZONE_NUM],
+}
+
+static GLOBAL_ZONES: Lazy<Mutex<Zones>> = Lazy::new(|| {
+    Mutex::new(Zones {
+        allocated: Bitmap::new(),
+        users: [0; ZONE_NUM],
+    })
+});
+
+fn is_fixed(zone_id: ZoneId) -> bool {
+    zone_id == ZONE_ID_PRIVILEGED || zone_id == ZONE_ID_SHARED
 }
+
 // Use bitmap to manage zone allocation & deallocation.
 pub fn zone_init() {
     let mut global_zone = GLOBAL_ZONES.lock();
-    global_zone.set(ZONE_ID_SHARED, true);
-    global_zone.set(ZONE_ID_PRIVILEGED, true);
+    global_zone.allocated.set(ZONE_ID_SHARED, true);
+    global_zone.allocated.set(ZONE_ID_PRIVILEGED, true);
+    #[cfg(target_arch = "x86_64")]
+    vkey::init();
 }
 
-pub fn zone_alloc() -> Option<ZoneId> {
-    let mut global_zone = GLOBAL_ZONES.lock();
-    let allocated_zone = global_zone.first_false_index().unwrap_or(ZONE_ID_SHARED);
+/// Allocate a zone with one user, fails when all zone ids are taken.
+pub fn zone_alloc() -> Result<ZoneId, &'static str> {
+    let zone_id = {
+        let mut global_zone = GLOBAL_ZONES.lock();
+        let zone_id = global_zone
+            .allocated
+            .first_false_index()
+            .ok_or("zone ids exhausted")?;
+        global_zone.allocated.set(zone_id, true);
+        global_zone.users[zone_id] = 1;
+        zone_id
+    };
+    // New zones take a free key if any, otherwise they get one when activated.
+    #[cfg(target_arch = "x86_64")]
+    let _ = vkey::try_bind(zone_id);
+    Ok(zone_id)
+}
 
-    global_zone.set(allocated_zone, true);
+/// Whether `zone_id` is allocated, the privileged and shared zones always are.
+pub fn zone_allocated(zone_id: ZoneId) -> bool {
+    zone_id < ZONE_NUM && GLOBAL_ZONES.lock().allocated.get(zone_id)
+}
 
-    Some(allocated_zone)
+/// Allocated zones with their number of users, the privileged and shared zones are skipped.
+pub fn zone_users() -> impl Iterator<Item = (ZoneId, usize)> {
+    let (allocated, users) = {
+        let global_zone = GLOBAL_ZONES.lock();
+        (global_zone.allocated, global_zone.users)
+    };
+    (0..ZONE_NUM)
+        .filter(move |zone_id| !is_fixed(*zone_id) && allocated.get(*zone_id))
+        .map(move |zone_id| (zone_id, users[zone_id]))
 }
 
-pub fn zone_free(zone_id: ZoneId) {
+/// Add a user to an allocated zone.
+pub fn zone_get(zone_id: ZoneId) {
     let mut global_zone = GLOBAL_ZONES.lock();
-    global_zone.set(zone_id, false);
+    if !is_fixed(zone_id) && zone_id < ZONE_NUM && global_zone.allocated.get(zone_id) {
+        global_zone.users[zone_id] += 1;
+    }
 }
 
-// pub const fn zone_protected() -> ZoneId {
-//     ZONE_ID_PRIVILEGED
-// }
+/// Drop a user of an allocated zone, return whether it was the last one.
+pub fn zone_put(zone_id: ZoneId) -> bool {
+    let mut global_zone = GLOBAL_ZONES.lock();
+    if is_fixed(zone_id) || zone_id >= ZONE_NUM || !global_zone.allocated.get(zone_id) {
+        return false;
+    }
+    let users = &mut global_zone.users[zone_id];
+    *users = users.saturating_sub(1);
+    *users == 0
+}
+
+pub fn zone_free(zone_id: ZoneId) {
+    if is_fixed(zone_id) || zone_id >= ZONE_NUM {
+        return;
+    }
+    {
+        let mut global_zone = GLOBAL_ZONES.lock();
+        global_zone.allocated.set(zone_id, false);
+        global_zone.users[zone_id] = 0;
+    }
+    clear_grants(zone_id);
+    #[cfg(target_arch = "x86_64")]
+    vkey::release(zone_id);
+}
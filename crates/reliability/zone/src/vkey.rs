@@ -0,0 +1,200 @@
+//! Protection key virtualization.
+//!
+//! MPK provides 16 hardware keys, key 0 belongs to the privileged zone and key 15 to the shared zone,
+//! leaving 14 keys for the rest of logical zones. Keys are bound to zones on demand,
+//! when a zone needs a key while all of them are taken, the least recently used one is evicted:
+//! pages of the victim zone are retagged with `PKEY_EVICTED`, which only the privileged zone can access,
+//! and retagged back the next time the victim gets a key.
+//!
+//! Pages are tagged through the `retag` callback registered by the kernel page table.
+//! Keys are bound and evicted with interrupts disabled, x86_64 boards run on a single core,
+//! so nothing can rebind a key between looking it up and loading it into PKRU.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::{Mutex, Once};
+
+use super::arch::{irq_restore, irq_save};
+use super::pkey::{ZONE_ID_PRIVILEGED, ZONE_ID_SHARED, ZONE_NUM};
+use super::ZoneId;
+
+/// Hardware protection key.
+pub type PKey = usize;
+
+/// Number of hardware protection keys.
+pub const PKEY_NUM: usize = 16;
+
+/// Key of pages whose zone holds no key, only accessible by the privileged zone.
+pub const PKEY_EVICTED: PKey = 0;
+
+const PKEY_NONE: PKey = usize::MAX;
+const ZONE_NONE: ZoneId = usize::MAX;
+
+/// Callbacks provided by the kernel to apply key bindings to page tables.
+pub struct KeyOps {
+    /// Retag all pages of the zone with the key.
+    pub retag: fn(ZoneId, PKey),
+}
+
+static KEY_OPS: Once<KeyOps> = Once::new();
+
+/// Register page table callbacks, called once page table is ready.
+pub fn register_key_ops(ops: KeyOps) {
+    KEY_OPS.call_once(|| ops);
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const KEY_NONE: AtomicUsize = AtomicUsize::new(PKEY_NONE);
+#[allow(clippy::declare_interior_mutable_const)]
+const OWNER_NONE: AtomicUsize = AtomicUsize::new(ZONE_NONE);
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_STALE: AtomicBool = AtomicBool::new(false);
+
+/// Key bound to each zone, read lock-free when mapping pages.
+static ZONE_KEYS: [AtomicUsize; ZONE_NUM] = [KEY_NONE; ZONE_NUM];
+/// Zone bound to each key.
+static KEY_OWNERS: [AtomicUsize; PKEY_NUM] = [OWNER_NONE; PKEY_NUM];
+/// Zones with pages tagged `PKEY_EVICTED`, which need retagging once they get a key.
+static STALE: [AtomicBool; ZONE_NUM] = [NOT_STALE; ZONE_NUM];
+
+/// LRU stamps of keys, the lock also serializes binding and eviction.
+struct KeyLru {
+    last_used: [u64; PKEY_NUM],
+    clock: u64,
+}
+
+impl KeyLru {
+    fn touch(&mut self, key: PKey) {
+        self.clock += 1;
+        self.last_used[key] = self.clock;
+    }
+}
+
+static KEY_LRU: Mutex<KeyLru> = Mutex::new(KeyLru {
+    last_used: [0; PKEY_NUM],
+    clock: 0,
+});
+
+/// Bind fixed keys of privileged and shared zones.
+pub fn init() {
+    let mut lru = KEY_LRU.lock();
+    bind(&mut lru, ZONE_ID_PRIVILEGED, ZONE_ID_PRIVILEGED);
+    bind(&mut lru, ZONE_ID_SHARED, ZONE_ID_SHARED);
+}
+
+/// Get the key currently bound to `zone_id`.
+pub fn zone_key(zone_id: ZoneId) -> Option<PKey> {
+    let key = ZONE_KEYS.get(zone_id)?.load(Ordering::Acquire);
+    (key != PKEY_NONE).then_some(key)
+}
+
+/// Get the key to tag newly mapped pages of `zone_id` with.
+///
+/// Pages of a zone holding no key are tagged `PKEY_EVICTED` and retagged when it gets one.
+pub fn tag_key(zone_id: ZoneId) -> PKey {
+    match zone_key(zone_id) {
+        Some(key) => key,
+        None => {
+            if let Some(stale) = STALE.get(zone_id) {
+                stale.store(true, Ordering::Release);
+            }
+            PKEY_EVICTED
+        }
+    }
+}
+
+/// Get the zone `key` is bound to.
+pub fn key_owner(key: PKey) -> Option<ZoneId> {
+    let zone_id = KEY_OWNERS.get(key)?.load(Ordering::Acquire);
+    (zone_id != ZONE_NONE).then_some(zone_id)
+}
+
+fn free_key() -> Option<PKey> {
+    (ZONE_ID_PRIVILEGED + 1..ZONE_ID_SHARED).find(|key| key_owner(*key).is_none())
+}
+
+fn bind(lru: &mut KeyLru, zone_id: ZoneId, key: PKey) {
+    KEY_OWNERS[key].store(zone_id, Ordering::Release);
+    ZONE_KEYS[zone_id].store(key, Ordering::Release);
+    lru.touch(key);
+    if STALE[zone_id].swap(false, Ordering::AcqRel) {
+        match KEY_OPS.get() {
+            Some(ops) => (ops.retag)(zone_id, key),
+            None => STALE[zone_id].store(true, Ordering::Release),
+        }
+    }
+}
+
+fn unbind(zone_id: ZoneId, key: PKey) {
+    KEY_OWNERS[key].store(ZONE_NONE, Ordering::Release);
+    ZONE_KEYS[zone_id].store(PKEY_NONE, Ordering::Release);
+}
+
+/// Bind a free key to `zone_id` without evicting any zone, for newly allocated zones.
+pub fn try_bind(zone_id: ZoneId) -> Option<PKey> {
+    let flags = irq_save();
+    let key = {
+        let mut lru = KEY_LRU.lock();
+        let key = free_key();
+        if let Some(key) = key {
+            bind(&mut lru, zone_id, key);
+        }
+        key
+    };
+    irq_restore(flags);
+    key
+}
+
+/// Make sure `zone_id` holds a key and return it,
+/// the least recently used zone is evicted if all keys are taken.
+///
+/// Must not be called with the page table locked, since eviction retags pages.
+pub fn activate(zone_id: ZoneId) -> PKey {
+    if zone_id >= ZONE_NUM {
+        return ZONE_ID_SHARED;
+    }
+    let flags = irq_save();
+    let key = {
+        let mut lru = KEY_LRU.lock();
+        match zone_key(zone_id) {
+            Some(key) => {
+                lru.touch(key);
+                key
+            }
+            None => {
+                let key = free_key().unwrap_or_else(|| {
+                    let victim_key = (ZONE_ID_PRIVILEGED + 1..ZONE_ID_SHARED)
+                        .min_by_key(|key| lru.last_used[*key])
+                        .unwrap();
+                    let victim = key_owner(victim_key).unwrap();
+                    unbind(victim, victim_key);
+                    STALE[victim].store(true, Ordering::Release);
+                    if let Some(ops) = KEY_OPS.get() {
+                        (ops.retag)(victim, PKEY_EVICTED);
+                    }
+                    victim_key
+                });
+                bind(&mut lru, zone_id, key);
+                key
+            }
+        }
+    };
+    irq_restore(flags);
+    key
+}
+
+/// Release the key of a freed zone, its pages should be unmapped already.
+pub fn release(zone_id: ZoneId) {
+    if zone_id == ZONE_ID_PRIVILEGED || zone_id == ZONE_ID_SHARED || zone_id >= ZONE_NUM {
+        return;
+    }
+    let flags = irq_save();
+    {
+        let _lru = KEY_LRU.lock();
+        if let Some(key) = zone_key(zone_id) {
+            unbind(zone_id, key);
+        }
+        STALE[zone_id].store(false, Ordering::Release);
+    }
+    irq_restore(flags);
+}
@@ -1,9 +1,17 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
-use proc_macro2::Span;
-use quote::quote;
-use syn::{Error, ItemFn, ItemStatic};
+use proc_macro2::{Span, TokenTree};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::{
+    Block, Error, Expr, FnArg, Ident, ImplItem, ItemFn, ItemImpl, ItemStatic, LitStr, Pat, ReturnType,
+    Signature, Token, Type,
+};
 
+/// Run the function with privileged zone keys, returning to the caller's zone when it returns.
+///
+/// The zone is restored by a guard, so return values, early `return`s, `?` and unwinding panics
+/// are all handled. Works on free functions as well as methods in `impl` and trait `impl` blocks.
 #[proc_macro_attribute]
 pub fn privileged_func(attr: TokenStream, item: TokenStream) -> TokenStream {
     if !attr.is_empty() {
@@ -15,23 +23,86 @@ pub fn privileged_func(attr: TokenStream, item: TokenStream) -> TokenStream {
         .into();
     }
 
-    let func = syn::parse_macro_input!(item as ItemFn);
-    let func_vis = func.vis; // like pub
-    let func_block = &func.block; // { some statement or expression here }
+    let mut func = syn::parse_macro_input!(item as ItemFn);
+    if let Err(e) = check_privileged_sig(&func.sig) {
+        return e.to_compile_error().into();
+    }
+    *func.block = privileged_block(&func.sig, &func.block);
+    quote!(#func).into()
+}
 
-    let func_decl = func.sig;
+/// Apply `#[privileged_func]` to all methods of an `impl` block, `const fn`s are left as they are.
+#[proc_macro_attribute]
+pub fn privileged_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "expect an empty attribute: `#[privileged_impl]`",
+        )
+        .to_compile_error()
+        .into();
+    }
 
-    quote! {
-        #func_vis #func_decl {
-            use zone;
-            let ori_zone = zone::switch_to_privilege();
-            {
-                #func_block
+    let mut item_impl = syn::parse_macro_input!(item as ItemImpl);
+    for impl_item in item_impl.items.iter_mut() {
+        if let ImplItem::Fn(method) = impl_item {
+            if method.sig.constness.is_some() {
+                continue;
             }
-            zone::switch_from_privilege(ori_zone);
+            if let Err(e) = check_privileged_sig(&method.sig) {
+                return e.to_compile_error().into();
+            }
+            method.block = privileged_block(&method.sig, &method.block);
         }
     }
-    .into()
+    quote!(#item_impl).into()
+}
+
+fn check_privileged_sig(sig: &Signature) -> syn::Result<()> {
+    if sig.asyncness.is_some() {
+        return Err(Error::new_spanned(
+            sig,
+            "privileged zone keys can not be held across `await`",
+        ));
+    }
+    if sig.constness.is_some() {
+        return Err(Error::new_spanned(sig, "`const fn` can not switch zone keys"));
+    }
+    Ok(())
+}
+
+/// Wrap `block` with a `PrivilegeGuard`.
+///
+/// The result is bound to a local first, so temporaries of the tail expression,
+/// e.g. lock guards, are dropped before the guard switches back.
+fn privileged_block(sig: &Signature, block: &Block) -> Block {
+    let ret = match &sig.output {
+        ReturnType::Type(_, ty)
+            if !matches!(**ty, Type::Never(_)) && !contains_impl_trait(quote!(#ty)) =>
+        {
+            quote!(: #ty)
+        }
+        ReturnType::Type(..) => quote!(),
+        ReturnType::Default => quote!(: ()),
+    };
+    syn::parse_quote! {
+        {
+            let __privilege_guard = ::zone::PrivilegeGuard::enter();
+            #[allow(unreachable_code, clippy::let_unit_value, clippy::diverging_sub_expression)]
+            let __privileged_ret #ret = #block;
+            #[allow(unreachable_code)]
+            __privileged_ret
+        }
+    }
+}
+
+/// Whether the type contains `impl Trait`, which can not be written in `let` bindings, nor can `!`.
+fn contains_impl_trait(ty: proc_macro2::TokenStream) -> bool {
+    ty.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => ident == "impl",
+        TokenTree::Group(group) => contains_impl_trait(group.stream()),
+        _ => false,
+    })
 }
 
 #[proc_macro_attribute]
@@ -53,3 +124,158 @@ pub fn global_var(attr: TokenStream, item: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Place a static in the data section of the named zone, `#[zone_data("name")]`.
+///
+/// Statics of the same name are gathered in section `.zone_data.<name>`, which starts on a page
+/// of its own. At boot the kernel allocates a zone for each name and tags the pages with its key,
+/// only threads spawned into the named zone, see `ZoneSpec::Named`, and privileged code can access them.
+/// Expands to paths under `unishyper::shyperstd::zone`.
+#[proc_macro_attribute]
+pub fn zone_data(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let name = syn::parse_macro_input!(attr as LitStr);
+    let declaration = syn::parse_macro_input!(item as ItemStatic);
+
+    let zone_name = name.value();
+    if zone_name.is_empty()
+        || !zone_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Error::new(
+            name.span(),
+            "zone name should only contain ASCII alphanumerics and `_`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let section = format!(".zone_data.{}", zone_name);
+    let ident = &declaration.ident;
+    let ty = &declaration.ty;
+    let align = format_ident!("__ZONE_DATA_ALIGN_{}", ident);
+    let desc = format_ident!("__ZONE_DATA_DESC_{}", ident);
+
+    quote! {
+        #[link_section = #section]
+        #declaration
+
+        const _: () = {
+            // Makes every `.zone_data.<name>` input section page-aligned,
+            // so statics of different zones never share a page.
+            #[repr(C, align(4096))]
+            struct ZoneDataAlign;
+
+            #[used]
+            #[link_section = #section]
+            static #align: ZoneDataAlign = ZoneDataAlign;
+
+            #[used]
+            #[link_section = ".zone_data_desc"]
+            #[allow(unused_unsafe)]
+            static #desc: ::unishyper::shyperstd::zone::ZoneDataDesc =
+                ::unishyper::shyperstd::zone::ZoneDataDesc {
+                    name: #name,
+                    start: unsafe { ::core::ptr::addr_of!(#ident) } as *const u8,
+                    size: ::core::mem::size_of::<#ty>(),
+                };
+        };
+    }
+    .into()
+}
+
+/// Arguments of `#[zone_entry(zone = ...)]`.
+struct ZoneEntryArgs {
+    zone: Expr,
+}
+
+impl Parse for ZoneEntryArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key: Ident = input.parse()?;
+        if key != "zone" {
+            return Err(Error::new(key.span(), "expect `#[zone_entry(zone = ...)]`"));
+        }
+        input.parse::<Token![=]>()?;
+        let zone = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        if !input.is_empty() {
+            return Err(input.error("expect `#[zone_entry(zone = ...)]`"));
+        }
+        Ok(Self { zone })
+    }
+}
+
+/// Declare an entry point of a zone, which runs in that zone only when called from others.
+///
+/// `fn f(args) -> R` becomes `fn f(args) -> Result<R, &'static str>`, calls are forwarded to
+/// `unishyper::shyperstd::zone::zone_call`, which moves the arguments to a stack of the target
+/// zone, runs the body on it and moves the result back. The body is registered in section
+/// `.zone_entry_desc`, zone calls into functions not declared by `#[zone_entry]` are refused.
+/// The error is returned if the target zone is invalid or the body panics.
+///
+/// Arguments and result must be owned (`Send + 'static`).
+#[proc_macro_attribute]
+pub fn zone_entry(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(attr as ZoneEntryArgs);
+    let func = syn::parse_macro_input!(item as ItemFn);
+    let sig = &func.sig;
+
+    if sig.receiver().is_some()
+        || !sig.generics.params.is_empty()
+        || sig.asyncness.is_some()
+        || sig.variadic.is_some()
+    {
+        return Error::new_spanned(
+            sig,
+            "`#[zone_entry]` only supports plain non-generic functions",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let mut names = Vec::new();
+    let mut pats = Vec::new();
+    let mut tys = Vec::new();
+    for (i, input) in sig.inputs.iter().enumerate() {
+        if let FnArg::Typed(arg) = input {
+            let name = match arg.pat.as_ref() {
+                Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => pat.ident.clone(),
+                _ => format_ident!("__arg{}", i),
+            };
+            names.push(name);
+            pats.push(arg.pat.as_ref().clone());
+            tys.push(arg.ty.as_ref().clone());
+        }
+    }
+
+    let attrs = &func.attrs;
+    let vis = &func.vis;
+    let ident = &sig.ident;
+    let block = &func.block;
+    let zone = &args.zone;
+    let inner = format_ident!("__zone_entry_{}", ident);
+    let name = ident.to_string();
+    let ret = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    quote! {
+        #(#attrs)*
+        #vis fn #ident(#(#names: #tys),*) -> ::core::result::Result<#ret, &'static str> {
+            #[inline(never)]
+            fn #inner((#(#pats,)*): (#(#tys,)*)) -> #ret #block
+
+            #[used]
+            #[link_section = ".zone_entry_desc"]
+            static __ZONE_ENTRY_DESC: ::unishyper::shyperstd::zone::ZoneEntryDesc =
+                ::unishyper::shyperstd::zone::ZoneEntryDesc {
+                    name: #name,
+                    entry: #inner as fn((#(#tys,)*)) -> #ret as *const (),
+                };
+
+            ::unishyper::shyperstd::zone::zone_call(#zone, (#(#names,)*), #inner)
+        }
+    }
+    .into()
+}
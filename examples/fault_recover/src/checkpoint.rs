@@ -0,0 +1,43 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use unishyper::shyperstd::checkpoint::{self, Checkpointed};
+use unishyper::shyperstd::mm;
+use unishyper::shyperstd::panic::{self, PanicPolicy};
+use unishyper::*;
+
+#[allow(dead_code)]
+static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(dead_code)]
+pub extern "C" fn test_checkpoint(_arg: usize) {
+    panic::set_thread_panic_policy(Some(PanicPolicy::Rollback));
+
+    // Owned by this thread, its contents are rolled back with the checkpoint.
+    let counter = mm::allocate(4096).as_mut_ptr::<usize>();
+    unsafe { counter.write(0) };
+
+    match unsafe { checkpoint::checkpoint() } {
+        Ok(Checkpointed::Taken) => println!("checkpoint taken"),
+        Ok(Checkpointed::RolledBack) => {
+            println!("rolled back, counter {}", unsafe { counter.read() })
+        }
+        Err(e) => {
+            println!("failed to take a checkpoint: {}", e);
+            return;
+        }
+    }
+
+    // Statics are not rolled back.
+    let attempt = ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+    unsafe { counter.write(counter.read() + 1) };
+    if attempt < 2 {
+        panic!(
+            "Simulate a panic after the checkpoint, attempt #{}!",
+            attempt
+        );
+    }
+    println!("finished on attempt #{}, counter {}", attempt, unsafe {
+        counter.read()
+    });
+    let _ = checkpoint::discard();
+}
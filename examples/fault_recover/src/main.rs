@@ -12,7 +12,12 @@ extern crate alloc;
 
 use unishyper::*;
 
+mod checkpoint;
+mod panic_policy;
 mod resource;
+mod supervisor;
+#[cfg(feature = "watchdog")]
+mod watchdog;
 // mod sem;
 // mod fs;
 
@@ -20,6 +25,10 @@ mod resource;
 fn main() {
     println!("Hello, world!");
     thread_spawn(resource::test_recover, 123);
+    // thread_spawn(supervisor::test_supervisor, 123);
+    // thread_spawn(panic_policy::test_panic_policy, 123);
+    // thread_spawn(checkpoint::test_checkpoint, 123);
+    // thread_spawn(watchdog::test_watchdog, 123);
     // thread_spawn(sem::semaphore_test, 123);
     // thread_spawn(fs::test_fs, 123);
 }
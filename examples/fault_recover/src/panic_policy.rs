@@ -0,0 +1,43 @@
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use unishyper::shyperstd::panic::{self, PanicPolicy};
+use unishyper::shyperstd::thread;
+use unishyper::*;
+
+#[allow(dead_code)]
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(dead_code)]
+pub extern "C" fn test_panic_policy(_arg: usize) {
+    panic::set_panic_hook(Box::new(|info| {
+        println!("global panic hook: {:?}", info.location());
+    }));
+
+    // Recovered worker, panics on its first two runs.
+    let worker = thread::Builder::new()
+        .panic_policy(PanicPolicy::UnwindRestart)
+        .panic_hook(Box::new(|_| println!("worker panic hook")))
+        .spawn_restartable(|| {
+            let run = RUNS.fetch_add(1, Ordering::Relaxed);
+            if run < 2 {
+                panic!("Simulate a panic of worker run #{}!", run);
+            }
+            run
+        })
+        .unwrap();
+    println!("worker finished on run #{:?}", worker.join());
+
+    // Unwound worker, joining it fails.
+    let worker = thread::Builder::new()
+        .panic_policy(PanicPolicy::Unwind)
+        .spawn(|| panic!("Simulate a panic of an unwound worker!"))
+        .unwrap();
+    println!("unwound worker joined: {:?}", worker.join().is_ok());
+
+    // Critical thread, its panic halts the image.
+    thread::Builder::new()
+        .panic_policy(PanicPolicy::Abort)
+        .spawn(|| panic!("Simulate a panic of a critical thread!"))
+        .unwrap();
+}
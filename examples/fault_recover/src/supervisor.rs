@@ -0,0 +1,46 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use unishyper::libs::supervisor::{RestartPolicy, Strategy, Supervisor};
+use unishyper::*;
+
+#[allow(dead_code)]
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Panics on its first three runs, then finishes.
+#[allow(dead_code)]
+extern "C" fn flaky_worker(arg: usize) {
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    println!("flaky_worker arg {} run #{}", arg, run);
+    if run < 3 {
+        panic!("Simulate a panic of run #{}!", run);
+    }
+}
+
+#[allow(dead_code)]
+extern "C" fn steady_worker(arg: usize) {
+    loop {
+        println!("steady_worker arg {}", arg);
+        thread_yield();
+    }
+}
+
+#[allow(dead_code)]
+pub extern "C" fn test_supervisor(_arg: usize) {
+    let supervisor = Supervisor::new(
+        "demo",
+        RestartPolicy::new(Strategy::Backoff {
+            initial_ms: 10,
+            max_ms: 100,
+        })
+        .max_restarts(5, 1000),
+    );
+    supervisor.spawn("flaky", flaky_worker, 1);
+    supervisor.spawn("steady", steady_worker, 2);
+
+    while supervisor.children()[0].state == unishyper::libs::supervisor::ChildState::Running {
+        thread_yield();
+    }
+    for event in unishyper::libs::supervisor::events() {
+        println!("{}", event);
+    }
+}
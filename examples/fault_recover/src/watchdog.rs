@@ -0,0 +1,42 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use unishyper::shyperstd::watchdog::{self, Unhealthy};
+use unishyper::*;
+
+#[allow(dead_code)]
+static ALIVE: AtomicBool = AtomicBool::new(true);
+
+/// Wait for a health check at least, they run four times per timeout.
+#[allow(dead_code)]
+fn wait_checks() {
+    thread_block_current_with_timeout_us(watchdog::status().timeout_ms * 1000 / 2);
+    thread_yield();
+}
+
+/// Fails a liveness check for half a timeout, also without a hardware watchdog,
+/// e.g. on aarch64 QEMU, where the service only reports it.
+#[allow(dead_code)]
+pub extern "C" fn test_watchdog(_arg: usize) {
+    let id = watchdog::register_liveness_check("demo", || ALIVE.load(Ordering::Relaxed));
+    let status = watchdog::status();
+    println!(
+        "watchdog device {:?}, timeout {}ms",
+        status.device, status.timeout_ms
+    );
+
+    wait_checks();
+    assert!(watchdog::status().unhealthy.is_none());
+
+    ALIVE.store(false, Ordering::Relaxed);
+    wait_checks();
+    match watchdog::status().unhealthy {
+        Some(Unhealthy::CheckFailed(name)) => println!("liveness check {:?} failed", name),
+        other => panic!("liveness check not reported, health {:?}", other),
+    }
+
+    ALIVE.store(true, Ordering::Relaxed);
+    wait_checks();
+    assert!(watchdog::status().unhealthy.is_none());
+    watchdog::unregister_liveness_check(id).unwrap();
+    println!("watchdog test passed");
+}
@@ -26,6 +26,9 @@ static mut TEST_PROTECTED_GLOCAL: usize = 123;
 
 static mut TEST_SHARED_GLOCAL: usize = 456;
 
+#[zone_protected::zone_data("secret")]
+static mut TEST_ZONE_DATA: usize = 789;
+
 #[allow(unused)]
 fn test_stack_var_rw() {
     // Test write isolation for stack data.
@@ -136,6 +139,37 @@ fn test_global_var_rw() {
     }
 }
 
+#[allow(unused)]
+fn test_zone_data_rw() {
+    use std::thread::{Builder, ZoneSpec};
+
+    // Threads of the named zone can access its data.
+    Builder::new()
+        .zone(ZoneSpec::Named("secret"))
+        .spawn(|| unsafe {
+            TEST_ZONE_DATA += 1;
+            println!(
+                "On secret zone thread, zone data is {} at {:#p}",
+                TEST_ZONE_DATA, &TEST_ZONE_DATA
+            );
+        })
+        .expect("fail to spawn into zone secret")
+        .join()
+        .unwrap_or_else(|_| println!("The thread being joined has panicked"));
+
+    // Others can't.
+    std::thread::spawn(|| unsafe {
+        println!(
+            "On test thread, try to read zone data at {:#p}",
+            &TEST_ZONE_DATA
+        );
+        let zone_data = TEST_ZONE_DATA;
+        println!("On test thread, zone data is {}", zone_data);
+    })
+    .join()
+    .unwrap_or_else(|_| println!("The thread being joined has panicked"));
+}
+
 #[allow(unused)]
 fn test_heap_var_rw() {
     let num_pages = 1;
@@ -241,7 +275,13 @@ fn main() {
 
     // test_global_var_rw();
 
+    // test_zone_data_rw();
+
     test_heap_var_rw();
 
+    for (zone_id, count) in std::zone::violation_counts() {
+        println!("zone {} caused {} isolation violations", zone_id, count);
+    }
+
     println!("Memory isolation bench finished");
 }
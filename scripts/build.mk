@@ -1,6 +1,7 @@
 # Utils
 OBJCOPY := rust-objcopy
 OBJDUMP := rust-objdump
+NM := rust-nm
 
 # Rust flags, for unwind.
 export RUSTFLAGS := ${RUSTFLAGS} -C force-frame-pointers=yes -C link-arg=-T$(LD_SCRIPT)
@@ -63,7 +64,15 @@ ifeq ($(filter $(BUS),mmio pci),)
   $(error "BUS" must be one of "mmio", "pci")
 endif
 
-FEATURES := ${MACHINE}, unishyper/log-level-${LOG}, unishyper/$(BUS)
+comma := ,
+
+# Extra features, comma separated, e.g. APP_FEATURES=unishyper/ksyms.
+APP_FEATURES ?=
+
+FEATURES := ${MACHINE}, unishyper/log-level-${LOG}, unishyper/$(BUS)$(if $(APP_FEATURES),$(comma) $(APP_FEATURES))
+
+# The symbol table of backtraces is only embedded with feature "ksyms".
+KSYMS := $(filter ksyms unishyper/ksyms,$(subst $(comma), ,$(FEATURES)))
 
 # Currently we use [rboot](https://github.com/hky1999/rboot.git) as bootloader in x86_64.
 ifeq ($(ARCH), x86_64)
@@ -79,6 +88,7 @@ endef
 define cargo_build
 	cargo ${TOOLCHAIN} ${CARGO_ACTION} $(CARGO_ARGS) --features "${FEATURES}"
 	cp $(BUILD_ELF) $(OUT_ELF)
+	$(if $(KSYMS),${NM} --defined-only --demangle --numeric-sort ${OUT_ELF} | python3 $(CURDIR)/scripts/ksyms.py ${OUT_ELF})
 	${OBJCOPY} ${OUT_ELF} -O binary ${OUT_BIN}
 	${OBJDUMP} --demangle -d ${OUT_ELF} > ${OUT_ASM}
 endef
@@ -0,0 +1,50 @@
+#!/usr/bin/env python3
+"""Extract ELF core dumps streamed over serial, see src/libs/coredump.rs.
+
+Usage: coredump.py serial.log [prefix]
+
+Every dump between `COREDUMP BEGIN <size>` and `COREDUMP END` is written to `<prefix>.<n>`,
+`core.<n>` by default, and can be loaded with `gdb <image> <core>`.
+"""
+
+import base64
+import sys
+
+
+def extract(lines):
+    """Yield the decoded cores in `lines`, log lines interleaved with a dump are skipped."""
+    size = None
+    chunks = []
+    for line in lines:
+        line = line.strip()
+        # Serial logs may prefix lines with escape sequences or timestamps.
+        if "COREDUMP BEGIN" in line:
+            size = int(line.split("COREDUMP BEGIN", 1)[1])
+            chunks = []
+        elif "COREDUMP END" in line and size is not None:
+            core = base64.b64decode("".join(chunks))
+            if len(core) != size:
+                print(f"truncated core, {len(core)} of {size} bytes", file=sys.stderr)
+            yield core
+            size = None
+        elif size is not None and line and all(c.isalnum() or c in "+/=" for c in line):
+            chunks.append(line)
+
+
+def main():
+    if len(sys.argv) < 2:
+        sys.exit(__doc__)
+    prefix = sys.argv[2] if len(sys.argv) > 2 else "core"
+    with open(sys.argv[1], errors="replace") as log:
+        count = 0
+        for count, core in enumerate(extract(log), 1):
+            path = f"{prefix}.{count}"
+            with open(path, "wb") as f:
+                f.write(core)
+            print(f"{path}: {len(core)} bytes")
+    if count == 0:
+        sys.exit("no core dump found")
+
+
+if __name__ == "__main__":
+    main()
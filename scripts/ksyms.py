@@ -0,0 +1,99 @@
+#!/usr/bin/env python3
+"""Fill the `.ksyms` section of a kernel ELF with its function symbols, see src/libs/ksyms.rs.
+
+Usage: rust-nm --defined-only --demangle --numeric-sort kernel.elf | ksyms.py kernel.elf
+"""
+
+import re
+import struct
+import sys
+
+KSYMS_MAGIC = 0x4D59534B
+# Hash suffix of legacy Rust symbols, e.g. `::h0123456789abcdef`.
+RUST_HASH = re.compile(r"::h[0-9a-f]{16}$")
+TEXT_TYPES = "tTwW"
+
+
+def find_section(elf, name):
+    """Return (file offset, size) of section `name` in an ELF64 little endian image."""
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(index):
+        return struct.unpack_from("<IIQQQQIIQQ", elf, shoff + index * shentsize)
+
+    strtab_offset = header(shstrndx)[4]
+    for index in range(shnum):
+        sh = header(index)
+        start = strtab_offset + sh[0]
+        end = elf.index(b"\0", start)
+        if elf[start:end].decode() == name:
+            return sh[4], sh[5]
+    return None
+
+
+def read_symbols(lines):
+    """Return the text range and the sorted (address, name) of functions from `nm` output."""
+    symbols = []
+    labels = {}
+    for line in lines:
+        fields = line.rstrip("\n").split(" ", 2)
+        if len(fields) != 3:
+            continue
+        addr, kind, name = int(fields[0], 16), fields[1], fields[2]
+        if name in ("TEXT_START", "TEXT_END"):
+            labels[name] = addr
+        elif kind in TEXT_TYPES:
+            symbols.append((addr, RUST_HASH.sub("", name)))
+    start, end = labels["TEXT_START"], labels["TEXT_END"]
+    symbols = sorted(s for s in symbols if start <= s[0] < end)
+    return start, symbols
+
+
+def build_table(base, symbols):
+    entries = bytearray()
+    pool = bytearray()
+    names = {}
+    last = None
+    count = 0
+    for addr, name in symbols:
+        # Keep the first of aliases.
+        if addr == last:
+            continue
+        last = addr
+        if name not in names:
+            names[name] = len(pool)
+            pool += name.encode() + b"\0"
+        entries += struct.pack("<II", addr - base, names[name])
+        count += 1
+    return struct.pack("<IIQ", KSYMS_MAGIC, count, base) + entries + pool, count
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    path = sys.argv[1]
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+
+    section = find_section(elf, ".ksyms")
+    if section is None or section[1] == 0:
+        print("ksyms: no .ksyms reserved in {}, skipped".format(path))
+        return
+    offset, size = section
+
+    base, symbols = read_symbols(sys.stdin)
+    table, count = build_table(base, symbols)
+    if len(table) > size:
+        sys.exit(
+            "ksyms: table of {} bytes exceeds .ksyms of {} bytes, "
+            "raise KSYMS_SIZE in src/libs/ksyms.rs".format(len(table), size)
+        )
+    elf[offset : offset + size] = table + bytes(size - len(table))
+    with open(path, "wb") as f:
+        f.write(elf)
+    print("ksyms: {} symbols, {} of {} bytes".format(count, len(table), size))
+
+
+if __name__ == "__main__":
+    main()
@@ -67,6 +67,16 @@ QEMU_CMD := ${QEMU_CMD} \
 endif
 endif
 
+# QEMU_WATCHDOG_OPTIONS, the image is built with feature "unishyper/watchdog".
+# Only x86_64 gets a watchdog device, QEMU's aarch64 virt and riscv64 virt machines have none,
+# the watchdog service only monitors and reports the health there.
+
+ifeq ($(WATCHDOG), y)
+ifeq ($(ARCH), x86_64)
+QEMU_CMD := ${QEMU_CMD} -device i6300esb -watchdog-action reset
+endif
+endif
+
 QEMU_COMMON_OPTIONS := -serial stdio -display none
 
 define qemu_run
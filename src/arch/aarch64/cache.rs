@@ -0,0 +1,46 @@
+//! Data cache maintenance by virtual address range.
+//!
+//! Used by DMA buffers shared with non-coherent devices:
+//! * clean before the device reads memory written by CPU.
+//! * invalidate before CPU reads memory written by the device.
+
+/// Get the minimum data cache line size in bytes.
+#[inline]
+pub fn dcache_line_size() -> usize {
+    let ctr: usize;
+    unsafe { core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    // CTR_EL0.DminLine [19:16] is log2 of the number of words.
+    4 << ((ctr >> 16) & 0xf)
+}
+
+#[inline(always)]
+fn for_each_line<F: Fn(usize)>(vaddr: usize, size: usize, f: F) {
+    let line = dcache_line_size();
+    let start = vaddr & !(line - 1);
+    let end = vaddr + size;
+    for addr in (start..end).step_by(line) {
+        f(addr);
+    }
+    unsafe { core::arch::asm!("dsb sy") };
+}
+
+/// Clean data cache of range [vaddr, vaddr + size) to the point of coherency.
+pub fn clean_dcache_range(vaddr: usize, size: usize) {
+    for_each_line(vaddr, size, |addr| unsafe {
+        core::arch::asm!("dc cvac, {0:x}", in(reg) addr)
+    });
+}
+
+/// Invalidate data cache of range [vaddr, vaddr + size) to the point of coherency.
+pub fn invalidate_dcache_range(vaddr: usize, size: usize) {
+    for_each_line(vaddr, size, |addr| unsafe {
+        core::arch::asm!("dc ivac, {0:x}", in(reg) addr)
+    });
+}
+
+/// Clean and invalidate data cache of range [vaddr, vaddr + size) to the point of coherency.
+pub fn clean_invalidate_dcache_range(vaddr: usize, size: usize) {
+    for_each_line(vaddr, size, |addr| unsafe {
+        core::arch::asm!("dc civac, {0:x}", in(reg) addr)
+    });
+}
@@ -118,6 +118,36 @@ impl Into<Registers> for TrapContextFrame {
     }
 }
 
+#[cfg(feature = "coredump")]
+impl From<&TrapContextFrame> for super::coredump::CoreRegs {
+    fn from(ctx: &TrapContextFrame) -> Self {
+        use super::coredump::*;
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[..31].copy_from_slice(&ctx.gpr);
+        regs.0[SP] = ctx.sp;
+        regs.0[PC] = ctx.elr;
+        regs.0[PSTATE] = ctx.spsr;
+        regs
+    }
+}
+
+/// A yielded thread resumes at `lr`.
+#[cfg(feature = "coredump")]
+impl From<&ThreadContext> for super::coredump::CoreRegs {
+    fn from(ctx: &ThreadContext) -> Self {
+        use super::coredump::*;
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[19..30].copy_from_slice(&[
+            ctx.r19, ctx.r20, ctx.r21, ctx.r22, ctx.r23, ctx.r24, ctx.r25, ctx.r26, ctx.r27,
+            ctx.r28, ctx.r29,
+        ]);
+        regs.0[30] = ctx.lr;
+        regs.0[SP] = ctx.sp;
+        regs.0[PC] = ctx.lr;
+        regs
+    }
+}
+
 impl core::fmt::Display for TrapContextFrame {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), core::fmt::Error> {
         for i in 0..31 {
@@ -164,6 +194,11 @@ impl ContextFrameTrait for TrapContextFrame {
         self.sp = sp as u64;
     }
 
+    fn frame_pointer(&self) -> usize {
+        // fp -> x29
+        self.gpr[29] as usize
+    }
+
     fn gpr(&self, index: usize) -> usize {
         assert!(index < crate::arch::registers::GPR_NUM_MAX);
         self.gpr[index] as usize
@@ -320,3 +355,77 @@ unsafe extern "C" fn context_switch_to_trap(_current_ctx: &mut ThreadContext, _n
         options(noreturn),
     )
 }
+
+/// Call `_entry` with `_arg` on another stack whose top is `_sp`, then switch back.
+///
+/// Used by zone calls to run the callee on a stack of the target zone.
+/// ## Arguments
+/// * `_arg`    - argument passed to `_entry`, on `x0`.
+/// * `_entry`  - function to call, on `x1`.
+/// * `_sp`     - top of the stack, 16 bytes aligned, on `x2`.
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+#[naked]
+pub unsafe extern "C" fn call_on_stack(_arg: usize, _entry: extern "C" fn(usize), _sp: usize) {
+    asm!(
+        "stp    x29, x30, [sp, #-16]!",
+        "mov    x29, sp",
+        "mov    sp, x2",
+        "blr    x1",
+        "mov    sp, x29",
+        "ldp    x29, x30, [sp], #16",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Save current callee-saved registers into `_ctx` like `setjmp`, call `_capture`
+/// with the saved stack pointer and `_arg`, then return 0.
+///
+/// `restore_checkpoint_context` on `_ctx` returns from here again, with its `_ret`.
+/// ## Arguments
+/// * `_ctx`        - the pointer to the checkpoint's `ThreadContext`, on `x0`.
+/// * `_capture`    - function copying the stack from the saved stack pointer, on `x1`.
+/// * `_arg`        - second argument passed to `_capture`, on `x2`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn save_checkpoint_context(
+    _ctx: &mut ThreadContext,
+    _capture: extern "C" fn(usize, usize),
+    _arg: usize,
+) -> usize {
+    asm!(
+        save_yield_context!(),
+        // `save_yield_context` uses x19 and x20 as scratch registers.
+        "ldp    x19, x20, [x0, 2 * 8]",
+        "stp    x29, x30, [sp, #-16]!",
+        "mov    x29, sp",
+        "ldr    x0, [x0]",
+        "mov    x3, x1",
+        "mov    x1, x2",
+        "blr    x3",
+        "ldp    x29, x30, [sp], #16",
+        "mov    x0, #0",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Restore callee-saved registers from `_ctx` saved by `save_checkpoint_context`,
+/// which returns `_ret` again.
+///
+/// The stack above the saved stack pointer must be restored before.
+/// ## Arguments
+/// * `_ctx`    - the pointer to the checkpoint's `ThreadContext`, on `x0`.
+/// * `_ret`    - value returned by `save_checkpoint_context`, on `x1`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn restore_checkpoint_context(_ctx: &ThreadContext, _ret: usize) -> ! {
+    asm!(
+        "mov    x2, x1",
+        "mov    x1, x0",
+        restore_yield_context!(),
+        "mov    x0, x2",
+        "ret",
+        options(noreturn),
+    )
+}
@@ -0,0 +1,55 @@
+//! Registers of ELF core dumps, in the layout of `struct user_pt_regs` as gdb reads from NT_PRSTATUS.
+
+#[cfg(feature = "unwind")]
+use super::registers::{Aarch64, Registers};
+
+/// EM_AARCH64.
+pub const ELF_MACHINE: u16 = 183;
+pub const ELF_FLAGS: u32 = 0;
+
+/// x0 - x30, sp, pc, pstate.
+pub const GREG_NUM: usize = 34;
+pub const SP: usize = 31;
+pub const PC: usize = 32;
+pub const PSTATE: usize = 33;
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegs(pub [u64; GREG_NUM]);
+
+impl CoreRegs {
+    /// Registers of the caller, only pc, sp and fp are captured.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let (pc, sp, fp): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "adr {}, .",
+                "mov {}, sp",
+                "mov {}, x29",
+                out(reg) pc,
+                out(reg) sp,
+                out(reg) fp,
+            );
+        }
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[29] = fp;
+        regs.0[SP] = sp;
+        regs.0[PC] = pc;
+        regs
+    }
+}
+
+/// Registers to unwind from, whose return address is the faulting pc plus `CALLER_OFFSET`.
+#[cfg(feature = "unwind")]
+impl From<&Registers> for CoreRegs {
+    fn from(registers: &Registers) -> Self {
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        for i in 0..=30 {
+            regs.0[i] = registers[gimli::Register(Aarch64::X0.0 + i as u16)].unwrap_or(0);
+        }
+        regs.0[SP] = registers[Aarch64::SP].unwrap_or(0);
+        regs.0[PC] = registers[Aarch64::X30]
+            .map_or(0, |ra| ra - crate::libs::unwind::arch::CALLER_OFFSET);
+        regs
+    }
+}
@@ -14,6 +14,20 @@ core::arch::global_asm!(
     size_of_context_frame = const core::mem::size_of::<ContextFrame>()
 );
 
+/// Print the backtrace of the trapped context.
+unsafe fn print_trapped_backtrace(ctx: *mut ContextFrame) {
+    use crate::libs::traits::ContextFrameTrait;
+    let ctx = ctx.read();
+    crate::libs::backtrace::print_backtrace_from(ctx.exception_pc(), ctx.frame_pointer());
+}
+
+/// Dump the trapped context, which is fatal without unwinding.
+#[cfg(all(feature = "coredump", not(feature = "unwind")))]
+unsafe fn dump_trapped(ctx: *mut ContextFrame) {
+    let regs = super::coredump::CoreRegs::from(&*ctx);
+    crate::libs::coredump::dump(regs, crate::libs::coredump::SIGNAL_EXCEPTION);
+}
+
 #[no_mangle]
 unsafe extern "C" fn current_el_spx_synchronous(ctx: *mut ContextFrame) {
     let ec = ESR_EL1.read(ESR_EL1::EC);
@@ -25,6 +39,9 @@ unsafe extern "C" fn current_el_spx_synchronous(ctx: *mut ContextFrame) {
         ESR_EL1.get(),
         ctx.read()
     );
+    print_trapped_backtrace(ctx);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
 
     use crate::libs::traits::ContextFrameTrait;
     let ctx_mut = ctx.as_mut().unwrap();
@@ -63,15 +80,24 @@ unsafe extern "C" fn current_el_spx_irq(ctx: *mut ContextFrame) {
     use crate::drivers::gic::INT_TIMER;
     match irq {
         Some(INT_TIMER) => {
+            // Interrupts are handled with the full page table view, same as x86_64.
+            #[cfg(feature = "zone")]
+            let ori_zone = zone::switch_to_privilege();
             crate::libs::timer::interrupt();
             InterruptController::finish(INT_TIMER);
+            #[cfg(feature = "zone")]
+            zone::switch_from_privilege(ori_zone);
             // Give up CPU actively.
             crate::libs::thread::thread_yield();
         }
         Some(i) => {
             if i >= 32 {
+                #[cfg(feature = "zone")]
+                let ori_zone = zone::switch_to_privilege();
                 crate::libs::interrupt::interrupt(i);
                 InterruptController::finish(irq.unwrap());
+                #[cfg(feature = "zone")]
+                zone::switch_from_privilege(ori_zone);
             } else {
                 warn!(
                     "current_el_spx_irq, thread [{}], el{}, irq {}, daif: {:x}\n ctx on sp {:p}\n",
@@ -113,6 +139,9 @@ unsafe extern "C" fn current_el_sp0_synchronous(ctx: *mut ContextFrame) {
         ESR_EL1.get(),
         ctx.read()
     );
+    print_trapped_backtrace(ctx);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
 
     #[cfg(feature = "unwind")]
     {
@@ -144,6 +173,9 @@ unsafe extern "C" fn current_el_sp0_irq(ctx: *mut ContextFrame) {
 #[no_mangle]
 unsafe extern "C" fn current_el_spx_serror(ctx: *mut ContextFrame) {
     println!("current_el_spx_serror\n{}", ctx.read());
+    print_trapped_backtrace(ctx);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
     #[cfg(feature = "unwind")]
     {
         let ctx = *ctx.clone();
@@ -162,6 +194,9 @@ unsafe extern "C" fn lower_aarch64_synchronous(ctx: *mut ContextFrame) {
         tid,
         ctx.read()
     );
+    print_trapped_backtrace(ctx);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
 
     #[cfg(feature = "unwind")]
     {
@@ -194,6 +229,9 @@ unsafe extern "C" fn lower_aarch64_irq(ctx: *mut ContextFrame) {
 unsafe extern "C" fn lower_aarch64_serror(ctx: *mut ContextFrame) {
     let core_id = crate::arch::Arch::core_id();
     println!("core {} lower_aarch64_serror\n {}", core_id, ctx.read());
+    print_trapped_backtrace(ctx);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
 
     #[cfg(feature = "unwind")]
     {
@@ -1,4 +1,7 @@
+pub mod cache;
 mod context_frame;
+#[cfg(feature = "coredump")]
+pub mod coredump;
 mod exception;
 pub mod irq;
 mod mmu;
@@ -38,6 +41,10 @@ impl Address for usize {
 
 pub type ContextFrame = context_frame::TrapContextFrame;
 pub type ThreadContext = context_frame::ThreadContext;
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+pub use context_frame::call_on_stack;
+#[cfg(feature = "checkpoint")]
+pub use context_frame::{restore_checkpoint_context, save_checkpoint_context};
 
 use cortex_a::registers::*;
 use tock_registers::interfaces::{Readable, Writeable};
@@ -61,7 +68,8 @@ impl ArchTrait for Arch {
         unsafe {
             core::arch::asm!("dsb ishst");
             if let Some(vaddr) = vaddr {
-                core::arch::asm!("tlbi vaae1is, {}",  in(reg) vaddr);
+                // Operand holds VA[55:12], the address is flushed for all ASIDs.
+                core::arch::asm!("tlbi vaae1is, {}",  in(reg) vaddr >> 12);
             } else {
                 // flush the entire TLB
                 core::arch::asm!("tlbi vmalle1is");
@@ -9,7 +9,14 @@ use crate::mm::frame_allocator;
 use crate::mm::frame_allocator::AllocatedFrames;
 use crate::mm::interface::{PageTableEntryAttrTrait, PageTableTrait, Error, MapGranularity};
 use crate::mm::paging::{Entry, EntryAttribute};
+use crate::mm::wx;
 use crate::libs::synch::spinlock::SpinlockIrqSave;
+#[cfg(feature = "zone")]
+use crate::mm::zone_view::{view_attr, view_zones, VIEW_NUM};
+#[cfg(feature = "zone")]
+use core::ops::Range;
+#[cfg(feature = "zone")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub const PAGE_TABLE_L1_SHIFT: usize = 30;
 pub const PAGE_TABLE_L2_SHIFT: usize = 21;
@@ -105,8 +112,13 @@ impl core::convert::From<Aarch64PageTableEntry> for Entry {
 
 impl core::convert::From<Entry> for Aarch64PageTableEntry {
     fn from(pte: Entry) -> Self {
+        // Zones' page table views are told apart by ASID, see `mm::zone_view`.
+        #[cfg(feature = "zone")]
+        let not_global = PAGE_DESCRIPTOR::NG::True;
+        #[cfg(not(feature = "zone"))]
+        let not_global = PAGE_DESCRIPTOR::NG::False;
         Aarch64PageTableEntry(
-            (if pte.attribute().u_shared() {
+            (not_global + if pte.attribute().u_shared() {
                 PAGE_DESCRIPTOR::LIB::True
             } else {
                 PAGE_DESCRIPTOR::LIB::False
@@ -151,16 +163,42 @@ impl core::convert::From<Entry> for Aarch64PageTableEntry {
 pub struct Aarch64PageTable {
     directory: AllocatedFrames,
     pages: Mutex<Vec<AllocatedFrames>>,
+    /// Directories of zones' page table views, see `mm::zone_view`.
+    #[cfg(feature = "zone")]
+    #[allow(unused)]
+    views: Vec<AllocatedFrames>,
 }
 
 static PAGE_TABLE: Once<SpinlockIrqSave<Aarch64PageTable>> = Once::new();
 
+/// Physical address of each view's directory, indexed by zone id,
+/// the privileged zone's view is the full page table.
+///
+/// Views are switched with the page table lock possibly held, so they are recorded here.
+#[cfg(feature = "zone")]
+static VIEW_DIRECTORIES: Once<[usize; VIEW_NUM]> = Once::new();
+
+#[cfg(feature = "zone")]
+#[allow(clippy::declare_interior_mutable_const)]
+const BOOT_KERNEL_VIEW: AtomicUsize = AtomicUsize::new(0);
+/// Physical address of each view's kernel directory in TTBR1_EL1, indexed by zone id,
+/// zero for the boot directory. A view gets its own copy once pages of the kernel image
+/// are hidden from it, see `Aarch64PageTable::set_zone_range`.
+#[cfg(feature = "zone")]
+static KERNEL_VIEW_DIRECTORIES: [AtomicUsize; VIEW_NUM] = [BOOT_KERNEL_VIEW; VIEW_NUM];
+
 pub fn page_table() -> &'static SpinlockIrqSave<Aarch64PageTable> {
     PAGE_TABLE
         .get()
         .expect("FAILED page table is not successfully init")
 }
 
+/// Level 1 block descriptors are always available with a 4KB granule,
+/// so 1GB mappings are supported on this architecture.
+pub fn huge_1gb_supported() -> bool {
+    true
+}
+
 pub fn init() {
     PAGE_TABLE.call_once(|| {
         let pgdir_frame = frame_allocator::allocate_frames(1).unwrap();
@@ -169,14 +207,147 @@ pub fn init() {
             "Page table init ok, dir at {}",
             pgdir_frame.start().start_address()
         );
+
+        #[cfg(feature = "zone")]
+        let views = {
+            let mut directories = [pgdir_frame.start_address().value(); VIEW_NUM];
+            let mut views = Vec::new();
+            for zone_id in view_zones() {
+                let view_frame = frame_allocator::allocate_frames(1).unwrap();
+                view_frame.start().zero();
+                directories[zone_id] = view_frame.start_address().value();
+                views.push(view_frame);
+            }
+            VIEW_DIRECTORIES.call_once(|| directories);
+            views
+        };
+
+        let mut pages = Vec::new();
+        protect_kernel_image(&mut pages);
+
         SpinlockIrqSave::new(Aarch64PageTable {
             directory: pgdir_frame,
-            pages: Mutex::new(Vec::new()),
+            pages: Mutex::new(pages),
+            #[cfg(feature = "zone")]
+            views,
         })
     });
     info!("page table init ok, PAGE_TABLE at {:p}", &PAGE_TABLE);
 }
 
+/// The boot directory in start.S, installed in TTBR1_EL1 for the kernel address space.
+fn kernel_directory() -> Aarch64PageTableEntry {
+    extern "C" {
+        fn KERNEL_PAGE_DIRECTORY();
+    }
+    Aarch64PageTableEntry::from_pa((KERNEL_PAGE_DIRECTORY as usize).kva2pa())
+}
+
+/// Split the 1GB block of the boot directory holding the kernel image down to 4KB pages,
+/// and map them with permissions of their sections, see `mm::wx`.
+///
+/// The boot directory is also the identity mapping in TTBR0_EL1 at boot,
+/// so the alias of the image gets the same permissions.
+/// Memory outside the image is made non-executable.
+fn protect_kernel_image(pages: &mut Vec<AllocatedFrames>) {
+    use tock_registers::*;
+    const ENTRY_PER_PAGE: usize = PAGE_SIZE / MACHINE_SIZE;
+    const BLOCK_2MB: usize = MapGranularity::Page2MB as usize;
+
+    let directory = kernel_directory();
+    let image = wx::image_range();
+    if image.start.l1x() != (image.end - 1).l1x() {
+        warn!("kernel image {:#x?} crosses 1GB blocks, not protected", image);
+        return;
+    }
+    let l1e = directory.entry(image.start.l1x());
+    if !l1e.valid() || !l1e.blocked() {
+        warn!("kernel image is not mapped by 1GB block, not protected");
+        return;
+    }
+    let mut allocate_table = || {
+        let af = frame_allocator::allocate_frames(1)?;
+        af.start().zero();
+        let table = Aarch64PageTableEntry::make_table(af.start_address().value());
+        pages.push(af);
+        Some(table)
+    };
+    let l2e = match allocate_table() {
+        Some(l2e) => l2e,
+        None => {
+            warn!("failed to allocate frame for kernel image tables, not protected");
+            return;
+        }
+    };
+
+    // Attribute bits of the 1GB block are kept by the smaller mappings,
+    // those outside the image are non-executable.
+    let pxn = PAGE_DESCRIPTOR::PXN::True.value as usize;
+    let block_bits = l1e.to_pte() & !0x0000_FFFF_FFFF_F000;
+    let base_pa = l1e.to_pa();
+    let base_va = image.start & !(MapGranularity::Page1GB as usize - 1);
+    for index in 0..ENTRY_PER_PAGE {
+        let va = base_va + index * BLOCK_2MB;
+        let bits = if va < image.end && image.start < va + BLOCK_2MB {
+            block_bits
+        } else {
+            block_bits | pxn
+        };
+        l2e.set_entry(index, Aarch64PageTableEntry(bits | (base_pa + index * BLOCK_2MB)));
+    }
+
+    let chunks = (image.start & !(BLOCK_2MB - 1)..image.end).step_by(BLOCK_2MB);
+    for chunk in chunks {
+        let l3e = match allocate_table() {
+            Some(l3e) => l3e,
+            None => {
+                warn!("kernel image beyond {:#x} is not protected", chunk);
+                break;
+            }
+        };
+        let chunk_pa = base_pa + chunk.l2x() * BLOCK_2MB;
+        for index in 0..ENTRY_PER_PAGE {
+            let mut reg =
+                LocalRegisterCopy::<u64, PAGE_DESCRIPTOR::Register>::new((block_bits | pxn) as u64);
+            reg.modify(
+                PAGE_DESCRIPTOR::TYPE::Table
+                    + PAGE_DESCRIPTOR::OUTPUT_PPN.val(((chunk_pa >> PAGE_SHIFT) + index) as u64),
+            );
+            if let Some(perm) = wx::image_perm(chunk + index * PAGE_SIZE) {
+                reg.modify(
+                    if perm.writable() {
+                        PAGE_DESCRIPTOR::AP::RW_EL1
+                    } else {
+                        PAGE_DESCRIPTOR::AP::RO_EL1
+                    } + if perm.executable() {
+                        PAGE_DESCRIPTOR::PXN::False
+                    } else {
+                        PAGE_DESCRIPTOR::PXN::True
+                    },
+                );
+            }
+            l3e.set_entry(index, Aarch64PageTableEntry(reg.get() as usize));
+        }
+        l2e.set_entry(chunk.l2x(), l3e);
+    }
+
+    directory.set_entry(image.start.l1x(), l2e);
+    for index in 0..ENTRY_PER_PAGE {
+        let entry = directory.entry(index);
+        if index != image.start.l1x() && entry.valid() && entry.blocked() {
+            directory.set_entry(index, Aarch64PageTableEntry(entry.to_pte() | pxn));
+        }
+    }
+    crate::arch::Arch::flush_tlb(None);
+    info!("kernel image {:#x?} protected", image);
+}
+
+/// Look up the mapping of `va` in the kernel address space.
+#[cfg(feature = "wx-check")]
+pub fn kernel_entry(va: usize) -> Option<(Entry, MapGranularity)> {
+    lookup_in(kernel_directory(), va)
+}
+
 /// Install page table for user address,
 /// Store directory in TTBR0_EL1.
 pub fn install_page_table() {
@@ -193,20 +364,197 @@ pub fn install_page_table() {
     crate::arch::Arch::flush_tlb(None);
 }
 
-impl PageTableTrait for Aarch64PageTable {
-    fn base_pa(&self) -> usize {
-        self.directory.start_address().value()
+/// ASID field of TTBR0_EL1, TCR_EL1.A1 selects TTBR0_EL1 to define the ASID.
+#[cfg(feature = "zone")]
+const TTBR_ASID_SHIFT: usize = 48;
+
+/// Get the zone whose page table view is active on current core, which is its ASID.
+#[cfg(feature = "zone")]
+pub fn current_view() -> zone::ZoneId {
+    use cortex_a::registers::TTBR0_EL1;
+    use tock_registers::interfaces::Readable;
+    (TTBR0_EL1.get() >> TTBR_ASID_SHIFT) as zone::ZoneId
+}
+
+/// Activate the page table view of target zone on current core.
+///
+/// Mappings of the views are non-global, so TLB entries are tagged by the zone's ASID
+/// and need no flush here.
+#[cfg(feature = "zone")]
+pub fn switch_view(zone_id: zone::ZoneId) {
+    use cortex_a::registers::{TTBR0_EL1, TTBR1_EL1};
+    use tock_registers::interfaces::Writeable;
+    let directories = match VIEW_DIRECTORIES.get() {
+        Some(directories) => directories,
+        None => return,
+    };
+    let directory = match directories.get(zone_id) {
+        Some(directory) => *directory,
+        None => {
+            warn!("switch_view: zone {} has no page table view", zone_id);
+            return;
+        }
+    };
+    let kernel_directory = match KERNEL_VIEW_DIRECTORIES[zone_id].load(Ordering::Acquire) {
+        0 => kernel_directory().to_pa(),
+        directory => directory,
+    };
+    TTBR1_EL1.set(kernel_directory as u64);
+    TTBR0_EL1.set((zone_id << TTBR_ASID_SHIFT | directory) as u64);
+    unsafe { core::arch::asm!("isb") };
+}
+
+impl Aarch64PageTable {
+    fn directory_entry(&self) -> Aarch64PageTableEntry {
+        Aarch64PageTableEntry::from_pa(self.base_pa())
     }
 
-    fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
-        // debug!(
-        //     "page table map va 0x{:016x} pa: 0x{:016x}, attr {:?}, directory 0x{:x}",
-        //     va,
-        //     pa,
-        //     attr,
-        //     self.base_pa()
-        // );
-        let directory = Aarch64PageTableEntry::from_pa(self.base_pa());
+    /// Call `f` with the directory of each zone view which can see a mapping of `attr`,
+    /// and the attribute it's mapped with in that view.
+    #[cfg(feature = "zone")]
+    fn for_each_view<F>(&self, attr: EntryAttribute, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Aarch64PageTableEntry, EntryAttribute) -> Result<(), Error>,
+    {
+        let directories = match VIEW_DIRECTORIES.get() {
+            Some(directories) => directories,
+            None => return Ok(()),
+        };
+        for zone_id in view_zones() {
+            if let Some(view_attr) = view_attr(zone_id, attr) {
+                f(Aarch64PageTableEntry::from_pa(directories[zone_id]), view_attr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the table of `table` into a new frame, return the entry of the copy.
+    #[cfg(feature = "zone")]
+    fn copy_table(&self, table: Aarch64PageTableEntry) -> Result<Aarch64PageTableEntry, Error> {
+        let af = match frame_allocator::allocate_frames(1) {
+            Some(af) => af,
+            None => {
+                warn!("copy_table: failed to allocate one frame");
+                return Err(ERROR_OOM);
+            }
+        };
+        let copy = Aarch64PageTableEntry::make_table(af.start_address().value());
+        for index in 0..PAGE_SIZE / MACHINE_SIZE {
+            copy.set_entry(index, table.entry(index));
+        }
+        self.pages.lock().push(af);
+        Ok(copy)
+    }
+
+    /// Get the kernel directory of the view of `zone_id`, copy the boot directory for it
+    /// if it has none.
+    #[cfg(feature = "zone")]
+    fn kernel_view(&self, zone_id: zone::ZoneId) -> Result<Aarch64PageTableEntry, Error> {
+        match KERNEL_VIEW_DIRECTORIES[zone_id].load(Ordering::Acquire) {
+            0 => {
+                let copy = self.copy_table(kernel_directory())?;
+                KERNEL_VIEW_DIRECTORIES[zone_id].store(copy.to_pa(), Ordering::Release);
+                Ok(copy)
+            }
+            directory => Ok(Aarch64PageTableEntry::from_pa(directory)),
+        }
+    }
+
+    /// Get the level 3 table of `va` in the view's kernel directory `view`, copying the tables
+    /// it shares with the boot directory on the way, so the view can map `va` differently.
+    #[cfg(feature = "zone")]
+    fn private_l3(
+        &self,
+        view: Aarch64PageTableEntry,
+        va: usize,
+    ) -> Result<Aarch64PageTableEntry, Error> {
+        let full_l1e = kernel_directory().entry(va.l1x());
+        let mut l1e = view.entry(va.l1x());
+        if !l1e.valid() || l1e.blocked() {
+            return Err(ERROR_INVARG);
+        }
+        if l1e.to_pa() == full_l1e.to_pa() {
+            l1e = self.copy_table(l1e)?;
+            view.set_entry(va.l1x(), l1e);
+        }
+        let full_l2e = full_l1e.entry(va.l2x());
+        let mut l2e = l1e.entry(va.l2x());
+        if !l2e.valid() || l2e.blocked() {
+            return Err(ERROR_INVARG);
+        }
+        if l2e.to_pa() == full_l2e.to_pa() {
+            l2e = self.copy_table(l2e)?;
+            l1e.set_entry(va.l2x(), l2e);
+        }
+        Ok(l2e)
+    }
+
+    /// Hide pages of `range` in the kernel image from all zone views but the one of `zone_id`,
+    /// e.g. the data sections of named zones. The boot directory, which is the privileged
+    /// zone's view, still maps them.
+    ///
+    /// The pages are made non-global, so their TLB entries are tagged by the zone running.
+    #[cfg(feature = "zone")]
+    pub fn set_zone_range(&mut self, range: Range<usize>, zone_id: zone::ZoneId) {
+        assert_eq!(range.start % PAGE_SIZE, 0);
+        assert_eq!(range.end % PAGE_SIZE, 0);
+        let not_global = PAGE_DESCRIPTOR::NG::True.value as usize;
+        for va in range.clone().step_by(PAGE_SIZE) {
+            let l2e = match lookup_in(kernel_directory(), va) {
+                Some((_, MapGranularity::Page4KB)) => {
+                    kernel_directory().entry(va.l1x()).entry(va.l2x())
+                }
+                _ => {
+                    warn!("zone data {:#x} is not mapped by 4KB page, not hidden", va);
+                    return;
+                }
+            };
+            let l3e = l2e.entry(va.l3x());
+            l2e.set_entry(va.l3x(), Aarch64PageTableEntry(l3e.to_pte() | not_global));
+        }
+        for view in view_zones() {
+            if view == zone_id && KERNEL_VIEW_DIRECTORIES[view].load(Ordering::Acquire) == 0 {
+                continue;
+            }
+            let directory = match self.kernel_view(view) {
+                Ok(directory) => directory,
+                Err(e) => {
+                    warn!(
+                        "zone view {}: failed to copy kernel directory, error {}",
+                        view, e
+                    );
+                    continue;
+                }
+            };
+            for va in range.clone().step_by(PAGE_SIZE) {
+                let hidden = self.private_l3(directory, va).map(|l3e| {
+                    // The view of the owner zone may have its own table since another range.
+                    let entry = if view == zone_id {
+                        kernel_directory()
+                            .entry(va.l1x())
+                            .entry(va.l2x())
+                            .entry(va.l3x())
+                    } else {
+                        Aarch64PageTableEntry(0)
+                    };
+                    l3e.set_entry(va.l3x(), entry);
+                });
+                if let Err(e) = hidden {
+                    warn!("zone view {}: failed to hide {:#x}, error {}", view, va, e);
+                    break;
+                }
+            }
+        }
+        crate::arch::Arch::flush_tlb(None);
+    }
+
+    /// Get the level 2 table of `va` in `directory`, allocate the missing tables on the way.
+    fn walk_create(
+        &self,
+        directory: Aarch64PageTableEntry,
+        va: usize,
+        level: usize,
+    ) -> Result<Aarch64PageTableEntry, Error> {
         let mut l1e = directory.entry(va.l1x());
         if !l1e.valid() {
             let af = match frame_allocator::allocate_frames(1) {
@@ -222,6 +570,9 @@ impl PageTableTrait for Aarch64PageTable {
             self.pages.lock().push(af);
             directory.set_entry(va.l1x(), l1e);
         }
+        if level == 1 {
+            return Ok(l1e);
+        }
         let mut l2e = l1e.entry(va.l2x());
         if !l2e.valid() {
             let af = match frame_allocator::allocate_frames(1) {
@@ -237,7 +588,175 @@ impl PageTableTrait for Aarch64PageTable {
             self.pages.lock().push(af);
             l1e.set_entry(va.l2x(), l2e);
         }
+        Ok(l2e)
+    }
+
+    fn map_in(
+        &self,
+        directory: Aarch64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l2e = self.walk_create(directory, va, 2)?;
         l2e.set_entry(va.l3x(), Aarch64PageTableEntry::from(Entry::new(attr, pa)));
+        Ok(())
+    }
+
+    fn map_2mb_in(
+        &self,
+        directory: Aarch64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l1e = self.walk_create(directory, va, 1)?;
+        let l2e = l1e.entry(va.l2x());
+        if !l2e.valid() {
+            // Map as PTE_BLOCK.
+            let entry = Aarch64PageTableEntry::from(Entry::new(attr, pa));
+            l1e.set_entry(va.l2x(), entry);
+        } else {
+            warn!("map_2mb: lvl 2 already mapped with 0x{:x}", l2e.to_pte());
+        }
+        Ok(())
+    }
+
+    fn map_1gb_in(
+        &self,
+        directory: Aarch64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l1e = directory.entry(va.l1x());
+        if !l1e.valid() {
+            // Map as level 1 block.
+            let entry = Aarch64PageTableEntry::from(Entry::new(attr, pa));
+            directory.set_entry(va.l1x(), entry);
+            Ok(())
+        } else {
+            warn!("map_1gb: lvl 1 already mapped with 0x{:x}", l1e.to_pte());
+            Err(ERROR_INVARG)
+        }
+    }
+
+    /// Clear the entry of `va` at `level` in `directory`, return false if its table is missing.
+    fn unmap_in(&self, directory: Aarch64PageTableEntry, va: usize, level: usize) -> bool {
+        if level == 1 {
+            if !directory.entry(va.l1x()).valid() {
+                return false;
+            }
+            directory.set_entry(va.l1x(), Aarch64PageTableEntry(0));
+            return true;
+        }
+        let l1e = directory.entry(va.l1x());
+        if !l1e.valid() || l1e.blocked() {
+            return false;
+        }
+        if level == 2 {
+            l1e.set_entry(va.l2x(), Aarch64PageTableEntry(0));
+            return true;
+        }
+        let l2e = l1e.entry(va.l2x());
+        if !l2e.valid() || l2e.blocked() {
+            return false;
+        }
+        l2e.set_entry(va.l3x(), Aarch64PageTableEntry(0));
+        true
+    }
+
+    /// Clear the entry of `va` from all zone views, views which can't see it are skipped.
+    #[cfg(feature = "zone")]
+    fn unmap_views(&self, va: usize, level: usize) {
+        if let Some(directories) = VIEW_DIRECTORIES.get() {
+            for zone_id in view_zones() {
+                self.unmap_in(Aarch64PageTableEntry::from_pa(directories[zone_id]), va, level);
+            }
+        }
+    }
+}
+
+/// Look up the mapping of `va` in `directory`.
+fn lookup_in(directory: Aarch64PageTableEntry, va: usize) -> Option<(Entry, MapGranularity)> {
+    let l1e = directory.entry(va.l1x());
+    if !l1e.valid() {
+        return None;
+    }
+    if l1e.blocked() {
+        return Some((Entry::from(l1e), MapGranularity::Page1GB));
+    }
+    let l2e = l1e.entry(va.l2x());
+    if !l2e.valid() {
+        return None;
+    }
+    if l2e.blocked() {
+        return Some((Entry::from(l2e), MapGranularity::Page2MB));
+    }
+    let l3e = l2e.entry(va.l3x());
+    if l3e.valid() {
+        return Some((Entry::from(l3e), MapGranularity::Page4KB));
+    } else {
+        return None;
+    }
+}
+
+/// Call `f` with each valid leaf entry in `directory`, whose address space starts at `base`.
+fn walk_in(
+    directory: Aarch64PageTableEntry,
+    base: usize,
+    f: &mut dyn FnMut(usize, Entry, MapGranularity),
+) {
+    const ENTRY_PER_PAGE: usize = PAGE_SIZE / MACHINE_SIZE;
+    for l1x in 0..ENTRY_PER_PAGE {
+        let l1e = directory.entry(l1x);
+        if !l1e.valid() {
+            continue;
+        }
+        let l1_va = base + (l1x << PAGE_TABLE_L1_SHIFT);
+        if l1e.blocked() {
+            f(l1_va, Entry::from(l1e), MapGranularity::Page1GB);
+            continue;
+        }
+        for l2x in 0..ENTRY_PER_PAGE {
+            let l2e = l1e.entry(l2x);
+            if !l2e.valid() {
+                continue;
+            }
+            let l2_va = l1_va + (l2x << PAGE_TABLE_L2_SHIFT);
+            if l2e.blocked() {
+                f(l2_va, Entry::from(l2e), MapGranularity::Page2MB);
+                continue;
+            }
+            for l3x in 0..ENTRY_PER_PAGE {
+                let l3e = l2e.entry(l3x);
+                if l3e.valid() {
+                    let va = l2_va + (l3x << PAGE_TABLE_L3_SHIFT);
+                    f(va, Entry::from(l3e), MapGranularity::Page4KB);
+                }
+            }
+        }
+    }
+}
+
+impl PageTableTrait for Aarch64PageTable {
+    fn base_pa(&self) -> usize {
+        self.directory.start_address().value()
+    }
+
+    fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
+        // debug!(
+        //     "page table map va 0x{:016x} pa: 0x{:016x}, attr {:?}, directory 0x{:x}",
+        //     va,
+        //     pa,
+        //     attr,
+        //     self.base_pa()
+        // );
+        self.map_in(self.directory_entry(), va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_in(directory, va, pa, attr)
+        })?;
 
         crate::arch::Arch::flush_tlb(Some(va));
         Ok(())
@@ -260,73 +779,63 @@ impl PageTableTrait for Aarch64PageTable {
             return Err(ERROR_INVARG);
         }
 
-        let directory = Aarch64PageTableEntry::from_pa(self.base_pa());
-        let mut l1e = directory.entry(va.l1x());
+        self.map_2mb_in(self.directory_entry(), va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_2mb_in(directory, va, pa, attr)
+        })?;
+        crate::arch::Arch::flush_tlb(Some(va));
+        Ok(())
+    }
 
-        if !l1e.valid() {
-            let af = match frame_allocator::allocate_frames(1) {
-                Some(af) => af,
-                None => {
-                    warn!("map_2mb: failed to allocate one frame for l1e");
-                    return Err(ERROR_OOM);
-                }
-            };
-            let frame = af.start().clone();
-            frame.zero();
-            l1e = Aarch64PageTableEntry::make_table(frame.start_address().value());
-            self.pages.lock().push(af);
-            directory.set_entry(va.l1x(), l1e);
-        }
-        let l2e = l1e.entry(va.l2x());
-        if !l2e.valid() {
-            // Map as PTE_BLOCK.
-            let entry = Aarch64PageTableEntry::from(Entry::new(attr, pa));
-            l1e.set_entry(va.l2x(), entry);
-        } else {
-            warn!("map_2mb: lvl 2 already mapped with 0x{:x}", l2e.to_pte());
+    fn map_1gb(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        assert!(pa % MapGranularity::Page1GB as usize == 0);
+        trace!(
+            "page table map_1gb va 0x{:016x} pa: 0x{:016x}, directory 0x{:x}",
+            va,
+            pa,
+            self.base_pa()
+        );
+        if !attr.block() {
+            warn!("map_1gb: required block attribute");
+            return Err(ERROR_INVARG);
         }
+        self.map_1gb_in(self.directory_entry(), va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_1gb_in(directory, va, pa, attr)
+        })?;
         crate::arch::Arch::flush_tlb(Some(va));
         Ok(())
     }
 
     fn unmap(&mut self, va: usize) {
         trace!("unmap va {:x}", va);
-        let directory = Aarch64PageTableEntry::from_pa(self.directory.start_address().value());
-        let l1e = directory.entry(va.l1x());
-        assert!(l1e.valid());
-        let l2e = l1e.entry(va.l2x());
-        assert!(l2e.valid());
-        l2e.set_entry(va.l3x(), Aarch64PageTableEntry(0));
+        assert!(self.unmap_in(self.directory_entry(), va, 3));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 3);
     }
 
     fn unmap_2mb(&mut self, va: usize) {
         trace!("unmap_2mb va {:x}", va);
         assert!(va % MapGranularity::Page2MB as usize == 0);
-        let directory = Aarch64PageTableEntry::from_pa(self.directory.start_address().value());
-        let l1e = directory.entry(va.l1x());
-        assert!(l1e.valid());
-        l1e.set_entry(va.l2x(), Aarch64PageTableEntry(0));
+        assert!(self.unmap_in(self.directory_entry(), va, 2));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 2);
+    }
+
+    fn unmap_1gb(&mut self, va: usize) {
+        trace!("unmap_1gb va {:x}", va);
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        assert!(self.unmap_in(self.directory_entry(), va, 1));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 1);
+        crate::arch::Arch::flush_tlb(Some(va));
     }
 
     fn lookup_entry(&self, va: usize) -> Option<(Entry, MapGranularity)> {
-        let directory = Aarch64PageTableEntry::from_pa(self.directory.start_address().value());
-        let l1e = directory.entry(va.l1x());
-        if !l1e.valid() {
-            return None;
-        }
-        let l2e = l1e.entry(va.l2x());
-        if !l2e.valid() {
-            return None;
-        }
-        if l2e.blocked() {
-            return Some((Entry::from(l2e), MapGranularity::Page2MB));
-        }
-        let l3e = l2e.entry(va.l3x());
-        if l3e.valid() {
-            return Some((Entry::from(l3e), MapGranularity::Page4KB));
-        } else {
-            return None;
-        }
+        lookup_in(self.directory_entry(), va)
     }
 
     fn lookup_page(&self, va: usize) -> Option<Entry> {
@@ -346,4 +855,9 @@ impl PageTableTrait for Aarch64PageTable {
             None
         }
     }
+    /// Walks the user address space in TTBR0_EL1, then the kernel one in TTBR1_EL1.
+    fn for_each_entry(&self, f: &mut dyn FnMut(usize, Entry, MapGranularity)) {
+        walk_in(self.directory_entry(), 0, f);
+        walk_in(kernel_directory(), super::PA2KVA, f);
+    }
 }
@@ -37,6 +37,11 @@ register_bitfields! {u64,
       True = 1
     ],
     OUTPUT_PPN OFFSET(12) NUMBITS(36) [], // [47:12]
+    // Not global, TLB entries are tagged by current ASID.
+    NG       OFFSET(11) NUMBITS(1) [
+      False = 0,
+      True = 1
+    ],
     AF       OFFSET(10) NUMBITS(1) [
       False = 0,
       True = 1
@@ -4,6 +4,8 @@ use core::arch::asm;
 use riscv::regs::SSTATUS;
 
 use crate::libs::traits::ContextFrameTrait;
+#[cfg(feature = "unwind")]
+use crate::arch::registers::{Registers, Riscv64};
 
 #[repr(C, align(16))]
 #[derive(Copy, Clone, Debug)]
@@ -33,6 +35,85 @@ impl core::fmt::Display for Riscv64TrapContextFrame {
     }
 }
 
+/// The return address register is set to the faulting instruction plus `CALLER_OFFSET`,
+/// so unwinding starts from the frame of the faulting function.
+#[cfg(feature = "unwind")]
+impl Into<Registers> for Riscv64TrapContextFrame {
+    fn into(self) -> Registers {
+        let mut reg = Registers::default();
+        reg[Riscv64::SP] = Some(self.gpr[2]);
+        reg[Riscv64::GP] = Some(self.gpr[3]);
+        reg[Riscv64::TP] = Some(self.gpr[4]);
+        reg[Riscv64::T0] = Some(self.gpr[5]);
+        reg[Riscv64::T1] = Some(self.gpr[6]);
+        reg[Riscv64::T2] = Some(self.gpr[7]);
+        reg[Riscv64::S0] = Some(self.gpr[8]);
+        reg[Riscv64::S1] = Some(self.gpr[9]);
+        reg[Riscv64::A0] = Some(self.gpr[10]);
+        reg[Riscv64::A1] = Some(self.gpr[11]);
+        reg[Riscv64::A2] = Some(self.gpr[12]);
+        reg[Riscv64::A3] = Some(self.gpr[13]);
+        reg[Riscv64::A4] = Some(self.gpr[14]);
+        reg[Riscv64::A5] = Some(self.gpr[15]);
+        reg[Riscv64::A6] = Some(self.gpr[16]);
+        reg[Riscv64::A7] = Some(self.gpr[17]);
+        reg[Riscv64::S2] = Some(self.gpr[18]);
+        reg[Riscv64::S3] = Some(self.gpr[19]);
+        reg[Riscv64::S4] = Some(self.gpr[20]);
+        reg[Riscv64::S5] = Some(self.gpr[21]);
+        reg[Riscv64::S6] = Some(self.gpr[22]);
+        reg[Riscv64::S7] = Some(self.gpr[23]);
+        reg[Riscv64::S8] = Some(self.gpr[24]);
+        reg[Riscv64::S9] = Some(self.gpr[25]);
+        reg[Riscv64::S10] = Some(self.gpr[26]);
+        reg[Riscv64::S11] = Some(self.gpr[27]);
+        reg[Riscv64::T3] = Some(self.gpr[28]);
+        reg[Riscv64::T4] = Some(self.gpr[29]);
+        reg[Riscv64::T5] = Some(self.gpr[30]);
+        reg[Riscv64::T6] = Some(self.gpr[31]);
+        reg[Riscv64::RA] = Some(self.sepc + crate::libs::unwind::arch::CALLER_OFFSET);
+        reg
+    }
+}
+
+#[cfg(feature = "coredump")]
+impl From<&Riscv64TrapContextFrame> for super::coredump::CoreRegs {
+    fn from(ctx: &Riscv64TrapContextFrame) -> Self {
+        use super::coredump::*;
+        let mut regs = CoreRegs(ctx.gpr);
+        regs.0[PC] = ctx.sepc;
+        regs
+    }
+}
+
+/// A yielded thread resumes at `ra`.
+#[cfg(feature = "coredump")]
+impl From<&ThreadContext> for super::coredump::CoreRegs {
+    fn from(ctx: &ThreadContext) -> Self {
+        use super::coredump::*;
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[PC] = ctx.ra as u64;
+        regs.0[RA] = ctx.ra as u64;
+        regs.0[SP] = ctx.sp as u64;
+        regs.0[4] = ctx.tp as u64;
+        regs.0[8] = ctx.s0 as u64;
+        regs.0[9] = ctx.s1 as u64;
+        regs.0[18..28].copy_from_slice(&[
+            ctx.s2 as u64,
+            ctx.s3 as u64,
+            ctx.s4 as u64,
+            ctx.s5 as u64,
+            ctx.s6 as u64,
+            ctx.s7 as u64,
+            ctx.s8 as u64,
+            ctx.s9 as u64,
+            ctx.s10 as u64,
+            ctx.s11 as u64,
+        ]);
+        regs
+    }
+}
+
 impl ContextFrameTrait for Riscv64TrapContextFrame {
     fn init(&mut self, _tid: usize, tls_area: usize) {
         self.sstatus = (SSTATUS::SD::SET
@@ -75,6 +156,10 @@ impl ContextFrameTrait for Riscv64TrapContextFrame {
     fn set_stack_pointer(&mut self, sp: usize) {
         self.gpr[2] = sp as u64;
     }
+    fn frame_pointer(&self) -> usize {
+        // fp -> s0(x8)
+        self.gpr[8] as usize
+    }
     fn gpr(&self, index: usize) -> usize {
         self.gpr[index + 10] as usize
     }
@@ -288,3 +373,80 @@ unsafe extern "C" fn context_switch_to_trap(_current_ctx: &mut ThreadContext, _n
         options(noreturn),
     )
 }
+
+/// Call `_entry` with `_arg` on another stack whose top is `_sp`, then switch back.
+///
+/// Used by zone calls to run the callee on a stack of the target zone.
+/// ## Arguments
+/// * `_arg`    - argument passed to `_entry`, on `a0`.
+/// * `_entry`  - function to call, on `a1`.
+/// * `_sp`     - top of the stack, 16 bytes aligned, on `a2`.
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+#[naked]
+pub unsafe extern "C" fn call_on_stack(_arg: usize, _entry: extern "C" fn(usize), _sp: usize) {
+    asm!(
+        "addi   sp, sp, -16",
+        "sd     ra, 8(sp)",
+        "sd     s0, 0(sp)",
+        "mv     s0, sp",
+        "mv     sp, a2",
+        "jalr   a1",
+        "mv     sp, s0",
+        "ld     s0, 0(sp)",
+        "ld     ra, 8(sp)",
+        "addi   sp, sp, 16",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Save current callee-saved registers into `_ctx` like `setjmp`, call `_capture`
+/// with the saved stack pointer and `_arg`, then return 0.
+///
+/// `restore_checkpoint_context` on `_ctx` returns from here again, with its `_ret`.
+/// ## Arguments
+/// * `_ctx`        - the pointer to the checkpoint's `ThreadContext`, on `a0`.
+/// * `_capture`    - function copying the stack from the saved stack pointer, on `a1`.
+/// * `_arg`        - second argument passed to `_capture`, on `a2`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn save_checkpoint_context(
+    _ctx: &mut ThreadContext,
+    _capture: extern "C" fn(usize, usize),
+    _arg: usize,
+) -> usize {
+    asm!(
+        save_yield_context!(),
+        "addi   sp, sp, -16",
+        "sd     ra, 8(sp)",
+        "mv     t0, a1",
+        "ld     a0, 1*8(a0)",
+        "mv     a1, a2",
+        "jalr   t0",
+        "ld     ra, 8(sp)",
+        "addi   sp, sp, 16",
+        "li     a0, 0",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Restore callee-saved registers from `_ctx` saved by `save_checkpoint_context`,
+/// which returns `_ret` again.
+///
+/// The stack above the saved stack pointer must be restored before.
+/// ## Arguments
+/// * `_ctx`    - the pointer to the checkpoint's `ThreadContext`, on `a0`.
+/// * `_ret`    - value returned by `save_checkpoint_context`, on `a1`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn restore_checkpoint_context(_ctx: &ThreadContext, _ret: usize) -> ! {
+    asm!(
+        "mv     t0, a1",
+        "mv     a1, a0",
+        restore_yield_context!(),
+        "mv     a0, t0",
+        "ret",
+        options(noreturn),
+    )
+}
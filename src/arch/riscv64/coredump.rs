@@ -0,0 +1,55 @@
+//! Registers of ELF core dumps, in the layout of `elf_gregset_t` as gdb reads from NT_PRSTATUS.
+
+#[cfg(feature = "unwind")]
+use super::registers::Registers;
+
+/// EM_RISCV.
+pub const ELF_MACHINE: u16 = 243;
+/// EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE.
+pub const ELF_FLAGS: u32 = 0x5;
+
+/// pc, x1 - x31.
+pub const GREG_NUM: usize = 32;
+pub const PC: usize = 0;
+pub const RA: usize = 1;
+pub const SP: usize = 2;
+pub const FP: usize = 8;
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegs(pub [u64; GREG_NUM]);
+
+impl CoreRegs {
+    /// Registers of the caller, only pc, sp and fp are captured.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let (pc, sp, fp): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "auipc {}, 0",
+                "mv {}, sp",
+                "mv {}, s0",
+                out(reg) pc,
+                out(reg) sp,
+                out(reg) fp,
+            );
+        }
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[PC] = pc;
+        regs.0[SP] = sp;
+        regs.0[FP] = fp;
+        regs
+    }
+}
+
+/// Registers to unwind from, whose return address is the faulting pc plus `CALLER_OFFSET`.
+#[cfg(feature = "unwind")]
+impl From<&Registers> for CoreRegs {
+    fn from(registers: &Registers) -> Self {
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        for i in 1..GREG_NUM {
+            regs.0[i] = registers[gimli::Register(i as u16)].unwrap_or(0);
+        }
+        regs.0[PC] = regs.0[RA].saturating_sub(crate::libs::unwind::arch::CALLER_OFFSET);
+        regs
+    }
+}
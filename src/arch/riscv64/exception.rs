@@ -35,6 +35,9 @@ core::arch::global_asm!(
 const INTERRUPT_SUPERVISOR_SOFTWARE: usize = 1;
 const INTERRUPT_SUPERVISOR_TIMER: usize = 5;
 const INTERRUPT_SUPERVISOR_EXTERNAL: usize = 9;
+const EXCEPTION_INSTRUCTION_PAGE_FAULT: usize = 12;
+const EXCEPTION_LOAD_PAGE_FAULT: usize = 13;
+const EXCEPTION_STORE_PAGE_FAULT: usize = 15;
 
 #[no_mangle]
 unsafe extern "C" fn exception_entry(ctx: *mut ContextFrame) {
@@ -56,25 +59,80 @@ unsafe extern "C" fn exception_entry(ctx: *mut ContextFrame) {
         match code {
             INTERRUPT_SUPERVISOR_SOFTWARE => panic!("Interrupt::SupervisorSoft"),
             INTERRUPT_SUPERVISOR_TIMER => {
+                // Interrupts are handled with the full page table view, same as x86_64.
+                #[cfg(feature = "zone")]
+                let ori_zone = zone::switch_to_privilege();
                 crate::libs::timer::interrupt();
+                #[cfg(feature = "zone")]
+                zone::switch_from_privilege(ori_zone);
                 crate::libs::thread::thread_yield();
             }
             INTERRUPT_SUPERVISOR_EXTERNAL => {
+                #[cfg(feature = "zone")]
+                let ori_zone = zone::switch_to_privilege();
                 if let Some(int) = crate::drivers::InterruptController::fetch() {
                     crate::libs::interrupt::interrupt(int);
                     crate::drivers::InterruptController::finish(int);
                 } else {
                     warn!("PLIC report no irq");
                 }
+                #[cfg(feature = "zone")]
+                zone::switch_from_privilege(ori_zone);
             }
             _ => panic!("Interrupt::Unknown"),
         }
     } else {
-        warn!("SCAUSE {:016x}", cause);
-        warn!("SEPC {:016x}", ctx.read().exception_pc());
-        warn!("FAR  {:016x}", crate::arch::Arch::fault_address());
-        panic!("Unhandled kernel exception");
+        match code {
+            EXCEPTION_INSTRUCTION_PAGE_FAULT
+            | EXCEPTION_LOAD_PAGE_FAULT
+            | EXCEPTION_STORE_PAGE_FAULT => page_fault(cause, ctx),
+            _ => {
+                warn!("SCAUSE {:016x}", cause);
+                warn!("SEPC {:016x}", ctx.read().exception_pc());
+                warn!("FAR  {:016x}", crate::arch::Arch::fault_address());
+                print_trapped_backtrace(ctx);
+                #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+                dump_trapped(ctx);
+                panic!("Unhandled kernel exception");
+            }
+        }
+    }
+}
+
+/// Print the backtrace of the trapped context.
+unsafe fn print_trapped_backtrace(ctx: *mut ContextFrame) {
+    let ctx = ctx.read();
+    crate::libs::backtrace::print_backtrace_from(ctx.exception_pc(), ctx.frame_pointer());
+}
+
+/// Dump the trapped context, which is fatal without unwinding.
+#[cfg(all(feature = "coredump", not(feature = "unwind")))]
+unsafe fn dump_trapped(ctx: *mut ContextFrame) {
+    let regs = super::coredump::CoreRegs::from(&*ctx);
+    crate::libs::coredump::dump(regs, crate::libs::coredump::SIGNAL_EXCEPTION);
+}
+
+/// Instruction, load and store page faults unwind the faulting thread from the trap context,
+/// its landing pads run and the thread exits if none is found.
+unsafe fn page_fault(cause: u64, ctx: *mut ContextFrame) {
+    println!(
+        "EXCEPTION: PAGE FAULT on Thread {}\nSCAUSE {:016x} FAR {:016x}\n{}",
+        crate::libs::thread::current_thread_id(),
+        cause,
+        crate::arch::Arch::fault_address(),
+        ctx.read()
+    );
+    print_trapped_backtrace(ctx);
+
+    #[cfg(feature = "unwind")]
+    {
+        let ctx = ctx.read();
+        crate::libs::unwind::unwind_from_exception(ctx.into());
     }
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    dump_trapped(ctx);
+    #[cfg(not(feature = "unwind"))]
+    panic!("Unhandled kernel page fault");
 }
 
 pub fn init() {
@@ -1,10 +1,13 @@
 mod context_frame;
+#[cfg(feature = "coredump")]
+pub mod coredump;
 mod exception;
 mod start;
 mod vm_descriptor;
 
 pub mod irq;
 pub mod page_table;
+pub mod registers;
 
 use core::mem::size_of;
 use tock_registers::interfaces::Readable;
@@ -34,6 +37,10 @@ impl Address for usize {
 
 pub type ContextFrame = context_frame::Riscv64TrapContextFrame;
 pub type ThreadContext = context_frame::ThreadContext;
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+pub use context_frame::call_on_stack;
+#[cfg(feature = "checkpoint")]
+pub use context_frame::{restore_checkpoint_context, save_checkpoint_context};
 
 pub struct Arch;
 
@@ -52,7 +59,8 @@ impl ArchTrait for Arch {
 
     fn flush_tlb(vaddr: Option<usize>) {
         if let Some(vaddr) = vaddr {
-            riscv::barrier::sfence_vma(0, vaddr)
+            // Flush the address in all address spaces, zones' page table views use their own ASIDs.
+            unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) vaddr) }
         } else {
             riscv::barrier::sfence_vma_all()
         }
@@ -9,7 +9,12 @@ use crate::mm::frame_allocator;
 use crate::mm::frame_allocator::AllocatedFrames;
 use crate::mm::interface::{PageTableEntryAttrTrait, PageTableTrait, Error, MapGranularity};
 use crate::mm::paging::{Entry, EntryAttribute};
+use crate::mm::wx;
 use crate::libs::synch::spinlock::SpinlockIrqSave;
+#[cfg(feature = "zone")]
+use crate::mm::zone_view::{view_attr, view_zones, VIEW_NUM};
+#[cfg(feature = "zone")]
+use core::ops::Range;
 
 pub const PAGE_TABLE_L1_SHIFT: usize = 30;
 pub const PAGE_TABLE_L2_SHIFT: usize = 21;
@@ -153,14 +158,31 @@ impl core::convert::From<Entry> for RISCV64PageTableEntry {
 pub struct RISCV64PageTable {
     directory_entry: RISCV64PageTableEntry,
     frames: Mutex<Vec<AllocatedFrames>>,
+    /// Directories of zones' page table views, see `mm::zone_view`.
+    #[cfg(feature = "zone")]
+    #[allow(unused)]
+    views: Vec<AllocatedFrames>,
 }
 
 static PAGE_TABLE: Once<SpinlockIrqSave<RISCV64PageTable>> = Once::new();
 
+/// Physical address of each view's directory, indexed by zone id,
+/// the privileged zone's view is the full page table.
+///
+/// Views are switched with the page table lock possibly held, so they are recorded here.
+#[cfg(feature = "zone")]
+static VIEW_DIRECTORIES: Once<[usize; VIEW_NUM]> = Once::new();
+
 pub fn page_table() -> &'static SpinlockIrqSave<RISCV64PageTable> {
     PAGE_TABLE.get().unwrap()
 }
 
+/// Sv39 allows leaf entries in the root table (gigapages),
+/// so 1GB mappings are supported on this architecture.
+pub fn huge_1gb_supported() -> bool {
+    true
+}
+
 pub fn init() {
     PAGE_TABLE.call_once(|| {
         extern "C" {
@@ -169,13 +191,185 @@ pub fn init() {
         }
         let dir_entry = RISCV64PageTableEntry::from_pa((KERNEL_PAGE_DIRECTORY as usize).kva2pa());
         // debug!("page_table init entry at {:#x}", dir_entry.0);
+
+        let mut frames = Vec::new();
+        protect_kernel_image(dir_entry, &mut frames);
+
+        // Each view starts with the kernel's gigapages of the boot directory,
+        // they are leaf entries except the ones holding the kernel image and its identity alias,
+        // whose tables are shared but never change after boot.
+        #[cfg(feature = "zone")]
+        let views = {
+            let mut directories = [dir_entry.to_pa(); VIEW_NUM];
+            let mut views = Vec::new();
+            for zone_id in view_zones() {
+                let view_frame = frame_allocator::allocate_frames(1).unwrap();
+                view_frame.start().zero();
+                let view_pa = view_frame.start_address().value();
+                let view_entry = RISCV64PageTableEntry::from_pa(view_pa);
+                for index in 0..PAGE_SIZE / MACHINE_SIZE {
+                    let entry = dir_entry.entry(index);
+                    if entry.valid() {
+                        view_entry.set_entry(index, entry);
+                    }
+                }
+                directories[zone_id] = view_pa;
+                views.push(view_frame);
+            }
+            VIEW_DIRECTORIES.call_once(|| directories);
+            views
+        };
+
         SpinlockIrqSave::new(RISCV64PageTable {
             directory_entry: dir_entry,
-            frames: Mutex::new(Vec::new()),
+            frames: Mutex::new(frames),
+            #[cfg(feature = "zone")]
+            views,
         })
     });
 }
 
+/// Split the gigapage of the boot directory holding the kernel image down to 4KB pages,
+/// and map them with permissions of their sections, see `mm::wx`.
+///
+/// The identity gigapage of the boot directory, used by harts to turn on paging,
+/// shares the tables, so the alias of the image gets the same permissions.
+/// Memory outside the image is made non-executable.
+fn protect_kernel_image(directory: RISCV64PageTableEntry, frames: &mut Vec<AllocatedFrames>) {
+    const ENTRY_PER_PAGE: usize = PAGE_SIZE / MACHINE_SIZE;
+    const BLOCK_2MB: usize = MapGranularity::Page2MB as usize;
+    // Flag bits of a leaf entry, and its XWR bits.
+    const FLAGS_MASK: usize = 0x3ff;
+    const R: usize = 1 << 1;
+    const W: usize = 1 << 2;
+    const X: usize = 1 << 3;
+
+    let image = wx::image_range();
+    if image.start.l1x() != (image.end - 1).l1x() {
+        warn!("kernel image {:#x?} crosses gigapages, not protected", image);
+        return;
+    }
+    let l1e = directory.entry(image.start.l1x());
+    if !l1e.valid() || !l1e.blocked() {
+        warn!("kernel image is not mapped by gigapage, not protected");
+        return;
+    }
+    let mut allocate_table = || {
+        let af = frame_allocator::allocate_frames(1)?;
+        af.start().zero();
+        let table = RISCV64PageTableEntry::make_table(af.start_address().value());
+        frames.push(af);
+        Some(table)
+    };
+    let l2e = match allocate_table() {
+        Some(l2e) => l2e,
+        None => {
+            warn!("failed to allocate frame for kernel image tables, not protected");
+            return;
+        }
+    };
+
+    // Flags of the gigapage are kept by the smaller mappings,
+    // those outside the image are non-executable.
+    let flags = l1e.to_pte() & FLAGS_MASK;
+    let base_pa = l1e.to_pa();
+    let base_va = image.start & !(MapGranularity::Page1GB as usize - 1);
+    for index in 0..ENTRY_PER_PAGE {
+        let pa = base_pa + index * BLOCK_2MB;
+        let va = base_va + index * BLOCK_2MB;
+        let flags = if va < image.end && image.start < va + BLOCK_2MB {
+            flags
+        } else {
+            flags & !X
+        };
+        l2e.set_entry(index, RISCV64PageTableEntry(RISCV64PageTableEntry::from_pa(pa).0 | flags));
+    }
+
+    let chunks = (image.start & !(BLOCK_2MB - 1)..image.end).step_by(BLOCK_2MB);
+    for chunk in chunks {
+        let l3e = match allocate_table() {
+            Some(l3e) => l3e,
+            None => {
+                warn!("kernel image beyond {:#x} is not protected", chunk);
+                break;
+            }
+        };
+        let chunk_pa = base_pa + chunk.l2x() * BLOCK_2MB;
+        for index in 0..ENTRY_PER_PAGE {
+            let pa = chunk_pa + index * PAGE_SIZE;
+            let flags = match wx::image_perm(chunk + index * PAGE_SIZE) {
+                Some(perm) => {
+                    (flags & !(R | W | X))
+                        | R
+                        | if perm.writable() { W } else { 0 }
+                        | if perm.executable() { X } else { 0 }
+                }
+                None => flags & !X,
+            };
+            l3e.set_entry(index, RISCV64PageTableEntry(RISCV64PageTableEntry::from_pa(pa).0 | flags));
+        }
+        l2e.set_entry(chunk.l2x(), l3e);
+    }
+
+    directory.set_entry(image.start.l1x(), l2e);
+    for index in 0..ENTRY_PER_PAGE {
+        let entry = directory.entry(index);
+        if index == image.start.l1x() || !entry.valid() || !entry.blocked() {
+            continue;
+        }
+        if entry.to_pa() == base_pa {
+            directory.set_entry(index, l2e);
+        } else {
+            directory.set_entry(index, RISCV64PageTableEntry(entry.0 & !X));
+        }
+    }
+    crate::arch::Arch::flush_tlb(None);
+    info!("kernel image {:#x?} protected", image);
+}
+
+/// Look up the mapping of `va` in the kernel address space, which is in the full page table.
+#[cfg(feature = "wx-check")]
+pub fn kernel_entry(va: usize) -> Option<(Entry, MapGranularity)> {
+    page_table().lock().lookup_entry(va)
+}
+
+/// Sv39 translation mode and the ASID field of satp.
+#[cfg(feature = "zone")]
+const SATP_MODE_SV39: usize = 8 << 60;
+#[cfg(feature = "zone")]
+const SATP_ASID_SHIFT: usize = 44;
+#[cfg(feature = "zone")]
+const SATP_ASID_MASK: usize = 0xffff;
+
+/// Get the zone whose page table view is active on current core, which is its ASID.
+#[cfg(feature = "zone")]
+pub fn current_view() -> zone::ZoneId {
+    let satp: usize;
+    unsafe { core::arch::asm!("csrr {}, satp", out(reg) satp) };
+    (satp >> SATP_ASID_SHIFT) & SATP_ASID_MASK
+}
+
+/// Activate the page table view of target zone on current core.
+///
+/// Mappings of the views are non-global, so TLB entries are tagged by the zone's ASID
+/// and need no flush here.
+#[cfg(feature = "zone")]
+pub fn switch_view(zone_id: zone::ZoneId) {
+    let directories = match VIEW_DIRECTORIES.get() {
+        Some(directories) => directories,
+        None => return,
+    };
+    let directory = match directories.get(zone_id) {
+        Some(directory) => *directory,
+        None => {
+            warn!("switch_view: zone {} has no page table view", zone_id);
+            return;
+        }
+    };
+    let satp = SATP_MODE_SV39 | zone_id << SATP_ASID_SHIFT | directory >> PAGE_SHIFT;
+    unsafe { core::arch::asm!("csrw satp, {}", in(reg) satp) };
+}
+
 impl RISCV64PageTable {
     #[allow(unused)]
     pub fn dump_entry_flags_of_va(&mut self, va: usize) {
@@ -204,21 +398,115 @@ impl RISCV64PageTable {
             l3e.0 >> 10
         );
     }
-}
 
-impl PageTableTrait for RISCV64PageTable {
-    fn base_pa(&self) -> usize {
-        self.directory_entry.0
+    /// Call `f` with the directory of each zone view which can see a mapping of `attr`,
+    /// and the attribute it's mapped with in that view.
+    #[cfg(feature = "zone")]
+    fn for_each_view<F>(&self, attr: EntryAttribute, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(RISCV64PageTableEntry, EntryAttribute) -> Result<(), Error>,
+    {
+        let directories = match VIEW_DIRECTORIES.get() {
+            Some(directories) => directories,
+            None => return Ok(()),
+        };
+        for zone_id in view_zones() {
+            if let Some(view_attr) = view_attr(zone_id, attr) {
+                f(RISCV64PageTableEntry::from_pa(directories[zone_id]), view_attr)?;
+            }
+        }
+        Ok(())
     }
 
-    fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
-        trace!(
-            "page table map va {:#x} pa: 0x{:#x}, directory 0x{:x}",
-            va,
-            pa,
-            self.base_pa()
-        );
-        let directory = self.directory_entry;
+    /// Copy the table of `table` into a new frame, return the entry of the copy.
+    #[cfg(feature = "zone")]
+    fn copy_table(&self, table: RISCV64PageTableEntry) -> Result<RISCV64PageTableEntry, Error> {
+        let af = match frame_allocator::allocate_frames(1) {
+            Some(af) => af,
+            None => {
+                warn!("copy_table: failed to allocate one frame");
+                return Err(ERROR_OOM);
+            }
+        };
+        let copy = RISCV64PageTableEntry::make_table(af.start_address().value());
+        for index in 0..PAGE_SIZE / MACHINE_SIZE {
+            copy.set_entry(index, table.entry(index));
+        }
+        self.frames.lock().push(af);
+        Ok(copy)
+    }
+
+    /// Get the level 3 table of `va` in the view directory `view`, copying the tables it
+    /// shares with the full page table on the way, so the view can map `va` differently.
+    ///
+    /// Root entries of the view aliasing the copied table, e.g. the identity gigapage,
+    /// are pointed to the copy as well.
+    #[cfg(feature = "zone")]
+    fn private_l3(
+        &self,
+        view: RISCV64PageTableEntry,
+        va: usize,
+    ) -> Result<RISCV64PageTableEntry, Error> {
+        let full_l1e = self.directory_entry.entry(va.l1x());
+        let mut l1e = view.entry(va.l1x());
+        if !l1e.valid() || l1e.blocked() {
+            return Err(ERROR_INVARG);
+        }
+        if l1e.to_pa() == full_l1e.to_pa() {
+            let copy = self.copy_table(l1e)?;
+            for index in 0..PAGE_SIZE / MACHINE_SIZE {
+                let entry = view.entry(index);
+                if entry.valid() && !entry.blocked() && entry.to_pa() == l1e.to_pa() {
+                    view.set_entry(index, copy);
+                }
+            }
+            l1e = copy;
+        }
+        let full_l2e = full_l1e.entry(va.l2x());
+        let mut l2e = l1e.entry(va.l2x());
+        if !l2e.valid() || l2e.blocked() {
+            return Err(ERROR_INVARG);
+        }
+        if l2e.to_pa() == full_l2e.to_pa() {
+            l2e = self.copy_table(l2e)?;
+            l1e.set_entry(va.l2x(), l2e);
+        }
+        Ok(l2e)
+    }
+
+    /// Hide pages of `range` in the kernel image from all zone views but the one of `zone_id`,
+    /// e.g. the data sections of named zones. The full page table, which is the privileged
+    /// zone's view, still maps them.
+    #[cfg(feature = "zone")]
+    pub fn set_zone_range(&mut self, range: Range<usize>, zone_id: zone::ZoneId) {
+        assert_eq!(range.start % PAGE_SIZE, 0);
+        assert_eq!(range.end % PAGE_SIZE, 0);
+        let directories = match VIEW_DIRECTORIES.get() {
+            Some(directories) => directories,
+            None => return,
+        };
+        for view in view_zones().filter(|view| *view != zone_id) {
+            let directory = RISCV64PageTableEntry::from_pa(directories[view]);
+            for va in range.clone().step_by(PAGE_SIZE) {
+                match self.private_l3(directory, va) {
+                    Ok(l3e) => l3e.set_entry(va.l3x(), RISCV64PageTableEntry(0)),
+                    Err(e) => {
+                        warn!("zone view {}: failed to hide {:#x}, error {}", view, va, e);
+                        break;
+                    }
+                }
+            }
+        }
+        crate::arch::Arch::flush_tlb(None);
+    }
+
+    /// Get the table of `va` at `level` in `directory`, allocate the missing tables on the way.
+    fn walk_create(
+        &self,
+        directory: RISCV64PageTableEntry,
+        va: usize,
+        level: usize,
+    ) -> Result<RISCV64PageTableEntry, Error> {
         let mut l1e = directory.entry(va.l1x());
         if !l1e.valid() {
             let af = match frame_allocator::allocate_frames(1) {
@@ -235,6 +523,9 @@ impl PageTableTrait for RISCV64PageTable {
             frames.push(af);
             directory.set_entry(va.l1x(), l1e);
         }
+        if level == 1 {
+            return Ok(l1e);
+        }
         let mut l2e = l1e.entry(va.l2x());
         if !l2e.valid() {
             let af = match frame_allocator::allocate_frames(1) {
@@ -251,7 +542,112 @@ impl PageTableTrait for RISCV64PageTable {
             frames.push(af);
             l1e.set_entry(va.l2x(), l2e);
         }
+        Ok(l2e)
+    }
+
+    fn map_in(
+        &self,
+        directory: RISCV64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l2e = self.walk_create(directory, va, 2)?;
         l2e.set_entry(va.l3x(), RISCV64PageTableEntry::from(Entry::new(attr, pa)));
+        Ok(())
+    }
+
+    fn map_2mb_in(
+        &self,
+        directory: RISCV64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l1e = self.walk_create(directory, va, 1)?;
+        let l2e = l1e.entry(va.l2x());
+        if !l2e.valid() {
+            // 2MB Mapped.
+            let entry = RISCV64PageTableEntry::from(Entry::new(attr, pa));
+            l1e.set_entry(va.l2x(), entry);
+        } else {
+            warn!("map_2mb: lvl 2 already mapped with 0x{:x}", l2e.to_pte());
+        }
+        Ok(())
+    }
+
+    fn map_1gb_in(
+        &self,
+        directory: RISCV64PageTableEntry,
+        va: usize,
+        pa: usize,
+        attr: EntryAttribute,
+    ) -> Result<(), Error> {
+        let l1e = directory.entry(va.l1x());
+        if !l1e.valid() {
+            // Map as level 1 block.
+            let entry = RISCV64PageTableEntry::from(Entry::new(attr, pa));
+            directory.set_entry(va.l1x(), entry);
+            Ok(())
+        } else {
+            warn!("map_1gb: lvl 1 already mapped with 0x{:x}", l1e.to_pte());
+            Err(ERROR_INVARG)
+        }
+    }
+
+    /// Clear the entry of `va` at `level` in `directory`, return false if its table is missing.
+    fn unmap_in(&self, directory: RISCV64PageTableEntry, va: usize, level: usize) -> bool {
+        if level == 1 {
+            if !directory.entry(va.l1x()).valid() {
+                return false;
+            }
+            directory.set_entry(va.l1x(), RISCV64PageTableEntry(0));
+            return true;
+        }
+        let l1e = directory.entry(va.l1x());
+        if !l1e.valid() || l1e.blocked() {
+            return false;
+        }
+        if level == 2 {
+            l1e.set_entry(va.l2x(), RISCV64PageTableEntry(0));
+            return true;
+        }
+        let l2e = l1e.entry(va.l2x());
+        if !l2e.valid() || l2e.blocked() {
+            return false;
+        }
+        l2e.set_entry(va.l3x(), RISCV64PageTableEntry(0));
+        true
+    }
+
+    /// Clear the entry of `va` from all zone views, views which can't see it are skipped.
+    #[cfg(feature = "zone")]
+    fn unmap_views(&self, va: usize, level: usize) {
+        if let Some(directories) = VIEW_DIRECTORIES.get() {
+            for zone_id in view_zones() {
+                self.unmap_in(RISCV64PageTableEntry::from_pa(directories[zone_id]), va, level);
+            }
+        }
+    }
+}
+
+impl PageTableTrait for RISCV64PageTable {
+    fn base_pa(&self) -> usize {
+        self.directory_entry.0
+    }
+
+    fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
+        trace!(
+            "page table map va {:#x} pa: 0x{:#x}, directory 0x{:x}",
+            va,
+            pa,
+            self.base_pa()
+        );
+        self.map_in(self.directory_entry, va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_in(directory, va, pa, attr)
+        })?;
         crate::arch::Arch::flush_tlb(Some(va));
         // self.dump_entry_flags_of_va(va);
         Ok(())
@@ -270,52 +666,59 @@ impl PageTableTrait for RISCV64PageTable {
             warn!("map_2mb: required block attribute");
             return Err(ERROR_INVARG);
         }
-        let directory = self.directory_entry;
-        let mut l1e = directory.entry(va.l1x());
-        if !l1e.valid() {
-            let af = match frame_allocator::allocate_frames(1) {
-                Some(af) => af,
-                None => {
-                    warn!("map: failed to allocate one frame for l1e");
-                    return Err(ERROR_OOM);
-                }
-            };
-            let frame = af.start().clone();
-            frame.zero();
-            l1e = RISCV64PageTableEntry::make_table(frame.start_address().value());
-            let mut frames = self.frames.lock();
-            frames.push(af);
-            directory.set_entry(va.l1x(), l1e);
-        }
-        let l2e = l1e.entry(va.l2x());
-        if !l2e.valid() {
-            // 2MB Mapped.
-            let entry = RISCV64PageTableEntry::from(Entry::new(attr, pa));
-            l1e.set_entry(va.l2x(), entry);
-        } else {
-            warn!("map_2mb: lvl 2 already mapped with 0x{:x}", l2e.to_pte());
-        }
+        self.map_2mb_in(self.directory_entry, va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_2mb_in(directory, va, pa, attr)
+        })?;
 		crate::arch::Arch::flush_tlb(Some(va));
         Ok(())
     }
 
+    fn map_1gb(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        assert!(pa % MapGranularity::Page1GB as usize == 0);
+        trace!(
+            "page table map_1gb va 0x{:016x} pa: 0x{:016x}, directory 0x{:x}",
+            va,
+            pa,
+            self.base_pa()
+        );
+        if !attr.block() {
+            warn!("map_1gb: required block attribute");
+            return Err(ERROR_INVARG);
+        }
+        self.map_1gb_in(self.directory_entry, va, pa, attr)?;
+        #[cfg(feature = "zone")]
+        self.for_each_view(attr, |directory, attr| {
+            self.map_1gb_in(directory, va, pa, attr)
+        })?;
+        crate::arch::Arch::flush_tlb(Some(va));
+        Ok(())
+    }
+
     fn unmap(&mut self, va: usize) {
         trace!("unmap va {:x}", va);
-        let directory = self.directory_entry;
-        let l1e = directory.entry(va.l1x());
-        assert!(l1e.valid());
-        let l2e = l1e.entry(va.l2x());
-        assert!(l2e.valid());
-        l2e.set_entry(va.l3x(), RISCV64PageTableEntry(0));
+        assert!(self.unmap_in(self.directory_entry, va, 3));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 3);
     }
 
     fn unmap_2mb(&mut self, va: usize) {
         trace!("unmap_2mb va {:x}", va);
         assert!(va % MapGranularity::Page2MB as usize == 0);
-        let directory = self.directory_entry;
-        let l1e = directory.entry(va.l1x());
-        assert!(l1e.valid());
-        l1e.set_entry(va.l2x(), RISCV64PageTableEntry(0));
+        assert!(self.unmap_in(self.directory_entry, va, 2));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 2);
+    }
+
+    fn unmap_1gb(&mut self, va: usize) {
+        trace!("unmap_1gb va {:x}", va);
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        assert!(self.unmap_in(self.directory_entry, va, 1));
+        #[cfg(feature = "zone")]
+        self.unmap_views(va, 1);
+        crate::arch::Arch::flush_tlb(Some(va));
     }
 
     fn lookup_entry(&self, va: usize) -> Option<(Entry, MapGranularity)> {
@@ -324,6 +727,9 @@ impl PageTableTrait for RISCV64PageTable {
         if !l1e.valid() {
             return None;
         }
+        if l1e.blocked() {
+            return Some((Entry::from(l1e), MapGranularity::Page1GB));
+        }
         let l2e = l1e.entry(va.l2x());
         if !l2e.valid() {
             return None;
@@ -356,4 +762,46 @@ impl PageTableTrait for RISCV64PageTable {
             None
         }
     }
+
+    fn for_each_entry(&self, f: &mut dyn FnMut(usize, Entry, MapGranularity)) {
+        const ENTRY_PER_PAGE: usize = PAGE_SIZE / MACHINE_SIZE;
+        // Virtual addresses are sign extended from bit 38 in Sv39.
+        let canonical = |va: usize| {
+            if va & (1 << 38) != 0 {
+                va | !((1 << 39) - 1)
+            } else {
+                va
+            }
+        };
+        let directory = self.directory_entry;
+        for l1x in 0..ENTRY_PER_PAGE {
+            let l1e = directory.entry(l1x);
+            if !l1e.valid() {
+                continue;
+            }
+            let l1_va = canonical(l1x << PAGE_TABLE_L1_SHIFT);
+            if l1e.blocked() {
+                f(l1_va, Entry::from(l1e), MapGranularity::Page1GB);
+                continue;
+            }
+            for l2x in 0..ENTRY_PER_PAGE {
+                let l2e = l1e.entry(l2x);
+                if !l2e.valid() {
+                    continue;
+                }
+                let l2_va = l1_va + (l2x << PAGE_TABLE_L2_SHIFT);
+                if l2e.blocked() {
+                    f(l2_va, Entry::from(l2e), MapGranularity::Page2MB);
+                    continue;
+                }
+                for l3x in 0..ENTRY_PER_PAGE {
+                    let l3e = l2e.entry(l3x);
+                    if l3e.valid() {
+                        let va = l2_va + (l3x << PAGE_TABLE_L3_SHIFT);
+                        f(va, Entry::from(l3e), MapGranularity::Page4KB);
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use core::ops::{Index, IndexMut};
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+
+use gimli::Register;
+
+#[macro_export]
+macro_rules! registers {
+    ($struct_name:ident, { $($name:ident = ($val:expr, $disp:expr)),+ $(,)? }) => {
+        #[allow(missing_docs)]
+        impl $struct_name {
+            $(
+                pub const $name: Register = Register($val);
+            )+
+        }
+
+        impl $struct_name {
+            /// The name of a register, or `None` if the register number is unknown.
+            #[allow(dead_code)]
+            pub fn register_name(register: Register) -> Option<&'static str> {
+                match register {
+                    $(
+                        Self::$name => Some($disp),
+                    )+
+                    _ => return None,
+                }
+            }
+        }
+    };
+}
+
+/// Number of general purpose registers in the DWARF numbering of RISC-V ELF psABI.
+const DWARF_REG_NUM: usize = 32;
+
+#[derive(Clone)]
+pub struct Registers {
+    registers: [Option<u64>; DWARF_REG_NUM],
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers {
+            registers: [None; DWARF_REG_NUM],
+        }
+    }
+}
+
+impl Debug for Registers {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        for (i, reg) in self.registers.iter().enumerate() {
+            match *reg {
+                None => {}
+                Some(r) => write!(
+                    fmt,
+                    "[{}]: {:#X}, \n",
+                    Riscv64::register_name(Register(i as u16)).unwrap_or("?"),
+                    r
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Index<gimli::Register> for Registers {
+    type Output = Option<u64>;
+    fn index(&self, index: Register) -> &Self::Output {
+        &self.registers[index.0 as usize]
+    }
+}
+
+impl IndexMut<gimli::Register> for Registers {
+    fn index_mut(&mut self, index: Register) -> &mut Self::Output {
+        &mut self.registers[index.0 as usize]
+    }
+}
+
+/// DWARF register numbers of riscv64, x0 - x31 are numbered 0 - 31,
+/// see RISC-V ELF psABI, DWARF register numbers.
+#[derive(Debug, Clone, Copy)]
+pub struct Riscv64;
+
+registers!(Riscv64, {
+    ZERO = (0, "ZERO"),
+    RA = (1, "RA"),
+    SP = (2, "SP"),
+    GP = (3, "GP"),
+    TP = (4, "TP"),
+    T0 = (5, "T0"),
+    T1 = (6, "T1"),
+    T2 = (7, "T2"),
+    S0 = (8, "S0"),
+    S1 = (9, "S1"),
+    A0 = (10, "A0"),
+    A1 = (11, "A1"),
+    A2 = (12, "A2"),
+    A3 = (13, "A3"),
+    A4 = (14, "A4"),
+    A5 = (15, "A5"),
+    A6 = (16, "A6"),
+    A7 = (17, "A7"),
+    S2 = (18, "S2"),
+    S3 = (19, "S3"),
+    S4 = (20, "S4"),
+    S5 = (21, "S5"),
+    S6 = (22, "S6"),
+    S7 = (23, "S7"),
+    S8 = (24, "S8"),
+    S9 = (25, "S9"),
+    S10 = (26, "S10"),
+    S11 = (27, "S11"),
+    T3 = (28, "T3"),
+    T4 = (29, "T4"),
+    T5 = (30, "T5"),
+    T6 = (31, "T6"),
+});
+
+#[cfg(feature = "unwind")]
+pub const REG_RETURN_ADDRESS: Register = Riscv64::RA;
+#[cfg(feature = "unwind")]
+pub const REG_STACK_POINTER: Register = Riscv64::SP;
+#[cfg(feature = "unwind")]
+pub const REG_ARGUMENT: Register = Riscv64::A0;
@@ -83,6 +83,35 @@ impl core::fmt::Display for X86_64TrapContextFrame {
     }
 }
 
+#[cfg(feature = "coredump")]
+impl From<&X86_64TrapContextFrame> for super::coredump::CoreRegs {
+    fn from(ctx: &X86_64TrapContextFrame) -> Self {
+        use super::coredump::*;
+        let gpr = &ctx.gpr;
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[R15] = gpr.r15 as u64;
+        regs.0[R14] = gpr.r14 as u64;
+        regs.0[R13] = gpr.r13 as u64;
+        regs.0[R12] = gpr.r12 as u64;
+        regs.0[RBP] = gpr.rbp as u64;
+        regs.0[RBX] = gpr.rbx as u64;
+        regs.0[R11] = gpr.r11 as u64;
+        regs.0[R10] = gpr.r10 as u64;
+        regs.0[R9] = gpr.r9 as u64;
+        regs.0[R8] = gpr.r8 as u64;
+        regs.0[RAX] = gpr.rax as u64;
+        regs.0[RCX] = gpr.rcx as u64;
+        regs.0[RDX] = gpr.rdx as u64;
+        regs.0[RSI] = gpr.rsi as u64;
+        regs.0[RDI] = gpr.rdi as u64;
+        regs.0[RIP] = gpr.rip as u64;
+        regs.0[EFLAGS] = gpr.rflags as u64;
+        regs.0[RSP] = gpr.rsp as u64;
+        regs.0[FS_BASE] = gpr.fsbase as u64;
+        regs
+    }
+}
+
 impl ContextFrameTrait for X86_64TrapContextFrame {
     fn init(&mut self, _tid: usize, _tls_area: usize) {
         self.gpr.rflags = 0x1202;
@@ -104,6 +133,10 @@ impl ContextFrameTrait for X86_64TrapContextFrame {
         self.gpr.rsp = sp;
     }
 
+    fn frame_pointer(&self) -> usize {
+        self.gpr.rbp
+    }
+
     fn gpr(&self, index: usize) -> usize {
         match index {
             0 => self.gpr.rdi,
@@ -165,10 +198,31 @@ struct YieldContextFrame {
     rip: u64,
 }
 
+#[repr(C)]
 pub struct ThreadContext {
     rsp: u64,
 }
 
+/// A yielded thread saved its callee-saved registers as a `YieldContextFrame` on its stack,
+/// it resumes at the return address on top of them.
+#[cfg(feature = "coredump")]
+impl From<&ThreadContext> for super::coredump::CoreRegs {
+    fn from(ctx: &ThreadContext) -> Self {
+        use super::coredump::*;
+        let frame = unsafe { &*(ctx.rsp as *const YieldContextFrame) };
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[R15] = frame.r15;
+        regs.0[R14] = frame.r14;
+        regs.0[R13] = frame.r13;
+        regs.0[R12] = frame.r12;
+        regs.0[RBX] = frame.rbx;
+        regs.0[RBP] = frame.rbp;
+        regs.0[RIP] = frame.rip;
+        regs.0[RSP] = ctx.rsp + core::mem::size_of::<YieldContextFrame>() as u64;
+        regs
+    }
+}
+
 impl ThreadContext {
     /// Creates a new default `ThreadContext` for a new thread.
     pub const fn new() -> Self {
@@ -422,3 +476,77 @@ pub(super) unsafe extern "C" fn _pop_context_first(_next_stack: usize) {
         options(noreturn),
     )
 }
+
+/// Call `_entry` with `_arg` on another stack whose top is `_sp`, then switch back.
+///
+/// Used by zone calls to run the callee on a stack of the target zone.
+/// ## Arguments
+/// * `_arg`    - argument passed to `_entry`, on `rdi`.
+/// * `_entry`  - function to call, on `rsi`.
+/// * `_sp`     - top of the stack, 16 bytes aligned, on `rdx`.
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+#[naked]
+pub unsafe extern "C" fn call_on_stack(_arg: usize, _entry: extern "C" fn(usize), _sp: usize) {
+    asm!(
+        "push   rbp",
+        "mov    rbp, rsp",
+        "mov    rsp, rdx",
+        "call   rsi",
+        "mov    rsp, rbp",
+        "pop    rbp",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Save current callee-saved registers as a `YieldContextFrame` on current stack like `setjmp`,
+/// record the stack pointer into `_ctx`, call `_capture` with it and `_arg`, then return 0.
+///
+/// The frame is captured with the stack, `restore_checkpoint_context` on `_ctx`
+/// returns from here again, with its `_ret`.
+/// ## Arguments
+/// * `_ctx`        - the pointer to the checkpoint's `ThreadContext`, on `rdi`.
+/// * `_capture`    - function copying the stack from the saved stack pointer, on `rsi`.
+/// * `_arg`        - second argument passed to `_capture`, on `rdx`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn save_checkpoint_context(
+    _ctx: &mut ThreadContext,
+    _capture: extern "C" fn(usize, usize),
+    _arg: usize,
+) -> usize {
+    asm!(
+        save_yield_context!(),
+        "mov    [rdi], rsp",
+        "mov    rdi, rsp",
+        "mov    rax, rsi",
+        "mov    rsi, rdx",
+        // Align the stack to 16 bytes, the return address and 6 registers are pushed.
+        "sub    rsp, 8",
+        "call   rax",
+        "add    rsp, 8",
+        restore_yield_context!(),
+        "xor    eax, eax",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Pop the `YieldContextFrame` saved by `save_checkpoint_context` at the stack pointer
+/// in `_ctx`, which returns `_ret` again.
+///
+/// The stack above the saved stack pointer must be restored before.
+/// ## Arguments
+/// * `_ctx`    - the pointer to the checkpoint's `ThreadContext`, on `rdi`.
+/// * `_ret`    - value returned by `save_checkpoint_context`, on `rsi`.
+#[cfg(feature = "checkpoint")]
+#[naked]
+pub unsafe extern "C" fn restore_checkpoint_context(_ctx: &ThreadContext, _ret: usize) -> ! {
+    asm!(
+        "mov    rsp, [rdi]",
+        restore_yield_context!(),
+        "mov    rax, rsi",
+        "ret",
+        options(noreturn),
+    )
+}
@@ -0,0 +1,115 @@
+//! Registers of ELF core dumps, in the layout of `struct user_regs_struct` as gdb reads from NT_PRSTATUS.
+
+#[cfg(feature = "unwind")]
+use super::registers::{Registers, X86_64};
+
+/// EM_X86_64.
+pub const ELF_MACHINE: u16 = 62;
+pub const ELF_FLAGS: u32 = 0;
+
+pub const GREG_NUM: usize = 27;
+pub const R15: usize = 0;
+pub const R14: usize = 1;
+pub const R13: usize = 2;
+pub const R12: usize = 3;
+pub const RBP: usize = 4;
+pub const RBX: usize = 5;
+pub const R11: usize = 6;
+pub const R10: usize = 7;
+pub const R9: usize = 8;
+pub const R8: usize = 9;
+pub const RAX: usize = 10;
+pub const RCX: usize = 11;
+pub const RDX: usize = 12;
+pub const RSI: usize = 13;
+pub const RDI: usize = 14;
+pub const RIP: usize = 16;
+pub const CS: usize = 17;
+pub const EFLAGS: usize = 18;
+pub const RSP: usize = 19;
+pub const SS: usize = 20;
+pub const FS_BASE: usize = 21;
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegs(pub [u64; GREG_NUM]);
+
+impl CoreRegs {
+    /// Registers of the caller, only rip, rsp and rbp are captured.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let (pc, sp, fp): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "lea {}, [rip]",
+                "mov {}, rsp",
+                "mov {}, rbp",
+                out(reg) pc,
+                out(reg) sp,
+                out(reg) fp,
+            );
+        }
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[RIP] = pc;
+        regs.0[RSP] = sp;
+        regs.0[RBP] = fp;
+        regs
+    }
+}
+
+impl From<&super::exception::ExceptionFrame> for CoreRegs {
+    fn from(frame: &super::exception::ExceptionFrame) -> Self {
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        regs.0[R15] = frame.r15;
+        regs.0[R14] = frame.r14;
+        regs.0[R13] = frame.r13;
+        regs.0[R12] = frame.r12;
+        regs.0[RBP] = frame.rbp;
+        regs.0[RBX] = frame.rbx;
+        regs.0[R11] = frame.r11;
+        regs.0[R10] = frame.r10;
+        regs.0[R9] = frame.r9;
+        regs.0[R8] = frame.r8;
+        regs.0[RAX] = frame.rax;
+        regs.0[RCX] = frame.rcx;
+        regs.0[RDX] = frame.rdx;
+        regs.0[RSI] = frame.rsi;
+        regs.0[RDI] = frame.rdi;
+        regs.0[RIP] = frame.rip;
+        regs.0[CS] = frame.cs;
+        regs.0[EFLAGS] = frame.rflags;
+        regs.0[RSP] = frame.rsp;
+        regs.0[SS] = frame.ss;
+        regs
+    }
+}
+
+/// Registers to unwind from, whose return address is the faulting rip plus `CALLER_OFFSET`.
+#[cfg(feature = "unwind")]
+impl From<&Registers> for CoreRegs {
+    fn from(registers: &Registers) -> Self {
+        let mut regs = CoreRegs([0; GREG_NUM]);
+        for (index, register) in [
+            (R15, X86_64::R15),
+            (R14, X86_64::R14),
+            (R13, X86_64::R13),
+            (R12, X86_64::R12),
+            (RBP, X86_64::RBP),
+            (RBX, X86_64::RBX),
+            (R11, X86_64::R11),
+            (R10, X86_64::R10),
+            (R9, X86_64::R9),
+            (R8, X86_64::R8),
+            (RAX, X86_64::RAX),
+            (RCX, X86_64::RCX),
+            (RDX, X86_64::RDX),
+            (RSI, X86_64::RSI),
+            (RDI, X86_64::RDI),
+            (RSP, X86_64::RSP),
+        ] {
+            regs.0[index] = registers[register].unwrap_or(0);
+        }
+        regs.0[RIP] = registers[X86_64::RA]
+            .map_or(0, |ra| ra - crate::libs::unwind::arch::CALLER_OFFSET);
+        regs
+    }
+}
@@ -35,10 +35,14 @@ pub fn init_idt() {
         .set_handler_fn(segment_not_present_handler);
     idt.stack_segment_fault
         .set_handler_fn(stack_segment_fault_handler);
-    idt.general_protection_fault
-        .set_handler_fn(general_protection_fault_handler);
-    // Set page fault handler.
-    idt.page_fault.set_handler_fn(page_fault_handler);
+    // Page fault and general protection fault handlers get the registers at the fault,
+    // which are needed to unwind the faulting thread.
+    unsafe {
+        idt.general_protection_fault
+            .set_handler_addr(x86_64::VirtAddr::new(general_protection_fault_entry as u64));
+        idt.page_fault
+            .set_handler_addr(x86_64::VirtAddr::new(page_fault_entry as u64));
+    }
     // Set timer handler.
     idt[apic::INT_TIMER].set_handler_fn(timer_interrupt_handler);
     idt[apic::ERROR_INTERRUPT_NUMBER as usize].set_handler_fn(error_interrupt_handler);
@@ -81,6 +85,32 @@ pub fn irq_install_handler(irq_number: u32, handler: usize) {
     }
 }
 
+/// Print the backtrace of the code trapped by an `x86-interrupt` handler,
+/// whose rbp is saved by the prologue of the handler.
+#[inline(always)]
+fn print_trapped_backtrace(stack_frame: &InterruptStackFrame) {
+    let rbp = unsafe { (crate::libs::backtrace::frame_pointer() as *const usize).read() };
+    crate::libs::backtrace::print_backtrace_from(
+        stack_frame.instruction_pointer.as_u64() as usize,
+        rbp,
+    );
+}
+
+/// Dump the code trapped by an `x86-interrupt` handler, with the registers kept in its stack frame.
+#[cfg(feature = "coredump")]
+#[inline(always)]
+fn dump_trapped(stack_frame: &InterruptStackFrame) {
+    use super::coredump::{CoreRegs, CS, EFLAGS, GREG_NUM, RBP, RIP, RSP, SS};
+    let mut regs = CoreRegs([0; GREG_NUM]);
+    regs.0[RBP] = unsafe { (crate::libs::backtrace::frame_pointer() as *const u64).read() };
+    regs.0[RIP] = stack_frame.instruction_pointer.as_u64();
+    regs.0[CS] = stack_frame.code_segment;
+    regs.0[EFLAGS] = stack_frame.cpu_flags;
+    regs.0[RSP] = stack_frame.stack_pointer.as_u64();
+    regs.0[SS] = stack_frame.stack_segment;
+    crate::libs::coredump::dump(regs, crate::libs::coredump::SIGNAL_EXCEPTION);
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
@@ -91,6 +121,9 @@ extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
 
 extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    print_trapped_backtrace(&stack_frame);
+    #[cfg(feature = "coredump")]
+    dump_trapped(&stack_frame);
     hlt_loop();
 }
 
@@ -102,6 +135,9 @@ extern "x86-interrupt" fn double_fault_handler(
         "EXCEPTION: DOUBLE FAULT error code {}\n{:#?}",
         error_code, stack_frame
     );
+    print_trapped_backtrace(&stack_frame);
+    #[cfg(feature = "coredump")]
+    dump_trapped(&stack_frame);
     hlt_loop();
 }
 
@@ -110,6 +146,9 @@ extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame,
         "EXCEPTION: INVALID TSS error code {}\n{:#?}",
         error_code, stack_frame
     );
+    print_trapped_backtrace(&stack_frame);
+    #[cfg(feature = "coredump")]
+    dump_trapped(&stack_frame);
     hlt_loop();
 }
 
@@ -121,6 +160,9 @@ extern "x86-interrupt" fn segment_not_present_handler(
         "EXCEPTION: SEGMENT NOT PRESENT error code {}\n{:#?}",
         error_code, stack_frame
     );
+    print_trapped_backtrace(&stack_frame);
+    #[cfg(feature = "coredump")]
+    dump_trapped(&stack_frame);
     hlt_loop();
 }
 
@@ -132,49 +174,210 @@ extern "x86-interrupt" fn stack_segment_fault_handler(
         "EXCEPTION: STACK SEGMENT FAULT error code {}\n{:#?}",
         error_code, stack_frame
     );
+    print_trapped_backtrace(&stack_frame);
+    #[cfg(feature = "coredump")]
+    dump_trapped(&stack_frame);
     hlt_loop();
 }
 
-extern "x86-interrupt" fn general_protection_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: u64,
-) {
+/// Registers saved by `exception_entry!` on top of the frame pushed by CPU, from low address to high.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExceptionFrame {
+    /// Padding to keep the stack 16 bytes aligned.
+    _align: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rax: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl core::fmt::Display for ExceptionFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "r15: {:016x} ", self.r15)?;
+        writeln!(f, "r14: {:016x} ", self.r14)?;
+        write!(f, "r13: {:016x} ", self.r13)?;
+        writeln!(f, "r12: {:016x} ", self.r12)?;
+        write!(f, "r11: {:016x} ", self.r11)?;
+        writeln!(f, "r10: {:016x} ", self.r10)?;
+        write!(f, " r9: {:016x} ", self.r9)?;
+        writeln!(f, " r8: {:016x} ", self.r8)?;
+        write!(f, "rdi: {:016x} ", self.rdi)?;
+        writeln!(f, "rsi: {:016x} ", self.rsi)?;
+        write!(f, "rbp: {:016x} ", self.rbp)?;
+        writeln!(f, "rbx: {:016x} ", self.rbx)?;
+        write!(f, "rdx: {:016x} ", self.rdx)?;
+        writeln!(f, "rcx: {:016x} ", self.rcx)?;
+        write!(f, "rax: {:016x} ", self.rax)?;
+        writeln!(f, "rflags: {:016x} ", self.rflags)?;
+        write!(f, "rip: {:016x} ", self.rip)?;
+        writeln!(f, "rsp: {:016x} ", self.rsp)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "unwind")]
+impl Into<super::registers::Registers> for &ExceptionFrame {
+    fn into(self) -> super::registers::Registers {
+        use super::registers::{Registers, X86_64};
+        let mut reg = Registers::default();
+        reg[X86_64::RAX] = Some(self.rax);
+        reg[X86_64::RDX] = Some(self.rdx);
+        reg[X86_64::RCX] = Some(self.rcx);
+        reg[X86_64::RBX] = Some(self.rbx);
+        reg[X86_64::RSI] = Some(self.rsi);
+        reg[X86_64::RDI] = Some(self.rdi);
+        reg[X86_64::RBP] = Some(self.rbp);
+        reg[X86_64::RSP] = Some(self.rsp);
+        reg[X86_64::R8] = Some(self.r8);
+        reg[X86_64::R9] = Some(self.r9);
+        reg[X86_64::R10] = Some(self.r10);
+        reg[X86_64::R11] = Some(self.r11);
+        reg[X86_64::R12] = Some(self.r12);
+        reg[X86_64::R13] = Some(self.r13);
+        reg[X86_64::R14] = Some(self.r14);
+        reg[X86_64::R15] = Some(self.r15);
+        // Unwinding looks up the caller at return address minus `CALLER_OFFSET`,
+        // which should be the faulting instruction itself.
+        reg[X86_64::RA] = Some(self.rip + crate::libs::unwind::arch::CALLER_OFFSET);
+        reg
+    }
+}
+
+/// Entry of an exception with error code, saves general registers as `ExceptionFrame`
+/// and calls `$handler` with it, see `ExceptionFrame`.
+macro_rules! exception_entry {
+    ($name:ident, $handler:ident) => {
+        #[naked]
+        unsafe extern "C" fn $name() {
+            core::arch::asm!(
+                "push rax",
+                "push rcx",
+                "push rdx",
+                "push rbx",
+                "push rbp",
+                "push rsi",
+                "push rdi",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "sub rsp, 8",
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "add rsp, 8",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rdi",
+                "pop rsi",
+                "pop rbp",
+                "pop rbx",
+                "pop rdx",
+                "pop rcx",
+                "pop rax",
+                // Pop error code.
+                "add rsp, 8",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn),
+            )
+        }
+    };
+}
+
+exception_entry!(general_protection_fault_entry, general_protection_fault_handler);
+exception_entry!(page_fault_entry, page_fault_handler);
+
+extern "C" fn general_protection_fault_handler(frame: &ExceptionFrame) {
     println!(
-        "EXCEPTION: GENERAL PROTECTION FAULT error code {}\n{:#?}",
-        error_code, stack_frame
+        "EXCEPTION: GENERAL PROTECTION FAULT error code {}\n{}",
+        frame.error_code, frame
     );
+    crate::libs::backtrace::print_backtrace_from(frame.rip as usize, frame.rbp as usize);
+    #[cfg(feature = "unwind")]
+    crate::libs::unwind::unwind_from_exception(frame.into());
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    crate::libs::coredump::dump(frame.into(), crate::libs::coredump::SIGNAL_EXCEPTION);
     hlt_loop();
 }
 
 use super::hlt_loop;
 use x86_64::structures::idt::PageFaultErrorCode;
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
+extern "C" fn page_fault_handler(frame: &ExceptionFrame) {
     use x86_64::registers::control::Cr2;
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
     #[cfg(feature = "zone")]
     let cur_pkru = zone::rdpkru();
     #[cfg(feature = "zone")]
-    let _ = zone::switch_to_privilege();
+    let ori_zone = zone::switch_to_privilege();
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
+    // Access denied by protection keys, the PK bit of the error code is set.
     #[cfg(feature = "zone")]
     if error_code.contains(PageFaultErrorCode::PROTECTION_KEY) {
-        println!(
-            "\nMEMORY PROTECTION KEY VIOLATION on {}!!! current PKRU {:#x}\n",
-            crate::libs::thread::current_thread_id(),
+        use crate::libs::violation::{self, Violation};
+        let violation = Violation::new(
+            ori_zone,
             cur_pkru,
+            frame.rip as usize,
+            Cr2::read().as_u64() as usize,
+            error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE),
         );
-
-        crate::arch::page_table::page_table()
-            .lock()
-            .dump_entry_flags_of_va(Cr2::read().as_u64() as usize);
+        let policy = violation::report_violation(&violation);
+        // The fault may be raised while the page table is locked, don't wait for it.
+        if let Ok(mut page_table) = crate::arch::page_table::page_table().try_lock() {
+            page_table.dump_entry_flags_of_va(violation.address);
+        }
+        #[cfg(feature = "unwind")]
+        if policy == violation::ViolationPolicy::Unwind {
+            // Unwind in the faulting zone, where its landing pads run.
+            zone::switch_from_privilege(ori_zone);
+            crate::libs::unwind::unwind_from_exception(frame.into());
+        }
+        violation::recover(policy);
     }
-    println!("{:#?}", stack_frame);
 
+    println!("EXCEPTION: PAGE FAULT");
+    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Error Code: {:?}", error_code);
+    println!("{}", frame);
+    crate::libs::backtrace::print_backtrace_from(frame.rip as usize, frame.rbp as usize);
+
+    #[cfg(feature = "unwind")]
+    {
+        #[cfg(feature = "zone")]
+        zone::switch_from_privilege(ori_zone);
+        crate::libs::unwind::unwind_from_exception(frame.into());
+    }
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    crate::libs::coredump::dump(frame.into(), crate::libs::coredump::SIGNAL_EXCEPTION);
     crate::libs::thread::thread_exit();
 }
 
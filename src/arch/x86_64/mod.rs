@@ -2,11 +2,14 @@ use crate::libs::traits::Address;
 use crate::libs::traits::ArchTrait;
 
 mod context_frame;
+#[cfg(feature = "coredump")]
+pub mod coredump;
 mod exception;
 mod gdt;
 pub mod irq;
 pub mod page_table;
 mod processor;
+pub mod registers;
 
 pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xFFFF_8000_0000_0000;
 
@@ -37,6 +40,10 @@ impl Address for usize {
 
 pub type ContextFrame = context_frame::X86_64TrapContextFrame;
 pub type ThreadContext = context_frame::ThreadContext;
+#[cfg(any(feature = "zone", feature = "checkpoint"))]
+pub use context_frame::call_on_stack;
+#[cfg(feature = "checkpoint")]
+pub use context_frame::{restore_checkpoint_context, save_checkpoint_context};
 
 pub use exception::irq_install_handler;
 pub use exception::init_idt;
@@ -1,12 +1,14 @@
 use alloc::collections::BTreeMap;
+use core::ops::Range;
 use spin::Once;
 
 use x86_64::{PhysAddr, VirtAddr};
-use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::Size2MiB;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{Size1GiB, Size2MiB};
 use x86_64::structures::paging::{
     frame::PhysFrame as Frame,
-    mapper::OffsetPageTable,
+    mapper::{MappedFrame, OffsetPageTable, Translate, TranslateResult},
     Mapper,
     page::{Page, Size4KiB},
     page_table::{PageTable as x86PageTable, PageTableFlags},
@@ -21,12 +23,14 @@ use crate::mm::frame_allocator::AllocatedFrames;
 use crate::mm::interface::{PageTableEntryAttrTrait, PageTableTrait, Error, MapGranularity};
 #[cfg(feature = "zone")]
 use crate::mm::interface::PageTableEntryAttrZoneTrait;
+#[cfg(feature = "zone")]
+use zone::{PKey, ZoneId};
 use crate::mm::paging::{Entry, EntryAttribute};
+use crate::mm::wx;
 use crate::libs::synch::spinlock::SpinlockIrqSave;
 
 pub const PAGE_TABLE_L1_SHIFT: usize = 30;
 pub const PAGE_TABLE_L2_SHIFT: usize = 21;
-#[allow(unused)]
 pub const PAGE_TABLE_L3_SHIFT: usize = 12;
 
 #[repr(transparent)]
@@ -101,6 +105,44 @@ pub fn page_table() -> &'static SpinlockIrqSave<X86_64PageTable> {
     PAGE_TABLE.get().unwrap()
 }
 
+/// Look up the mapping of `va` in the kernel address space, which is in the page table.
+#[cfg(feature = "wx-check")]
+pub fn kernel_entry(va: usize) -> Option<(Entry, MapGranularity)> {
+    page_table().lock().lookup_entry(va)
+}
+
+/// Make writable leaf entries in the table at `table_pa` non-executable, except the ones
+/// overlapping `image`. The table maps from `base` at `level`, 3 for a level 3 table (PDPT).
+fn forbid_exec_outside(table_pa: PhysAddr, base: usize, level: usize, image: &Range<usize>) {
+    let shift = PAGE_TABLE_L3_SHIFT + (level - 1) * 9;
+    let table = unsafe { &mut *frame_to_page_table(Frame::containing_address(table_pa)) };
+    for (index, entry) in table.iter_mut().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let va = base + (index << shift);
+        if level > 1 && !flags.contains(PageTableFlags::HUGE_PAGE) {
+            forbid_exec_outside(entry.addr(), va, level - 1, image);
+            continue;
+        }
+        let overlaps_image = va < image.end && image.start <= va + ((1 << shift) - 1);
+        if flags.contains(PageTableFlags::WRITABLE) && !overlaps_image {
+            entry.set_flags(flags | PageTableFlags::NO_EXECUTE);
+        }
+    }
+}
+
+/// 1GB pages are only available if the CPU reports `pdpe1gb`.
+pub fn huge_1gb_supported() -> bool {
+    static SUPPORTED: Once<bool> = Once::new();
+    *SUPPORTED.call_once(|| {
+        raw_cpuid::CpuId::new()
+            .get_extended_processor_and_feature_identifiers()
+            .map_or(false, |info| info.has_1gib_pages())
+    })
+}
+
 pub fn init() {
     let frame = Cr3::read().0;
     debug!("page table init, frame {:#?}", frame);
@@ -152,8 +194,18 @@ pub fn init() {
         page_table().lock().base_pa()
     );
 
+    // Honor NO_EXECUTE in entries, and read-only entries in kernel mode.
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+    page_table().lock().protect_kernel_image();
+
     #[cfg(feature = "zone")]
-    page_table().lock().init_main_zone_flags();
+    {
+        page_table().lock().init_main_zone_flags();
+        zone::register_key_ops(zone::KeyOps { retag: retag_zone });
+    }
 }
 
 /// Todo: this seems awkward.
@@ -224,6 +276,52 @@ impl X86_64PageTable {
         );
     }
 
+    /// Remap 4KB pages of the kernel image with permissions of their sections, see `mm::wx`.
+    ///
+    /// Writable memory outside the image, e.g. the physical memory mapping which aliases
+    /// the image, is made non-executable.
+    fn protect_kernel_image(&mut self) {
+        for (range, perm) in wx::image_sections() {
+            debug!("kernel image [{:#x} to {:#x}] {:?}", range.start, range.end, perm);
+            for va in range.step_by(PAGE_SIZE) {
+                let page = Page::<Size4KiB>::containing_address(VirtAddr::new(va as u64));
+                let flags = match self.page_table.translate(page.start_address()) {
+                    TranslateResult::Mapped {
+                        frame: MappedFrame::Size4KiB(_),
+                        flags,
+                        ..
+                    } => flags,
+                    _ => {
+                        warn!("kernel image va {:#x} is not mapped by 4KB page, skip", va);
+                        continue;
+                    }
+                };
+                let mut flags = flags - (PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+                if perm.writable() {
+                    flags |= PageTableFlags::WRITABLE;
+                }
+                if !perm.executable() {
+                    flags |= PageTableFlags::NO_EXECUTE;
+                }
+                match unsafe { self.page_table.update_flags(page, flags) } {
+                    Ok(flush) => flush.ignore(),
+                    Err(e) => warn!("kernel image va {:#x} update flags failed, {:?}", va, e),
+                }
+            }
+        }
+
+        let image = wx::image_range();
+        let l4_table = unsafe { &mut *frame_to_page_table(self.dir_frame) };
+        for (l4_idx, l4_entry) in l4_table.iter_mut().enumerate() {
+            if l4_entry.flags().contains(PageTableFlags::PRESENT) {
+                let l4_va = VirtAddr::new_truncate((l4_idx << 39) as u64).as_u64() as usize;
+                forbid_exec_outside(l4_entry.addr(), l4_va, 3, &image);
+            }
+        }
+        x86_64::instructions::tlb::flush_all();
+    }
+
+    #[cfg(feature = "zone")]
     fn init_main_zone_flags(&mut self) {
         extern "C" {
             fn PROTECTED_DATA_START();
@@ -241,51 +339,106 @@ impl X86_64PageTable {
         assert_eq!(protected_data_end % PAGE_SIZE, 0);
 
         for va in (protected_data_start..protected_data_end).step_by(PAGE_SIZE) {
-            let page_4kb = Page::<Size4KiB>::containing_address(VirtAddr::new(va as u64));
-            let l4_index = page_4kb.p4_index();
-            let l3_index = page_4kb.p3_index();
-            let l2_index = page_4kb.p2_index();
-            let l1_index = page_4kb.p1_index();
-            let l4_entry = &self.page_table.level_4_table()[usize::from(l4_index)];
-            debug!(
-                "get l4 pte of index {}, {:?}",
-                usize::from(l4_index),
-                l4_entry.flags()
-            );
-            let l3_page_table = unsafe { &*frame_to_page_table(l4_entry.frame().unwrap()) };
-            let l3_entry = &l3_page_table[l3_index];
-            debug!(
-                "get l3 pte of index {}, {:?}",
-                usize::from(l3_index),
-                l3_entry.flags()
-            );
-            let l2_page_table = unsafe { &*frame_to_page_table(l3_entry.frame().unwrap()) };
-            let l2_entry = &l2_page_table[l2_index];
-            debug!(
-                "get l2 pte of index {}, {:?}",
-                usize::from(l2_index),
-                l2_entry.flags()
-            );
-            if l2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
-                panic!("va {:#x} mapped as 2MB Huge page", va);
-                return;
-            }
-            let l1_page_table = unsafe { &mut *frame_to_page_table(l2_entry.frame().unwrap()) };
-            let mut l1_entry = &mut l1_page_table[l1_index];
-            debug!(
-                "get l1 pte of index {}, {:?}",
-                usize::from(l1_index),
-                l1_entry.flags()
-            );
-            l1_entry.set_flags(
-                l1_entry.flags() | PageTableFlags::BIT_59 | PageTableFlags::USER_ACCESSIBLE,
-            );
-            debug!("va {:#x} mapped as flags {:?}", va, l1_entry.flags());
+            self.set_page_zone(va, PROTECTED_DATA_ZONE);
             println!("==============================================================");
         }
         x86_64::instructions::tlb::flush_all();
     }
 
+    /// Tag a 4KB page of the kernel image with `zone_id`, TLB is not flushed.
+    #[cfg(feature = "zone")]
+    fn set_page_zone(&mut self, va: usize, zone_id: ZoneId) {
+        let page_4kb = Page::<Size4KiB>::containing_address(VirtAddr::new(va as u64));
+        let l4_index = page_4kb.p4_index();
+        let l3_index = page_4kb.p3_index();
+        let l2_index = page_4kb.p2_index();
+        let l1_index = page_4kb.p1_index();
+        let l4_entry = &self.page_table.level_4_table()[usize::from(l4_index)];
+        debug!(
+            "get l4 pte of index {}, {:?}",
+            usize::from(l4_index),
+            l4_entry.flags()
+        );
+        let l3_page_table = unsafe { &*frame_to_page_table(l4_entry.frame().unwrap()) };
+        let l3_entry = &l3_page_table[l3_index];
+        debug!(
+            "get l3 pte of index {}, {:?}",
+            usize::from(l3_index),
+            l3_entry.flags()
+        );
+        let l2_page_table = unsafe { &*frame_to_page_table(l3_entry.frame().unwrap()) };
+        let l2_entry = &l2_page_table[l2_index];
+        debug!(
+            "get l2 pte of index {}, {:?}",
+            usize::from(l2_index),
+            l2_entry.flags()
+        );
+        if l2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            panic!("va {:#x} mapped as 2MB Huge page", va);
+            return;
+        }
+        let l1_page_table = unsafe { &mut *frame_to_page_table(l2_entry.frame().unwrap()) };
+        let mut l1_entry = &mut l1_page_table[l1_index];
+        debug!(
+            "get l1 pte of index {}, {:?}",
+            usize::from(l1_index),
+            l1_entry.flags()
+        );
+        let cleared = PageTableFlags::from_bits_truncate(
+            l1_entry.flags().bits()
+                & !(ZONE_ID_MASK << ZONE_ID_SHIFT)
+                & !(PKEY_MASK << PKEY_SHIFT),
+        );
+        l1_entry.set_flags(cleared | zone_flags(zone_id) | PageTableFlags::USER_ACCESSIBLE);
+        debug!("va {:#x} mapped as flags {:?}", va, l1_entry.flags());
+    }
+
+    /// Tag pages of `range` in the kernel image with `zone_id`, e.g. the data sections of named zones.
+    #[cfg(feature = "zone")]
+    pub fn set_zone_range(&mut self, range: Range<usize>, zone_id: ZoneId) {
+        assert_eq!(range.start % PAGE_SIZE, 0);
+        assert_eq!(range.end % PAGE_SIZE, 0);
+        for va in range.step_by(PAGE_SIZE) {
+            self.set_page_zone(va, zone_id);
+        }
+        x86_64::instructions::tlb::flush_all();
+    }
+
+    /// Retag leaf entries of logical zone `zone_id` with protection key `key`, return the number retagged.
+    #[cfg(feature = "zone")]
+    fn retag_zone(&mut self, zone_id: ZoneId, key: PKey) -> usize {
+        fn retag_table(table: &mut x86PageTable, level: usize, zone_id: ZoneId, key: PKey) -> usize {
+            let mut count = 0;
+            for entry in table.iter_mut() {
+                if entry.is_unused() {
+                    continue;
+                }
+                let flags = entry.flags();
+                if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+                    if flags_zone(flags) == zone_id {
+                        let pkey = PageTableFlags::from_bits_truncate(
+                            (key as u64 & PKEY_MASK) << PKEY_SHIFT,
+                        );
+                        let cleared = PageTableFlags::from_bits_truncate(
+                            flags.bits() & !(PKEY_MASK << PKEY_SHIFT),
+                        );
+                        entry.set_flags(cleared | pkey);
+                        count += 1;
+                    }
+                } else {
+                    let next = unsafe { &mut *frame_to_page_table(entry.frame().unwrap()) };
+                    count += retag_table(next, level - 1, zone_id, key);
+                }
+            }
+            count
+        }
+
+        let count = retag_table(self.page_table.level_4_table(), 4, zone_id, key);
+        // x86_64 boards run on a single core, flushing the local TLB is enough.
+        x86_64::instructions::tlb::flush_all();
+        count
+    }
+
     pub fn dump_entry(&mut self, va: usize) {
         let page_4kb = Page::<Size4KiB>::containing_address(VirtAddr::new(va as u64));
         let l4_index = page_4kb.p4_index();
@@ -374,6 +527,63 @@ impl X86_64PageTable {
     }
 }
 
+/// Convert flags of a leaf entry to `Entry`.
+fn flags_to_entry(flags: PageTableFlags, pa: usize) -> Entry {
+    let executable = !flags.contains(PageTableFlags::NO_EXECUTE);
+    #[allow(unused_mut)]
+    let mut attr = EntryAttribute::new(
+        flags.contains(PageTableFlags::WRITABLE),
+        flags.contains(PageTableFlags::USER_ACCESSIBLE),
+        flags.contains(PageTableFlags::NO_CACHE),
+        executable,
+        executable,
+        false,
+        false,
+        flags.contains(PageTableFlags::HUGE_PAGE),
+    );
+    #[cfg(feature = "zone")]
+    attr.set_zone(flags_zone(flags));
+    Entry::new(attr, pa)
+}
+
+/// Software-available bits 52-58 of leaf entries keep the logical zone of the page.
+#[cfg(feature = "zone")]
+const ZONE_ID_SHIFT: u64 = 52;
+#[cfg(feature = "zone")]
+const ZONE_ID_MASK: u64 = 0x7f;
+/// Bits 62:59 of leaf entries keep the protection key currently bound to the zone.
+#[cfg(feature = "zone")]
+const PKEY_SHIFT: u64 = 59;
+#[cfg(feature = "zone")]
+const PKEY_MASK: u64 = 0xf;
+
+/// Zone of the pages keeping the protected data, which belongs to the main thread's zone,
+/// the first one allocated.
+#[cfg(feature = "zone")]
+const PROTECTED_DATA_ZONE: ZoneId = 1;
+
+/// Get the zone bits of a leaf entry mapping pages of `zone_id`.
+#[cfg(feature = "zone")]
+fn zone_flags(zone_id: ZoneId) -> PageTableFlags {
+    PageTableFlags::from_bits_truncate(
+        ((zone_id as u64 & ZONE_ID_MASK) << ZONE_ID_SHIFT)
+            | ((zone::tag_key(zone_id) as u64 & PKEY_MASK) << PKEY_SHIFT),
+    )
+}
+
+/// Get the logical zone of a leaf entry.
+#[cfg(feature = "zone")]
+fn flags_zone(flags: PageTableFlags) -> ZoneId {
+    ((flags.bits() >> ZONE_ID_SHIFT) & ZONE_ID_MASK) as ZoneId
+}
+
+/// Retag the pages of a logical zone with the protection key bound to it, registered to zone crate.
+#[cfg(feature = "zone")]
+fn retag_zone(zone_id: ZoneId, key: PKey) {
+    let count = page_table().lock().retag_zone(zone_id, key);
+    debug!("zone {} retagged with key {}, {} entries", zone_id, key, count);
+}
+
 // Todo：remove redundant functions, not fully implemented yet!!!
 impl PageTableTrait for X86_64PageTable {
     fn base_pa(&self) -> usize {
@@ -396,20 +606,7 @@ impl PageTableTrait for X86_64PageTable {
         #[cfg(feature = "zone")]
         {
             // (the protection key located in bits 62:59 of the paging-structure entry that mapped the page containing the linear address.
-            let zone_id = attr.get_zone_id();
-
-            if zone_id & 1 == 1 {
-                flags |= PageTableFlags::BIT_59;
-            }
-            if zone_id & 2 == 2 {
-                flags |= PageTableFlags::BIT_60;
-            }
-            if zone_id & 4 == 4 {
-                flags |= PageTableFlags::BIT_61;
-            }
-            if zone_id & 8 == 8 {
-                flags |= PageTableFlags::BIT_62;
-            }
+            flags |= zone_flags(attr.get_zone_id());
         }
 
         trace!(
@@ -460,20 +657,8 @@ impl PageTableTrait for X86_64PageTable {
 
         #[cfg(feature = "zone")]
         {
-            let zone_id = attr.get_zone_id();
             // (the protection key located in bits 62:59 of the paging-structure entry that mapped the page containing the linear address.
-            if zone_id & 1 == 1 {
-                flags |= PageTableFlags::BIT_59;
-            }
-            if zone_id & 2 == 2 {
-                flags |= PageTableFlags::BIT_60;
-            }
-            if zone_id & 4 == 4 {
-                flags |= PageTableFlags::BIT_61;
-            }
-            if zone_id & 8 == 8 {
-                flags |= PageTableFlags::BIT_62;
-            }
+            flags |= zone_flags(attr.get_zone_id());
         }
 
         trace!(
@@ -485,25 +670,86 @@ impl PageTableTrait for X86_64PageTable {
 
         let page_2mb = Page::<Size2MiB>::containing_address(VirtAddr::new(va as u64));
         let frame_2mb = Frame::<Size2MiB>::containing_address(PhysAddr::new(pa as u64));
-        match unsafe {
-            self.page_table
-                .map_to(page_2mb, frame_2mb, flags, &mut FrameAllocatorForX86)
-        } {
-            Ok(mapper_flush) => {
-                mapper_flush.flush();
-            }
-            Err(err) => {
-                warn!(
-                    "x86_64 map 2MB page {:#x} failed of pa {:#x}, err {:?}",
-                    va, pa, err
-                );
-                return Err(ERROR_INTERNAL);
+        let mut res = Ok(());
+        // Page table frames are allocated with privileged zone keys, same as `map`.
+        zone::protected_function_wrapper(|| {
+            match unsafe {
+                self.page_table
+                    .map_to(page_2mb, frame_2mb, flags, &mut FrameAllocatorForX86)
+            } {
+                Ok(mapper_flush) => {
+                    mapper_flush.flush();
+                }
+                Err(err) => {
+                    warn!(
+                        "x86_64 map 2MB page {:#x} failed of pa {:#x}, err {:?}",
+                        va, pa, err
+                    );
+                    res = Err(ERROR_INTERNAL);
+                }
             }
-        }
+        });
+        res?;
         // self.dump_entry_2mb(va);
         Ok(())
     }
 
+    fn map_1gb(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        assert!(pa % MapGranularity::Page1GB as usize == 0);
+        if !attr.block() {
+            warn!("map_1gb: required block attribute");
+            return Err(ERROR_INVARG);
+        }
+        if !huge_1gb_supported() {
+            warn!("map_1gb: 1GB page is not supported by current CPU");
+            return Err(ERROR_INVARG);
+        }
+
+        let mut flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        // MPK only works for user pages.
+        flags |= PageTableFlags::USER_ACCESSIBLE;
+
+        #[cfg(feature = "zone")]
+        {
+            // (the protection key located in bits 62:59 of the paging-structure entry that mapped the page containing the linear address.
+            flags |= zone_flags(attr.get_zone_id());
+        }
+
+        trace!(
+            "page table map_1gb va 0x{:016x} pa: 0x{:016x}, flags {:?}",
+            va,
+            pa,
+            flags.clone()
+        );
+
+        let page_1gb = Page::<Size1GiB>::containing_address(VirtAddr::new(va as u64));
+        let frame_1gb = Frame::<Size1GiB>::containing_address(PhysAddr::new(pa as u64));
+        let mut res = Ok(());
+        // Page table frames are allocated with privileged zone keys, same as `map`.
+        zone::protected_function_wrapper(|| {
+            match unsafe {
+                self.page_table
+                    .map_to(page_1gb, frame_1gb, flags, &mut FrameAllocatorForX86)
+            } {
+                Ok(mapper_flush) => {
+                    mapper_flush.flush();
+                }
+                Err(err) => {
+                    warn!(
+                        "x86_64 map 1GB page {:#x} failed of pa {:#x}, err {:?}",
+                        va, pa, err
+                    );
+                    res = Err(ERROR_INTERNAL);
+                }
+            }
+        });
+        res?;
+        Ok(())
+    }
+
     fn unmap(&mut self, va: usize) {
         trace!("unmap va {:x}", va);
         zone::protected_function_wrapper(|| {
@@ -524,16 +770,34 @@ impl PageTableTrait for X86_64PageTable {
     fn unmap_2mb(&mut self, va: usize) {
         trace!("unmap_2mb va {:x}", va);
         assert!(va % MapGranularity::Page2MB as usize == 0);
-        self.page_table
-            .unmap(Page::<Size2MiB>::containing_address(VirtAddr::new(
-                va as u64,
-            )))
-            .unwrap_or_else(|e| {
-                self.dump_entry_2mb(va);
-                panic!("unmap va {:#x} error {:?}", va, e);
-            })
-            .1
-            .flush();
+        zone::protected_function_wrapper(|| {
+            self.page_table
+                .unmap(Page::<Size2MiB>::containing_address(VirtAddr::new(
+                    va as u64,
+                )))
+                .unwrap_or_else(|e| {
+                    self.dump_entry_2mb(va);
+                    panic!("unmap va {:#x} error {:?}", va, e);
+                })
+                .1
+                .flush();
+        })
+    }
+
+    fn unmap_1gb(&mut self, va: usize) {
+        trace!("unmap_1gb va {:x}", va);
+        assert!(va % MapGranularity::Page1GB as usize == 0);
+        zone::protected_function_wrapper(|| {
+            self.page_table
+                .unmap(Page::<Size1GiB>::containing_address(VirtAddr::new(
+                    va as u64,
+                )))
+                .unwrap_or_else(|e| {
+                    panic!("unmap_1gb va {:#x} error {:?}", va, e);
+                })
+                .1
+                .flush();
+        })
     }
 
     // fn insert_page(
@@ -545,11 +809,76 @@ impl PageTableTrait for X86_64PageTable {
     //     Ok(())
     // }
 
-    fn lookup_entry(&self, _va: usize) -> Option<(Entry, MapGranularity)> {
-        None
+    fn lookup_entry(&self, va: usize) -> Option<(Entry, MapGranularity)> {
+        match self.page_table.translate(VirtAddr::new(va as u64)) {
+            TranslateResult::Mapped { frame, flags, .. } => {
+                let (pa, granularity) = match frame {
+                    MappedFrame::Size4KiB(frame) => {
+                        (frame.start_address().as_u64(), MapGranularity::Page4KB)
+                    }
+                    MappedFrame::Size2MiB(frame) => {
+                        (frame.start_address().as_u64(), MapGranularity::Page2MB)
+                    }
+                    MappedFrame::Size1GiB(frame) => {
+                        (frame.start_address().as_u64(), MapGranularity::Page1GB)
+                    }
+                };
+                Some((flags_to_entry(flags, pa as usize), granularity))
+            }
+            _ => None,
+        }
+    }
+
+    fn lookup_page(&self, va: usize) -> Option<Entry> {
+        match self.lookup_entry(va) {
+            Some((entry, MapGranularity::Page4KB)) => Some(entry),
+            _ => None,
+        }
     }
 
-    fn lookup_page(&self, _va: usize) -> Option<Entry> {
-        None
+    fn for_each_entry(&self, f: &mut dyn FnMut(usize, Entry, MapGranularity)) {
+        let table_of =
+            |addr: PhysAddr| unsafe { &*frame_to_page_table(Frame::containing_address(addr)) };
+        // Virtual addresses are sign extended from bit 47.
+        let canonical = |va: usize| VirtAddr::new_truncate(va as u64).as_u64() as usize;
+        let l4_table = table_of(self.dir_frame.start_address());
+        for (l4_idx, l4_entry) in l4_table.iter().enumerate() {
+            if !l4_entry.flags().contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            let l4_va = canonical(l4_idx << 39);
+            for (l3_idx, l3_entry) in table_of(l4_entry.addr()).iter().enumerate() {
+                let flags = l3_entry.flags();
+                if !flags.contains(PageTableFlags::PRESENT) {
+                    continue;
+                }
+                let l3_va = l4_va + (l3_idx << PAGE_TABLE_L1_SHIFT);
+                if flags.contains(PageTableFlags::HUGE_PAGE) {
+                    let entry = flags_to_entry(flags, l3_entry.addr().as_u64() as usize);
+                    f(l3_va, entry, MapGranularity::Page1GB);
+                    continue;
+                }
+                for (l2_idx, l2_entry) in table_of(l3_entry.addr()).iter().enumerate() {
+                    let flags = l2_entry.flags();
+                    if !flags.contains(PageTableFlags::PRESENT) {
+                        continue;
+                    }
+                    let l2_va = l3_va + (l2_idx << PAGE_TABLE_L2_SHIFT);
+                    if flags.contains(PageTableFlags::HUGE_PAGE) {
+                        let entry = flags_to_entry(flags, l2_entry.addr().as_u64() as usize);
+                        f(l2_va, entry, MapGranularity::Page2MB);
+                        continue;
+                    }
+                    for (l1_idx, l1_entry) in table_of(l2_entry.addr()).iter().enumerate() {
+                        let flags = l1_entry.flags();
+                        if flags.contains(PageTableFlags::PRESENT) {
+                            let entry = flags_to_entry(flags, l1_entry.addr().as_u64() as usize);
+                            let va = l2_va + (l1_idx << PAGE_TABLE_L3_SHIFT);
+                            f(va, entry, MapGranularity::Page4KB);
+                        }
+                    }
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,106 @@
+use core::ops::{Index, IndexMut};
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+
+use gimli::Register;
+
+#[macro_export]
+macro_rules! registers {
+    ($struct_name:ident, { $($name:ident = ($val:expr, $disp:expr)),+ $(,)? }) => {
+        #[allow(missing_docs)]
+        impl $struct_name {
+            $(
+                pub const $name: Register = Register($val);
+            )+
+        }
+
+        impl $struct_name {
+            /// The name of a register, or `None` if the register number is unknown.
+            #[allow(dead_code)]
+            pub fn register_name(register: Register) -> Option<&'static str> {
+                match register {
+                    $(
+                        Self::$name => Some($disp),
+                    )+
+                    _ => return None,
+                }
+            }
+        }
+    };
+}
+
+/// Number of registers in the DWARF numbering of System V AMD64 ABI, up to the return address.
+const DWARF_REG_NUM: usize = 17;
+
+#[derive(Clone)]
+pub struct Registers {
+    registers: [Option<u64>; DWARF_REG_NUM],
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers {
+            registers: [None; DWARF_REG_NUM],
+        }
+    }
+}
+
+impl Debug for Registers {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        for (i, reg) in self.registers.iter().enumerate() {
+            match *reg {
+                None => {}
+                Some(r) => write!(
+                    fmt,
+                    "[{}]: {:#X}, \n",
+                    X86_64::register_name(Register(i as u16)).unwrap_or("?"),
+                    r
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Index<gimli::Register> for Registers {
+    type Output = Option<u64>;
+    fn index(&self, index: Register) -> &Self::Output {
+        &self.registers[index.0 as usize]
+    }
+}
+
+impl IndexMut<gimli::Register> for Registers {
+    fn index_mut(&mut self, index: Register) -> &mut Self::Output {
+        &mut self.registers[index.0 as usize]
+    }
+}
+
+/// DWARF register numbers of x86_64, see System V AMD64 ABI, figure 3.36.
+#[derive(Debug, Clone, Copy)]
+pub struct X86_64;
+
+registers!(X86_64, {
+    RAX = (0, "RAX"),
+    RDX = (1, "RDX"),
+    RCX = (2, "RCX"),
+    RBX = (3, "RBX"),
+    RSI = (4, "RSI"),
+    RDI = (5, "RDI"),
+    RBP = (6, "RBP"),
+    RSP = (7, "RSP"),
+    R8 = (8, "R8"),
+    R9 = (9, "R9"),
+    R10 = (10, "R10"),
+    R11 = (11, "R11"),
+    R12 = (12, "R12"),
+    R13 = (13, "R13"),
+    R14 = (14, "R14"),
+    R15 = (15, "R15"),
+    RA = (16, "RA"),
+});
+
+#[cfg(feature = "unwind")]
+pub const REG_RETURN_ADDRESS: Register = X86_64::RA;
+#[cfg(feature = "unwind")]
+pub const REG_STACK_POINTER: Register = X86_64::RSP;
+#[cfg(feature = "unwind")]
+pub const REG_ARGUMENT: Register = X86_64::RAX;
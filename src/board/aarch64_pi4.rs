@@ -18,6 +18,10 @@ pub const ELF_IMAGE_LOAD_ADDR: usize = 0x8000_0000;
 pub const GICD_BASE: usize = 0xff841000;
 pub const GICC_BASE: usize = 0xff842000;
 
+/// Refresh and control frames of the SBSA generic watchdog.
+#[cfg(feature = "watchdog")]
+pub const SBSA_GWDT_BASE: Option<(usize, usize)> = None;
+
 pub const GLOBAL_HEAP_SIZE: usize = 64 * 1024 * 1024; // 64 MB
 
 cfg_if::cfg_if! {
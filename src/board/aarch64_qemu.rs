@@ -23,6 +23,14 @@ pub const ELF_IMAGE_LOAD_ADDR: usize = 0x8000_0000;
 pub const GICD_BASE: usize = 0x08000000;
 pub const GICC_BASE: usize = 0x08010000;
 
+/// Refresh and control frames of the SBSA generic watchdog.
+///
+/// This board has no hardware watchdog: QEMU's virt machine has none, and `sbsa-ref`,
+/// which has one at (0x5001_0000, 0x5001_1000), is not supported. The watchdog service
+/// only monitors and reports the health here, nothing resets a wedged kernel.
+#[cfg(feature = "watchdog")]
+pub const SBSA_GWDT_BASE: Option<(usize, usize)> = None;
+
 pub const GLOBAL_HEAP_SIZE: usize = 64 * 1024 * 1024; // 64 MB
 
 cfg_if::cfg_if! {
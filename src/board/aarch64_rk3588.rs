@@ -14,6 +14,10 @@ pub const GICD_BASE: usize = 0xfe600000;
 
 pub const GICC_BASE: usize = 0xfe610000;
 
+/// Refresh and control frames of the SBSA generic watchdog.
+#[cfg(feature = "watchdog")]
+pub const SBSA_GWDT_BASE: Option<(usize, usize)> = None;
+
 pub const BOARD_NORMAL_MEMORY_RANGE: Range<usize> = 0x0000_0000..0xc000_0000;
 pub const BOARD_DEVICE_MEMORY_RANGE: Range<usize> = 0xc000_0000..0x1_0000_0000;
 pub const ELF_IMAGE_LOAD_ADDR: usize = 0x8000_0000;
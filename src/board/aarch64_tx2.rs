@@ -24,6 +24,10 @@ pub const GICC_BASE: usize = 0x3882000;
 #[cfg(feature = "shyper")]
 pub const GICC_BASE: usize = 0x8010000;
 
+/// Refresh and control frames of the SBSA generic watchdog.
+#[cfg(feature = "watchdog")]
+pub const SBSA_GWDT_BASE: Option<(usize, usize)> = None;
+
 #[cfg(feature = "tx2")]
 pub const BOARD_NORMAL_MEMORY_RANGE: Range<usize> = 0x8000_0000..0xf000_0000;
 #[cfg(feature = "tx2")]
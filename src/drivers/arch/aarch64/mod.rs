@@ -4,5 +4,7 @@ pub mod gic;
 pub mod psci;
 pub mod timer;
 pub mod uart;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 
 pub use gic::{Interrupt, InterruptController};
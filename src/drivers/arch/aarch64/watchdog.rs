@@ -0,0 +1,110 @@
+//! SBSA generic watchdog, with its refresh and control frames given by the board.
+//!
+//! The watchdog counts the system counter up to the compare value `WCV`,
+//! set to the counter plus the offset `WOR` by each refresh.
+//! The first expiry raises WS0 and sets `WCV` again, the second one raises WS1,
+//! which resets the system. A refresh clears WS0.
+
+use spin::Once;
+
+use crate::mm::paging::map_device_memory_range;
+
+/// Refresh frame.
+const WRR: usize = 0x000;
+
+/// Control frame.
+const WCS: usize = 0x000;
+const WOR: usize = 0x008;
+const W_IIDR: usize = 0xFCC;
+
+const WCS_EN: u32 = 1 << 0;
+const WCS_WS1: u32 = 1 << 2;
+/// Bits of `WCS` above WS1 are reserved.
+const WCS_RESERVED: u32 = !0b111;
+/// Architecture version in `W_IIDR`, 0 and 1 are defined.
+const W_IIDR_ARCH_SHIFT: u32 = 16;
+const W_IIDR_ARCH_MAX: u32 = 1;
+
+struct Gwdt {
+    refresh: usize,
+    control: usize,
+}
+
+static GWDT: Once<Gwdt> = Once::new();
+
+impl Gwdt {
+    fn read(&self, frame: usize, reg: usize) -> u32 {
+        unsafe { ((frame + reg) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, frame: usize, reg: usize, value: u32) {
+        unsafe { ((frame + reg) as *mut u32).write_volatile(value) }
+    }
+}
+
+fn gwdt() -> Result<&'static Gwdt, &'static str> {
+    GWDT.get().ok_or("SBSA generic watchdog is not probed")
+}
+
+/// Map the frames given by the board and check they belong to a generic watchdog,
+/// it's left stopped.
+///
+/// Returns whether the last reset was caused by the watchdog.
+pub fn probe() -> Result<bool, &'static str> {
+    let (refresh, control) =
+        crate::board::SBSA_GWDT_BASE.ok_or("board has no SBSA generic watchdog")?;
+    let page_size = crate::arch::PAGE_SIZE;
+    let gwdt = Gwdt {
+        refresh: map_device_memory_range(refresh, page_size).value(),
+        control: map_device_memory_range(control, page_size).value(),
+    };
+
+    let iidr = gwdt.read(gwdt.control, W_IIDR);
+    let wcs = gwdt.read(gwdt.control, WCS);
+    if iidr == 0
+        || iidr == u32::MAX
+        || (iidr >> W_IIDR_ARCH_SHIFT) & 0xF > W_IIDR_ARCH_MAX
+        || wcs & WCS_RESERVED != 0
+    {
+        return Err("no SBSA generic watchdog found at the board's frames");
+    }
+    let gwdt = GWDT.call_once(|| gwdt);
+    info!(
+        "SBSA generic watchdog found at {:#x}, iidr {:#x}",
+        control, iidr
+    );
+
+    gwdt.write(gwdt.control, WCS, 0);
+    Ok(wcs & WCS_WS1 != 0)
+}
+
+pub fn name() -> &'static str {
+    "sbsa-gwdt"
+}
+
+/// Start the watchdog, resetting the system `timeout_ms` after the last kick.
+///
+/// Returns the timeout actually set, it's limited by the 32 bits of `WOR`.
+pub fn start(timeout_ms: usize) -> Result<usize, &'static str> {
+    let gwdt = gwdt()?;
+    let freq = crate::drivers::timer::frequency();
+    // Half of the timeout for each of WS0 and WS1.
+    let offset = (timeout_ms / 2 * freq / 1000).clamp(1, u32::MAX as usize);
+    gwdt.write(gwdt.control, WOR, offset as u32);
+    gwdt.write(gwdt.refresh, WRR, 0);
+    gwdt.write(gwdt.control, WCS, WCS_EN);
+    Ok(offset * 2 * 1000 / freq)
+}
+
+/// Restart the countdown.
+pub fn kick() {
+    if let Some(gwdt) = GWDT.get() {
+        gwdt.write(gwdt.refresh, WRR, 0);
+    }
+}
+
+pub fn stop() -> Result<(), &'static str> {
+    let gwdt = gwdt()?;
+    gwdt.write(gwdt.control, WCS, 0);
+    Ok(())
+}
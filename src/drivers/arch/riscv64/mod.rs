@@ -6,5 +6,7 @@ pub mod timer;
 #[cfg_attr(not(feature = "k210"), path = "uart_ns16550.rs")]
 #[cfg_attr(feature = "k210", path = "uart_k210.rs")]
 pub mod uart;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 
 pub use plic::{Interrupt, InterruptController};
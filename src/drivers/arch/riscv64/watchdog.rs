@@ -0,0 +1,19 @@
+//! No watchdog is driven on riscv64, heartbeats and liveness checks are still monitored.
+
+pub fn probe() -> Result<bool, &'static str> {
+    Err("no hardware watchdog driver on riscv64")
+}
+
+pub fn name() -> &'static str {
+    "none"
+}
+
+pub fn start(_timeout_ms: usize) -> Result<usize, &'static str> {
+    Err("no hardware watchdog driver on riscv64")
+}
+
+pub fn kick() {}
+
+pub fn stop() -> Result<(), &'static str> {
+    Err("no hardware watchdog driver on riscv64")
+}
@@ -2,6 +2,8 @@ pub mod apic;
 pub mod rtc;
 pub mod timer;
 pub mod uart;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 mod uart_16550_port;
 
 pub use self::apic::{Interrupt, InterruptController};
@@ -0,0 +1,225 @@
+//! Intel 6300ESB watchdog timer, `-device i6300esb` on QEMU.
+//!
+//! The device is found by scanning the PCI configuration space, its registers are in BAR0.
+//! It counts down two stages of `timer` ticks, about 1ms each at the 1KHz scale,
+//! and resets the machine when the second stage expires, a reload restarts the first stage.
+//!
+//! The config and lock registers are only decoded by byte and word accesses,
+//! so the configuration space is accessed here rather than by `drivers::pci`.
+
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+use crate::mm::paging::map_device_memory_range;
+
+const PCI_CONFIG_ADDRESS_PORT: u16 = 0xCF8;
+const PCI_CONFIG_ADDRESS_ENABLE: u32 = 1 << 31;
+const PCI_CONFIG_DATA_PORT: u16 = 0xCFC;
+const PCI_MAX_BUS_NUMBER: u8 = 32;
+const PCI_MAX_DEVICE_NUMBER: u8 = 32;
+
+const PCI_ID_REGISTER: u8 = 0x00;
+const PCI_COMMAND_REGISTER: u8 = 0x04;
+const PCI_BAR0_REGISTER: u8 = 0x10;
+const PCI_COMMAND_MEMORY: u16 = 1 << 1;
+const PCI_BASE_ADDRESS_IO_SPACE: u32 = 1 << 0;
+const PCI_MEM_BASE_ADDRESS_MASK: u32 = 0xFFFF_FFF0;
+
+const ESB_VENDOR_ID: u16 = 0x8086;
+const ESB_DEVICE_ID: u16 = 0x25AB;
+
+/// Configuration space registers.
+const ESB_CONFIG_REG: u8 = 0x60;
+const ESB_LOCK_REG: u8 = 0x68;
+
+/// Memory mapped registers.
+const ESB_TIMER1_REG: usize = 0x00;
+const ESB_TIMER2_REG: usize = 0x04;
+const ESB_RELOAD_REG: usize = 0x0C;
+
+/// Config: 1KHz scale, no interrupt on stage one, reboot enabled.
+const ESB_CONFIG_1KHZ_NO_INT: u16 = 0x0003;
+const ESB_WDT_ENABLE: u8 = 1 << 1;
+const ESB_WDT_LOCK: u8 = 1 << 0;
+const ESB_WDT_TIMEOUT: u16 = 1 << 9;
+const ESB_WDT_RELOAD: u16 = 1 << 8;
+const ESB_UNLOCK1: u16 = 0x80;
+const ESB_UNLOCK2: u16 = 0x86;
+
+/// Stage ticks are 20 bits, 512 of them are about half a second.
+const ESB_TICKS_MAX: usize = 0xF_FFFF;
+
+struct Esb {
+    bus: u8,
+    device: u8,
+    base: usize,
+}
+
+static ESB: Once<Esb> = Once::new();
+
+fn config_address(bus: u8, device: u8, register: u8) -> u32 {
+    PCI_CONFIG_ADDRESS_ENABLE
+        | u32::from(bus) << 16
+        | u32::from(device) << 11
+        | u32::from(register & 0xFC)
+}
+
+fn read_config(bus: u8, device: u8, register: u8) -> u32 {
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS_PORT).write(config_address(bus, device, register));
+        Port::<u32>::new(PCI_CONFIG_DATA_PORT).read()
+    }
+}
+
+fn read_config_u8(bus: u8, device: u8, register: u8) -> u8 {
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS_PORT).write(config_address(bus, device, register));
+        Port::<u8>::new(PCI_CONFIG_DATA_PORT + u16::from(register & 3)).read()
+    }
+}
+
+fn write_config_u8(bus: u8, device: u8, register: u8, value: u8) {
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS_PORT).write(config_address(bus, device, register));
+        Port::<u8>::new(PCI_CONFIG_DATA_PORT + u16::from(register & 3)).write(value)
+    }
+}
+
+fn read_config_u16(bus: u8, device: u8, register: u8) -> u16 {
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS_PORT).write(config_address(bus, device, register));
+        Port::<u16>::new(PCI_CONFIG_DATA_PORT + u16::from(register & 2)).read()
+    }
+}
+
+fn write_config_u16(bus: u8, device: u8, register: u8, value: u16) {
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS_PORT).write(config_address(bus, device, register));
+        Port::<u16>::new(PCI_CONFIG_DATA_PORT + u16::from(register & 2)).write(value)
+    }
+}
+
+impl Esb {
+    fn read16(&self, reg: usize) -> u16 {
+        unsafe { ((self.base + reg) as *const u16).read_volatile() }
+    }
+
+    fn write16(&self, reg: usize, value: u16) {
+        unsafe { ((self.base + reg) as *mut u16).write_volatile(value) }
+    }
+
+    fn write32(&self, reg: usize, value: u32) {
+        unsafe { ((self.base + reg) as *mut u32).write_volatile(value) }
+    }
+
+    /// Each write to the timer or reload registers must follow the unlock sequence.
+    fn unlock(&self) {
+        self.write16(ESB_RELOAD_REG, ESB_UNLOCK1);
+        self.write16(ESB_RELOAD_REG, ESB_UNLOCK2);
+    }
+
+    fn reload(&self) {
+        self.unlock();
+        self.write16(ESB_RELOAD_REG, ESB_WDT_RELOAD);
+    }
+
+    fn set_lock(&self, value: u8) {
+        write_config_u8(self.bus, self.device, ESB_LOCK_REG, value);
+    }
+
+    fn lock(&self) -> u8 {
+        read_config_u8(self.bus, self.device, ESB_LOCK_REG)
+    }
+}
+
+fn find() -> Option<(u8, u8)> {
+    for bus in 0..PCI_MAX_BUS_NUMBER {
+        for device in 0..PCI_MAX_DEVICE_NUMBER {
+            let id = read_config(bus, device, PCI_ID_REGISTER);
+            if id as u16 == ESB_VENDOR_ID && (id >> 16) as u16 == ESB_DEVICE_ID {
+                return Some((bus, device));
+            }
+        }
+    }
+    None
+}
+
+fn esb() -> Result<&'static Esb, &'static str> {
+    ESB.get().ok_or("i6300esb is not probed")
+}
+
+/// Find the watchdog and map its registers, it's left stopped.
+///
+/// Returns whether the last reset was caused by the watchdog.
+pub fn probe() -> Result<bool, &'static str> {
+    let (bus, device) = find().ok_or("no i6300esb found on PCI bus")?;
+    let bar = read_config(bus, device, PCI_BAR0_REGISTER);
+    if bar & PCI_BASE_ADDRESS_IO_SPACE != 0 || bar & PCI_MEM_BASE_ADDRESS_MASK == 0 {
+        return Err("i6300esb BAR0 is not a memory BAR");
+    }
+    let command = read_config_u16(bus, device, PCI_COMMAND_REGISTER);
+    write_config_u16(
+        bus,
+        device,
+        PCI_COMMAND_REGISTER,
+        command | PCI_COMMAND_MEMORY,
+    );
+
+    let addr = (bar & PCI_MEM_BASE_ADDRESS_MASK) as usize;
+    let page = addr & !(crate::arch::PAGE_SIZE - 1);
+    let base = map_device_memory_range(page, crate::arch::PAGE_SIZE).value() + addr - page;
+    let esb = ESB.call_once(|| Esb { bus, device, base });
+    info!(
+        "i6300esb found at {:02X}:{:02X}, registers at {:#x}",
+        bus, device, addr
+    );
+
+    write_config_u16(bus, device, ESB_CONFIG_REG, ESB_CONFIG_1KHZ_NO_INT);
+    if esb.lock() & ESB_WDT_LOCK != 0 {
+        return Err("i6300esb is locked, it can not be configured");
+    }
+    esb.set_lock(0);
+
+    esb.unlock();
+    let timed_out = esb.read16(ESB_RELOAD_REG) & ESB_WDT_TIMEOUT != 0;
+    esb.unlock();
+    esb.write16(ESB_RELOAD_REG, ESB_WDT_TIMEOUT | ESB_WDT_RELOAD);
+    Ok(timed_out)
+}
+
+pub fn name() -> &'static str {
+    "i6300esb"
+}
+
+/// Start the watchdog, resetting the machine `timeout_ms` after the last kick.
+///
+/// Returns the timeout actually set, it's rounded to the device's precision.
+pub fn start(timeout_ms: usize) -> Result<usize, &'static str> {
+    let esb = esb()?;
+    // Half of the timeout for each stage.
+    let ticks = (timeout_ms * 512 / 1000).clamp(1, ESB_TICKS_MAX);
+    esb.unlock();
+    esb.write32(ESB_TIMER1_REG, ticks as u32);
+    esb.unlock();
+    esb.write32(ESB_TIMER2_REG, ticks as u32);
+    esb.reload();
+    esb.set_lock(ESB_WDT_ENABLE);
+    Ok(ticks * 1000 / 512)
+}
+
+/// Restart the countdown.
+pub fn kick() {
+    if let Some(esb) = ESB.get() {
+        esb.reload();
+    }
+}
+
+pub fn stop() -> Result<(), &'static str> {
+    let esb = esb()?;
+    esb.unlock();
+    esb.set_lock(0);
+    if esb.lock() & ESB_WDT_ENABLE != 0 {
+        return Err("i6300esb can not be stopped");
+    }
+    Ok(())
+}
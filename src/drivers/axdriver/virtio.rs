@@ -1,7 +1,9 @@
 use core::marker::PhantomData;
 use core::ptr::NonNull;
 
-use crate::{libs::traits::Address, mm::address::VAddr};
+use crate::libs::traits::Address;
+use crate::mm::address::{PAddr, VAddr};
+use crate::mm::dma::DmaDirection;
 // use axalloc::global_allocator;
 // use axhal::mem::{phys_to_virt, virt_to_phys};
 use cfg_if::cfg_if;
@@ -145,20 +147,10 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
     fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
         debug!("dma_alloc pages {} dir {:?}", pages, _direction);
 
-        let vaddr = if let Some(vaddr) = crate::mm::kallocate(pages * crate::arch::PAGE_SIZE) {
-            vaddr.value()
-        } else {
-            return (0, NonNull::dangling());
-        };
-        // let vaddr = if let Ok(vaddr) = global_allocator().alloc_pages(pages, 0x1000) {
-        //     vaddr
-        // } else {
-        //     return (0, NonNull::dangling());
-        // };
-        // let paddr = virt_to_phys(vaddr.into());
-        let paddr = vaddr.kva2pa();
-        let ptr = NonNull::new(vaddr as _).unwrap();
-        (paddr, ptr)
+        match crate::mm::dma::dma_alloc_pages(pages) {
+            Some((paddr, vaddr)) => (paddr.value(), NonNull::new(vaddr.as_mut_ptr()).unwrap()),
+            None => (0, NonNull::dangling()),
+        }
     }
 
     unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, _pages: usize) -> i32 {
@@ -169,8 +161,7 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
             _pages
         );
 
-        crate::mm::deallocate(VAddr::new_canonical(vaddr.as_ptr() as usize));
-        // global_allocator().dealloc_pages(vaddr.as_ptr() as usize, pages);
+        crate::mm::dma::dma_free_pages(VAddr::new_canonical(vaddr.as_ptr() as usize));
         0
     }
 
@@ -180,12 +171,30 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
     }
 
     #[inline]
-    unsafe fn share(buffer: NonNull<[u8]>, _direction: BufferDirection) -> PhysAddr {
-        let vaddr = buffer.as_ptr() as *mut u8 as usize;
-        // virt_to_phys(vaddr.into()).into()
-        vaddr.kva2pa().into()
+    unsafe fn share(buffer: NonNull<[u8]>, direction: BufferDirection) -> PhysAddr {
+        let vaddr = VAddr::new_canonical(buffer.as_ptr() as *mut u8 as usize);
+        match crate::mm::dma::share(vaddr, buffer.len(), dma_direction(direction)) {
+            Ok(paddr) => paddr.value(),
+            Err(e) => panic!("virtio share buffer {}: {}", vaddr, e),
+        }
     }
 
     #[inline]
-    unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
+    unsafe fn unshare(paddr: PhysAddr, buffer: NonNull<[u8]>, direction: BufferDirection) {
+        let vaddr = VAddr::new_canonical(buffer.as_ptr() as *mut u8 as usize);
+        crate::mm::dma::unshare(
+            PAddr::new_canonical(paddr),
+            vaddr,
+            buffer.len(),
+            dma_direction(direction),
+        );
+    }
+}
+
+fn dma_direction(direction: BufferDirection) -> DmaDirection {
+    match direction {
+        BufferDirection::DriverToDevice => DmaDirection::ToDevice,
+        BufferDirection::DeviceToDriver => DmaDirection::FromDevice,
+        BufferDirection::Both => DmaDirection::Bidirectional,
+    }
 }
@@ -1701,7 +1701,7 @@ impl Drop for MemDescr {
         match self.dealloc {
             Dealloc::Not => (),
             Dealloc::AsSlice => unsafe { drop(Vec::from_raw_parts(self.ptr, self._mem_len, 0)) },
-            Dealloc::AsPage => crate::mm::deallocate(VAddr::from(self.ptr as usize)),
+            Dealloc::AsPage => crate::mm::dma::dma_free_pages(VAddr::from(self.ptr as usize)),
         }
     }
 }
@@ -1894,9 +1894,12 @@ impl MemPool {
 
         let len = bytes.0;
 
-        // Allocate heap memory via a vec, leak and cast
+        // Allocate physically contiguous DMA memory
         let _mem_len = align_up!(len, PAGE_SIZE);
-        let ptr = (crate::mm::kallocate(_mem_len).unwrap().value() as *const u8) as *mut u8;
+        let ptr = crate::mm::dma::dma_alloc_pages(_mem_len / PAGE_SIZE)
+            .unwrap()
+            .1
+            .as_mut_ptr::<u8>();
 
         // Assert descriptor does not cross a page barrier
         let start_virt = ptr as usize;
@@ -1930,9 +1933,12 @@ impl MemPool {
         // debug!("pull_untracked");
         let len = bytes.0;
 
-        // Allocate heap memory via a vec, leak and cast
+        // Allocate physically contiguous DMA memory
         let _mem_len = align_up!(len, PAGE_SIZE);
-        let ptr = (crate::mm::kallocate(_mem_len).unwrap().value() as *const u8) as *mut u8;
+        let ptr = crate::mm::dma::dma_alloc_pages(_mem_len / PAGE_SIZE)
+            .unwrap()
+            .1
+            .as_mut_ptr::<u8>();
 
         // Assert descriptor does not cross a page barrier
         let start_virt = ptr as usize;
@@ -372,22 +372,30 @@ impl SplitVq {
 
         let size = vq_handler.set_vq_size(size.0);
 
-        // Allocate heap memory via a vec, leak and cast
+        // Allocate physically contiguous DMA memory
         let _mem_len = align_up!(
             size as usize * core::mem::size_of::<Descriptor>(),
             PAGE_SIZE
         );
-        let table_raw = (crate::mm::kallocate(_mem_len).unwrap().value() as *const Descriptor)
-            as *mut Descriptor;
+        let table_raw = crate::mm::dma::dma_alloc_pages(_mem_len / PAGE_SIZE)
+            .unwrap()
+            .1
+            .as_mut_ptr::<Descriptor>();
 
         let descr_table = DescrTable {
             raw: unsafe { core::slice::from_raw_parts_mut(table_raw, size as usize) },
         };
 
         let _mem_len = align_up!(6 + (size as usize * 2), PAGE_SIZE);
-        let avail_raw = (crate::mm::kallocate(_mem_len).unwrap().value() as *const u8) as *mut u8;
+        let avail_raw = crate::mm::dma::dma_alloc_pages(_mem_len / PAGE_SIZE)
+            .unwrap()
+            .1
+            .as_mut_ptr::<u8>();
         let _mem_len = align_up!(6 + (size as usize * 8), PAGE_SIZE);
-        let used_raw = (crate::mm::kallocate(_mem_len).unwrap().value() as *const u8) as *mut u8;
+        let used_raw = crate::mm::dma::dma_alloc_pages(_mem_len / PAGE_SIZE)
+            .unwrap()
+            .1
+            .as_mut_ptr::<u8>();
 
         let avail_ring = unsafe {
             AvailRing {
@@ -0,0 +1,43 @@
+//! Checkpoints of current thread to roll back to, enabled by feature "checkpoint".
+//!
+//! Set the thread's panic policy to `PanicPolicy::Rollback` to roll it back to its
+//! checkpoint on panics and exceptions, instead of unwinding it.
+
+use super::io;
+use crate::libs::checkpoint;
+use crate::libs::error::ShyperError;
+
+pub use crate::libs::checkpoint::{Checkpointed, ROLLBACK_MAX};
+
+/// Take a checkpoint of current thread's registers, stack and memory regions,
+/// replacing its previous one.
+///
+/// Returns `Taken` at once, and `RolledBack` each time the thread is rolled back to it.
+///
+/// # Safety
+///
+/// Like `setjmp`, this returns twice. Values owned by frames live at the checkpoint
+/// must not be dropped, moved out or freed until the checkpoint is replaced or discarded.
+pub unsafe fn checkpoint() -> io::Result<Checkpointed> {
+    checkpoint::checkpoint().map_err(|e| {
+        warn!("checkpoint: {}", e);
+        ShyperError::NoMemory
+    })
+}
+
+/// Roll current thread back to its checkpoint, returns only if it fails.
+pub fn rollback() -> io::Result<!> {
+    checkpoint::rollback().map_err(|e| {
+        warn!("rollback: {}", e);
+        ShyperError::BadState
+    })
+}
+
+/// Discard current thread's checkpoint.
+pub fn discard() -> io::Result<()> {
+    if checkpoint::discard() {
+        Ok(())
+    } else {
+        Err(ShyperError::NotFound)
+    }
+}
@@ -22,7 +22,43 @@ pub fn allocate_zone(size: usize) -> VAddr {
     }
 }
 
+/// Allocate memory mapped by huge pages (2MB, or 1GB if size allows),
+/// `size` is rounded up to 2MB.
+#[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+pub fn allocate_huge(size: usize) -> VAddr {
+    match crate::mm::allocate_huge_pages(size, false) {
+        Some(addr) => addr,
+        None => {
+            error!("failed to allocate huge memory of size {}", size);
+            VAddr::zero()
+        }
+    }
+}
+
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
 pub fn deallocate(address: VAddr) {
     crate::mm::deallocate(address);
 }
+
+/// Allocator of a zone's heap arena, e.g. `Box::new_in(v, ZoneAllocator::shared())`.
+#[cfg(feature = "zone")]
+pub use crate::mm::zone_heap::ZoneAllocator;
+
+#[cfg(feature = "quota")]
+pub use crate::mm::quota::QuotaKind;
+
+/// Limit the memory of current thread, `None` means unlimited.
+#[cfg(feature = "quota")]
+pub fn set_quota(kind: QuotaKind, limit: Option<usize>) {
+    if let Ok(t) = crate::libs::thread::current_thread() {
+        t.quota().set_limit(kind, limit);
+    }
+}
+
+/// Limit the memory of current thread's zone, `None` means unlimited.
+#[cfg(all(feature = "quota", feature = "zone"))]
+pub fn set_zone_quota(kind: QuotaKind, limit: Option<usize>) {
+    if let Ok(t) = crate::libs::thread::current_thread() {
+        let _ = crate::mm::quota::set_zone_limit(t.zone_id(), kind, limit);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod io;
+pub mod panic;
 pub mod thread;
 
 #[cfg(feature = "net")]
@@ -13,4 +14,13 @@ pub mod fs;
 
 pub mod mm;
 
+#[cfg(feature = "zone")]
+pub mod zone;
+
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
+
 pub mod time;
@@ -0,0 +1,22 @@
+//! Panic hooks and policies, see `thread::Builder::panic_policy` for per-thread ones.
+
+use alloc::boxed::Box;
+
+pub use crate::panic::{PanicHook, PanicPolicy};
+pub use crate::panic::{abort, default_panic_policy, set_default_panic_policy};
+pub use crate::panic::{set_panic_hook, take_panic_hook};
+
+/// Set the hook called when current thread panics, before the global one.
+pub fn set_thread_panic_hook(hook: Box<PanicHook>) {
+    super::thread::current().set_panic_hook(Some(hook));
+}
+
+/// Unregister current thread's hook.
+pub fn clear_thread_panic_hook() {
+    super::thread::current().set_panic_hook(None);
+}
+
+/// Set the panic policy of current thread, None to follow the default one.
+pub fn set_thread_panic_policy(policy: Option<PanicPolicy>) {
+    super::thread::current().set_panic_policy(policy);
+}
@@ -2,17 +2,41 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::boxed::Box;
 use core::cell::UnsafeCell;
+use core::fmt;
 
 use super::io;
+use super::panic::{PanicHook, PanicPolicy};
 use crate::libs::error::ShyperError;
 use crate::libs::thread as imp;
 
-#[derive(Debug)]
+#[cfg(feature = "zone")]
+pub use crate::libs::thread::ZoneSpec;
+
 pub struct Builder {
     // A name for the thread-to-be, for identification in panic messages
     name: Option<String>,
     // The size of the stack for the spawned thread in bytes
     stack_size: Option<usize>,
+    // The zone to spawn the thread into, inherited from the spawner if None
+    #[cfg(feature = "zone")]
+    zone: Option<ZoneSpec>,
+    // The panic policy of the thread, the default one if None
+    panic_policy: Option<PanicPolicy>,
+    // The hook called when the thread panics
+    panic_hook: Option<Box<PanicHook>>,
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Builder");
+        d.field("name", &self.name)
+            .field("stack_size", &self.stack_size);
+        #[cfg(feature = "zone")]
+        d.field("zone", &self.zone);
+        d.field("panic_policy", &self.panic_policy)
+            .field("panic_hook", &self.panic_hook.is_some())
+            .finish()
+    }
 }
 
 impl Builder {
@@ -20,6 +44,10 @@ impl Builder {
         Builder {
             name: None,
             stack_size: None,
+            #[cfg(feature = "zone")]
+            zone: None,
+            panic_policy: None,
+            panic_hook: None,
         }
     }
 
@@ -33,6 +61,28 @@ impl Builder {
         self
     }
 
+    /// Spawn the thread into the given zone rather than the spawner's one.
+    #[cfg(feature = "zone")]
+    pub fn zone(mut self, zone: ZoneSpec) -> Builder {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// Set what a panic of the thread does, e.g. `Abort` for critical threads
+    /// and `UnwindRestart` for workers that can recover.
+    ///
+    /// Threads spawned by `spawn` can't restart, use `spawn_restartable` for `UnwindRestart`.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Builder {
+        self.panic_policy = Some(policy);
+        self
+    }
+
+    /// Set the hook called when the thread panics, before the global one.
+    pub fn panic_hook(mut self, hook: Box<PanicHook>) -> Builder {
+        self.panic_hook = Some(hook);
+        self
+    }
+
     pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
     where
         F: FnOnce() -> T,
@@ -48,7 +98,51 @@ impl Builder {
         F: Send + 'static,
         T: Send + 'static,
     {
-        let Builder { name, stack_size } = self;
+        // A closure run once can't be restarted.
+        if self.panic_policy == Some(PanicPolicy::UnwindRestart) {
+            return Err(ShyperError::InvalidInput);
+        }
+        self.spawn_inner(move || Some(f()))
+    }
+
+    /// Spawn a thread running `f`, which runs again after a panic
+    /// if the thread's panic policy is `UnwindRestart`.
+    ///
+    /// Joining the thread fails if `f` panicked and was not restarted.
+    pub fn spawn_restartable<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: Fn() -> T,
+        F: Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        #[cfg(feature = "unwind")]
+        let main = move || imp::run_catching(&f);
+        #[cfg(not(feature = "unwind"))]
+        let main = move || Some(f());
+        unsafe { self.spawn_inner(main) }
+    }
+
+    unsafe fn spawn_inner<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> Option<T>,
+        F: Send + 'static,
+        T: Send + 'static,
+    {
+        #[cfg(feature = "zone")]
+        let Builder {
+            name,
+            stack_size,
+            zone,
+            panic_policy,
+            panic_hook,
+        } = self;
+        #[cfg(not(feature = "zone"))]
+        let Builder {
+            name,
+            stack_size,
+            panic_policy,
+            panic_hook,
+        } = self;
 
         let stack_size = stack_size.unwrap_or(crate::mm::config::STACK_SIZE);
 
@@ -60,6 +154,14 @@ impl Builder {
         let their_packet = my_packet.clone();
 
         let main = move || {
+            // Set by the thread itself before it runs `f`, so they cover all its panics.
+            if panic_policy.is_some() {
+                current().set_panic_policy(panic_policy);
+            }
+            if panic_hook.is_some() {
+                current().set_panic_hook(panic_hook);
+            }
+
             let try_result = f();
 
             // SAFETY: `their_packet` as been built just above and moved by the
@@ -68,25 +170,33 @@ impl Builder {
             // safe (not modify it and affect a value far away).
             // unsafe { *their_packet.get() = Some(try_result) };
             // drop(their_packet);
-            unsafe { *their_packet.result.get() = Some(try_result) };
+            unsafe { *their_packet.result.get() = try_result };
             drop(their_packet);
         };
 
+        // SAFETY:
+        //
+        // `imp::Thread::new` takes a closure with a `'static` lifetime, since it's passed
+        // through FFI or otherwise used with low-level threading primitives that have no
+        // notion of or way to enforce lifetimes.
+        //
+        // As mentioned in the `Safety` section of this function's documentation, the caller of
+        // this function needs to guarantee that the passed-in lifetime is sufficiently long
+        // for the lifetime of the thread.
+        //
+        // Similarly, the `sys` implementation must guarantee that no references to the closure
+        // exist after the thread has terminated, which is signaled by `Thread::join`
+        // returning.
+        #[cfg(feature = "zone")]
+        let native = match zone {
+            Some(zone) => imp::spawn_raw_in(Box::new(main), name, stack_size, zone)?,
+            None => imp::spawn_raw(Box::new(main), name, stack_size)?,
+        };
+        #[cfg(not(feature = "zone"))]
+        let native = imp::spawn_raw(Box::new(main), name, stack_size)?;
+
         Ok(JoinHandle(JoinInner {
-            // SAFETY:
-            //
-            // `imp::Thread::new` takes a closure with a `'static` lifetime, since it's passed
-            // through FFI or otherwise used with low-level threading primitives that have no
-            // notion of or way to enforce lifetimes.
-            //
-            // As mentioned in the `Safety` section of this function's documentation, the caller of
-            // this function needs to guarantee that the passed-in lifetime is sufficiently long
-            // for the lifetime of the thread.
-            //
-            // Similarly, the `sys` implementation must guarantee that no references to the closure
-            // exist after the thread has terminated, which is signaled by `Thread::join`
-            // returning.
-            native: Some(imp::spawn_raw(Box::new(main), name, stack_size)),
+            native: Some(native),
             packet: my_packet,
         }))
     }
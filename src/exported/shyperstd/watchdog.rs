@@ -0,0 +1,49 @@
+//! Liveness checks of the watchdog service, enabled by feature "watchdog".
+//!
+//! The kernel kicks the hardware watchdog only while every core schedules and every
+//! registered check passes, so an app wedged in a way the scheduler can't see
+//! gets the board reset once the watchdog timeout expires.
+
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use super::io;
+use crate::libs::error::ShyperError;
+use crate::libs::watchdog;
+
+pub use crate::libs::watchdog::{CheckId, Status, Unhealthy};
+
+/// Register `check`, called periodically by the watchdog service, which stops kicking
+/// the watchdog while it returns false. It must not block.
+pub fn register_liveness_check<F>(name: &str, check: F) -> CheckId
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    watchdog::register_check(name, Arc::new(check))
+}
+
+/// Unregister a liveness check, e.g. before its thread exits normally.
+pub fn unregister_liveness_check(id: CheckId) -> io::Result<()> {
+    if watchdog::unregister_check(id) {
+        Ok(())
+    } else {
+        Err(ShyperError::NotFound)
+    }
+}
+
+/// Restart the hardware watchdog with a new timeout, returns the timeout actually set.
+pub fn set_timeout(timeout: Duration) -> io::Result<Duration> {
+    watchdog::set_timeout(timeout.as_millis() as usize)
+        .map(|ms| Duration::from_millis(ms as u64))
+        .map_err(|e| {
+            warn!("watchdog set_timeout: {}", e);
+            match watchdog::status().device {
+                Some(_) => ShyperError::InvalidInput,
+                None => ShyperError::Unsupported,
+            }
+        })
+}
+
+pub fn status() -> Status {
+    watchdog::status()
+}
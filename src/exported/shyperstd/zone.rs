@@ -0,0 +1,132 @@
+//! Zone isolation, enabled by feature "zone".
+//!
+//! Threads are spawned into zones by `thread::Builder::zone`, zones allocated here
+//! live until `free` is called and their last thread exits.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::io;
+use super::thread::ThreadId;
+use crate::libs::error::ShyperError;
+
+pub use ::zone::{ZoneId, ZoneKeys, ZONE_ID_SHARED};
+use ::zone::ZONE_NUM;
+
+pub use crate::libs::thread::ZoneSpec;
+
+/// Used by `#[zone_data]`.
+#[doc(hidden)]
+pub use ::zone::ZoneDataDesc;
+
+/// Used by `#[zone_entry]`, entry points are only called through the functions it declares.
+#[doc(hidden)]
+pub use ::zone::ZoneEntryDesc;
+#[doc(hidden)]
+pub use crate::libs::zone_gate::zone_call;
+
+pub use crate::libs::violation::{
+    reset_violation_counts, set_violation_policy, violation_count, violation_counts,
+    violation_policy, ViolationPolicy,
+};
+
+/// A zone with its threads and grants, see `list`.
+#[derive(Debug, Clone)]
+pub struct ZoneInfo {
+    pub id: ZoneId,
+    /// Name of the zone's data section, see `#[zone_data]`.
+    pub name: Option<&'static str>,
+    /// Threads running in the zone.
+    pub threads: Vec<ThreadId>,
+    /// Zones it's granted access to, and whether it may write them.
+    pub grants: Vec<(ZoneId, bool)>,
+    /// Keys it runs with, PKRU on x86_64.
+    pub keys: ZoneKeys,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_HELD: AtomicBool = AtomicBool::new(false);
+/// Zones whose reference taken by `alloc` is not dropped by `free` yet.
+static HELD: [AtomicBool; ZONE_NUM] = [NOT_HELD; ZONE_NUM];
+
+/// Allocate a new zone, threads can be spawned into it by `ZoneSpec::Zone`.
+pub fn alloc() -> io::Result<ZoneId> {
+    let _guard = ::zone::PrivilegeGuard::enter();
+    let zone_id = crate::libs::thread::zone_alloc().map_err(|e| {
+        warn!("zone alloc: {}", e);
+        ShyperError::NoMemory
+    })?;
+    HELD[zone_id].store(true, Ordering::Release);
+    Ok(zone_id)
+}
+
+/// Drop the reference taken by `alloc`, the zone is freed once its last thread exits.
+///
+/// Fails if the zone is not allocated by `alloc` or it's already freed.
+pub fn free(zone_id: ZoneId) -> io::Result<()> {
+    let _guard = ::zone::PrivilegeGuard::enter();
+    if zone_id >= ZONE_NUM || !HELD[zone_id].swap(false, Ordering::AcqRel) {
+        return Err(ShyperError::InvalidInput);
+    }
+    crate::libs::thread::zone_put(zone_id);
+    Ok(())
+}
+
+/// Get the zone owning the statics declared by `#[zone_data("name")]`.
+pub fn named(name: &str) -> Option<ZoneId> {
+    crate::mm::zone_data::named_zone(name)
+}
+
+/// Get the zone current thread runs in.
+pub fn current() -> ZoneId {
+    ::zone::current_zone()
+}
+
+/// Grant `zone_id` access to pages of `target`, read-only unless `write`.
+///
+/// Threads of the zone get the access the next time they enter it, e.g. when they're scheduled.
+/// Only supported on x86_64, where a zone can be granted up to `MAX_GRANTS` zones.
+pub fn grant(zone_id: ZoneId, target: ZoneId, write: bool) -> io::Result<()> {
+    let _guard = ::zone::PrivilegeGuard::enter();
+    ::zone::zone_grant(zone_id, target, write).map_err(|e| {
+        warn!("zone grant {} to {}: {}", target, zone_id, e);
+        if cfg!(target_arch = "x86_64") {
+            ShyperError::InvalidInput
+        } else {
+            ShyperError::Unsupported
+        }
+    })
+}
+
+/// Revoke the access of `zone_id` to `target`.
+pub fn revoke(zone_id: ZoneId, target: ZoneId) -> io::Result<()> {
+    let _guard = ::zone::PrivilegeGuard::enter();
+    ::zone::zone_revoke(zone_id, target).map_err(|e| {
+        warn!("zone revoke {} from {}: {}", target, zone_id, e);
+        ShyperError::InvalidInput
+    })
+}
+
+/// List allocated zones besides the privileged and shared ones.
+pub fn list() -> Vec<ZoneInfo> {
+    let _guard = ::zone::PrivilegeGuard::enter();
+    let named_zones = crate::mm::zone_data::named_zones();
+    let mut zones = ::zone::zone_users()
+        .map(|(id, _)| ZoneInfo {
+            id,
+            name: named_zones
+                .iter()
+                .find(|(_, zone_id)| *zone_id == id)
+                .map(|(name, _)| *name),
+            threads: Vec::new(),
+            grants: ::zone::zone_grants(id).collect(),
+            keys: ZoneKeys::from(id),
+        })
+        .collect::<Vec<_>>();
+    crate::libs::thread::for_each_thread(|t| {
+        if let Some(zone) = zones.iter_mut().find(|zone| zone.id == t.zone_id()) {
+            zone.threads.push(t.id());
+        }
+    });
+    zones
+}
@@ -137,6 +137,8 @@ pub extern "C" fn loader_main(core_id: usize) {
         mm::init();
         arch::Arch::page_table_init();
         debug!("page table init ok");
+        #[cfg(all(feature = "zone", not(target_arch = "x86_64")))]
+        mm::zone_view::init();
 
         #[cfg(feature = "smp")]
         board::launch_other_cores();
@@ -156,6 +158,8 @@ pub extern "C" fn loader_main(core_id: usize) {
 
         // #[cfg(feature = "zone")]
         zone::zone_init();
+        #[cfg(feature = "zone")]
+        mm::zone_data::init();
 
         info!("board init ok");
 
@@ -182,11 +186,15 @@ pub extern "C" fn loader_main(core_id: usize) {
             }
             start = runtime_entry as usize;
             first_arg = 0;
+            panic::init();
         }
 
         #[cfg(feature = "terminal")]
         libs::terminal::init();
 
+        #[cfg(feature = "watchdog")]
+        libs::watchdog::init();
+
         crate::libs::thread::init_main_thread(core_id, (start, first_arg));
     } else {
         crate::libs::thread::init_secondary_thread(core_id);
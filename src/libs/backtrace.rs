@@ -0,0 +1,105 @@
+//! Lightweight backtrace by walking frame pointers.
+//!
+//! The kernel is built with `-C force-frame-pointers=yes` (see scripts/build.mk),
+//! so the return addresses can be collected without the `unwind` feature.
+//!
+//! Frame record layouts:
+//! * aarch64: `fp -> [prev fp, lr]`
+//! * x86_64:  `rbp -> [prev rbp, return address]`
+//! * riscv64: `fp(s0) -> [.., prev fp, ra]`, which are stored at `fp - 16` and `fp - 8`.
+
+use crate::arch::MACHINE_SIZE;
+use crate::mm::config::STACK_SIZE;
+
+/// Frame pointer of the caller of this function.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, x29", out(reg) fp);
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rbp", out(reg) fp);
+        #[cfg(target_arch = "riscv64")]
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+    }
+    fp
+}
+
+/// Get the (previous frame pointer, return address) of the frame record at `fp`.
+#[inline(always)]
+unsafe fn frame_record(fp: usize) -> (usize, usize) {
+    #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+    let record = fp as *const usize;
+    #[cfg(target_arch = "riscv64")]
+    let record = (fp - 2 * MACHINE_SIZE) as *const usize;
+    (record.read_volatile(), record.add(1).read_volatile())
+}
+
+/// Walk the frame pointer chain starting at `fp`,
+/// fill `frames` with return addresses and return the number of valid entries.
+///
+/// The walk stops on a null, unaligned or non-increasing frame pointer,
+/// or if the next frame is out of range of a thread stack.
+pub fn walk_frame_pointers(fp: usize, skip: usize, frames: &mut [usize]) -> usize {
+    let mut fp = fp;
+    let mut depth = 0;
+    let mut skipped = 0;
+    while depth < frames.len() {
+        if fp == 0 || fp % MACHINE_SIZE != 0 {
+            break;
+        }
+        let (prev_fp, return_address) = unsafe { frame_record(fp) };
+        if return_address == 0 {
+            break;
+        }
+        if skipped < skip {
+            skipped += 1;
+        } else {
+            frames[depth] = return_address;
+            depth += 1;
+        }
+        if prev_fp <= fp || prev_fp - fp > STACK_SIZE {
+            break;
+        }
+        fp = prev_fp;
+    }
+    depth
+}
+
+/// Collect the return addresses of current call stack, skipping `skip` innermost frames.
+#[inline(always)]
+pub fn capture(skip: usize, frames: &mut [usize]) -> usize {
+    walk_frame_pointers(frame_pointer(), skip, frames)
+}
+
+/// Frames printed by `print_backtrace` and `print_backtrace_from`.
+const PRINT_DEPTH: usize = 32;
+
+/// Print frames as `#n addr symbol+off`, symbols are resolved from the embedded table, see `ksyms`.
+pub fn print_frames(frames: &[usize]) {
+    for (i, addr) in frames.iter().enumerate() {
+        match crate::libs::ksyms::lookup(*addr) {
+            Some((symbol, offset)) => println!("#{:<2} {:#018x} {}+{:#x}", i, addr, symbol, offset),
+            None => println!("#{:<2} {:#018x} ??", i, addr),
+        }
+    }
+}
+
+/// Print the backtrace of current call stack, skipping `skip` innermost frames.
+#[inline(never)]
+pub fn print_backtrace(skip: usize) {
+    let mut frames = [0; PRINT_DEPTH];
+    let depth = capture(skip, &mut frames);
+    println!("BACKTRACE:");
+    print_frames(&frames[..depth]);
+}
+
+/// Print the backtrace of a trapped context, from its faulting `pc` and frame pointer `fp`.
+pub fn print_backtrace_from(pc: usize, fp: usize) {
+    let mut frames = [0; PRINT_DEPTH];
+    frames[0] = pc;
+    let depth = walk_frame_pointers(fp, 0, &mut frames[1..]) + 1;
+    println!("BACKTRACE:");
+    print_frames(&frames[..depth]);
+}
@@ -0,0 +1,259 @@
+//! Thread checkpoints for rollback recovery, enabled by feature "checkpoint".
+//!
+//! `checkpoint` saves current thread's callee-saved registers like `setjmp`, together with
+//! a copy of its stack above the saved stack pointer and of the memory regions it owns.
+//! `rollback` copies them back and returns from `checkpoint` again, with `RolledBack`.
+//!
+//! Under `PanicPolicy::Rollback`, panics and exceptions handled by unwinding roll the thread
+//! back instead, up to `ROLLBACK_MAX` times per checkpoint, then it unwinds as usual.
+//! With feature "std", only exceptions roll back, std panics unwind, see `crate::panic`.
+//!
+//! A thread is only rolled back on its own stack and in the zone it took the checkpoint in,
+//! e.g. a fault in the callee of a zone call is unwound to the gate, which switches back.
+//!
+//! Regions are copied eagerly, page faults don't resolve copy-on-write mappings.
+//! Regions allocated since the checkpoint are freed by a rollback, and one freed since
+//! makes it fail. State outside the stack and the regions, e.g. the heap, is not rolled back.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arch::ThreadContext;
+use crate::libs::thread::{current_thread, Thread};
+use crate::mm::address::VAddr;
+use crate::panic::PanicPolicy;
+
+/// Max rollbacks to a checkpoint from faults, a fault raised again after them is unwound.
+pub const ROLLBACK_MAX: usize = 5;
+
+/// Size of the stack a rollback runs on, while it overwrites the thread's own stack.
+const ROLLBACK_STACK_SIZE: usize = 4 * crate::arch::PAGE_SIZE;
+
+/// How `checkpoint` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checkpointed {
+    /// The checkpoint is taken.
+    Taken,
+    /// The thread is rolled back to the checkpoint.
+    RolledBack,
+}
+
+/// Checkpoint of a thread, kept in its control block.
+pub(crate) struct Checkpoint {
+    ctx: ThreadContext,
+    /// Saved stack pointer, zero if the stack is not captured.
+    sp: usize,
+    stack: Range<usize>,
+    /// Stack contents from `sp` to the stack top.
+    stack_contents: Vec<u8>,
+    /// Contents of the memory regions owned by the thread, by start address.
+    regions: Vec<(VAddr, Vec<u8>)>,
+    irq_enabled: bool,
+    rollback_stack: Vec<u8>,
+    /// Rollbacks from faults since the checkpoint is taken.
+    fault_rollbacks: AtomicUsize,
+    /// Zone the checkpoint is taken in.
+    #[cfg(feature = "zone")]
+    zone_id: zone::ZoneId,
+    /// Last lock guard registered before the checkpoint, see `HeldLocks::last_seq`.
+    #[cfg(feature = "lock-poison")]
+    lock_seq: usize,
+}
+
+/// Called by `save_checkpoint_context` with the stack pointer it saved.
+extern "C" fn capture_stack(sp: usize, checkpoint: usize) {
+    let checkpoint = unsafe { &mut *(checkpoint as *mut Checkpoint) };
+    if !checkpoint.stack.contains(&sp) {
+        return;
+    }
+    let len = checkpoint.stack.end - sp;
+    if checkpoint.stack_contents.try_reserve_exact(len).is_err() {
+        return;
+    }
+    checkpoint
+        .stack_contents
+        .extend_from_slice(unsafe { core::slice::from_raw_parts(sp as *const u8, len) });
+    checkpoint.sp = sp;
+}
+
+/// Take a checkpoint of current thread, replacing its previous one.
+///
+/// Returns `Taken` at once, and `RolledBack` each time the thread is rolled back to it.
+///
+/// # Safety
+///
+/// Like `setjmp`, this returns twice. Values owned by frames live at the checkpoint
+/// must not be dropped, moved out or freed until the checkpoint is replaced or discarded,
+/// a rollback revives them and they would be dropped again.
+#[inline(never)]
+pub unsafe fn checkpoint() -> Result<Checkpointed, &'static str> {
+    // The thread is not kept across the checkpoint, its reference would be dropped twice.
+    let mut checkpoint = {
+        let thread = current_thread().map_err(|_| "no current thread")?;
+        let mut regions = Vec::new();
+        thread.for_each_mem_region(|addr, region| {
+            let contents =
+                core::slice::from_raw_parts(addr.value() as *const u8, region.size_in_bytes());
+            regions.push((addr, contents.to_vec()));
+        });
+        let irq_enabled = crate::arch::irq::nested_disable();
+        crate::arch::irq::nested_enable(irq_enabled);
+        Box::new(Checkpoint {
+            ctx: ThreadContext::new(),
+            sp: 0,
+            stack: thread.stack_range(),
+            stack_contents: Vec::new(),
+            regions,
+            irq_enabled,
+            rollback_stack: vec![0; ROLLBACK_STACK_SIZE],
+            fault_rollbacks: AtomicUsize::new(0),
+            #[cfg(feature = "zone")]
+            zone_id: zone::current_zone(),
+            #[cfg(feature = "lock-poison")]
+            lock_seq: crate::libs::synch::poison::last_seq(),
+        })
+    };
+
+    let ptr = &mut *checkpoint as *mut Checkpoint;
+    if crate::arch::save_checkpoint_context(&mut (*ptr).ctx, capture_stack, ptr as usize) != 0 {
+        // The checkpoint is owned by the thread since it's taken.
+        core::mem::forget(checkpoint);
+        return Ok(Checkpointed::RolledBack);
+    }
+    if checkpoint.sp == 0 {
+        return Err("failed to capture the stack");
+    }
+    let thread = current_thread().map_err(|_| "no current thread")?;
+    *thread.checkpoint().lock() = Some(checkpoint);
+    Ok(Checkpointed::Taken)
+}
+
+/// Discard current thread's checkpoint, returns false if it has none.
+pub fn discard() -> bool {
+    match current_thread() {
+        Ok(t) => t.checkpoint().lock().take().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Free regions allocated since the checkpoint and copy the others back.
+fn restore_regions(thread: &Thread, checkpoint: &Checkpoint) -> Result<(), &'static str> {
+    let mut current = Vec::new();
+    thread.for_each_mem_region(|addr, region| current.push((addr, region.size_in_bytes())));
+    for (addr, contents) in checkpoint.regions.iter() {
+        if !current.contains(&(*addr, contents.len())) {
+            return Err("a region of the checkpoint was freed since");
+        }
+    }
+    for (addr, _) in current {
+        if !checkpoint.regions.iter().any(|(a, _)| *a == addr) {
+            thread.free_mem_region(addr);
+        }
+    }
+    for (addr, contents) in checkpoint.regions.iter() {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                contents.as_ptr(),
+                addr.value() as *mut u8,
+                contents.len(),
+            )
+        };
+    }
+    Ok(())
+}
+
+/// Copy the stack back and return from the checkpoint, on the rollback stack.
+extern "C" fn rollback_on_stack(checkpoint: usize) {
+    let checkpoint = unsafe { &*(checkpoint as *const Checkpoint) };
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            checkpoint.stack_contents.as_ptr(),
+            checkpoint.sp as *mut u8,
+            checkpoint.stack_contents.len(),
+        );
+    }
+    if checkpoint.irq_enabled {
+        crate::arch::irq::enable();
+    }
+    unsafe { crate::arch::restore_checkpoint_context(&checkpoint.ctx, 1) }
+}
+
+/// Roll current thread back to its checkpoint, discarding the frames since.
+///
+/// Values owned by the discarded frames are leaked, locks they hold are poisoned and
+/// released with feature "lock-poison". Returns only if it fails, e.g. when current
+/// thread runs on another stack or in another zone than at the checkpoint.
+pub fn rollback() -> Result<!, &'static str> {
+    let thread = current_thread().map_err(|_| "no current thread")?;
+    let checkpoint = match thread.checkpoint().lock().as_deref() {
+        Some(checkpoint) => checkpoint as *const Checkpoint,
+        None => return Err("no checkpoint taken"),
+    };
+    // A checkpoint is only replaced or discarded by its own thread, which is here.
+    let checkpoint = unsafe { &*checkpoint };
+    // Frames on a gate stack or in a zone entered since can't be discarded,
+    // the zone call must return first.
+    let here = 0u8;
+    if !checkpoint.stack.contains(&(&here as *const u8 as usize)) {
+        return Err("not on the stack of the checkpoint");
+    }
+    #[cfg(feature = "zone")]
+    if zone::current_zone() != checkpoint.zone_id {
+        return Err("not in the zone of the checkpoint");
+    }
+    let irq_enabled = crate::arch::irq::nested_disable();
+    if let Err(e) = restore_regions(&thread, checkpoint) {
+        crate::arch::irq::nested_enable(irq_enabled);
+        return Err(e);
+    }
+    #[cfg(feature = "quota")]
+    thread.quota().set_unwinding(false);
+    #[cfg(feature = "lock-poison")]
+    crate::libs::synch::poison::release_since(checkpoint.lock_seq, "rolled back");
+    drop(thread);
+
+    let top = checkpoint.rollback_stack.as_ptr() as usize + ROLLBACK_STACK_SIZE;
+    unsafe {
+        crate::arch::call_on_stack(
+            checkpoint as *const Checkpoint as usize,
+            rollback_on_stack,
+            top & !0xF,
+        )
+    };
+    unreachable!("rollback returned")
+}
+
+/// Roll current thread back from a panic or an exception if its panic policy is `Rollback`,
+/// returns if it can't, to unwind instead.
+pub(crate) fn rollback_from_fault() {
+    let thread = match current_thread() {
+        Ok(t) if t.panic_policy() == PanicPolicy::Rollback && !t.panic_state().stopping() => t,
+        _ => return,
+    };
+    let id = thread.id();
+    let rollbacks = match thread.checkpoint().lock().as_ref() {
+        Some(checkpoint) => checkpoint.fault_rollbacks.fetch_add(1, Ordering::Relaxed),
+        None => {
+            warn!(
+                "Thread [{}] has no checkpoint to roll back to, unwinding",
+                id
+            );
+            return;
+        }
+    };
+    if rollbacks >= ROLLBACK_MAX {
+        warn!(
+            "Thread [{}] rolled back {} times, unwinding",
+            id, ROLLBACK_MAX
+        );
+        return;
+    }
+    drop(thread);
+    info!("Thread [{}] rolling back to its checkpoint", id);
+    if let Err(e) = rollback() {
+        warn!("Thread [{}] failed to roll back, {}, unwinding", id, e);
+    }
+}
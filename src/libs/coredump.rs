@@ -0,0 +1,387 @@
+//! Crash dumps in ELF core format, enabled by feature "coredump".
+//!
+//! On a fatal panic or exception, the faulting thread's registers and stack, optionally the
+//! contexts and stacks of all threads, and the memory regions added by `add_region` are
+//! written as an ELF core file, by `DumpTarget`:
+//! * `Serial` streams it in base64 between `COREDUMP BEGIN` and `COREDUMP END` lines,
+//!   which `scripts/coredump.py` extracts from a serial log,
+//! * `File` writes it to `core.<tid>` under `FS_ROOT`, feature "fs" is required.
+//!
+//! Load it with the image the gdb scripts in `gdb/` use, e.g.
+//! `gdb examples/user/target/aarch64qemu/release/user core.1`,
+//! every dumped thread is shown as a gdb thread whose LWP is its thread id.
+//!
+//! A panic or exception is fatal when it's not recovered: with feature "unwind",
+//! the dump is taken when unwinding finds no landing pad, otherwise when it happens.
+//! Threads running on other cores are dumped with their last saved contexts.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use crate::arch::coredump::{CoreRegs, ELF_FLAGS, ELF_MACHINE, GREG_NUM};
+use crate::arch::ContextFrame;
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::thread::{current_thread_id, for_each_thread, Thread, Tid};
+
+/// Signal recorded for a panic, SIGABRT.
+pub const SIGNAL_PANIC: i16 = 6;
+/// Signal recorded for an exception, SIGSEGV.
+pub const SIGNAL_EXCEPTION: i16 = 11;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+const NT_PRSTATUS: u32 = 1;
+/// Size of `struct elf_prstatus`, the registers are at `PRSTATUS_REG_OFFSET`.
+const PRSTATUS_SIZE: usize = PRSTATUS_REG_OFFSET + GREG_NUM * 8 + 8;
+const PRSTATUS_REG_OFFSET: usize = 112;
+const NOTE_SIZE: usize = 12 + NOTE_NAME.len() + PRSTATUS_SIZE;
+
+/// Where dumps are written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DumpTarget {
+    /// Disable dumps.
+    None = 0,
+    Serial = 1,
+    #[cfg(feature = "fs")]
+    File = 2,
+}
+
+impl From<u8> for DumpTarget {
+    fn from(target: u8) -> Self {
+        match target {
+            1 => DumpTarget::Serial,
+            #[cfg(feature = "fs")]
+            2 => DumpTarget::File,
+            _ => DumpTarget::None,
+        }
+    }
+}
+
+static TARGET: AtomicU8 = AtomicU8::new(DumpTarget::Serial as u8);
+static ALL_THREADS: AtomicBool = AtomicBool::new(false);
+static REGIONS: SpinlockIrqSave<Vec<Range<usize>>> = SpinlockIrqSave::new(Vec::new());
+/// Set while dumping, a fault during a dump is not dumped again.
+static DUMPING: AtomicBool = AtomicBool::new(false);
+/// Without unwinding a dumped thread never runs again, so the panic that follows
+/// an unhandled exception is not dumped over it.
+#[cfg(not(feature = "unwind"))]
+static LAST_DUMPED: AtomicUsize = AtomicUsize::new(usize::MAX);
+static DUMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_target(target: DumpTarget) {
+    TARGET.store(target as u8, Ordering::Relaxed);
+}
+
+pub fn target() -> DumpTarget {
+    TARGET.load(Ordering::Relaxed).into()
+}
+
+/// Whether to dump the contexts and stacks of all threads besides the faulting one.
+pub fn set_all_threads(all_threads: bool) {
+    ALL_THREADS.store(all_threads, Ordering::Relaxed);
+}
+
+/// Add a memory region to dumps, it must stay mapped and readable.
+pub fn add_region(region: Range<usize>) {
+    REGIONS.lock().push(region);
+}
+
+pub fn clear_regions() {
+    REGIONS.lock().clear();
+}
+
+/// Number of dumps written since boot.
+pub fn dump_count() -> usize {
+    DUMP_COUNT.load(Ordering::Relaxed)
+}
+
+/// Dump current thread with the registers of the caller.
+#[inline(always)]
+pub fn dump_current(signal: i16) {
+    dump(CoreRegs::current(), signal);
+}
+
+/// Dump current thread, which faulted with `regs`, to the configured target.
+pub fn dump(regs: CoreRegs, signal: i16) {
+    let target = target();
+    if target == DumpTarget::None || DUMPING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let tid = current_thread_id();
+    #[cfg(not(feature = "unwind"))]
+    if LAST_DUMPED.swap(tid.0, Ordering::Relaxed) == tid.0 {
+        DUMPING.store(false, Ordering::Release);
+        return;
+    }
+    println!("COREDUMP: dumping {} with signal {}", tid, signal);
+    let result = match target {
+        DumpTarget::Serial => write_core(&mut SerialSink::new(), tid, regs, signal),
+        #[cfg(feature = "fs")]
+        DumpTarget::File => {
+            let path = alloc::format!("{}core.{}", crate::libs::fs::FS_ROOT, tid.0);
+            FileSink::create(&path).and_then(|mut sink| {
+                write_core(&mut sink, tid, regs, signal)?;
+                println!("COREDUMP: written to {}", path);
+                Ok(())
+            })
+        }
+        DumpTarget::None => Ok(()),
+    };
+    match result {
+        Ok(()) => {
+            DUMP_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => println!("COREDUMP: failed, {}", e),
+    }
+    DUMPING.store(false, Ordering::Release);
+}
+
+/// Registers a thread resumes with, from its trap context or yield context.
+fn thread_regs(t: &Thread) -> CoreRegs {
+    if t.in_trap_context() {
+        CoreRegs::from(unsafe { &*(t.last_stack_pointer() as *const ContextFrame) })
+    } else {
+        CoreRegs::from(unsafe { &*t.ctx_mut_ptr() })
+    }
+}
+
+/// Write the core file of thread `tid` with `regs`, followed by other threads if required.
+fn write_core(sink: &mut dyn Sink, tid: Tid, regs: CoreRegs, signal: i16) -> Result<(), &'static str> {
+    let mut threads: Vec<(Tid, CoreRegs)> = alloc::vec![(tid, regs)];
+    let mut segments: Vec<Range<usize>> = Vec::new();
+    let all_threads = ALL_THREADS.load(Ordering::Relaxed);
+    for_each_thread(|t| {
+        if t.id() == tid {
+            segments.insert(0, t.stack_range());
+        } else if all_threads && !t.is_idle() {
+            threads.push((t.id(), thread_regs(t)));
+            segments.push(t.stack_range());
+        }
+    });
+    for region in REGIONS.lock().iter() {
+        if !region.is_empty() && !segments.contains(region) {
+            segments.push(region.clone());
+        }
+    }
+
+    // ELF header, program headers of notes and segments, notes, then contents of segments.
+    let phnum = 1 + segments.len();
+    let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    let notes_size = threads.len() * NOTE_SIZE;
+    let mut header = Vec::with_capacity(notes_offset + notes_size);
+
+    push_ehdr(&mut header, phnum as u16);
+    push_phdr(&mut header, PT_NOTE, 0, notes_offset, 0, notes_size);
+    let mut offset = notes_offset + notes_size;
+    for segment in segments.iter() {
+        push_phdr(&mut header, PT_LOAD, PF_R | PF_W, offset, segment.start, segment.len());
+        offset += segment.len();
+    }
+    for (i, (tid, regs)) in threads.iter().enumerate() {
+        push_prstatus(&mut header, *tid, regs, if i == 0 { signal } else { 0 });
+    }
+
+    sink.begin(offset)?;
+    sink.write(&header)?;
+    for segment in segments.iter() {
+        let content = unsafe { core::slice::from_raw_parts(segment.start as *const u8, segment.len()) };
+        sink.write(content)?;
+    }
+    sink.end()
+}
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+fn push_ehdr(buf: &mut Vec<u8>, phnum: u16) {
+    // ELFCLASS64, ELFDATA2LSB, EV_CURRENT.
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    buf.extend_from_slice(&[0; 8]);
+    // ET_CORE.
+    buf.extend_from_slice(&4u16.to_le_bytes());
+    buf.extend_from_slice(&ELF_MACHINE.to_le_bytes());
+    buf.extend_from_slice(&1u32.to_le_bytes());
+    // e_entry, e_phoff, e_shoff.
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    buf.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    buf.extend_from_slice(&ELF_FLAGS.to_le_bytes());
+    buf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    buf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    buf.extend_from_slice(&phnum.to_le_bytes());
+    // e_shentsize, e_shnum, e_shstrndx.
+    buf.extend_from_slice(&[0; 6]);
+}
+
+fn push_phdr(buf: &mut Vec<u8>, kind: u32, flags: u32, offset: usize, vaddr: usize, size: usize) {
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(&flags.to_le_bytes());
+    buf.extend_from_slice(&(offset as u64).to_le_bytes());
+    buf.extend_from_slice(&(vaddr as u64).to_le_bytes());
+    // p_paddr.
+    buf.extend_from_slice(&0u64.to_le_bytes());
+    buf.extend_from_slice(&(size as u64).to_le_bytes());
+    buf.extend_from_slice(&(size as u64).to_le_bytes());
+    let align: u64 = if kind == PT_LOAD { 1 } else { 4 };
+    buf.extend_from_slice(&align.to_le_bytes());
+}
+
+/// NT_PRSTATUS note of a thread, only the signal, the pid and the registers are filled.
+fn push_prstatus(buf: &mut Vec<u8>, tid: Tid, regs: &CoreRegs, signal: i16) {
+    buf.extend_from_slice(&(5u32).to_le_bytes());
+    buf.extend_from_slice(&(PRSTATUS_SIZE as u32).to_le_bytes());
+    buf.extend_from_slice(&NT_PRSTATUS.to_le_bytes());
+    buf.extend_from_slice(NOTE_NAME);
+
+    let mut prstatus = [0u8; PRSTATUS_SIZE];
+    // pr_info.si_signo and pr_cursig.
+    prstatus[0..4].copy_from_slice(&(signal as i32).to_le_bytes());
+    prstatus[12..14].copy_from_slice(&signal.to_le_bytes());
+    // pr_pid.
+    prstatus[32..36].copy_from_slice(&(tid.0 as i32).to_le_bytes());
+    for (i, reg) in regs.0.iter().enumerate() {
+        let offset = PRSTATUS_REG_OFFSET + i * 8;
+        prstatus[offset..offset + 8].copy_from_slice(&reg.to_le_bytes());
+    }
+    buf.extend_from_slice(&prstatus);
+}
+
+trait Sink {
+    /// Start a core file of `size` bytes.
+    fn begin(&mut self, size: usize) -> Result<(), &'static str>;
+    fn write(&mut self, buf: &[u8]) -> Result<(), &'static str>;
+    fn end(&mut self) -> Result<(), &'static str>;
+}
+
+/// Base64 characters per line of the serial stream.
+const SERIAL_LINE_LEN: usize = 76;
+
+/// Streams a core file in base64 lines.
+struct SerialSink {
+    pending: [u8; 3],
+    pending_len: usize,
+    line: String,
+}
+
+impl SerialSink {
+    fn new() -> Self {
+        SerialSink {
+            pending: [0; 3],
+            pending_len: 0,
+            line: String::with_capacity(SERIAL_LINE_LEN + 1),
+        }
+    }
+
+    fn encode(&mut self, group: &[u8]) {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let b = [
+            group[0],
+            group.get(1).copied().unwrap_or(0),
+            group.get(2).copied().unwrap_or(0),
+        ];
+        let chars = [
+            ALPHABET[(b[0] >> 2) as usize],
+            ALPHABET[(((b[0] & 0x3) << 4) | (b[1] >> 4)) as usize],
+            ALPHABET[(((b[1] & 0xf) << 2) | (b[2] >> 6)) as usize],
+            ALPHABET[(b[2] & 0x3f) as usize],
+        ];
+        for (i, c) in chars.iter().enumerate() {
+            // Pad the last group.
+            self.line.push(if i > group.len() { '=' } else { *c as char });
+        }
+        if self.line.len() >= SERIAL_LINE_LEN {
+            self.flush_line();
+        }
+    }
+
+    fn flush_line(&mut self) {
+        if !self.line.is_empty() {
+            println!("{}", self.line);
+            self.line.clear();
+        }
+    }
+}
+
+impl Sink for SerialSink {
+    fn begin(&mut self, size: usize) -> Result<(), &'static str> {
+        println!("COREDUMP BEGIN {}", size);
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), &'static str> {
+        for b in buf {
+            self.pending[self.pending_len] = *b;
+            self.pending_len += 1;
+            if self.pending_len == 3 {
+                let group = self.pending;
+                self.encode(&group);
+                self.pending_len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), &'static str> {
+        if self.pending_len != 0 {
+            let group = self.pending;
+            self.encode(&group[..self.pending_len]);
+            self.pending_len = 0;
+        }
+        self.flush_line();
+        println!("COREDUMP END");
+        Ok(())
+    }
+}
+
+/// Writes a core file to the file system.
+#[cfg(feature = "fs")]
+struct FileSink {
+    fd: i32,
+}
+
+#[cfg(feature = "fs")]
+impl FileSink {
+    fn create(path: &str) -> Result<Self, &'static str> {
+        use crate::libs::fs::interface::{O_CREAT, O_TRUNC, O_WRONLY};
+        let fd = crate::libs::fs::open(path, O_CREAT | O_WRONLY | O_TRUNC, 0o644);
+        if fd < 0 {
+            return Err("failed to create core file");
+        }
+        Ok(FileSink { fd })
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Sink for FileSink {
+    fn begin(&mut self, _size: usize) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), &'static str> {
+        let mut written = 0;
+        while written < buf.len() {
+            let n = crate::libs::fs::write(
+                self.fd,
+                buf[written..].as_ptr(),
+                buf.len() - written,
+            );
+            if n <= 0 {
+                return Err("failed to write core file");
+            }
+            written += n as usize;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), &'static str> {
+        crate::libs::fs::close(self.fd);
+        Ok(())
+    }
+}
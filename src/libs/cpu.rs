@@ -1,3 +1,6 @@
+#[cfg(feature = "watchdog")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use spin::Once;
 
 use crate::board::BOARD_CORE_NUMBER;
@@ -15,6 +18,9 @@ pub struct Core {
     sched: SchedulerType,
     #[cfg(target_arch = "x86_64")]
     arch_specific_data: crate::arch::Cpu,
+    /// Beaten by each schedule, monitored by the watchdog service.
+    #[cfg(feature = "watchdog")]
+    heartbeat: AtomicUsize,
 }
 
 // Note: only the core itself can be allowed to access its `Core`
@@ -29,6 +35,8 @@ const CORE: Core = Core {
     sched: SchedulerType::None,
     #[cfg(target_arch = "x86_64")]
     arch_specific_data: crate::arch::Cpu::new(),
+    #[cfg(feature = "watchdog")]
+    heartbeat: AtomicUsize::new(0),
 };
 
 static mut CORES: [Core; BOARD_CORE_NUMBER] = [CORE; BOARD_CORE_NUMBER];
@@ -52,10 +60,16 @@ impl Core {
     }
 
     #[allow(unused)]
-    fn running_thread_ref(&self) -> Option<&Thread> {
+    pub(crate) fn running_thread_ref(&self) -> Option<&Thread> {
         self.running_thread.as_ref()
     }
 
+    /// Number of schedules on this core, zero if it never scheduled.
+    #[cfg(feature = "watchdog")]
+    pub fn heartbeat(&self) -> usize {
+        self.heartbeat.load(Ordering::Relaxed)
+    }
+
     pub fn set_running_idle(&mut self) {
         let t = self.idle_thread();
         t.set_in_yield_context();
@@ -80,6 +94,7 @@ impl Core {
             None => {
                 let core_id = crate::arch::Arch::core_id();
                 let idle_thread_id = (core_id + 1) * 10 + (core_id + 1);
+                // Idle threads run in the shared zone, no zone is allocated for them.
                 let t = crate::libs::thread::thread_alloc(
                     Some(idle_thread_id),
                     Some(core_id),
@@ -87,7 +102,8 @@ impl Core {
                     core_id,
                     0,
                     true,
-                );
+                )
+                .expect("fail to allocate idle thread");
                 debug!(
                     "Alloc idle thread [{}] on core [{}], context on sp {:x}",
                     t.id(),
@@ -119,7 +135,23 @@ impl Core {
         }
     }
 
+    /// Remove the wakeup time of `thread` if it's blocked on this core.
+    pub fn cancel_wakeup(&self, thread: &Thread) {
+        match &self.sched {
+            SchedulerType::None => {}
+            SchedulerType::GlobalSchedCFS(cfs) => cfs.unblock(thread),
+            _ => self.scheduler().unblock(thread),
+        }
+    }
+
     pub fn schedule(&mut self) {
+        // Cores halt once the image is aborted, see `crate::panic::abort`.
+        if crate::panic::aborted() {
+            crate::panic::halt();
+        }
+        #[cfg(feature = "watchdog")]
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
+
         // Get prev thread.
         let prev = self.running_thread().unwrap_or_else(|| {
             panic!(
@@ -171,6 +203,12 @@ impl Core {
         next.set_status(Status::Running);
         // debug!("cpu schedule prev {} to next {}", prev.id(), next.id());
 
+        // Switch with privileged zone keys, so both stacks are accessible.
+        // The original keys are kept on prev's stack, and restored when prev is resumed below,
+        // a newly started thread enters its own zone by itself.
+        #[cfg(feature = "zone")]
+        let ori_zone = zone::switch_to_privilege();
+
         unsafe {
             let prev_ctx_ptr = prev.ctx_mut_ptr();
             let next_ctx_ptr = next.ctx_mut_ptr();
@@ -195,6 +233,9 @@ impl Core {
                 (*prev_ctx_ptr).switch_to_yield_ctx(&*next_ctx_ptr);
             }
         }
+
+        #[cfg(feature = "zone")]
+        zone::switch_from_privilege(ori_zone);
     }
 }
 
@@ -0,0 +1,413 @@
+//! Runtime fault injection, enabled by feature "fault-inject".
+//!
+//! A fault site is a point of kernel code with a unique ID like `fs::open`, marked by
+//! `inject::fault_site` on a function or by `inject_fault!` inside it. Sites register
+//! themselves on their first hit and inject nothing until configured:
+//! * `Fault::Panic` panics at the site,
+//! * `Fault::PageFault` reads the guard page beneath the current stack,
+//! * `Fault::Latency` busy waits for some microseconds,
+//! * `Fault::Error` makes the site return its error, which is an allocation failure or
+//!   a block or net I/O error, only for sites declared with `SiteKind::AllocFailure` or `SiteKind::IoError`.
+//!
+//! Each site injects with a probability drawn from one pseudo random sequence, so a run
+//! is reproducible with the same seed and the same schedule. Sites are configured by ID,
+//! or by prefix with a trailing `*`, and configurations apply to sites registered later.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+
+/// Probabilities are in parts per million.
+pub const PPM: u32 = 1_000_000;
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+const DEFAULT_LATENCY_US: u32 = 1000;
+
+/// Mark a fault site inside a function, returns true if the site must return its error.
+///
+/// ```ignore
+/// if crate::inject_fault!("net::tcp::read", SiteKind::IoError) {
+///     return Err(ShyperError::Io);
+/// }
+/// ```
+#[macro_export]
+macro_rules! inject_fault {
+    ($id:expr) => {
+        $crate::inject_fault!($id, $crate::libs::fault_inject::SiteKind::Plain)
+    };
+    ($id:expr, $kind:expr) => {{
+        static SITE: $crate::libs::fault_inject::FaultSite =
+            $crate::libs::fault_inject::FaultSite::new($id, $kind);
+        SITE.hit()
+    }};
+}
+
+/// Errors a site is able to return.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SiteKind {
+    /// The site has no error path.
+    Plain,
+    AllocFailure,
+    IoError,
+}
+
+impl fmt::Display for SiteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SiteKind::Plain => write!(f, "-"),
+            SiteKind::AllocFailure => write!(f, "alloc"),
+            SiteKind::IoError => write!(f, "io"),
+        }
+    }
+}
+
+/// Fault injected at a site.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    Panic,
+    PageFault,
+    /// Busy wait for the given microseconds.
+    Latency(u32),
+    /// Return the error of the site.
+    Error,
+}
+
+impl Fault {
+    fn encode(self) -> (u8, u32) {
+        match self {
+            Fault::Panic => (0, 0),
+            Fault::PageFault => (1, 0),
+            Fault::Latency(us) => (2, us),
+            Fault::Error => (3, 0),
+        }
+    }
+
+    fn decode(kind: u8, latency_us: u32) -> Self {
+        match kind {
+            1 => Fault::PageFault,
+            2 => Fault::Latency(latency_us),
+            3 => Fault::Error,
+            _ => Fault::Panic,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::Panic => write!(f, "panic"),
+            Fault::PageFault => write!(f, "pagefault"),
+            Fault::Latency(us) => write!(f, "latency {}us", us),
+            Fault::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl core::str::FromStr for Fault {
+    type Err = &'static str;
+
+    /// Parse "panic", "pagefault", "error" or "latency", whose latency is `DEFAULT_LATENCY_US`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "panic" => Ok(Fault::Panic),
+            "pagefault" => Ok(Fault::PageFault),
+            "latency" => Ok(Fault::Latency(DEFAULT_LATENCY_US)),
+            "error" => Ok(Fault::Error),
+            _ => Err("unknown fault"),
+        }
+    }
+}
+
+/// A fault site, declared as a static by `inject_fault!`.
+pub struct FaultSite {
+    id: &'static str,
+    kind: SiteKind,
+    registered: AtomicBool,
+    fault: AtomicU8,
+    latency_us: AtomicU32,
+    probability: AtomicU32,
+    hits: AtomicUsize,
+    injected: AtomicUsize,
+}
+
+impl FaultSite {
+    pub const fn new(id: &'static str, kind: SiteKind) -> Self {
+        FaultSite {
+            id,
+            kind,
+            registered: AtomicBool::new(false),
+            fault: AtomicU8::new(0),
+            latency_us: AtomicU32::new(0),
+            probability: AtomicU32::new(0),
+            hits: AtomicUsize::new(0),
+            injected: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    pub fn kind(&self) -> SiteKind {
+        self.kind
+    }
+
+    fn fault(&self) -> Fault {
+        Fault::decode(
+            self.fault.load(Ordering::Relaxed),
+            self.latency_us.load(Ordering::Relaxed),
+        )
+    }
+
+    fn configure(&self, fault: Fault, probability: u32) {
+        let (kind, latency_us) = fault.encode();
+        // Disable the site while it's changed.
+        self.probability.store(0, Ordering::Relaxed);
+        self.fault.store(kind, Ordering::Relaxed);
+        self.latency_us.store(latency_us, Ordering::Relaxed);
+        self.probability.store(probability.min(PPM), Ordering::Relaxed);
+    }
+
+    /// Count a hit and inject the configured fault by its probability,
+    /// returns true if the site must return its error.
+    pub fn hit(&'static self) -> bool {
+        if !self.registered.load(Ordering::Acquire) {
+            register(self);
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let probability = self.probability.load(Ordering::Relaxed);
+        if probability == 0 || next_random() % PPM as u64 >= probability as u64 {
+            return false;
+        }
+        self.injected.fetch_add(1, Ordering::Relaxed);
+        match self.fault() {
+            Fault::Panic => panic!("fault injected at {}", self.id),
+            Fault::PageFault => {
+                page_fault(self.id);
+                false
+            }
+            Fault::Latency(us) => {
+                let start = crate::libs::timer::current_us();
+                while crate::libs::timer::current_us() - start < us as usize {
+                    core::hint::spin_loop();
+                }
+                false
+            }
+            Fault::Error => self.kind != SiteKind::Plain,
+        }
+    }
+}
+
+/// Read the unmapped guard page beneath the stack of current thread.
+fn page_fault(id: &str) {
+    match crate::libs::thread::current_thread() {
+        Ok(t) => {
+            let guard = t.stack_range().start - core::mem::size_of::<usize>();
+            warn!("fault injected at {}, reading guard page {:#x}", id, guard);
+            unsafe {
+                core::ptr::read_volatile(guard as *const usize);
+            }
+        }
+        Err(_) => panic!("fault injected at {}, no thread to page fault", id),
+    }
+}
+
+/// Configuration of sites matching `pattern`.
+struct Rule {
+    pattern: String,
+    fault: Fault,
+    probability: u32,
+}
+
+fn matches(pattern: &str, id: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => id.starts_with(prefix),
+        None => pattern == id,
+    }
+}
+
+struct Registry {
+    sites: Vec<&'static FaultSite>,
+    rules: Vec<Rule>,
+}
+
+static REGISTRY: SpinlockIrqSave<Registry> = SpinlockIrqSave::new(Registry {
+    sites: Vec::new(),
+    rules: Vec::new(),
+});
+
+/// Register `site` and apply the latest matching rule.
+///
+/// The registry allocates, so a site hit by the allocator while the registry is locked
+/// is registered by a later hit.
+fn register(site: &'static FaultSite) {
+    let mut registry = match REGISTRY.try_lock() {
+        Ok(registry) => registry,
+        Err(_) => return,
+    };
+    if site.registered.load(Ordering::Acquire) {
+        return;
+    }
+    if let Some(rule) = registry.rules.iter().rev().find(|r| matches(&r.pattern, site.id)) {
+        site.configure(rule.fault, rule.probability);
+    }
+    registry.sites.push(site);
+    site.registered.store(true, Ordering::Release);
+}
+
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+static SEED: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+
+/// Restart the pseudo random sequence from `seed`.
+pub fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+    // Xorshift never leaves zero.
+    let state = if seed == 0 { DEFAULT_SEED } else { seed };
+    RANDOM_STATE.store(state, Ordering::Relaxed);
+}
+
+pub fn seed() -> u64 {
+    SEED.load(Ordering::Relaxed)
+}
+
+/// Xorshift64*.
+fn next_random() -> u64 {
+    let step = |mut x: u64| {
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        Some(x)
+    };
+    let state = RANDOM_STATE
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, step)
+        .unwrap();
+    step(state).unwrap().wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// Inject `fault` with `probability` in parts per million at sites matching `pattern`,
+/// which is an ID or a prefix followed by `*`, including sites registered later.
+///
+/// Returns the number of registered sites matched.
+pub fn configure(pattern: &str, fault: Fault, probability: u32) -> Result<usize, &'static str> {
+    if probability > PPM {
+        return Err("probability out of range");
+    }
+    let rule = Rule {
+        pattern: String::from(pattern),
+        fault,
+        probability,
+    };
+    let mut registry = REGISTRY.lock();
+    // Sites matched by prefix without an error path just ignore `Fault::Error`.
+    if fault == Fault::Error
+        && registry
+            .sites
+            .iter()
+            .any(|s| s.id == pattern && s.kind == SiteKind::Plain)
+    {
+        return Err("site has no error to return");
+    }
+    let mut matched = 0;
+    for site in registry.sites.iter().filter(|s| matches(pattern, s.id)) {
+        site.configure(fault, probability);
+        matched += 1;
+    }
+    registry.rules.push(rule);
+    Ok(matched)
+}
+
+/// Stop injecting at all sites and drop all configurations.
+pub fn disable_all() {
+    let mut registry = REGISTRY.lock();
+    registry.rules.clear();
+    for site in registry.sites.iter() {
+        site.probability.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Reset hit and injection counters of all sites.
+pub fn reset_counters() {
+    for site in REGISTRY.lock().sites.iter() {
+        site.hits.store(0, Ordering::Relaxed);
+        site.injected.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of a registered site.
+#[derive(Debug, Clone)]
+pub struct SiteInfo {
+    pub id: &'static str,
+    pub kind: SiteKind,
+    pub fault: Fault,
+    /// Parts per million.
+    pub probability: u32,
+    pub hits: usize,
+    pub injected: usize,
+}
+
+impl fmt::Display for SiteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<32} {:<6} {:>8} {:>8} ",
+            self.id, self.kind, self.hits, self.injected
+        )?;
+        if self.probability == 0 {
+            write!(f, "off")
+        } else {
+            write!(
+                f,
+                "{} at {}.{:02}%",
+                self.fault,
+                self.probability / 10000,
+                self.probability % 10000 / 100
+            )
+        }
+    }
+}
+
+/// Registered sites sorted by ID.
+pub fn sites() -> Vec<SiteInfo> {
+    let mut sites: Vec<SiteInfo> = REGISTRY
+        .lock()
+        .sites
+        .iter()
+        .map(|s| SiteInfo {
+            id: s.id,
+            kind: s.kind,
+            fault: s.fault(),
+            probability: s.probability.load(Ordering::Relaxed),
+            hits: s.hits.load(Ordering::Relaxed),
+            injected: s.injected.load(Ordering::Relaxed),
+        })
+        .collect();
+    sites.sort_by(|a, b| a.id.cmp(b.id));
+    sites
+}
+
+pub fn list_sites() {
+    let sites = sites();
+    println!("fault injection seed {:#x}, {} sites registered", seed(), sites.len());
+    println!(
+        "{:<32} {:<6} {:>8} {:>8} FAULT",
+        "SITE", "ERROR", "HITS", "INJECTED"
+    );
+    for site in sites.iter() {
+        println!("{}", site);
+    }
+}
+
+/// Parse a probability as a fraction like "0.05" or a percentage like "5%" into parts per million.
+pub fn parse_probability(s: &str) -> Option<u32> {
+    let (value, scale) = match s.strip_suffix('%') {
+        Some(percent) => (percent, 100.0),
+        None => (s, 1.0),
+    };
+    let value = value.parse::<f64>().ok()? / scale;
+    if !(0.0..=1.0).contains(&value) {
+        return None;
+    }
+    Some((value * PPM as f64) as u32)
+}
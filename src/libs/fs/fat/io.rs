@@ -1,46 +1,36 @@
-use core::slice;
-
 use crate::drivers::blk;
+use crate::mm::dma::{DmaChunk, DmaPool};
 
 use crate::libs::fs::interface::{BlkIO, AtaError};
 use crate::libs::fs::fat::diskcursor::BSIZE;
 
+/// Pool of sector buffers, shared by all block caches.
+static BLOCK_POOL: DmaPool = DmaPool::new(BSIZE);
+
 // reference: https://github.com/rafalh/rust-fatfs/issues/55
 // https://github.com/x37v/stm32h7xx-hal/blob/xnor/fatfs/src/sdmmc.rs#L1392-L1697
-#[derive(Debug, Clone)]
-#[repr(align(512))]
-#[repr(C)]
-pub struct DataBlock(pub [u8; BSIZE]);
+/// A sector buffer in DMA memory, so the block device can transfer to it directly.
+#[derive(Debug)]
+pub struct DataBlock(DmaChunk);
 
-#[allow(dead_code)]
 impl DataBlock {
-    pub const fn new() -> Self {
-        Self([0; BSIZE])
-    }
-
-    pub fn blocks_to_words(blocks: &[DataBlock]) -> &[u32] {
-        let word_count = blocks.len() * 128;
-        // SAFETY: `DataBlock` is 4-byte aligned.
-        unsafe { slice::from_raw_parts(blocks.as_ptr() as *mut u32, word_count) }
-    }
-
-    pub fn blocks_to_words_mut(blocks: &mut [DataBlock]) -> &mut [u32] {
-        let word_count = blocks.len() * 128;
-        // SAFETY: `DataBlock` is 4-byte aligned.
-        unsafe { slice::from_raw_parts_mut(blocks.as_mut_ptr() as *mut u32, word_count) }
+    pub fn new() -> Self {
+        Self(BLOCK_POOL.alloc().expect("failed to allocate DMA block"))
     }
 }
 
 impl BlkIO for DataBlock {
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("blk::read", io_error, ret = "Err(AtaError)"))]
     fn read(&mut self, sector: usize, count: usize) -> Result<(), AtaError> {
         debug_assert!(count == 1);
-        blk::read(sector, count, self.0.as_ptr() as usize);
+        blk::read(sector, count, self.0.vaddr().value());
         Ok(())
     }
 
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("blk::write", io_error, ret = "Err(AtaError)"))]
     fn write(&self, sector: usize, count: usize) -> Result<(), AtaError> {
         debug_assert!(count == 1);
-        blk::write(sector, count, self.0.as_ptr() as usize);
+        blk::write(sector, count, self.0.vaddr().value());
         Ok(())
     }
 
@@ -52,21 +52,41 @@ pub fn init() {
 
 use interface::*;
 
+use crate::libs::synch::spinlock::SpinlockGuard;
+
+/// Lock the filesystem, None if a thread died holding it,
+/// then its mounts and open files may be inconsistent.
+fn filesystem() -> Option<SpinlockGuard<'static, fs::Filesystem>> {
+    match fs::FILESYSTEM.lock_checked() {
+        Ok(fs) => Some(fs),
+        Err(_) => {
+            warn!("fs: filesystem lock poisoned, a thread died holding it");
+            None
+        }
+    }
+}
+
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
 pub fn unlink(path: &str) -> i32 {
     debug!("unlink {}", path);
 
-    fs::FILESYSTEM
-        .lock()
-        .unlink(path)
-        .expect("Unlinking failed!"); // TODO: error handling
-    0
+    match filesystem() {
+        Some(mut fs) => {
+            fs.unlink(path).expect("Unlinking failed!"); // TODO: error handling
+            0
+        }
+        None => -1,
+    }
 }
 
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+#[cfg_attr(feature = "fault-inject", inject::fault_site("fs::open", io_error, ret = "-1"))]
 pub fn open(path: &str, flags: i32, mode: i32) -> i32 {
     debug!("Open {}, {}, {}", path, flags, mode);
-    let mut fs = fs::FILESYSTEM.lock();
+    let mut fs = match filesystem() {
+        Some(fs) => fs,
+        None => return -1,
+    };
 
     let fd = fs.open(path, open_flags_to_perm(flags, mode as u32));
     match fd {
@@ -79,20 +99,28 @@ pub fn open(path: &str, flags: i32, mode: i32) -> i32 {
 }
 
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+#[cfg_attr(feature = "fault-inject", inject::fault_site("fs::close"))]
 pub fn close(fd: i32) -> i32 {
     assert!(fd > 2);
-    let mut fs = fs::FILESYSTEM.lock();
+    let mut fs = match filesystem() {
+        Some(fs) => fs,
+        None => return -1,
+    };
     fs.close(fd as u64);
     0
 }
 
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+#[cfg_attr(feature = "fault-inject", inject::fault_site("fs::read", io_error, ret = "-1"))]
 pub fn read(fd: i32, buf: *mut u8, len: usize) -> isize {
     assert!(len <= isize::MAX as usize);
     assert!(fd > 2);
     debug!("Read! {}, {}", fd, len);
 
-    let mut fs = fs::FILESYSTEM.lock();
+    let mut fs = match filesystem() {
+        Some(fs) => fs,
+        None => return -1,
+    };
     let mut read_bytes = 0;
     fs.fd_op(fd as u64, |file: &mut Box<dyn PosixFile + Send>| {
         let dat = file.read(len as u32).unwrap(); // TODO: might fail
@@ -106,6 +134,7 @@ pub fn read(fd: i32, buf: *mut u8, len: usize) -> isize {
 }
 
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+#[cfg_attr(feature = "fault-inject", inject::fault_site("fs::write", io_error, ret = "-1"))]
 pub fn write(fd: i32, buf: *const u8, len: usize) -> isize {
     assert!(len <= isize::MAX as usize);
     assert!(fd > 2);
@@ -113,7 +142,10 @@ pub fn write(fd: i32, buf: *const u8, len: usize) -> isize {
 
     // Normal file
     let mut written_bytes = 0;
-    let mut fs = fs::FILESYSTEM.lock();
+    let mut fs = match filesystem() {
+        Some(fs) => fs,
+        None => return -1,
+    };
     fs.fd_op(fd as u64, |file: &mut Box<dyn PosixFile + Send>| {
         written_bytes = file.write(buf).unwrap(); // TODO: might fail
     });
@@ -125,7 +157,10 @@ pub fn write(fd: i32, buf: *const u8, len: usize) -> isize {
 pub fn lseek(fd: i32, offset: isize, whence: i32) -> isize {
     debug!("lseek! {}, {}, {}", fd, offset, whence);
 
-    let mut fs = fs::FILESYSTEM.lock();
+    let mut fs = match filesystem() {
+        Some(fs) => fs,
+        None => return -1,
+    };
     let mut ret = 0;
     fs.fd_op(fd as u64, |file: &mut Box<dyn PosixFile + Send>| {
         ret = file.lseek(offset, whence.try_into().unwrap()).unwrap(); // TODO: might fail
@@ -149,13 +184,13 @@ pub fn print_dir(path: &str) -> Result<(), FileError> {
         return Ok(());
     }
 
-    let fs = fs::FILESYSTEM.lock();
+    let fs = filesystem().ok_or(FileError::EOTHERS)?;
     fs.print_dir(path)
 }
 
 #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
 pub fn create_dir<P: AsRef<str>>(path: P) -> Result<(), FileError> {
-    let fs = fs::FILESYSTEM.lock();
+    let fs = filesystem().ok_or(FileError::EOTHERS)?;
     fs.create_dir(path.as_ref())
 }
 
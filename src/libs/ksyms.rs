@@ -0,0 +1,128 @@
+//! Compact kernel symbol table for symbolized backtraces.
+//!
+//! With feature "ksyms", `.ksyms` reserves `KSYMS_SIZE` bytes in the read-only part of the image,
+//! which `scripts/ksyms.py` fills after linking with the function symbols of the ELF,
+//! see `cargo_build` in scripts/build.mk, it runs if the feature is passed by `APP_FEATURES`.
+//! The table is:
+//!
+//! ```text
+//! | magic: u32 | count: u32 | base: u64 |
+//! | [offset from base: u32, name offset in pool: u32]; count |  sorted by address
+//! | names pool, NUL terminated demangled names               |
+//! ```
+//!
+//! Without the feature, or if the table is not filled, addresses are not resolved.
+
+use core::ops::Range;
+
+/// "KSYM" in little endian.
+const KSYMS_MAGIC: u32 = 0x4d59_534b;
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 8;
+
+/// Bytes reserved for the table, `scripts/ksyms.py` refuses to embed a larger one.
+#[cfg(feature = "ksyms")]
+pub const KSYMS_SIZE: usize = 0x40000;
+
+/// The reserved table, initialized with an empty header so it's never placed in NOBITS.
+#[cfg(feature = "ksyms")]
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS_RESERVED: [u8; KSYMS_SIZE] = {
+    let mut table = [0; KSYMS_SIZE];
+    let magic = KSYMS_MAGIC.to_le_bytes();
+    table[0] = magic[0];
+    table[1] = magic[1];
+    table[2] = magic[2];
+    table[3] = magic[3];
+    table
+};
+
+/// The table as linked, read through linker symbols rather than `KSYMS_RESERVED`,
+/// whose content is known to the compiler before it's filled.
+fn table() -> &'static [u8] {
+    extern "C" {
+        // Note: link-time label, see linker.ld
+        fn KSYMS_START();
+        fn KSYMS_END();
+    }
+    let range = KSYMS_START as usize..KSYMS_END as usize;
+    unsafe { core::slice::from_raw_parts(range.start as *const u8, range.len()) }
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&table[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&table[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Number of symbols and base address of a filled table.
+fn header(table: &[u8]) -> Option<(usize, usize)> {
+    if table.len() < HEADER_SIZE || read_u32(table, 0) != KSYMS_MAGIC {
+        return None;
+    }
+    let count = read_u32(table, 4) as usize;
+    if HEADER_SIZE + count * ENTRY_SIZE > table.len() {
+        return None;
+    }
+    Some((count, read_u64(table, 8) as usize))
+}
+
+/// Number of symbols embedded.
+pub fn symbol_count() -> usize {
+    header(table()).map_or(0, |(count, _)| count)
+}
+
+/// Functions are looked up in `.text` only.
+fn text_range() -> Range<usize> {
+    extern "C" {
+        // Note: link-time label, see linker.ld
+        fn TEXT_START();
+        fn TEXT_END();
+    }
+    TEXT_START as usize..TEXT_END as usize
+}
+
+/// Resolve `addr` to the name of the function containing it and the offset into it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let table = table();
+    let (count, base) = header(table)?;
+    if count == 0 || addr < base || !text_range().contains(&addr) {
+        return None;
+    }
+    let entry = |i: usize| {
+        let offset = HEADER_SIZE + i * ENTRY_SIZE;
+        (
+            read_u32(table, offset) as usize,
+            read_u32(table, offset + 4) as usize,
+        )
+    };
+
+    // The last symbol starting at or before `addr`.
+    let target = addr - base;
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if entry(mid).0 <= target {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+    let (offset, name) = entry(low - 1);
+
+    let pool = &table[HEADER_SIZE + count * ENTRY_SIZE..];
+    let name = pool.get(name..)?;
+    let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    let name = core::str::from_utf8(&name[..len]).ok()?;
+    Some((name, target - offset))
+}
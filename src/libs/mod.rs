@@ -1,7 +1,13 @@
+pub mod backtrace;
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
 pub mod cpu;
 pub mod device;
 pub mod error;
+#[cfg(feature = "fault-inject")]
+pub mod fault_inject;
 pub mod interrupt;
+pub mod ksyms;
 pub mod print;
 pub mod scheduler;
 pub mod stack;
@@ -24,5 +30,19 @@ pub mod terminal;
 #[cfg(feature = "unwind")]
 pub mod unwind;
 
+#[cfg(feature = "coredump")]
+pub mod coredump;
+
+#[cfg(feature = "supervisor")]
+pub mod supervisor;
+
 #[cfg(feature = "unilib")]
 pub mod unilib;
+
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+
+#[cfg(feature = "zone")]
+pub mod violation;
+#[cfg(feature = "zone")]
+pub mod zone_gate;
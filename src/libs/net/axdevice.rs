@@ -89,6 +89,7 @@ impl Device for ShyperNet {
         caps
     }
 
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::device::receive", io_error, ret = "None"))]
     fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         let mut dev = get_network_driver().unwrap().lock();
         if let Err(e) = dev.recycle_tx_buffers() {
@@ -111,6 +112,7 @@ impl Device for ShyperNet {
         Some((AxNetRxToken(rx_buf), AxNetTxToken()))
     }
 
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::device::transmit", io_error, ret = "None"))]
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
         let mut dev = get_network_driver().unwrap().lock();
         if let Err(e) = dev.recycle_tx_buffers() {
@@ -105,6 +105,7 @@ impl Device for ShyperNet {
     ///
     /// The timestamp must be a number of milliseconds, monotonically increasing since an
     /// arbitrary moment in time, such as system startup.
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::device::receive", io_error, ret = "None"))]
     fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         // trace!("ShyperNet receive receive_rx_buffer()");
         match get_network_driver().unwrap().lock().receive_rx_buffer() {
@@ -117,6 +118,7 @@ impl Device for ShyperNet {
     ///
     /// The timestamp must be a number of milliseconds, monotonically increasing since an
     /// arbitrary moment in time, such as system startup.
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::device::transmit", io_error, ret = "None"))]
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
         // debug!("create TxToken to transfer data");
         Some(TxToken::new())
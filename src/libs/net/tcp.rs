@@ -324,6 +324,7 @@ impl TcpSocket {
         })
     }
 
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::tcp::read", io_error, ret = "Err(ShyperError::Io)"))]
     pub fn read(&self, buffer: &mut [u8]) -> Result<usize, ShyperError> {
         debug!("TcpSocket read()");
 
@@ -359,6 +360,7 @@ impl TcpSocket {
         })
     }
 
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::tcp::write", io_error, ret = "Err(ShyperError::Io)"))]
     pub fn write(&self, buffer: &[u8]) -> Result<usize, ShyperError> {
         debug!("TcpSocket write() {} bytes", buffer.len());
 
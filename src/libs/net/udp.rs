@@ -107,6 +107,7 @@ impl AsyncUdpSocket {
 
     /// Sends data on the socket to the given address. On success, returns the
     /// number of bytes written.
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::udp::send_to", io_error, ret = "Err(ShyperError::Io)"))]
     pub fn send_to(&self, buf: &[u8], remote_addr: SocketAddr) -> Result<usize, ShyperError> {
         if remote_addr.port() == 0 || remote_addr.ip().is_unspecified() {
             return Err(ShyperError::InvalidInput);
@@ -117,6 +118,7 @@ impl AsyncUdpSocket {
 
     /// Receives a single datagram message on the socket. On success, returns
     /// the number of bytes read and the origin.
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("net::udp::recv_from", io_error, ret = "Err(ShyperError::Io)"))]
     pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), ShyperError> {
         self.recv_impl(|socket| match socket.recv_slice(buf) {
             Ok((len, udpmetadata)) => Ok((len, ipendpoint_to_socketaddr(udpmetadata.endpoint))),
@@ -19,6 +19,8 @@ pub trait Scheduler {
     fn pop(&self) -> Option<Thread>;
     fn blocked(&self, thread: Thread, timeout: Option<usize>);
     fn get_wakeup_thread_by_time(&self, current_ms: usize) -> Option<Thread>;
+    /// Remove the wakeup time of a blocked thread woken up otherwise.
+    fn unblock(&self, thread: &Thread);
 }
 
 pub fn init() {
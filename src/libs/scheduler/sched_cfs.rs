@@ -30,4 +30,7 @@ impl Scheduler for CFSScheduler {
     fn get_wakeup_thread_by_time(&self, _current_ms: usize) -> Option<Thread> {
         todo!()
     }
+
+    /// No blocked thread is kept with a wakeup time yet, nothing to remove.
+    fn unblock(&self, _thread: &Thread) {}
 }
@@ -101,4 +101,8 @@ impl Scheduler for RoundRobinScheduler {
         }
         return None;
     }
+
+    fn unblock(&self, thread: &Thread) {
+        self.blocked_queue.lock().retain(|_, t| t != thread);
+    }
 }
@@ -0,0 +1,447 @@
+//! Supervised threads with restart policies, enabled by feature "supervisor".
+//!
+//! A `Supervisor` spawns its children from `extern "C" fn(usize)` entries and restarts them
+//! with their original arguments after they unwind from a panic or a fault.
+//! A child returning normally is finished and is not restarted.
+//!
+//! * `Strategy::OneForOne` restarts the failed child only.
+//! * `Strategy::OneForAll` stops the other running children and restarts all of them.
+//! * `Strategy::Backoff` restarts the failed child only, after a delay doubled by each
+//!   restart within the window.
+//!
+//! When restarts exceed `max_restarts` within `window_ms`, the supervisor gives up,
+//! the failed child exits and no child is restarted anymore.
+//! Restarts are recorded as `RestartEvent`s, see `events`, and listed by the terminal command `ps`.
+//!
+//! Children stopped by `OneForAll` unwind when they next yield, see `thread_stop`,
+//! releasing what they hold, and the failed child waits for them before restarting.
+//! Those not yielding within `STOP_TIMEOUT_MS` are destroyed without unwinding, like `kill`.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::thread::{thread_lookup, thread_spawn_name, thread_stop, Tid};
+use crate::libs::timer::current_ms;
+
+/// Restart events kept for `events`, older ones are dropped.
+const EVENT_NUM_MAX: usize = 32;
+
+/// Time for children stopped by `OneForAll` to unwind before they are destroyed.
+pub const STOP_TIMEOUT_MS: usize = 100;
+
+/// Which children to restart when one of them fails.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    OneForOne,
+    OneForAll,
+    /// Restart the failed child only, after `initial_ms` doubled by each former restart
+    /// within the window, up to `max_ms`.
+    Backoff { initial_ms: usize, max_ms: usize },
+}
+
+/// Restart strategy and intensity of a supervisor.
+#[derive(Debug, Copy, Clone)]
+pub struct RestartPolicy {
+    pub strategy: Strategy,
+    /// Restarts allowed within `window_ms` before the supervisor gives up.
+    pub max_restarts: usize,
+    pub window_ms: usize,
+}
+
+impl RestartPolicy {
+    /// A policy allowing 5 restarts in 5 seconds.
+    pub const fn new(strategy: Strategy) -> Self {
+        RestartPolicy {
+            strategy,
+            max_restarts: 5,
+            window_ms: 5000,
+        }
+    }
+
+    pub const fn max_restarts(mut self, max_restarts: usize, window_ms: usize) -> Self {
+        self.max_restarts = max_restarts;
+        self.window_ms = window_ms;
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChildState {
+    Running,
+    /// Stopped by `OneForAll`, to be restarted once it exits.
+    Stopping,
+    /// Returned normally.
+    Finished,
+    /// Failed after the supervisor gave up.
+    Failed,
+}
+
+impl fmt::Display for ChildState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildState::Running => write!(f, "running"),
+            ChildState::Stopping => write!(f, "stopping"),
+            ChildState::Finished => write!(f, "finished"),
+            ChildState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Snapshot of a supervised child.
+#[derive(Debug, Clone)]
+pub struct ChildInfo {
+    pub name: String,
+    /// Thread currently or lastly running the child.
+    pub tid: Tid,
+    pub state: ChildState,
+    pub restarts: usize,
+}
+
+/// A restart of a child, `new` is `None` if the supervisor gave up.
+#[derive(Debug, Clone)]
+pub struct RestartEvent {
+    pub supervisor: String,
+    pub child: String,
+    pub old: Tid,
+    pub new: Option<Tid>,
+    pub delay_ms: usize,
+    pub time_ms: usize,
+}
+
+impl fmt::Display for RestartEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:8}ms] {:?}/{:?} {} ",
+            self.time_ms, self.supervisor, self.child, self.old
+        )?;
+        match self.new {
+            Some(tid) if self.delay_ms != 0 => {
+                write!(f, "restarted as {} after {}ms", tid, self.delay_ms)
+            }
+            Some(tid) => write!(f, "restarted as {}", tid),
+            None => write!(f, "not restarted, supervisor gave up"),
+        }
+    }
+}
+
+struct Child {
+    name: String,
+    entry: extern "C" fn(usize),
+    arg: usize,
+    tid: Tid,
+    state: ChildState,
+    restarts: usize,
+}
+
+struct State {
+    children: Vec<Child>,
+    /// Times of restarts within the window.
+    restart_times: VecDeque<usize>,
+    restarts: usize,
+    given_up: bool,
+}
+
+struct Inner {
+    id: usize,
+    name: String,
+    policy: RestartPolicy,
+    state: SpinlockIrqSave<State>,
+}
+
+/// Handle of a supervisor, it lives as long as the kernel once created.
+#[derive(Clone)]
+pub struct Supervisor(Arc<Inner>);
+
+static SUPERVISORS: SpinlockIrqSave<Vec<Supervisor>> = SpinlockIrqSave::new(Vec::new());
+static EVENTS: SpinlockIrqSave<VecDeque<RestartEvent>> = SpinlockIrqSave::new(VecDeque::new());
+
+/// Argument of `supervised_start`, boxed and passed as the thread argument.
+struct Start {
+    supervisor: usize,
+    child: usize,
+    delay_ms: usize,
+}
+
+impl Supervisor {
+    pub fn new(name: &str, policy: RestartPolicy) -> Supervisor {
+        static SUPERVISOR_ID_ALLOCATOR: AtomicUsize = AtomicUsize::new(0);
+        let supervisor = Supervisor(Arc::new(Inner {
+            id: SUPERVISOR_ID_ALLOCATOR.fetch_add(1, Ordering::Relaxed),
+            name: String::from(name),
+            policy,
+            state: SpinlockIrqSave::new(State {
+                children: Vec::new(),
+                restart_times: VecDeque::new(),
+                restarts: 0,
+                given_up: false,
+            }),
+        }));
+        SUPERVISORS.lock().push(supervisor.clone());
+        supervisor
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn policy(&self) -> RestartPolicy {
+        self.0.policy
+    }
+
+    /// Spawn a supervised child thread named `name` running `entry(arg)`.
+    pub fn spawn(&self, name: &str, entry: extern "C" fn(usize), arg: usize) -> Tid {
+        let mut state = self.0.state.lock();
+        let child = state.children.len();
+        let tid = self.spawn_child(name, child, 0);
+        state.children.push(Child {
+            name: String::from(name),
+            entry,
+            arg,
+            tid,
+            state: ChildState::Running,
+            restarts: 0,
+        });
+        tid
+    }
+
+    /// Total restarts of all children.
+    pub fn restarts(&self) -> usize {
+        self.0.state.lock().restarts
+    }
+
+    /// Whether restarts exceeded the intensity of the policy.
+    pub fn given_up(&self) -> bool {
+        self.0.state.lock().given_up
+    }
+
+    pub fn children(&self) -> Vec<ChildInfo> {
+        self.0
+            .state
+            .lock()
+            .children
+            .iter()
+            .map(|c| ChildInfo {
+                name: c.name.clone(),
+                tid: c.tid,
+                state: c.state,
+                restarts: c.restarts,
+            })
+            .collect()
+    }
+
+    fn spawn_child(&self, name: &str, child: usize, delay_ms: usize) -> Tid {
+        let start = alloc::boxed::Box::new(Start {
+            supervisor: self.0.id,
+            child,
+            delay_ms,
+        });
+        thread_spawn_name(
+            supervised_start,
+            alloc::boxed::Box::into_raw(start) as usize,
+            name,
+        )
+    }
+
+    fn entry_of(&self, child: usize) -> (extern "C" fn(usize), usize) {
+        let state = self.0.state.lock();
+        let c = &state.children[child];
+        (c.entry, c.arg)
+    }
+
+    fn child_finished(&self, child: usize) {
+        let mut state = self.0.state.lock();
+        // A stopped child is restarted by the one which failed.
+        if state.children[child].state == ChildState::Running {
+            state.children[child].state = ChildState::Finished;
+        }
+    }
+
+    /// Apply the restart policy to a failed child, called on the failed child's thread.
+    fn child_failed(&self, child: usize) {
+        let policy = self.0.policy;
+        let now = current_ms();
+        let mut state = self.0.state.lock();
+        let failed = state.children[child].tid;
+
+        // A stopped child is restarted by the one which failed.
+        if state.children[child].state == ChildState::Stopping {
+            return;
+        }
+        if state.given_up {
+            state.children[child].state = ChildState::Failed;
+            return;
+        }
+
+        while let Some(&time) = state.restart_times.front() {
+            if now.saturating_sub(time) < policy.window_ms {
+                break;
+            }
+            state.restart_times.pop_front();
+        }
+        if state.restart_times.len() >= policy.max_restarts {
+            warn!(
+                "supervisor {:?} exceeds {} restarts in {}ms, gives up",
+                self.0.name, policy.max_restarts, policy.window_ms
+            );
+            state.given_up = true;
+            state.children[child].state = ChildState::Failed;
+            self.record(&state.children[child].name, failed, None, 0, now);
+            return;
+        }
+        let recent = state.restart_times.len();
+        state.restart_times.push_back(now);
+
+        let restart: Vec<usize> = match policy.strategy {
+            Strategy::OneForOne | Strategy::Backoff { .. } => alloc::vec![child],
+            Strategy::OneForAll => {
+                let mut stopping = Vec::new();
+                for (i, c) in state.children.iter_mut().enumerate() {
+                    if i != child && c.state == ChildState::Running {
+                        c.state = ChildState::Stopping;
+                        stopping.push((i, c.tid));
+                    }
+                }
+                // Stopped children report to the supervisor while unwinding.
+                drop(state);
+                for &(_, tid) in stopping.iter() {
+                    if let Some(t) = thread_lookup(tid) {
+                        thread_stop(t, STOP_TIMEOUT_MS);
+                    }
+                }
+                state = self.0.state.lock();
+                let mut restart: Vec<usize> = stopping.iter().map(|&(i, _)| i).collect();
+                restart.push(child);
+                restart.sort_unstable();
+                restart
+            }
+        };
+        let delay_ms = match policy.strategy {
+            Strategy::Backoff { initial_ms, max_ms } => initial_ms
+                .saturating_mul(1usize.checked_shl(recent as u32).unwrap_or(usize::MAX))
+                .min(max_ms),
+            _ => 0,
+        };
+
+        for i in restart {
+            let old = state.children[i].tid;
+            let name = state.children[i].name.clone();
+            let new = self.spawn_child(&name, i, delay_ms);
+            let c = &mut state.children[i];
+            c.tid = new;
+            c.state = ChildState::Running;
+            c.restarts += 1;
+            state.restarts += 1;
+            info!(
+                "supervisor {:?} restarts {:?} {} as {}",
+                self.0.name, name, old, new
+            );
+            self.record(&name, old, Some(new), delay_ms, now);
+        }
+    }
+
+    fn record(&self, child: &str, old: Tid, new: Option<Tid>, delay_ms: usize, time_ms: usize) {
+        let mut events = EVENTS.lock();
+        if events.len() == EVENT_NUM_MAX {
+            events.pop_front();
+        }
+        events.push_back(RestartEvent {
+            supervisor: self.0.name.clone(),
+            child: String::from(child),
+            old,
+            new,
+            delay_ms,
+            time_ms,
+        });
+    }
+}
+
+fn supervisor_by_id(id: usize) -> Option<Supervisor> {
+    SUPERVISORS.lock().iter().find(|s| s.0.id == id).cloned()
+}
+
+/// Entry of supervised children, runs the child's entry and reports how it ends.
+extern "C" fn supervised_start(arg: usize) {
+    #[cfg(not(feature = "std"))]
+    use crate::libs::unwind::catch::catch_unwind;
+    #[cfg(feature = "std")]
+    use std::panic::catch_unwind;
+
+    let start = unsafe { alloc::boxed::Box::from_raw(arg as *mut Start) };
+    let Start {
+        supervisor,
+        child,
+        delay_ms,
+    } = *start;
+    let supervisor = match supervisor_by_id(supervisor) {
+        Some(s) => s,
+        None => return,
+    };
+    if delay_ms != 0 {
+        crate::libs::thread::thread_block_current_with_timeout_us(delay_ms * 1000);
+    }
+
+    let (entry, arg) = supervisor.entry_of(child);
+    #[cfg(feature = "lock-poison")]
+    let lock_seq = crate::libs::synch::poison::last_seq();
+    match catch_unwind(|| entry(arg)) {
+        Ok(_) => supervisor.child_finished(child),
+        Err(_) => {
+            #[cfg(feature = "lock-poison")]
+            crate::libs::synch::poison::release_since(lock_seq, "unwound");
+            #[cfg(feature = "quota")]
+            if let Ok(t) = crate::libs::thread::current_thread() {
+                t.quota().set_unwinding(false);
+            }
+            supervisor.child_failed(child)
+        }
+    }
+}
+
+/// Recent restart events, from the oldest.
+pub fn events() -> Vec<RestartEvent> {
+    EVENTS.lock().iter().cloned().collect()
+}
+
+pub fn supervisors() -> Vec<Supervisor> {
+    SUPERVISORS.lock().clone()
+}
+
+/// List supervisors, their children and recent restart events.
+pub fn list_supervisors() {
+    let supervisors = supervisors();
+    if supervisors.is_empty() {
+        return;
+    }
+    println!("SUPERVISOR");
+    for s in supervisors.iter() {
+        let policy = s.policy();
+        println!(
+            "-{:?} {:?}, {} restarts, max {} in {}ms{}",
+            s.name(),
+            policy.strategy,
+            s.restarts(),
+            policy.max_restarts,
+            policy.window_ms,
+            if s.given_up() { ", gave up" } else { "" }
+        );
+        for c in s.children() {
+            println!(
+                " -[{:4}] {}\trestarts {}\t{:?}",
+                c.tid.0, c.state, c.restarts, c.name
+            );
+        }
+    }
+    let events = events();
+    if !events.is_empty() {
+        println!("RESTARTS");
+        for e in events.iter() {
+            println!("-{}", e);
+        }
+    }
+}
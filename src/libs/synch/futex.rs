@@ -11,7 +11,7 @@ use crate::libs::synch::spinlock::SpinlockIrqSave;
 use crate::libs::timer::current_us;
 use crate::libs::thread::{
     Thread, current_thread, thread_yield, thread_block_current_with_timeout_us,
-    thread_block_current, thread_wake,
+    thread_block_current, thread_wake, Status,
 };
 
 static PARKING_LOT: SpinlockIrqSave<HashMap<usize, VecDeque<Thread>, RandomState>> =
@@ -134,6 +134,8 @@ pub fn futex_wake(address: &AtomicU32, count: i32) -> i32 {
     let mut woken = 0;
     while woken != count || count == i32::MAX {
         match queue.get_mut().pop_front() {
+            // Waiters which died waiting don't take wakeups from live ones.
+            Some(t) if t.status() == Status::Exited => continue,
             Some(t) => thread_wake(t),
             None => break,
         }
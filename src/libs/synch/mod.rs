@@ -1,3 +1,4 @@
+pub mod poison;
 pub mod semaphore;
 pub mod spinlock;
 
@@ -0,0 +1,252 @@
+//! Poisoning of locks and semaphores whose holders died holding them.
+//!
+//! A lock guard dropped by a landing pad during unwinding releases its lock as usual.
+//! A guard is abandoned when its frame is discarded without running its drop:
+//! the faulting frame of an exception has no landing pad for a faulting load or store,
+//! unwinding may fail and exit the thread, and a destroyed thread never runs again.
+//!
+//! With feature "lock-poison", each guard of a `Spinlock` or `SpinlockIrqSave` registers
+//! itself in a slot of its thread's `HeldLocks` and deregisters in its drop.
+//! Locks whose guards are still registered are poisoned and released when no frame
+//! of the thread can own them anymore: all of them when the thread exits or is destroyed,
+//! those registered since the catch or the checkpoint when a panic is caught
+//! or the thread rolls back. Guards must not outlive the thread, or the run, acquiring them.
+//! Waiters then acquire the locks instead of spinning forever and see the poison
+//! with `lock_checked`. A guard released this way no longer touches its lock on drop.
+//! Owned semaphores are handled by `Semaphore` itself.
+
+use core::fmt;
+#[cfg(feature = "lock-poison")]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Error of a lock or semaphore acquired after its holder died holding it,
+/// the state it protects may be inconsistent.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+impl<G> PoisonError<G> {
+    pub fn new(guard: G) -> Self {
+        PoisonError { guard }
+    }
+
+    /// Get the guard anyway.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "poisoned lock: its holder died holding it")
+    }
+}
+
+/// Max locks tracked per thread, further ones are not released on death.
+#[cfg(feature = "lock-poison")]
+const HELD_LOCKS_MAX: usize = 16;
+
+/// `HeldLock::seq` of a slot being filled.
+#[cfg(feature = "lock-poison")]
+const SLOT_RESERVED: usize = usize::MAX;
+
+/// A lock held by ticket `ticket`, whose `dequeue` and `poisoned` are given by address.
+#[cfg(feature = "lock-poison")]
+pub struct HeldLock {
+    /// Registration number of the guard, zero if the slot is free.
+    seq: AtomicUsize,
+    dequeue: AtomicUsize,
+    ticket: AtomicUsize,
+    poisoned: AtomicUsize,
+}
+
+#[cfg(feature = "lock-poison")]
+impl HeldLock {
+    const fn new() -> Self {
+        HeldLock {
+            seq: AtomicUsize::new(0),
+            dequeue: AtomicUsize::new(0),
+            ticket: AtomicUsize::new(0),
+            poisoned: AtomicUsize::new(0),
+        }
+    }
+
+    /// Free the slot if it's still registered by `seq`.
+    fn take(&self, seq: usize) -> bool {
+        seq != 0
+            && seq != SLOT_RESERVED
+            && self
+                .seq
+                .compare_exchange(seq, 0, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    /// Poison and release the lock if it's still held by the guard registered as `seq`.
+    fn poison_and_release(&self, seq: usize) -> bool {
+        let ticket = self.ticket.load(Ordering::Relaxed);
+        let dequeue = self.dequeue.load(Ordering::Relaxed);
+        let poisoned = self.poisoned.load(Ordering::Relaxed);
+        if !self.take(seq) {
+            return false;
+        }
+        let dequeue = unsafe { &*(dequeue as *const AtomicUsize) };
+        let poisoned = unsafe { &*(poisoned as *const AtomicBool) };
+        if dequeue.load(Ordering::Acquire) != ticket {
+            return false;
+        }
+        poisoned.store(true, Ordering::Relaxed);
+        dequeue.store(ticket + 1, Ordering::Release);
+        true
+    }
+}
+
+/// Registration of a guard, kept in the guard itself.
+#[cfg(feature = "lock-poison")]
+#[derive(Clone, Copy)]
+pub struct HeldRef {
+    slot: &'static HeldLock,
+    seq: usize,
+}
+
+#[cfg(feature = "lock-poison")]
+impl HeldRef {
+    /// Deregister the guard, called by its drop.
+    /// Returns false if its lock was already released as abandoned,
+    /// then the guard must not release it again.
+    pub fn deregister(self) -> bool {
+        self.slot.take(self.seq)
+    }
+}
+
+/// Locks held by a thread.
+#[cfg(feature = "lock-poison")]
+pub struct HeldLocks {
+    locks: [HeldLock; HELD_LOCKS_MAX],
+    /// Last registration number.
+    seq: AtomicUsize,
+    untracked: AtomicUsize,
+}
+
+#[cfg(feature = "lock-poison")]
+impl HeldLocks {
+    pub const fn new() -> Self {
+        const SLOT: HeldLock = HeldLock::new();
+        HeldLocks {
+            locks: [SLOT; HELD_LOCKS_MAX],
+            seq: AtomicUsize::new(0),
+            untracked: AtomicUsize::new(0),
+        }
+    }
+
+    /// Register a guard of a lock acquired by `ticket`,
+    /// returns the registration the guard keeps, or None if all slots are used.
+    fn register(
+        &'static self,
+        dequeue: &AtomicUsize,
+        ticket: usize,
+        poisoned: &AtomicBool,
+    ) -> Option<HeldRef> {
+        for slot in self.locks.iter() {
+            if slot
+                .seq
+                .compare_exchange(0, SLOT_RESERVED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                let seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+                slot.ticket.store(ticket, Ordering::Relaxed);
+                slot.dequeue
+                    .store(dequeue as *const _ as usize, Ordering::Relaxed);
+                slot.poisoned
+                    .store(poisoned as *const _ as usize, Ordering::Relaxed);
+                slot.seq.store(seq, Ordering::Release);
+                return Some(HeldRef { slot, seq });
+            }
+        }
+        self.untracked.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Last registration number, guards registered later have greater ones.
+    pub fn last_seq(&self) -> usize {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    /// Poison and release the locks whose guards were registered after `after`
+    /// and are still registered, returns the number of locks released.
+    pub fn release_since(&self, after: usize) -> usize {
+        self.locks
+            .iter()
+            .filter(|slot| {
+                let seq = slot.seq.load(Ordering::Acquire);
+                seq > after && slot.poison_and_release(seq)
+            })
+            .count()
+    }
+
+    /// Poison and release all locks, called when no frame of the thread can own them.
+    pub fn release_all(&self) -> usize {
+        self.release_since(0)
+    }
+
+    /// Number of locks acquired while all slots were used.
+    pub fn untracked(&self) -> usize {
+        self.untracked.load(Ordering::Relaxed)
+    }
+}
+
+/// Register a guard of a lock acquired by current thread, see `HeldLocks::register`.
+#[cfg(feature = "lock-poison")]
+#[inline(always)]
+pub(super) fn track(
+    dequeue: &AtomicUsize,
+    ticket: usize,
+    poisoned: &AtomicBool,
+) -> Option<HeldRef> {
+    crate::libs::thread::current_held_locks()?.register(dequeue, ticket, poisoned)
+}
+
+/// Last registration number of current thread, see `HeldLocks::last_seq`.
+#[cfg(feature = "lock-poison")]
+pub fn last_seq() -> usize {
+    crate::libs::thread::current_held_locks().map_or(0, |held| held.last_seq())
+}
+
+/// Poison and release the locks whose guards were registered by current thread after
+/// `after` and are still registered, called once the frames that may own them are gone:
+/// when a panic is caught or before a rollback to a point where `last_seq` was `after`.
+#[cfg(feature = "lock-poison")]
+pub fn release_since(after: usize, frames: &str) {
+    if let Some(held) = crate::libs::thread::current_held_locks() {
+        let released = held.release_since(after);
+        if released != 0 {
+            warn!(
+                "Thread [{}] released {} locks held by {} frames, poisoned",
+                crate::libs::thread::current_thread_id(),
+                released,
+                frames
+            );
+        }
+    }
+}
+
+/// Poison and release all locks held by current thread, called when unwinding fails.
+#[cfg(feature = "lock-poison")]
+pub fn release_all() {
+    release_since(0, "unwinding");
+}
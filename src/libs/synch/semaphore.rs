@@ -1,14 +1,42 @@
 use crate::libs::thread::{
-    current_thread, thread_block_current, thread_wake_to_front, Thread, thread_yield,
+    current_thread, thread_block_current, thread_block_current_with_timeout_us,
+    thread_wake_blocked_to_front, Status, Thread, thread_yield,
 };
 
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 
-use super::spinlock::SpinlockIrqSave;
+use super::poison::PoisonError;
+use super::spinlock::{SpinlockIrqSave, SpinlockIrqSaveGuard};
+
+/// Interval in which waiters of an owned semaphore check whether its owners are alive.
+const OWNER_CHECK_US: usize = 10_000;
 
 struct SemaphoreState {
     value: isize,
     queue: Option<VecDeque<Thread>>,
+    /// Threads holding a resource, for semaphores created by `new_owned`.
+    owners: Option<Vec<Thread>>,
+    poisoned: bool,
+}
+
+impl SemaphoreState {
+    /// Take back the resources of owners which died holding them.
+    fn reclaim_dead_owners(&mut self) {
+        if let Some(owners) = &mut self.owners {
+            let alive = owners.len();
+            owners.retain(|t| t.status() != Status::Exited);
+            let dead = alive - owners.len();
+            if dead != 0 {
+                warn!(
+                    "semaphore reclaims {} resources of dead owners, poisoned",
+                    dead
+                );
+                self.value += dead as isize;
+                self.poisoned = true;
+            }
+        }
+    }
 }
 
 pub struct Semaphore {
@@ -27,7 +55,64 @@ unsafe impl Send for Semaphore {}
 impl Semaphore {
     pub const fn new(value: isize) -> Self {
         Semaphore {
-            inner: SpinlockIrqSave::new(SemaphoreState { value, queue: None }),
+            inner: SpinlockIrqSave::new(SemaphoreState {
+                value,
+                queue: None,
+                owners: None,
+                poisoned: false,
+            }),
+        }
+    }
+
+    /// Creates a semaphore whose resources are owned by the threads acquiring them
+    /// until they release them, like a lock.
+    ///
+    /// Resources of owners which die holding them are taken back and the semaphore
+    /// is poisoned, see `acquire_checked`. It must not be used for signaling,
+    /// where resources are released by other threads than the ones acquiring them.
+    pub const fn new_owned(value: isize) -> Self {
+        Semaphore {
+            inner: SpinlockIrqSave::new(SemaphoreState {
+                value,
+                queue: None,
+                owners: Some(Vec::new()),
+                poisoned: false,
+            }),
+        }
+    }
+
+    /// Lock the state, a thread which died holding the lock poisons the semaphore.
+    fn state(&self) -> SpinlockIrqSaveGuard<'_, SemaphoreState> {
+        match self.inner.lock_checked() {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("semaphore state lock poisoned, a thread died holding it");
+                self.inner.clear_poison();
+                let mut state = e.into_inner();
+                state.poisoned = true;
+                state
+            }
+        }
+    }
+
+    /// Whether an owner died holding a resource, or a thread holding its state lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.state().poisoned
+    }
+
+    /// Mark the protected state consistent again after recovering a poisoned semaphore.
+    pub fn clear_poison(&self) {
+        self.state().poisoned = false;
+    }
+
+    /// Acquires a resource like `acquire`, fails if the semaphore is poisoned,
+    /// the resource is acquired anyway.
+    pub fn acquire_checked(&self) -> Result<(), PoisonError<()>> {
+        self.acquire();
+        if self.is_poisoned() {
+            Err(PoisonError::new(()))
+        } else {
+            Ok(())
         }
     }
 
@@ -37,21 +122,28 @@ impl Semaphore {
     /// This method will block until the internal count of the semaphore is at
     /// least 1.
     #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("synch::semaphore::acquire"))]
     pub fn acquire(&self) {
         // Loop until we have acquired the semaphore.
         loop {
             trace!("acquire loop");
             match current_thread() {
                 Ok(t) => {
-                    let mut inner = self.inner.lock();
+                    let mut inner = self.state();
                     if inner.value <= 0 {
-                        thread_block_current();
-                        if let Some(queue) = &mut inner.queue {
-                            queue.push_back(t.clone());
+                        inner.reclaim_dead_owners();
+                    }
+                    if inner.value <= 0 {
+                        // Waiters of an owned semaphore wake up in time to check its owners.
+                        if inner.owners.is_some() {
+                            thread_block_current_with_timeout_us(OWNER_CHECK_US);
                         } else {
-                            let mut queue = VecDeque::new();
+                            thread_block_current();
+                        }
+                        let queue = inner.queue.get_or_insert_with(VecDeque::new);
+                        // It's still queued if it woke up by timeout.
+                        if !queue.contains(&t) {
                             queue.push_back(t.clone());
-                            inner.queue = Some(queue);
                         }
                         /* Before yield, we need to drop the lock. */
                         drop(inner);
@@ -60,6 +152,13 @@ impl Semaphore {
                     } else {
                         // Successfully acquired the semaphore.
                         inner.value -= 1;
+                        // Waiters woken up by timeout may acquire it while still queued.
+                        if let Some(queue) = &mut inner.queue {
+                            queue.retain(|q| *q != t);
+                        }
+                        if let Some(owners) = &mut inner.owners {
+                            owners.push(t);
+                        }
                         debug!(
                             "semaphore acquire success, current value {}, return",
                             inner.value
@@ -80,8 +179,9 @@ impl Semaphore {
     /// This will increment the number of resources in this semaphore by 1 and
     /// will notify any pending waiters in `acquire` or `access` if necessary.
     #[cfg_attr(feature = "unwind-test", inject::panic_inject, inject::count_stmts)]
+    #[cfg_attr(feature = "fault-inject", inject::fault_site("synch::semaphore::release"))]
     pub fn release(&self) {
-        let mut inner = self.inner.lock();
+        let mut inner = self.state();
         debug!(
             "semaphore release on thread [{}], value from {} to ({})",
             crate::libs::thread::current_thread_id(),
@@ -89,11 +189,27 @@ impl Semaphore {
             inner.value + 1
         );
         inner.value += 1;
+        if let Some(owners) = &mut inner.owners {
+            match current_thread() {
+                Ok(current) => match owners.iter().position(|t| *t == current) {
+                    Some(i) => {
+                        owners.swap_remove(i);
+                    }
+                    None => warn!("owned semaphore released by a thread not holding it"),
+                },
+                Err(_) => warn!("failed to get current_thread"),
+            }
+        }
         if let Some(queue) = &mut inner.queue {
-            if let Some(t) = queue.pop_front() {
-                /* Before yield, we need to drop the lock. */
-                drop(inner);
-                thread_wake_to_front(t);
+            // Skip waiters which died waiting, or their wakeups are lost.
+            while let Some(t) = queue.pop_front() {
+                if t.status() != Status::Exited {
+                    /* Before yield, we need to drop the lock. */
+                    drop(inner);
+                    // A waiter which woke up by timeout is not Blocked, and acquires it by itself.
+                    thread_wake_blocked_to_front(t);
+                    break;
+                }
             }
         }
     }
@@ -5,9 +5,15 @@ use core::cell::UnsafeCell;
 use core::fmt;
 use core::marker::Sync;
 use core::ops::{Deref, DerefMut, Drop};
+#[cfg(feature = "lock-poison")]
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use crossbeam_utils::{Backoff, CachePadded};
 
+#[cfg(feature = "lock-poison")]
+use super::poison::{track, HeldRef};
+use super::poison::{LockResult, PoisonError};
+
 /// This type provides a lock based on busy waiting to realize mutual exclusion of tasks.
 ///
 /// # Description
@@ -43,6 +49,9 @@ use crossbeam_utils::{Backoff, CachePadded};
 pub struct Spinlock<T: ?Sized> {
     queue: CachePadded<AtomicUsize>,
     dequeue: CachePadded<AtomicUsize>,
+    /// Set if a holder died holding it, see `poison`.
+    #[cfg(feature = "lock-poison")]
+    poisoned: AtomicBool,
     data: UnsafeCell<T>,
 }
 
@@ -52,6 +61,8 @@ pub struct Spinlock<T: ?Sized> {
 pub struct SpinlockGuard<'a, T: ?Sized> {
     dequeue: &'a CachePadded<AtomicUsize>,
     ticket: usize,
+    #[cfg(feature = "lock-poison")]
+    held: Option<HeldRef>,
     data: &'a mut T,
 }
 
@@ -64,6 +75,8 @@ impl<T> Spinlock<T> {
         Spinlock {
             queue: CachePadded::new(AtomicUsize::new(0)),
             dequeue: CachePadded::new(AtomicUsize::new(1)),
+            #[cfg(feature = "lock-poison")]
+            poisoned: AtomicBool::new(false),
             data: UnsafeCell::new(user_data),
         }
     }
@@ -79,6 +92,34 @@ impl<T> Spinlock<T> {
 }
 
 impl<T: ?Sized> Spinlock<T> {
+    /// Whether a holder died holding it, see `poison`.
+    #[cfg(feature = "lock-poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Locks are never poisoned without feature "lock-poison".
+    #[cfg(not(feature = "lock-poison"))]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    /// Mark the protected data consistent again after recovering a poisoned lock.
+    pub fn clear_poison(&self) {
+        #[cfg(feature = "lock-poison")]
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Acquire the lock, fails with the guard if it's poisoned.
+    pub fn lock_checked(&self) -> LockResult<SpinlockGuard<'_, T>> {
+        let guard = self.lock();
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
     pub fn lock(&self) -> SpinlockGuard<'_, T> {
         let backoff = Backoff::new();
         let ticket = self.queue.fetch_add(1, Ordering::Relaxed) + 1;
@@ -90,6 +131,8 @@ impl<T: ?Sized> Spinlock<T> {
         SpinlockGuard {
             dequeue: &self.dequeue,
             ticket,
+            #[cfg(feature = "lock-poison")]
+            held: track(&self.dequeue, ticket, &self.poisoned),
             data: unsafe { &mut *self.data.get() },
         }
     }
@@ -106,6 +149,8 @@ impl<T: ?Sized> Spinlock<T> {
             .map(|ticket| SpinlockGuard {
                 dequeue: &self.dequeue,
                 ticket: ticket + 1,
+                #[cfg(feature = "lock-poison")]
+                held: track(&self.dequeue, ticket + 1, &self.poisoned),
                 data: unsafe { &mut *self.data.get() },
             })
             .map_err(|_| {})
@@ -141,6 +186,13 @@ impl<'a, T: ?Sized> DerefMut for SpinlockGuard<'a, T> {
 impl<'a, T: ?Sized> Drop for SpinlockGuard<'a, T> {
     /// The dropping of the SpinlockGuard will release the lock it was created from.
     fn drop(&mut self) {
+        // Released already if it was abandoned, see `poison`.
+        #[cfg(feature = "lock-poison")]
+        if let Some(held) = self.held {
+            if !held.deregister() {
+                return;
+            }
+        }
         self.dequeue.store(self.ticket + 1, Ordering::Release);
     }
 }
@@ -181,6 +233,9 @@ impl<'a, T: ?Sized> Drop for SpinlockGuard<'a, T> {
 pub struct SpinlockIrqSave<T: ?Sized> {
     queue: CachePadded<AtomicUsize>,
     dequeue: CachePadded<AtomicUsize>,
+    /// Set if a holder died holding it, see `poison`.
+    #[cfg(feature = "lock-poison")]
+    poisoned: AtomicBool,
     data: UnsafeCell<T>,
 }
 
@@ -191,6 +246,8 @@ pub struct SpinlockIrqSaveGuard<'a, T: ?Sized> {
     dequeue: &'a CachePadded<AtomicUsize>,
     ticket: usize,
     irq: bool,
+    #[cfg(feature = "lock-poison")]
+    held: Option<HeldRef>,
     data: &'a mut T,
 }
 
@@ -203,6 +260,8 @@ impl<T> SpinlockIrqSave<T> {
         SpinlockIrqSave {
             queue: CachePadded::new(AtomicUsize::new(0)),
             dequeue: CachePadded::new(AtomicUsize::new(1)),
+            #[cfg(feature = "lock-poison")]
+            poisoned: AtomicBool::new(false),
             data: UnsafeCell::new(user_data),
         }
     }
@@ -218,6 +277,39 @@ impl<T> SpinlockIrqSave<T> {
 }
 
 impl<T: ?Sized> SpinlockIrqSave<T> {
+    /// Whether the lock is currently held, only a hint for deadlock avoidance.
+    pub fn is_locked(&self) -> bool {
+        self.dequeue.load(Ordering::Relaxed) != self.queue.load(Ordering::Relaxed) + 1
+    }
+
+    /// Whether a holder died holding it, see `poison`.
+    #[cfg(feature = "lock-poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Locks are never poisoned without feature "lock-poison".
+    #[cfg(not(feature = "lock-poison"))]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    /// Mark the protected data consistent again after recovering a poisoned lock.
+    pub fn clear_poison(&self) {
+        #[cfg(feature = "lock-poison")]
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Acquire the lock, fails with the guard if it's poisoned.
+    pub fn lock_checked(&self) -> LockResult<SpinlockIrqSaveGuard<'_, T>> {
+        let guard = self.lock();
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
     pub fn try_lock(&self) -> Result<SpinlockIrqSaveGuard<'_, T>, ()> {
         let irq = irq::nested_disable();
         self.queue
@@ -232,6 +324,8 @@ impl<T: ?Sized> SpinlockIrqSave<T> {
                 dequeue: &self.dequeue,
                 ticket: ticket + 1,
                 irq,
+                #[cfg(feature = "lock-poison")]
+                held: track(&self.dequeue, ticket + 1, &self.poisoned),
                 data: unsafe { &mut *self.data.get() },
             })
             .map_err(|_| irq::nested_enable(irq))
@@ -250,6 +344,8 @@ impl<T: ?Sized> SpinlockIrqSave<T> {
             dequeue: &self.dequeue,
             ticket,
             irq,
+            #[cfg(feature = "lock-poison")]
+            held: track(&self.dequeue, ticket, &self.poisoned),
             data: unsafe { &mut *self.data.get() },
         }
     }
@@ -284,6 +380,14 @@ impl<'a, T: ?Sized> DerefMut for SpinlockIrqSaveGuard<'a, T> {
 impl<'a, T: ?Sized> Drop for SpinlockIrqSaveGuard<'a, T> {
     /// The dropping of the SpinlockGuard will release the lock it was created from.
     fn drop(&mut self) {
+        // Released already if it was abandoned, see `poison`.
+        #[cfg(feature = "lock-poison")]
+        if let Some(held) = self.held {
+            if !held.deregister() {
+                irq::nested_enable(self.irq);
+                return;
+            }
+        }
         self.dequeue.store(self.ticket + 1, Ordering::Release);
         irq::nested_enable(self.irq);
     }
@@ -89,12 +89,18 @@ fn exec_cmd(cmd: &str) {
     };
     match command {
         "cat" => handle_cat(cmds.next()),
+        "allocs" => handle_allocs(cmds.next(), cmds.next(), cmds.next()),
         "free" => crate::mm::dump_mm_usage(),
+        "heapcheck" => handle_heapcheck(),
+        "inject" => handle_inject(cmds.next(), [cmds.next(), cmds.next(), cmds.next(), cmds.next()]),
         "kill" => handle_kill(cmds.next()),
         "ls" => handle_ls(cmds.next()),
         "mkdir" => handle_mkdir(cmds.next()),
-        "ps" => crate::libs::thread::list_threads(),
+        "ps" => handle_ps(),
         "run" => handle_run(cmds.next()),
+        "vmmap" => handle_vmmap(cmds.next()),
+        "watchdog" => handle_watchdog(),
+        "wxcheck" => handle_wxcheck(),
         "help" => print_help(),
         _ => println!(
             "command not found: \"{}\", please input 'help' for more info.",
@@ -103,6 +109,97 @@ fn exec_cmd(cmd: &str) {
     }
 }
 
+fn handle_ps() {
+    crate::libs::thread::list_threads();
+    #[cfg(feature = "supervisor")]
+    crate::libs::supervisor::list_supervisors();
+}
+
+fn handle_allocs(_op: Option<&str>, _from: Option<&str>, _to: Option<&str>) {
+    #[cfg(feature = "alloc-track")]
+    {
+        use crate::mm::heap_track;
+        match _op {
+            None => heap_track::dump_live(),
+            Some("snap") => println!("allocs: snapshot {} saved", heap_track::save_snapshot()),
+            Some("diff") => {
+                let from = _from.and_then(|s| s.parse::<usize>().ok());
+                let to = _to.and_then(|s| s.parse::<usize>().ok());
+                if let Err(e) = heap_track::dump_diff(from, to) {
+                    println!("allocs: diff failed, {}", e);
+                }
+            }
+            Some(op) => println!("allocs: unknown operation \"{}\"", op),
+        }
+    }
+    #[cfg(not(feature = "alloc-track"))]
+    println!("allocs: \"alloc-track\" feature is required.");
+}
+
+fn handle_heapcheck() {
+    #[cfg(feature = "heap-debug")]
+    {
+        let corrupted = crate::mm::heap_debug::check_all();
+        println!(
+            "heapcheck: {} live blocks, {} corrupted",
+            crate::mm::heap_debug::live_blocks(),
+            corrupted
+        );
+    }
+    #[cfg(not(feature = "heap-debug"))]
+    println!("heapcheck: \"heap-debug\" feature is required.");
+}
+
+fn handle_inject(_op: Option<&str>, _args: [Option<&str>; 4]) {
+    #[cfg(feature = "fault-inject")]
+    {
+        use crate::libs::fault_inject::{self, Fault};
+        match (_op, _args) {
+            (None, _) => fault_inject::list_sites(),
+            (Some("seed"), [Some(seed), ..]) => match seed.parse::<u64>() {
+                Ok(seed) => fault_inject::set_seed(seed),
+                Err(_) => println!("inject: illegal seed \"{}\"", seed),
+            },
+            (Some("set"), [Some(site), Some(fault), Some(probability), latency]) => {
+                let fault = match (fault.parse::<Fault>(), latency.map(|l| l.parse::<u32>())) {
+                    (Ok(Fault::Latency(_)), Some(Ok(us))) => Ok(Fault::Latency(us)),
+                    (Ok(fault), None) => Ok(fault),
+                    (Ok(_), Some(_)) => Err("illegal latency"),
+                    (Err(e), _) => Err(e),
+                };
+                let probability =
+                    fault_inject::parse_probability(probability).ok_or("illegal probability");
+                match fault.and_then(|f| probability.and_then(|p| fault_inject::configure(site, f, p))) {
+                    Ok(matched) => println!("inject: {} registered sites matched", matched),
+                    Err(e) => println!("inject: {}", e),
+                }
+            }
+            (Some("seed" | "set"), _) => {
+                println!("[warning] missing arguments in inject, please input \"help\" for more info.")
+            }
+            (Some("off"), _) => fault_inject::disable_all(),
+            (Some("reset"), _) => fault_inject::reset_counters(),
+            (Some(op), _) => println!("inject: unknown operation \"{}\"", op),
+        }
+    }
+    #[cfg(not(feature = "fault-inject"))]
+    println!("inject: \"fault-inject\" feature is required.");
+}
+
+fn handle_watchdog() {
+    #[cfg(feature = "watchdog")]
+    crate::libs::watchdog::print_status();
+    #[cfg(not(feature = "watchdog"))]
+    println!("watchdog: \"watchdog\" feature is required.");
+}
+
+fn handle_wxcheck() {
+    #[cfg(feature = "wx-check")]
+    println!("wxcheck: {} violations", crate::mm::wx::self_check());
+    #[cfg(not(feature = "wx-check"))]
+    println!("wxcheck: \"wx-check\" feature is required.");
+}
+
 fn handle_cat(_arg: Option<&str>) {
     #[cfg(feature = "fs")]
     match _arg {
@@ -186,6 +283,16 @@ fn handle_kill(arg: Option<&str>) {
     }
 }
 
+fn handle_vmmap(arg: Option<&str>) {
+    match arg.map(|arg| arg.parse::<usize>()) {
+        None => crate::mm::vmmap::dump_vmmap(None),
+        Some(Ok(tid)) => crate::mm::vmmap::dump_vmmap(Some(tid.into())),
+        Some(Err(_)) => {
+            println!("[warning] illegal argument in vmmap, please input \"help\" for more info.");
+        }
+    }
+}
+
 fn handle_run(arg: Option<&str>) {
     let arg = match arg {
         Some(arg) => arg.parse::<usize>(),
@@ -212,13 +319,19 @@ fn print_help() {
         "This is unishyper,\n",
         "a research unikernel targeting a scalable and predictable runtime for embedded devices.\n",
         "List of classes of commands:\n\n",
+        "allocs [snap|diff [A] [B]]\t-- Dump live heap allocations by call site, take a snapshot or diff two snapshots, \"alloc-track\" feature is required.\n",
         "cat [FILE]\t-- Concatenate files and print on the standard output, \"fs\" feature is required.\n",
         "free \t\t-- Dump memory usage info.\n",
+        "heapcheck \t-- Check red zones and poisoned memory of heap blocks, \"heap-debug\" feature is required.\n",
+        "inject [seed N|set SITE FAULT P [US]|off|reset]\t-- List fault sites with hit counters, set the seed, inject FAULT (panic, pagefault, latency or error) at SITE (ID or prefix*) with probability P (0.01 or 1%), \"fault-inject\" feature is required.\n",
         "kill [TID]\t-- Kill target thread according to TID, you can use \"ps\" command to check running threads.\n",
         "ls [DIR]\t-- List information about the FILEs (the current directory by default), \"fs\" feature is required.\n",
         "mkdir [DIR]\t-- Create the DIRECTORY, if they do not already exist, \"fs\" feature is required.\n",
-        "ps \t\t-- Report a snapshot of the current threads, you can use \"run [TID]\" to wake the ready ones.\n",
+        "ps \t\t-- Report a snapshot of the current threads and supervisors, you can use \"run [TID]\" to wake the ready ones.\n",
         "run [TID]\t-- Run target thread according to TID, you can use \"ps\" command to check available threads.\n",
+        "vmmap [TID]\t-- Dump page table mappings of target thread (the whole page table by default), with granularity, permissions and zone.\n",
+        "watchdog \t-- Report the hardware watchdog, heartbeat health and liveness checks, \"watchdog\" feature is required.\n",
+        "wxcheck \t-- Check that no mapping is both writable and executable, \"wx-check\" feature is required.\n",
         "help \t\t-- Print this message.\n"
     ));
 }
@@ -5,6 +5,7 @@ use alloc::sync::Arc;
 use alloc::boxed::Box;
 use core::fmt;
 use core::mem;
+use core::ops::Range;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
 use core::cell::UnsafeCell;
@@ -17,14 +18,22 @@ use crate::libs::cpu::{CoreId, cpu, get_cpu};
 use crate::libs::scheduler::Scheduler;
 use crate::libs::error::*;
 use crate::libs::synch::spinlock::{SpinlockIrqSave, Spinlock};
+#[cfg(feature = "lock-poison")]
+use crate::libs::synch::poison::HeldLocks;
+#[cfg(feature = "checkpoint")]
+use crate::libs::checkpoint::Checkpoint;
+#[cfg(feature = "zone")]
+use crate::libs::zone_gate::GateStack;
 use crate::mm::address::VAddr;
 use crate::mm::stack::Stack;
 use crate::mm::paging::MappedRegion;
 use crate::mm::config::STACK_SIZE;
+use crate::mm::interface::MapGranularity;
+#[cfg(feature = "quota")]
+use crate::mm::quota::{MemQuota, QuotaKind};
 use crate::util::{round_up, irqsave};
+use crate::panic::{PanicHook, PanicPolicy, ThreadPanic};
 
-#[cfg(feature = "zone")]
-use zone::ZoneKeys;
 
 pub const MAIN_THREAD_ID: usize = 100;
 
@@ -101,6 +110,10 @@ struct Inner {
     #[allow(unused)]
     stack: Stack,
     tls: crate::libs::tls::ThreadTls,
+    #[cfg(feature = "quota")]
+    quota: Arc<MemQuota>,
+    /// Start function, entry and argument the thread was allocated with, for restarting it.
+    start: (usize, usize, usize),
 }
 
 struct InnerMut {
@@ -115,8 +128,15 @@ struct InnerMut {
     waiting_queue: Spinlock<VecDeque<Thread>>,
     #[cfg(feature = "zone")]
     zone_id: Mutex<zone::ZoneId>,
+    /// Gate stacks cached for zone calls, see `zone_gate`.
     #[cfg(feature = "zone")]
-    zone_keys: Mutex<zone::ZoneKeys>,
+    gate_stacks: Mutex<alloc::vec::Vec<GateStack>>,
+    /// Locks held, poisoned and released if the thread dies holding them.
+    #[cfg(feature = "lock-poison")]
+    held_locks: HeldLocks,
+    panic: ThreadPanic,
+    #[cfg(feature = "checkpoint")]
+    checkpoint: Mutex<Option<Box<Checkpoint>>>,
 }
 
 unsafe impl Send for InnerMut {}
@@ -130,6 +150,86 @@ struct ControlBlock {
 impl Drop for ControlBlock {
     fn drop(&mut self) {
         trace!("Drop Thread [{}]'s ControlBlock", self.inner.uuid);
+        // Regions still held by this thread are dropped with it,
+        // give them back to the zone's quota.
+        #[cfg(feature = "quota")]
+        {
+            #[cfg(feature = "zone")]
+            let zone_id = *self.inner_mut.zone_id.lock();
+            #[cfg(not(feature = "zone"))]
+            let zone_id = 0;
+            crate::mm::quota::uncharge_region(
+                &self.inner.quota,
+                zone_id,
+                self.inner.quota.used(QuotaKind::Pages),
+            );
+        }
+        #[cfg(feature = "zone")]
+        zone_put(*self.inner_mut.zone_id.lock());
+    }
+}
+
+/// Drop a reference to the zone, the last one gives the zone id back,
+/// or retires it until its arena is no longer in use.
+#[cfg(feature = "zone")]
+pub(crate) fn zone_put(zone_id: zone::ZoneId) {
+    if zone::zone_put(zone_id) {
+        match crate::mm::zone_heap::release(zone_id) {
+            Ok(()) => zone::zone_free(zone_id),
+            Err(e) => warn!("zone {} is retired until its arena is empty: {}", zone_id, e),
+        }
+    }
+}
+
+/// Allocate a new zone, retired zones whose arenas are empty now are freed first.
+#[cfg(feature = "zone")]
+pub(crate) fn zone_alloc() -> Result<zone::ZoneId, &'static str> {
+    crate::mm::zone_heap::release_retired(zone::zone_free);
+    zone::zone_alloc()
+}
+
+/// Zone to spawn a thread into, threads inherit their parent's zone by default,
+/// except the children of the main thread, which get new zones.
+#[cfg(feature = "zone")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZoneSpec {
+    /// A newly allocated zone.
+    New,
+    /// The zone of an existing thread.
+    SameAs(Tid),
+    /// An allocated zone, e.g. one returned by `shyperstd::zone::alloc`.
+    Zone(zone::ZoneId),
+    /// The zone owning the statics declared by `#[zone_data("name")]`, see `mm::zone_data`.
+    Named(&'static str),
+    /// The shared zone, accessible by all zones.
+    Shared,
+}
+
+#[cfg(feature = "zone")]
+impl ZoneSpec {
+    /// Get the zone id and take a reference to it for the thread to spawn.
+    fn resolve(self) -> Result<zone::ZoneId, ShyperError> {
+        let zone_id = match self {
+            ZoneSpec::New => {
+                return zone_alloc().map_err(|e| {
+                    warn!("spawn: {}", e);
+                    ShyperError::NoMemory
+                })
+            }
+            ZoneSpec::SameAs(tid) => thread_lookup(tid)
+                .ok_or(ShyperError::NotFound)?
+                .zone_id(),
+            ZoneSpec::Zone(zone_id) => zone_id,
+            ZoneSpec::Named(name) => {
+                crate::mm::zone_data::named_zone(name).ok_or(ShyperError::NotFound)?
+            }
+            ZoneSpec::Shared => return Ok(zone::ZONE_ID_SHARED),
+        };
+        if zone_id == zone::ZONE_ID_PRIVILEGED || !zone::zone_allocated(zone_id) {
+            return Err(ShyperError::InvalidInput);
+        }
+        zone::zone_get(zone_id);
+        Ok(zone_id)
     }
 }
 
@@ -175,25 +275,97 @@ impl fmt::Debug for Thread {
     }
 }
 
+/// First code run by a new thread when zones are enabled.
+///
+/// A thread is first switched to with privileged zone keys, it enters its zone here
+/// and then jumps to the real `start`. On x86_64 this binds a protection key to the zone
+/// if it holds none, elsewhere it activates the zone's page table view.
+#[cfg(feature = "zone")]
+extern "C" fn zone_thread_start(entry: usize, arg: usize, start: usize, zone_id: usize) -> ! {
+    zone::switch_to_zone(zone_id);
+    let start: extern "C" fn(usize, usize) -> ! = unsafe { mem::transmute(start) };
+    start(entry, arg)
+}
+
 extern "C" fn thread_entry(entry: usize) -> ! {
     // debug!("thread_entry: {:#x}", entry);
-    unsafe {
-        Box::from_raw(entry as *mut Box<dyn FnOnce()>)();
+    let f = unsafe { Box::from_raw(entry as *mut Box<dyn FnOnce()>) };
+    // Closures are consumed by their first run, they can't be restarted.
+    #[cfg(feature = "unwind")]
+    {
+        #[cfg(not(feature = "std"))]
+        use crate::libs::unwind::catch::catch_unwind;
+        #[cfg(feature = "std")]
+        use std::panic::catch_unwind;
+
+        #[cfg(feature = "lock-poison")]
+        let lock_seq = crate::libs::synch::poison::last_seq();
+        if catch_unwind(core::panic::AssertUnwindSafe(f)).is_err() {
+            #[cfg(feature = "lock-poison")]
+            crate::libs::synch::poison::release_since(lock_seq, "unwound");
+            panic_caught();
+        }
     }
+    #[cfg(not(feature = "unwind"))]
+    f();
     thread_exit()
 }
 
 /// Spawns a new task with the given parameters.
 ///
-/// Returns the task reference.
-pub fn spawn_raw(f: Box<dyn FnOnce()>, name: Option<String>, stack_size: usize) -> Thread {
-    let t = Thread::new(f, name, stack_size);
+/// Returns the task reference, fails if a new zone can't be allocated for it.
+pub fn spawn_raw(
+    f: Box<dyn FnOnce()>,
+    name: Option<String>,
+    stack_size: usize,
+) -> Result<Thread, ShyperError> {
+    let t = Thread::new(f, name, stack_size).map_err(|e| {
+        warn!("spawn: {}", e);
+        ShyperError::NoMemory
+    })?;
     thread_wake(t.clone());
-    t
+    Ok(t)
+}
+
+/// Spawns a new task into the zone given by `zone`.
+///
+/// Fails if the zone can't be allocated, or the given thread or zone doesn't exist.
+#[cfg(feature = "zone")]
+pub fn spawn_raw_in(
+    f: Box<dyn FnOnce()>,
+    _name: Option<String>,
+    _stack_size: usize,
+    zone: ZoneSpec,
+) -> Result<Thread, ShyperError> {
+    let zone_id = {
+        let _guard = zone::PrivilegeGuard::enter();
+        zone.resolve()?
+    };
+    let entry = Box::into_raw(Box::new(f));
+    let t = thread_alloc_in(
+        None,
+        Some(0),
+        thread_entry as usize,
+        entry as *mut _ as usize,
+        0,
+        false,
+        Some(zone_id),
+    )
+    .map_err(|e| {
+        warn!("spawn: {}", e);
+        drop(unsafe { Box::from_raw(entry) });
+        ShyperError::NoMemory
+    })?;
+    thread_wake(t.clone());
+    Ok(t)
 }
 
 impl Thread {
-    pub(crate) fn new(f: Box<dyn FnOnce()>, _name: Option<String>, _stack_size: usize) -> Thread {
+    pub(crate) fn new(
+        f: Box<dyn FnOnce()>,
+        _name: Option<String>,
+        _stack_size: usize,
+    ) -> Result<Thread, &'static str> {
         let entry = Box::into_raw(Box::new(f));
         thread_alloc(
             None,
@@ -203,6 +375,10 @@ impl Thread {
             0,
             false,
         )
+        .map_err(|e| {
+            drop(unsafe { Box::from_raw(entry) });
+            e
+        })
     }
 
     fn get_ref_count(&self) -> usize {
@@ -261,6 +437,17 @@ impl Thread {
         *lock = status
     }
 
+    /// Set thread status as Ready if it's Blocked, returns whether it was.
+    fn ready_if_blocked(&self) -> bool {
+        let mut lock = self.0.inner_mut.status.lock();
+        if *lock == Status::Blocked {
+            *lock = Status::Ready;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Get thread privilege level.
     pub fn privilege(&self) -> PrivilegedLevel {
         self.0.inner.level
@@ -281,6 +468,50 @@ impl Thread {
         self.0.inner_mut.ctx.get()
     }
 
+    /// Poison and release all locks held, called when the thread dies.
+    #[cfg(feature = "lock-poison")]
+    fn release_held_locks(&self) {
+        let released = self.0.inner_mut.held_locks.release_all();
+        if released != 0 {
+            warn!(
+                "Thread [{}] died holding {} locks, released and poisoned",
+                self.id(),
+                released
+            );
+        }
+    }
+
+    /// Get the panic policy of this thread, the default one if it has none.
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.0.inner_mut.panic.policy()
+    }
+
+    /// Set the panic policy of this thread, None to follow the default one.
+    pub fn set_panic_policy(&self, policy: Option<PanicPolicy>) {
+        self.0.inner_mut.panic.set_policy(policy)
+    }
+
+    /// Set the hook called when this thread panics, before the global one.
+    pub fn set_panic_hook(&self, hook: Option<Box<PanicHook>>) {
+        self.0.inner_mut.panic.set_hook(hook)
+    }
+
+    pub(crate) fn panic_state(&self) -> &ThreadPanic {
+        &self.0.inner_mut.panic
+    }
+
+    /// Gate stacks of this thread for zone calls, see `libs::zone_gate`.
+    #[cfg(feature = "zone")]
+    pub(crate) fn gate_stacks(&self) -> &Mutex<alloc::vec::Vec<GateStack>> {
+        &self.0.inner_mut.gate_stacks
+    }
+
+    /// Checkpoint of this thread to roll back to, see `libs::checkpoint`.
+    #[cfg(feature = "checkpoint")]
+    pub(crate) fn checkpoint(&self) -> &Mutex<Option<Box<Checkpoint>>> {
+        &self.0.inner_mut.checkpoint
+    }
+
     #[inline]
     pub fn in_trap_context(&self) -> bool {
         let in_trap_context = self.0.inner_mut.in_trap_context.lock();
@@ -307,7 +538,40 @@ impl Thread {
     /// The freed region will be automically dropped.
     pub fn free_mem_region(&self, addr: VAddr) {
         let mut addr_space = self.0.inner_mut.mem_regions.lock();
-        addr_space.remove(&addr);
+        let _region = addr_space.remove(&addr);
+        #[cfg(feature = "quota")]
+        if let Some(region) = _region {
+            #[cfg(feature = "zone")]
+            let zone_id = self.zone_id();
+            #[cfg(not(feature = "zone"))]
+            let zone_id = 0;
+            crate::mm::quota::uncharge_region(self.quota(), zone_id, region.size_in_bytes());
+        }
+    }
+
+    /// Get the address range of this thread's stack.
+    pub fn stack_range(&self) -> Range<usize> {
+        let start = self.0.inner.stack.start_address().value();
+        start..start + self.0.inner.stack.size_in_bytes()
+    }
+
+    /// Call `f` on each memory region owned by this thread.
+    pub fn for_each_mem_region<F: FnMut(VAddr, &MappedRegion)>(&self, mut f: F) {
+        let addr_space = self.0.inner_mut.mem_regions.lock();
+        for (addr, region) in addr_space.iter() {
+            f(*addr, region);
+        }
+    }
+
+    /// Get the memory quota of this thread.
+    #[cfg(feature = "quota")]
+    pub fn quota(&self) -> &MemQuota {
+        &self.0.inner.quota
+    }
+
+    #[cfg(feature = "quota")]
+    pub(crate) fn quota_arc(&self) -> Arc<MemQuota> {
+        self.0.inner.quota.clone()
     }
 
     /// Get thread local storage region's start address.
@@ -331,10 +595,10 @@ impl Thread {
         *zone_id
     }
 
+    /// Zone keys of this thread, computed from the keys currently bound to its zone and the zones it's granted.
     #[cfg(feature = "zone")]
     pub fn zone_keys(&self) -> zone::ZoneKeys {
-        let zone_keys = self.0.inner_mut.zone_keys.lock();
-        *zone_keys
+        zone::ZoneKeys::from(self.zone_id())
     }
 }
 
@@ -355,7 +619,8 @@ static THREAD_EXIT_QUEUE: Once<SpinlockIrqSave<VecDeque<Thread>>> = Once::new();
 /// Init user first thread on core 0 by default.
 pub fn init_main_thread(core_id: usize, entry_tuple: (usize, usize)) {
     // Init user first thread on core 0 by default.
-    let t = thread_alloc(None, Some(core_id), entry_tuple.0, entry_tuple.1, 123, true);
+    let t = thread_alloc(None, Some(core_id), entry_tuple.0, entry_tuple.1, 123, true)
+        .expect("fail to allocate main thread");
     // libs::thread::thread_wake(&t);
     t.set_status(Status::Running);
     t.set_in_yield_context();
@@ -366,6 +631,9 @@ pub fn init_main_thread(core_id: usize, entry_tuple: (usize, usize)) {
 
     thread_spawn_privilege(gc_thread, 0, "gc_thread");
 
+    #[cfg(feature = "heap-debug")]
+    crate::mm::heap_debug::init();
+
     // debug!("init_main_thread {}", t.id());
     crate::libs::cpu::cpu().set_running_thread(Some(t));
 }
@@ -401,6 +669,15 @@ pub fn list_threads() {
     }
 }
 
+/// Call `f` on every thread in THREAD_MAP.
+#[allow(unused)]
+pub fn for_each_thread<F: FnMut(&Thread)>(mut f: F) {
+    let thread_map = THREAD_MAP.lock();
+    for t in thread_map.values() {
+        f(t);
+    }
+}
+
 /// This is the main thread alloc logic, which contains the following logic.
 /// 1.  generate new thread id(or use the given thread id);
 /// 2.  alloc mapped memory region for stack according to stack size;
@@ -418,7 +695,7 @@ pub fn list_threads() {
 /// * `privilege` - Thread's privilige level, if true the thread is set as KERNEL thread, which can not be killed by user.
 ///
 /// Notes: the generated thread is at Ready state, you need to wake it up.
-#[allow(unused_assignments)]
+/// Fails if a new zone can't be allocated for the thread, see `ZoneSpec`.
 pub fn thread_alloc(
     id: Option<usize>,
     affinity_core: Option<CoreId>,
@@ -426,7 +703,22 @@ pub fn thread_alloc(
     entry: usize,
     arg: usize,
     privilege: bool,
-) -> Thread {
+) -> Result<Thread, &'static str> {
+    thread_alloc_in(id, affinity_core, start, entry, arg, privilege, None)
+}
+
+/// Same as `thread_alloc`, but runs the thread in `zone`, whose reference is taken over by the thread,
+/// rather than the zone chosen by default, see `ZoneSpec`.
+#[allow(unused_assignments, unused_variables)]
+fn thread_alloc_in(
+    id: Option<usize>,
+    affinity_core: Option<CoreId>,
+    start: usize,
+    entry: usize,
+    arg: usize,
+    privilege: bool,
+    zone: Option<usize>,
+) -> Result<Thread, &'static str> {
     // Generally it should call the new_tid function to get a newly generated id,
     // During thread_restart, the reallocated thread may use its original id.
     let id = match id {
@@ -445,42 +737,57 @@ pub fn thread_alloc(
     #[cfg(not(feature = "zone"))]
     let zone_id = 0;
 
-    // By default, zone is set as SHARED.
+    // Idle threads run in the SHARED zone, the main thread and its children get new zones,
+    // other threads share their parent's zone.
     #[cfg(feature = "zone")]
     use zone::ZONE_ID_SHARED;
     #[cfg(feature = "zone")]
     {
-        zone_id = match current_thread() {
-            Ok(father_thread) => {
+        let new_zone = || {
+            zone_alloc().map_err(|e| {
+                warn!("thread_alloc: fail to allocate zone for {}, {}", id, e);
+                e
+            })
+        };
+        let zone = match (zone, current_thread()) {
+            (Some(zone_id), _) => Ok(zone_id),
+            (None, Ok(father_thread)) => {
                 if father_thread.id().0 == MAIN_THREAD_ID {
-                    zone::zone_alloc().unwrap_or(ZONE_ID_SHARED)
+                    new_zone()
                 } else {
-                    father_thread.zone_id()
+                    let zone_id = father_thread.zone_id();
+                    zone::zone_get(zone_id);
+                    Ok(zone_id)
                 }
             }
-            Err(_) => {
+            (None, Err(_)) => {
                 if id < Tid(MAIN_THREAD_ID) {
-                    ZONE_ID_SHARED
+                    Ok(ZONE_ID_SHARED)
                 } else {
-                    zone::zone_alloc().unwrap_or(ZONE_ID_SHARED)
+                    new_zone()
                 }
             }
         };
+        zone_id = match zone {
+            Ok(zone_id) => zone_id,
+            Err(e) => {
+                zone::switch_from_privilege(ori_pkru);
+                return Err(e);
+            }
+        };
     }
 
     #[cfg(feature = "zone")]
-    let zone_keys = ZoneKeys::from(zone_id);
+    debug!("{} get zone id {}", id, zone_id);
 
-    #[cfg(feature = "zone")]
-    debug!(
-        "{} get zone id {} with zone_keys {:x}",
-        id,
-        zone_id,
-        zone_keys.as_pkru()
-    );
-
-    let stack_region = crate::mm::stack::alloc_stack(stack_size / PAGE_SIZE, zone_id)
-        .expect("fail to allocate user thread stack");
+    // Stacks of whole 2MB pages are mapped by huge pages if contiguous frames are available.
+    let stack_region = if stack_size % MapGranularity::Page2MB as usize == 0 {
+        crate::mm::stack::alloc_stack_huge(stack_size / PAGE_SIZE, zone_id)
+            .or_else(|| crate::mm::stack::alloc_stack(stack_size / PAGE_SIZE, zone_id))
+    } else {
+        crate::mm::stack::alloc_stack(stack_size / PAGE_SIZE, zone_id)
+    }
+    .expect("fail to allocate user thread stack");
     let stack_start = stack_region.start_address();
 
     let sp = stack_start + stack_region.size_in_bytes();
@@ -514,9 +821,16 @@ pub fn thread_alloc(
         context_frame.set_exception_pc(start);
         context_frame.set_gpr(0, entry);
         context_frame.set_gpr(1, arg);
+        // The zone is entered by the thread itself, see `zone_thread_start`.
+        #[cfg(feature = "zone")]
+        {
+            context_frame.set_exception_pc(zone_thread_start as usize);
+            context_frame.set_gpr(2, start);
+            context_frame.set_gpr(3, zone_id);
+        }
         context_frame.set_stack_pointer(sp.value());
         #[cfg(feature = "zone")]
-        context_frame.set_pkru(zone_keys.as_pkru());
+        context_frame.set_pkru(zone::PKRU_PRIVILEGED);
         trace!(
             "NEW context_frame: on {:#p} \n{}",
             context_frame,
@@ -540,6 +854,9 @@ pub fn thread_alloc(
             },
             stack: stack_region,
             tls,
+            #[cfg(feature = "quota")]
+            quota: Arc::new(MemQuota::new()),
+            start: (start, entry, arg),
         },
         inner_mut: InnerMut {
             affinity_core,
@@ -551,8 +868,13 @@ pub fn thread_alloc(
             #[cfg(feature = "zone")]
             zone_id: Mutex::new(zone_id),
             #[cfg(feature = "zone")]
-            zone_keys: Mutex::new(zone_keys),
+            gate_stacks: Mutex::new(alloc::vec::Vec::new()),
             waiting_queue: Spinlock::new(VecDeque::new()),
+            #[cfg(feature = "lock-poison")]
+            held_locks: HeldLocks::new(),
+            panic: ThreadPanic::new(),
+            #[cfg(feature = "checkpoint")]
+            checkpoint: Mutex::new(None),
         },
     }));
 
@@ -564,7 +886,26 @@ pub fn thread_alloc(
         "thread_alloc success id [{}]\n\t\t\tsp [{} to {:#x}]",
         id, stack_start, sp
     );
-    t
+    Ok(t)
+}
+
+/// Allocate and wake a new thread running the same entry as `t`, with the same name.
+///
+/// Threads spawned from closures can not be restarted, since their closures are consumed.
+pub(crate) fn thread_restart(t: &Thread) -> Result<Tid, &'static str> {
+    let (start, entry, arg) = t.0.inner.start;
+    if start == thread_entry as usize {
+        return Err("thread spawned from a closure can not be restarted");
+    }
+    let privilege = matches!(t.0.inner.level, PrivilegedLevel::Kernel);
+    let new_thread = thread_alloc(None, t.affinity_core(), start, entry, arg, privilege)?;
+    let tid = new_thread.id();
+    if let Some(name) = THREAD_NAME_MAP.lock().get(&t.id()).cloned() {
+        THREAD_NAME_MAP.lock().insert(tid, name);
+    }
+    new_thread.panic_state().inherit(t.panic_state());
+    thread_wake(new_thread);
+    Ok(tid)
 }
 
 /// Find target thread by thread id.
@@ -585,6 +926,14 @@ pub fn thread_destroy(t: Thread) {
         }
     }
 
+    // A destroyed thread never runs again, the locks it holds are released
+    // unless it's running on another core.
+    #[cfg(feature = "lock-poison")]
+    if t.status() != Status::Running {
+        t.release_held_locks();
+    }
+    t.set_status(Status::Exited);
+
     let _ = THREAD_NAME_MAP.lock().remove(&t.id());
     let _ = THREAD_MAP.lock().remove(&t.id());
 }
@@ -607,6 +956,34 @@ pub fn thread_destroy_by_tid(tid: Tid) {
     }
 }
 
+/// Stop target thread by unwinding it, waiting up to `timeout_ms` for it to exit.
+///
+/// It unwinds when it next yields, woken up if it's blocked, so it releases
+/// what its frames hold like after a panic, regardless of its hooks and panic policy.
+/// A thread not yielding in time, e.g. busy looping, is destroyed without unwinding,
+/// see `thread_destroy`. Returns whether it exited by itself.
+#[cfg(feature = "unwind")]
+pub fn thread_stop(t: Thread, timeout_ms: usize) -> bool {
+    use crate::libs::timer::current_ms;
+    debug!("thread_stop {}", t.id());
+    t.panic_state().request_stop();
+    thread_wake_blocked_to_front(t.clone());
+    let end = current_ms() + timeout_ms;
+    while t.status() != Status::Exited {
+        if current_ms() >= end {
+            warn!(
+                "Thread [{}] not stopped in {}ms, destroyed",
+                t.id(),
+                timeout_ms
+            );
+            thread_destroy(t);
+            return false;
+        }
+        thread_yield();
+    }
+    true
+}
+
 static CORE_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
 /// Wake up target thread.
@@ -654,6 +1031,26 @@ pub fn thread_wake_by_tid(tid: Tid) {
 pub fn thread_wake_to_front(t: Thread) {
     trace!("thread_wake set thread {} as next thread", t.id());
     t.set_status(Status::Ready);
+    add_front(t);
+}
+
+/// Wake up target thread as the next scheduled thread if it's still Blocked,
+/// and cancel its wakeup time, returns false if it's not Blocked.
+/// A thread blocked with timeout may have woken up by itself.
+pub fn thread_wake_blocked_to_front(t: Thread) -> bool {
+    if !t.ready_if_blocked() {
+        return false;
+    }
+    trace!("thread_wake set blocked thread {} as next thread", t.id());
+    for core_id in 0..crate::board::BOARD_CORE_NUMBER {
+        get_cpu(core_id).cancel_wakeup(&t);
+    }
+    add_front(t);
+    true
+}
+
+/// Add a Ready thread to the front of its scheduler's queue.
+fn add_front(t: Thread) {
     let affinity_core_id = match t.affinity_core() {
         Some(affinity_core_id) => affinity_core_id,
         None => CORE_COUNTER.fetch_add(1, Ordering::SeqCst) % crate::board::BOARD_CORE_NUMBER,
@@ -743,8 +1140,11 @@ pub fn thread_join(id: Tid) {
 pub fn handle_blocked_threads() {
     use crate::libs::timer::current_ms;
     while let Some(t) = cpu().scheduler().get_wakeup_thread_by_time(current_ms()) {
-        debug!("handle_blocked_threads: thread [{}] is wake up", t.id());
-        thread_wake(t);
+        // It may have been woken up already by another thread.
+        if t.ready_if_blocked() {
+            debug!("handle_blocked_threads: thread [{}] is wake up", t.id());
+            thread_wake(t);
+        }
     }
 }
 
@@ -767,6 +1167,13 @@ pub fn thread_yield() {
 
         // debug!("back to thread {}", current_thread_id());
     });
+    // A thread asked to stop unwinds from here, see `thread_stop`.
+    #[cfg(feature = "unwind")]
+    if let Some(t) = cpu().running_thread_ref() {
+        if t.panic_state().begin_stop() {
+            panic!("Thread [{}] stopped", t.id());
+        }
+    }
 }
 
 /// Get current running thread id, return 0 if there is no running thread.
@@ -777,6 +1184,15 @@ pub fn current_thread_id() -> Tid {
     }
 }
 
+/// Get the locks held by current thread, None if there is no running thread.
+/// It doesn't clone the thread since every lock acquisition calls it.
+#[cfg(feature = "lock-poison")]
+pub(crate) fn current_held_locks() -> Option<&'static HeldLocks> {
+    cpu()
+        .running_thread_ref()
+        .map(|t| unsafe { &*(&t.0.inner_mut.held_locks as *const HeldLocks) })
+}
+
 /// Get current running thread.
 pub fn current_thread() -> Result<Thread, Error> {
     match cpu().running_thread() {
@@ -792,6 +1208,8 @@ pub fn thread_exit() -> ! {
     crate::arch::irq::disable();
     let t = current_thread().unwrap_or_else(|_| panic!("failed to get current thread"));
 
+    #[cfg(feature = "lock-poison")]
+    t.release_held_locks();
     t.set_status(Status::Exited);
     t.handle_waiting_threads();
 
@@ -813,28 +1231,66 @@ pub fn thread_exit() -> ! {
     }
 }
 
+/// Max times a thread is run under `PanicPolicy::UnwindRestart` before it gives up.
 #[cfg(feature = "unwind")]
-extern "C" fn thread_wrapper(thread_entry: extern "C" fn(usize), arg: usize) -> usize {
-    const RETRY_MAX: usize = 5;
+const RETRY_MAX: usize = 5;
+
+/// Called once a panic of current thread is caught,
+/// returns whether its panic policy restarts it.
+#[cfg(feature = "unwind")]
+fn panic_caught() -> bool {
+    match current_thread() {
+        Ok(t) => {
+            #[cfg(feature = "quota")]
+            t.quota().set_unwinding(false);
+            t.panic_policy() == PanicPolicy::UnwindRestart
+        }
+        Err(_) => false,
+    }
+}
+
+/// Run `f` on current thread catching its panics, `f` is run again after a panic
+/// if current thread's panic policy is `UnwindRestart`, up to `RETRY_MAX` times.
+///
+/// Returns None if `f` panicked and was not restarted.
+#[cfg(feature = "unwind")]
+pub fn run_catching<R, F: FnMut() -> R>(mut f: F) -> Option<R> {
+    use core::panic::AssertUnwindSafe;
     #[cfg(not(feature = "std"))]
     use crate::libs::unwind::catch::catch_unwind;
     #[cfg(feature = "std")]
     use std::panic::catch_unwind;
 
     for i in 0..RETRY_MAX {
-        match catch_unwind(|| thread_entry(arg)) {
-            Ok(_) => {
-                return 0;
+        #[cfg(feature = "lock-poison")]
+        let lock_seq = crate::libs::synch::poison::last_seq();
+        match catch_unwind(AssertUnwindSafe(&mut f)) {
+            Ok(r) => {
+                return Some(r);
             }
             Err(_) => {
-                info!("thread_wrapper: retry #{}", i);
+                #[cfg(feature = "lock-poison")]
+                crate::libs::synch::poison::release_since(lock_seq, "unwound");
+                if !panic_caught() {
+                    info!("run_catching: thread unwound, exit");
+                    return None;
+                }
+                info!("run_catching: retry #{}", i);
             }
         }
     }
     warn!(
-        "thread_wrapper: thread restart times exceed MAX{RETRY_MAX}, abort!!!",
+        "run_catching: thread restart times exceed MAX{RETRY_MAX}, abort!!!",
     );
-    ERROR_PANIC
+    None
+}
+
+#[cfg(feature = "unwind")]
+extern "C" fn thread_wrapper(thread_entry: extern "C" fn(usize), arg: usize) -> usize {
+    match run_catching(|| thread_entry(arg)) {
+        Some(_) => 0,
+        None => ERROR_PANIC,
+    }
 }
 
 /// Main spawn logic.
@@ -886,6 +1342,7 @@ fn _inner_spawn(
             }
         };
 
+        // Callers have no way to handle the failure.
         let child_thread = thread_alloc(
             None,
             affinity_core,
@@ -893,7 +1350,8 @@ fn _inner_spawn(
             func as usize,
             arg,
             privilege,
-        );
+        )
+        .unwrap_or_else(|e| panic!("thread_spawn: {}", e));
 
         tid = child_thread.id();
 
@@ -34,6 +34,8 @@ pub trait ContextFrameTrait {
     fn stack_pointer(&self) -> usize;
     /// Set context frame's stack pointer.
     fn set_stack_pointer(&mut self, sp: usize);
+    /// Get context frame's frame pointer, for walking the call stack of the trapped code.
+    fn frame_pointer(&self) -> usize;
     /// Get context frame's general purpose register value of given index.
     /// Note: the callee may check the index's legality(x0-x30 on aarch 64).
     fn gpr(&self, index: usize) -> usize;
@@ -10,15 +10,8 @@ use addr2line::object::read::File;
 use addr2line::Context;
 use addr2line::gimli::{RunTimeEndian, EndianReader};
 
-// Store kernel's elf image.
-extern "C" {
-    static ELF_IMAGE: [u8; 0x40000000];
-}
-
 static CONTEXT: Lazy<Mutex<Context<EndianReader<RunTimeEndian, Arc<[u8]>>>>> = Lazy::new(|| {
-    let data = unsafe {
-        core::slice::from_raw_parts(&ELF_IMAGE as *const _ as *const u8, 0x40000000 as usize)
-    };
+    let data = super::elf::elf_image();
     let elf = File::parse(data).expect("failed to parse elf image file");
 
     let endian = if elf.is_little_endian() {
@@ -1,6 +1,9 @@
 use crate::arch::registers::Registers;
 use crate::arch::registers::Aarch64;
 
+/// A return address points after the `bl` instruction, the caller is found at the instruction before it.
+pub const CALLER_OFFSET: u64 = 4;
+
 /// The calling convention dictates the following order of arguments:
 /// * first arg in `x0` register, the pointer of the UnwindingContext,
 /// * second arg in `x1` register, the stack pointer,
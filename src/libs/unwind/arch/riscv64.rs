@@ -0,0 +1,185 @@
+use tock_registers::interfaces::Readable;
+use tock_registers::LocalRegisterCopy;
+use riscv::regs::SSTATUS;
+
+use crate::arch::registers::Registers;
+use crate::arch::registers::Riscv64;
+
+/// A return address points after the `jal`/`jalr` instruction, which may be a 2 bytes compressed one,
+/// the caller is found at the half word before it.
+pub const CALLER_OFFSET: u64 = 2;
+
+/// The calling convention dictates the following order of arguments:
+/// * first arg in `a0` register, the pointer of the UnwindingContext,
+/// * second arg in `a1` register, the stack pointer of the caller after `unwind_trampoline` returns,
+/// * third arg in `a2` register, the saved register values used to recover execution context
+///   after we change the register values during unwinding,
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+unsafe extern "C" fn unwind_recorder(
+    ctx: *mut super::UnwindingContext,
+    stack: u64,
+    saved_regs: *mut CalleeSavedRegs,
+) {
+    let saved_regs = &*saved_regs;
+    let mut registers = Registers::default();
+
+    registers[Riscv64::S0] = Some(saved_regs.s[0]);
+    registers[Riscv64::S1] = Some(saved_regs.s[1]);
+    registers[Riscv64::S2] = Some(saved_regs.s[2]);
+    registers[Riscv64::S3] = Some(saved_regs.s[3]);
+    registers[Riscv64::S4] = Some(saved_regs.s[4]);
+    registers[Riscv64::S5] = Some(saved_regs.s[5]);
+    registers[Riscv64::S6] = Some(saved_regs.s[6]);
+    registers[Riscv64::S7] = Some(saved_regs.s[7]);
+    registers[Riscv64::S8] = Some(saved_regs.s[8]);
+    registers[Riscv64::S9] = Some(saved_regs.s[9]);
+    registers[Riscv64::S10] = Some(saved_regs.s[10]);
+    registers[Riscv64::S11] = Some(saved_regs.s[11]);
+    registers[Riscv64::SP] = Some(stack);
+    registers[Riscv64::RA] = Some(saved_regs.ra);
+
+    super::unwind_from_panic_stub(registers, ctx);
+}
+
+#[naked]
+pub unsafe extern "C" fn unwind_trampoline(ctx: usize) {
+    core::arch::asm!(
+        "mv a1, sp",
+        "addi sp, sp, -0x70",
+        ".cfi_adjust_cfa_offset 0x70",
+        "sd s0, 0x00(sp)",
+        "sd s1, 0x08(sp)",
+        "sd s2, 0x10(sp)",
+        "sd s3, 0x18(sp)",
+        "sd s4, 0x20(sp)",
+        "sd s5, 0x28(sp)",
+        "sd s6, 0x30(sp)",
+        "sd s7, 0x38(sp)",
+        "sd s8, 0x40(sp)",
+        "sd s9, 0x48(sp)",
+        "sd s10, 0x50(sp)",
+        "sd s11, 0x58(sp)",
+        "sd ra, 0x60(sp)",
+        ".cfi_rel_offset ra, 0x60",
+        "mv a2, sp",
+        "call unwind_recorder",
+        "ld ra, 0x60(sp)",
+        ".cfi_restore ra",
+        "addi sp, sp, 0x70",
+        ".cfi_adjust_cfa_offset -0x70",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Restore registers from `LandingRegs` on `a0` and jump to its `pc` with `sret`,
+/// `a1` is the `sstatus` to land with, interrupts are disabled until `sret`.
+///
+/// `gp` and `tp` are left untouched, they are never changed across frames of a thread.
+#[naked]
+unsafe extern "C" fn unwind_lander(regs: *const LandingRegs, sstatus: u64) -> ! {
+    core::arch::asm!(
+        "csrw sstatus, a1",
+        "ld t0, 0x100(a0)",
+        "csrw sepc, t0",
+        "ld x1, 0x08(a0)",
+        "ld x2, 0x10(a0)",
+        "ld x5, 0x28(a0)",
+        "ld x6, 0x30(a0)",
+        "ld x7, 0x38(a0)",
+        "ld x8, 0x40(a0)",
+        "ld x9, 0x48(a0)",
+        "ld x11, 0x58(a0)",
+        "ld x12, 0x60(a0)",
+        "ld x13, 0x68(a0)",
+        "ld x14, 0x70(a0)",
+        "ld x15, 0x78(a0)",
+        "ld x16, 0x80(a0)",
+        "ld x17, 0x88(a0)",
+        "ld x18, 0x90(a0)",
+        "ld x19, 0x98(a0)",
+        "ld x20, 0xa0(a0)",
+        "ld x21, 0xa8(a0)",
+        "ld x22, 0xb0(a0)",
+        "ld x23, 0xb8(a0)",
+        "ld x24, 0xc0(a0)",
+        "ld x25, 0xc8(a0)",
+        "ld x26, 0xd0(a0)",
+        "ld x27, 0xd8(a0)",
+        "ld x28, 0xe0(a0)",
+        "ld x29, 0xe8(a0)",
+        "ld x30, 0xf0(a0)",
+        "ld x31, 0xf8(a0)",
+        "ld x10, 0x50(a0)",
+        "sret",
+        options(noreturn),
+    )
+}
+
+/// **Landing** refers to the process of jumping to a handler for a stack frame,
+/// e.g., an unwinding cleanup function, or an exception "catch" block.
+///
+/// This function basically fills the actual CPU registers with the values in the given `LandingRegisters`
+/// and then jumps to the exception handler (landing pad) at `landing_pad_address`.
+///
+/// Landing always goes through `sret` so that the stack switch and the jump happen at once
+/// with interrupts disabled. Exceptions are taken with interrupts disabled, the landing pad
+/// of an unwinding from exception is entered with interrupts enabled as the faulting thread ran.
+pub unsafe fn land(regs: &Registers, landing_pad_address: u64, from_exception: bool) {
+    let mut lr = LandingRegs {
+        x: [0; 32],
+        pc: landing_pad_address,
+    };
+    lr.x[1] = regs[Riscv64::RA].unwrap_or(0);
+    lr.x[2] = regs[Riscv64::SP].unwrap_or(0);
+    lr.x[5] = regs[Riscv64::T0].unwrap_or(0);
+    lr.x[6] = regs[Riscv64::T1].unwrap_or(0);
+    lr.x[7] = regs[Riscv64::T2].unwrap_or(0);
+    lr.x[8] = regs[Riscv64::S0].unwrap_or(0);
+    lr.x[9] = regs[Riscv64::S1].unwrap_or(0);
+    lr.x[10] = regs[Riscv64::A0].unwrap_or(0);
+    lr.x[11] = regs[Riscv64::A1].unwrap_or(0);
+    lr.x[12] = regs[Riscv64::A2].unwrap_or(0);
+    lr.x[13] = regs[Riscv64::A3].unwrap_or(0);
+    lr.x[14] = regs[Riscv64::A4].unwrap_or(0);
+    lr.x[15] = regs[Riscv64::A5].unwrap_or(0);
+    lr.x[16] = regs[Riscv64::A6].unwrap_or(0);
+    lr.x[17] = regs[Riscv64::A7].unwrap_or(0);
+    lr.x[18] = regs[Riscv64::S2].unwrap_or(0);
+    lr.x[19] = regs[Riscv64::S3].unwrap_or(0);
+    lr.x[20] = regs[Riscv64::S4].unwrap_or(0);
+    lr.x[21] = regs[Riscv64::S5].unwrap_or(0);
+    lr.x[22] = regs[Riscv64::S6].unwrap_or(0);
+    lr.x[23] = regs[Riscv64::S7].unwrap_or(0);
+    lr.x[24] = regs[Riscv64::S8].unwrap_or(0);
+    lr.x[25] = regs[Riscv64::S9].unwrap_or(0);
+    lr.x[26] = regs[Riscv64::S10].unwrap_or(0);
+    lr.x[27] = regs[Riscv64::S11].unwrap_or(0);
+    lr.x[28] = regs[Riscv64::T3].unwrap_or(0);
+    lr.x[29] = regs[Riscv64::T4].unwrap_or(0);
+    lr.x[30] = regs[Riscv64::T5].unwrap_or(0);
+    lr.x[31] = regs[Riscv64::T6].unwrap_or(0);
+
+    let mut sstatus = LocalRegisterCopy::<u64, SSTATUS::Register>::new(SSTATUS.get());
+    let interrupt_enabled = from_exception || sstatus.is_set(SSTATUS::SIE);
+    sstatus.modify(
+        SSTATUS::SPP::Supervisor
+            + SSTATUS::SPIE.val(interrupt_enabled as u64)
+            + SSTATUS::SIE.val(0),
+    );
+    unwind_lander(&lr, sstatus.get());
+}
+
+#[repr(C)]
+pub struct LandingRegs {
+    pub x: [u64; 32], // x0 - x31, x0, gp and tp are ignored
+    pub pc: u64,
+}
+
+#[repr(C)]
+pub struct CalleeSavedRegs {
+    pub s: [u64; 12],
+    // s0 - s11
+    pub ra: u64,
+}
@@ -0,0 +1,166 @@
+use crate::arch::registers::Registers;
+use crate::arch::registers::X86_64;
+
+/// A return address points after the `call` instruction, the caller is found at the byte before it.
+pub const CALLER_OFFSET: u64 = 1;
+
+/// The calling convention dictates the following order of arguments:
+/// * first arg in `rdi` register, the pointer of the UnwindingContext,
+/// * second arg in `rsi` register, the stack pointer of the caller after `unwind_trampoline` returns,
+/// * third arg in `rdx` register, the saved register values used to recover execution context
+///   after we change the register values during unwinding,
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+unsafe extern "C" fn unwind_recorder(
+    ctx: *mut super::UnwindingContext,
+    stack: u64,
+    saved_regs: *mut CalleeSavedRegs,
+) {
+    let saved_regs = &*saved_regs;
+    let mut registers = Registers::default();
+
+    registers[X86_64::R15] = Some(saved_regs.r15);
+    registers[X86_64::R14] = Some(saved_regs.r14);
+    registers[X86_64::R13] = Some(saved_regs.r13);
+    registers[X86_64::R12] = Some(saved_regs.r12);
+    registers[X86_64::RBX] = Some(saved_regs.rbx);
+    registers[X86_64::RBP] = Some(saved_regs.rbp);
+    registers[X86_64::RSP] = Some(stack);
+    registers[X86_64::RA] = Some(saved_regs.ra);
+
+    super::unwind_from_panic_stub(registers, ctx);
+}
+
+#[naked]
+pub unsafe extern "C" fn unwind_trampoline(ctx: usize) {
+    core::arch::asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        ".cfi_adjust_cfa_offset 0x30",
+        // Return address is above the saved registers.
+        "lea rsi, [rsp + 0x38]",
+        "mov rdx, rsp",
+        // Keep the stack 16 bytes aligned at the call.
+        "sub rsp, 8",
+        ".cfi_adjust_cfa_offset 8",
+        "call unwind_recorder",
+        "add rsp, 8",
+        ".cfi_adjust_cfa_offset -8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        ".cfi_adjust_cfa_offset -0x30",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// Restore registers from `LandingRegs` on `rdi`, switch to its stack,
+/// then restore rflags and jump to the landing pad at once with `popfq; ret`.
+#[naked]
+unsafe extern "C" fn unwind_lander(regs: *const LandingRegs) -> ! {
+    core::arch::asm!(
+        "mov rax, [rdi + 0x00]",
+        "mov rbx, [rdi + 0x08]",
+        "mov rcx, [rdi + 0x10]",
+        "mov rdx, [rdi + 0x18]",
+        "mov rsi, [rdi + 0x20]",
+        "mov rbp, [rdi + 0x30]",
+        "mov r8,  [rdi + 0x38]",
+        "mov r9,  [rdi + 0x40]",
+        "mov r10, [rdi + 0x48]",
+        "mov r11, [rdi + 0x50]",
+        "mov r12, [rdi + 0x58]",
+        "mov r13, [rdi + 0x60]",
+        "mov r14, [rdi + 0x68]",
+        "mov r15, [rdi + 0x70]",
+        "mov rsp, [rdi + 0x78]",
+        "push qword ptr [rdi + 0x80]",
+        "push qword ptr [rdi + 0x88]",
+        "mov rdi, [rdi + 0x28]",
+        "popfq",
+        "ret",
+        options(noreturn),
+    )
+}
+
+/// **Landing** refers to the process of jumping to a handler for a stack frame,
+/// e.g., an unwinding cleanup function, or an exception "catch" block.
+///
+/// This function basically fills the actual CPU registers with the values in the given `LandingRegisters`
+/// and then jumps to the exception handler (landing pad) at `landing_pad_address`.
+///
+/// Exceptions are taken with interrupts disabled, the landing pad of an unwinding
+/// from exception is entered with interrupts enabled as the faulting thread ran.
+pub unsafe fn land(regs: &Registers, landing_pad_address: u64, from_exception: bool) {
+    /// Interrupt enable flag of rflags.
+    const RFLAGS_IF: u64 = 1 << 9;
+    let rflags = x86_64::registers::rflags::read_raw();
+    let lr = LandingRegs {
+        rax: regs[X86_64::RAX].unwrap_or(0),
+        rbx: regs[X86_64::RBX].unwrap_or(0),
+        rcx: regs[X86_64::RCX].unwrap_or(0),
+        rdx: regs[X86_64::RDX].unwrap_or(0),
+        rsi: regs[X86_64::RSI].unwrap_or(0),
+        rdi: regs[X86_64::RDI].unwrap_or(0),
+        rbp: regs[X86_64::RBP].unwrap_or(0),
+        r8: regs[X86_64::R8].unwrap_or(0),
+        r9: regs[X86_64::R9].unwrap_or(0),
+        r10: regs[X86_64::R10].unwrap_or(0),
+        r11: regs[X86_64::R11].unwrap_or(0),
+        r12: regs[X86_64::R12].unwrap_or(0),
+        r13: regs[X86_64::R13].unwrap_or(0),
+        r14: regs[X86_64::R14].unwrap_or(0),
+        r15: regs[X86_64::R15].unwrap_or(0),
+        rsp: regs[X86_64::RSP].unwrap_or(0),
+        rip: landing_pad_address,
+        rflags: if from_exception {
+            rflags | RFLAGS_IF
+        } else {
+            rflags
+        },
+    };
+    unwind_lander(&lr);
+}
+
+#[repr(C)]
+pub struct LandingRegs {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rsp: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+/// Pushed by `unwind_trampoline`, from low address to high.
+#[repr(C)]
+pub struct CalleeSavedRegs {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    // Return address of `unwind_trampoline`.
+    pub ra: u64,
+}
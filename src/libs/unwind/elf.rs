@@ -6,9 +6,28 @@ use spin::Once;
 use xmas_elf::*;
 use xmas_elf::sections::SectionData;
 
-// Store kernel's elf image.
-extern "C" {
-    static ELF_IMAGE: [u8; 0x40000000];
+/// Kernel's elf image, loaded by the bootloader.
+pub fn elf_image() -> &'static [u8] {
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        // Store kernel's elf image.
+        extern "C" {
+            static ELF_IMAGE: [u8; 0x40000000];
+        }
+        unsafe { &ELF_IMAGE }
+    }
+    // rboot loads the kernel's elf file as initramfs, see `x86boot/EFI/Boot/rboot.conf`.
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::libs::traits::Address;
+        let boot_info = crate::arch::boot_info();
+        unsafe {
+            core::slice::from_raw_parts(
+                (boot_info.initramfs_addr as usize).pa2kva() as *const u8,
+                boot_info.initramfs_size as usize,
+            )
+        }
+    }
 }
 
 static BASE_ADDRESSES: Lazy<BaseAddresses> = Lazy::new(|| {
@@ -31,7 +50,7 @@ static BASE_ADDRESSES: Lazy<BaseAddresses> = Lazy::new(|| {
 });
 
 static ELF_FILE: Lazy<ElfFile> =
-    Lazy::new(|| ElfFile::new(unsafe { &ELF_IMAGE }).expect("failed to parse elf file"));
+    Lazy::new(|| ElfFile::new(elf_image()).expect("failed to parse elf file"));
 
 pub fn base_addresses() -> BaseAddresses {
     BASE_ADDRESSES.clone()
@@ -46,6 +46,12 @@ use arch::*;
 #[cfg(target_arch = "aarch64")]
 #[path = "arch/aarch64.rs"]
 pub mod arch;
+#[cfg(target_arch = "x86_64")]
+#[path = "arch/x86_64.rs"]
+pub mod arch;
+#[cfg(target_arch = "riscv64")]
+#[path = "arch/riscv64.rs"]
+pub mod arch;
 
 mod addr2line;
 pub mod catch;
@@ -178,7 +184,7 @@ impl FallibleIterator for StackFrameIter {
             Some(ra) => ra,
         };
         // The caller should be the last instruction to return address.
-        let caller = return_address - 4;
+        let caller = return_address - CALLER_OFFSET;
 
         // debug!("return_address {return_address:#x}");
         // addr2line::print_addr2line(return_address);
@@ -242,10 +248,16 @@ impl FallibleIterator for StackFrameIter {
 /// by working backwards up the call stack starting from the exception context frame.
 ///
 /// # Arguments
-/// * `registers`: exception context frame.
+/// * `registers`: exception context frame, whose return address register should be
+///   the faulting instruction plus `arch::CALLER_OFFSET`.
 ///
+/// The current thread exits if no landing pad is found.
 pub fn unwind_from_exception(registers: Registers) -> ! {
     debug!("unwind_from_exception:\n{:?}", registers);
+    #[cfg(feature = "checkpoint")]
+    crate::libs::checkpoint::rollback_from_fault();
+    #[cfg(feature = "coredump")]
+    let core_regs = crate::arch::coredump::CoreRegs::from(&registers);
 
     let ctx = Box::new(UnwindingContext {
         skip: 0,
@@ -257,7 +269,9 @@ pub fn unwind_from_exception(registers: Registers) -> ! {
     unwind(ctx);
     cleanup(ctx);
     error!("unwind_from_exception failed!");
-    loop {}
+    #[cfg(feature = "coredump")]
+    crate::libs::coredump::dump(core_regs, crate::libs::coredump::SIGNAL_EXCEPTION);
+    crate::libs::thread::thread_exit()
 }
 
 /// Starts the unwinding procedure for the current thread from panic.
@@ -283,7 +297,7 @@ pub fn unwind_from_panic(stack_frames_to_skip: usize) -> ! {
     // From this point on, if there is a failure
     // we need to free the unwinding context pointer to avoid leaking things.
 
-    // This is an internal assembly function in arch/aarch64.rs
+    // This is an internal assembly function in arch/<target_arch>.rs
     // that the current register values by pushing them onto the stack
     // before invoking the function "unwind_recorder" with those register values as the only argument.
     // This is needed because the unwind info tables describe register values as operations (offsets/addends)
@@ -295,6 +309,10 @@ pub fn unwind_from_panic(stack_frames_to_skip: usize) -> ! {
     }
     cleanup(ctx);
     error!("unwind_from_panic failed!");
+    #[cfg(feature = "lock-poison")]
+    crate::libs::synch::poison::release_all();
+    #[cfg(feature = "coredump")]
+    crate::libs::coredump::dump_current(crate::libs::coredump::SIGNAL_PANIC);
     loop {}
 }
 
@@ -508,5 +526,9 @@ pub fn unwind_resume(arg: usize) -> ! {
     unwind(ctx);
     cleanup(ctx);
     error!("unwind failed!");
+    #[cfg(feature = "lock-poison")]
+    crate::libs::synch::poison::release_all();
+    #[cfg(feature = "coredump")]
+    crate::libs::coredump::dump_current(crate::libs::coredump::SIGNAL_PANIC);
     loop {}
 }
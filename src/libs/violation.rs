@@ -0,0 +1,179 @@
+//! Isolation violation reports and recovery, enabled by feature "zone".
+//!
+//! An access denied by zone isolation, e.g. a protection-key page fault on x86_64,
+//! is decoded by the arch exception handler into a `Violation`, which is counted per zone,
+//! reported and then handled by the configured `ViolationPolicy`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use zone::{ZoneId, ZONE_NUM};
+
+use crate::libs::thread::{current_thread, thread_exit, thread_restart, Tid};
+use crate::mm::interface::{PageTableEntryAttrZoneTrait, PageTableTrait};
+
+/// Violations a zone may cause before `Restart` gives up and kills the thread.
+const RESTART_LIMIT: usize = 5;
+
+/// What to do with the thread causing a violation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ViolationPolicy {
+    /// Exit the thread.
+    Kill = 0,
+    /// Unwind the thread from the faulting instruction, handled as `Kill` where
+    /// unwinding from exceptions is not supported.
+    Unwind = 1,
+    /// Exit the thread and spawn a new one with the same entry.
+    Restart = 2,
+}
+
+impl From<u8> for ViolationPolicy {
+    fn from(policy: u8) -> Self {
+        match policy {
+            1 => ViolationPolicy::Unwind,
+            2 => ViolationPolicy::Restart,
+            _ => ViolationPolicy::Kill,
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(ViolationPolicy::Kill as u8);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_VIOLATION: AtomicUsize = AtomicUsize::new(0);
+/// Violations caused by each zone.
+static COUNTERS: [AtomicUsize; ZONE_NUM] = [NO_VIOLATION; ZONE_NUM];
+
+pub fn set_violation_policy(policy: ViolationPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn violation_policy() -> ViolationPolicy {
+    POLICY.load(Ordering::Relaxed).into()
+}
+
+/// Number of violations caused by `zone_id`.
+pub fn violation_count(zone_id: ZoneId) -> usize {
+    COUNTERS.get(zone_id).map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
+/// Zones which caused violations, with their counts.
+pub fn violation_counts() -> impl Iterator<Item = (ZoneId, usize)> {
+    (0..ZONE_NUM)
+        .map(|zone_id| (zone_id, violation_count(zone_id)))
+        .filter(|(_, count)| *count != 0)
+}
+
+pub fn reset_violation_counts() {
+    for counter in COUNTERS.iter() {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Zone owning the page accessed by a violation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TargetZone {
+    Zone(ZoneId),
+    /// The page is not mapped.
+    Unmapped,
+    /// The page table was locked when the violation was raised, e.g. by the faulting code.
+    Unknown,
+}
+
+/// An access denied by zone isolation.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// Zone the faulting code runs in.
+    pub zone_id: ZoneId,
+    /// Zone keys the faulting code runs with, PKRU on x86_64.
+    pub keys: u32,
+    pub thread: Option<Tid>,
+    /// Address of the faulting instruction.
+    pub pc: usize,
+    /// Address accessed.
+    pub address: usize,
+    pub write: bool,
+    /// Zone owning the page accessed.
+    pub target_zone: TargetZone,
+}
+
+impl Violation {
+    /// Build a violation of current thread, the target zone is looked up from the page table.
+    ///
+    /// The page table is only tried to lock, the fault may be raised while it's held.
+    pub fn new(zone_id: ZoneId, keys: u32, pc: usize, address: usize, write: bool) -> Self {
+        let target_zone = match crate::arch::page_table::page_table().try_lock() {
+            Ok(page_table) => match page_table.lookup_entry(address) {
+                Some((entry, _)) => TargetZone::Zone(entry.attribute().get_zone_id()),
+                None => TargetZone::Unmapped,
+            },
+            Err(_) => TargetZone::Unknown,
+        };
+        Violation {
+            zone_id,
+            keys,
+            thread: current_thread().ok().map(|t| t.id()),
+            pc,
+            address,
+            write,
+            target_zone,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ISOLATION VIOLATION")?;
+        match self.thread {
+            Some(tid) => writeln!(f, "  thread:      {}", tid)?,
+            None => writeln!(f, "  thread:      none")?,
+        }
+        writeln!(f, "  zone:        {} (keys {:#x})", self.zone_id, self.keys)?;
+        writeln!(f, "  pc:          {:#x}", self.pc)?;
+        writeln!(
+            f,
+            "  access:      {} {:#x}",
+            if self.write { "write" } else { "read" },
+            self.address
+        )?;
+        match self.target_zone {
+            TargetZone::Zone(zone_id) => write!(f, "  target zone: {}", zone_id),
+            TargetZone::Unmapped => write!(f, "  target zone: unmapped"),
+            TargetZone::Unknown => write!(f, "  target zone: unknown"),
+        }
+    }
+}
+
+/// Count and report a violation, return the policy to apply to the faulting thread.
+pub fn report_violation(violation: &Violation) -> ViolationPolicy {
+    let count = COUNTERS
+        .get(violation.zone_id)
+        .map_or(0, |c| c.fetch_add(1, Ordering::Relaxed) + 1);
+    error!("{}\n  violations:  {} by zone {}", violation, count, violation.zone_id);
+
+    let policy = violation_policy();
+    if policy == ViolationPolicy::Restart && count > RESTART_LIMIT {
+        warn!(
+            "zone {} exceeds {} violations, thread is not restarted",
+            violation.zone_id, RESTART_LIMIT
+        );
+        return ViolationPolicy::Kill;
+    }
+    policy
+}
+
+/// Apply `Kill` or `Restart` to current thread, `Unwind` should be handled by the caller,
+/// it's applied as `Kill` here.
+pub fn recover(policy: ViolationPolicy) -> ! {
+    if policy == ViolationPolicy::Restart {
+        match current_thread() {
+            Ok(t) => match thread_restart(&t) {
+                Ok(tid) => info!("thread {} is restarted as {}", t.id(), tid),
+                Err(e) => warn!("thread {} is not restarted: {}", t.id(), e),
+            },
+            Err(_) => warn!("no thread to restart"),
+        }
+    }
+    thread_exit()
+}
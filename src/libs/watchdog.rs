@@ -0,0 +1,235 @@
+//! Hardware watchdog kicked while the kernel is healthy, enabled by feature "watchdog".
+//!
+//! `init` probes the board's watchdog, see `drivers::watchdog`, starts it with
+//! `DEFAULT_TIMEOUT_MS` and spawns the privileged thread "watchdog", which wakes four times
+//! per timeout and kicks the watchdog only if the kernel is healthy:
+//!
+//! * every core which ever scheduled has scheduled since the last check,
+//!   a core stuck with interrupts masked stops beating its heartbeat, see `Core::heartbeat`;
+//! * every liveness check registered by `register_check` passes.
+//!
+//! Otherwise the watchdog is not kicked, and resets the board when its timeout expires
+//! unless the kernel recovers before. A wedged scheduler never runs the thread either.
+//! Without a hardware watchdog, e.g. on aarch64 and riscv64 QEMU, the thread still
+//! monitors and reports the health, but nothing resets a wedged kernel.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::board::BOARD_CORE_NUMBER;
+use crate::drivers::watchdog as device;
+use crate::libs::cpu::get_cpu;
+use crate::libs::synch::spinlock::Spinlock;
+use crate::libs::thread::{thread_block_current_with_timeout_us, thread_spawn_privilege};
+
+pub const DEFAULT_TIMEOUT_MS: usize = 10_000;
+
+/// Checks per timeout, so a transient failure doesn't reset the board.
+const CHECKS_PER_TIMEOUT: usize = 4;
+
+pub type LivenessCheck = dyn Fn() -> bool + Send + Sync + 'static;
+
+/// Handle of a registered liveness check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckId(usize);
+
+struct Check {
+    id: CheckId,
+    name: String,
+    check: Arc<LivenessCheck>,
+}
+
+static CHECKS: Spinlock<Vec<Check>> = Spinlock::new(Vec::new());
+static NEXT_CHECK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a hardware watchdog is started.
+static STARTED: AtomicBool = AtomicBool::new(false);
+static TIMEOUT_MS: AtomicUsize = AtomicUsize::new(DEFAULT_TIMEOUT_MS);
+static KICKS: AtomicUsize = AtomicUsize::new(0);
+static RESET_BY_WATCHDOG: AtomicBool = AtomicBool::new(false);
+static UNHEALTHY: Spinlock<Option<Unhealthy>> = Spinlock::new(None);
+
+/// Why the watchdog is not kicked.
+#[derive(Debug, Clone)]
+pub enum Unhealthy {
+    /// A core didn't schedule since the last check.
+    CoreStalled(usize),
+    /// A liveness check failed.
+    CheckFailed(String),
+}
+
+impl fmt::Display for Unhealthy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unhealthy::CoreStalled(core) => write!(f, "core {} stalled", core),
+            Unhealthy::CheckFailed(name) => write!(f, "liveness check {:?} failed", name),
+        }
+    }
+}
+
+/// Snapshot of the watchdog service.
+#[derive(Debug, Clone)]
+pub struct Status {
+    /// Name of the hardware watchdog, None if there is none.
+    pub device: Option<&'static str>,
+    pub timeout_ms: usize,
+    pub kicks: usize,
+    /// Whether the last reset was caused by the watchdog.
+    pub reset_by_watchdog: bool,
+    /// Why the last check failed, None if it passed.
+    pub unhealthy: Option<Unhealthy>,
+    pub checks: Vec<String>,
+}
+
+/// Register `check`, called by each health check, the watchdog is not kicked
+/// while it returns false. It must not block.
+pub fn register_check(name: &str, check: Arc<LivenessCheck>) -> CheckId {
+    let id = CheckId(NEXT_CHECK_ID.fetch_add(1, Ordering::Relaxed));
+    CHECKS.lock().push(Check {
+        id,
+        name: name.to_string(),
+        check,
+    });
+    id
+}
+
+/// Unregister a liveness check, returns false if it's not registered.
+pub fn unregister_check(id: CheckId) -> bool {
+    let mut checks = CHECKS.lock();
+    let len = checks.len();
+    checks.retain(|c| c.id != id);
+    checks.len() != len
+}
+
+/// Restart the hardware watchdog with a new timeout, returns the timeout actually set.
+pub fn set_timeout(timeout_ms: usize) -> Result<usize, &'static str> {
+    if timeout_ms < CHECKS_PER_TIMEOUT {
+        return Err("watchdog timeout too short");
+    }
+    if !STARTED.load(Ordering::Relaxed) {
+        return Err("no hardware watchdog started");
+    }
+    let timeout_ms = device::start(timeout_ms)?;
+    TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+    Ok(timeout_ms)
+}
+
+/// Stop the hardware watchdog, the health is still monitored.
+pub fn stop() -> Result<(), &'static str> {
+    if !STARTED.swap(false, Ordering::Relaxed) {
+        return Err("no hardware watchdog started");
+    }
+    device::stop().map_err(|e| {
+        STARTED.store(true, Ordering::Relaxed);
+        e
+    })
+}
+
+pub fn status() -> Status {
+    Status {
+        device: STARTED.load(Ordering::Relaxed).then(device::name),
+        timeout_ms: TIMEOUT_MS.load(Ordering::Relaxed),
+        kicks: KICKS.load(Ordering::Relaxed),
+        reset_by_watchdog: RESET_BY_WATCHDOG.load(Ordering::Relaxed),
+        unhealthy: UNHEALTHY.lock().clone(),
+        checks: CHECKS.lock().iter().map(|c| c.name.clone()).collect(),
+    }
+}
+
+/// Print the status, for the terminal command `watchdog`.
+pub fn print_status() {
+    let status = status();
+    match status.device {
+        Some(device) => println!(
+            "watchdog: {}, timeout {}ms, {} kicks",
+            device, status.timeout_ms, status.kicks
+        ),
+        None => println!("watchdog: no hardware watchdog, monitoring only"),
+    }
+    if status.reset_by_watchdog {
+        println!("last reset was caused by the watchdog");
+    }
+    match status.unhealthy {
+        Some(reason) => println!("unhealthy: {}", reason),
+        None => println!("healthy"),
+    }
+    for name in status.checks.iter() {
+        println!("-check {:?}", name);
+    }
+}
+
+/// Check heartbeats against their values at the last check, then liveness checks.
+fn check_health(last_heartbeats: &mut [usize; BOARD_CORE_NUMBER]) -> Result<(), Unhealthy> {
+    let mut stalled = None;
+    for (core, last) in last_heartbeats.iter_mut().enumerate() {
+        let heartbeat = get_cpu(core).heartbeat();
+        if heartbeat != 0 && heartbeat == *last && stalled.is_none() {
+            stalled = Some(core);
+        }
+        *last = heartbeat;
+    }
+    if let Some(core) = stalled {
+        return Err(Unhealthy::CoreStalled(core));
+    }
+
+    // Checks are cloned out, so they may register or unregister checks.
+    let checks: Vec<(String, Arc<LivenessCheck>)> = CHECKS
+        .lock()
+        .iter()
+        .map(|c| (c.name.clone(), c.check.clone()))
+        .collect();
+    for (name, check) in checks {
+        if !check() {
+            return Err(Unhealthy::CheckFailed(name));
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn watchdog_thread(_arg: usize) {
+    let mut last_heartbeats = [0; BOARD_CORE_NUMBER];
+    loop {
+        let period_ms = TIMEOUT_MS.load(Ordering::Relaxed) / CHECKS_PER_TIMEOUT;
+        thread_block_current_with_timeout_us(period_ms * 1000);
+
+        let health = check_health(&mut last_heartbeats);
+        let mut unhealthy = UNHEALTHY.lock();
+        match health {
+            Ok(()) => {
+                if let Some(reason) = unhealthy.take() {
+                    info!("watchdog: recovered from \"{}\", kicking again", reason);
+                }
+                if STARTED.load(Ordering::Relaxed) {
+                    device::kick();
+                    KICKS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(reason) => {
+                if unhealthy.is_none() {
+                    error!("watchdog: {}, stop kicking", reason);
+                }
+                *unhealthy = Some(reason);
+            }
+        }
+    }
+}
+
+/// Probe and start the hardware watchdog, and spawn the thread kicking it.
+pub fn init() {
+    match device::probe().and_then(|reset| device::start(DEFAULT_TIMEOUT_MS).map(|t| (reset, t))) {
+        Ok((reset, timeout_ms)) => {
+            if reset {
+                warn!("watchdog: last reset was caused by the watchdog");
+            }
+            RESET_BY_WATCHDOG.store(reset, Ordering::Relaxed);
+            TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+            STARTED.store(true, Ordering::Relaxed);
+            info!("watchdog: {} started, timeout {}ms", device::name(), timeout_ms);
+        }
+        Err(e) => warn!("watchdog: {}, monitoring only", e),
+    }
+    thread_spawn_privilege(watchdog_thread, 0, "watchdog");
+}
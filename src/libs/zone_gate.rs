@@ -0,0 +1,192 @@
+//! Zone call gates, enabled by feature "zone".
+//!
+//! A zone call runs an entry point declared by `#[zone_entry(zone = ...)]` in the target zone,
+//! with access to the target zone and the shared zone only, rather than jumping to full privilege
+//! like `#[privileged_func]`.
+//!
+//! * Entry points are registered by `#[zone_entry]` in section `.zone_entry_desc`,
+//!   the gate refuses to enter a zone through any other function.
+//! * The callee runs on a gate stack mapped in the target zone, since it can access neither
+//!   the caller's stack nor its zone. Each thread caches one gate stack per zone it calls into,
+//!   which holds a reference to the zone until the thread is dropped.
+//! * Arguments are moved into a call frame on top of the gate stack, and results are moved
+//!   back through it. Heap memory owned by results must be allocated from the shared zone,
+//!   e.g. by `ZoneAllocator::shared()`, to be accessible by the caller.
+//! * With feature "unwind", a panic in the callee is caught on the gate stack
+//!   and returned to the caller as an error.
+
+use core::mem::{align_of, size_of};
+
+use zone::{ZoneEntryDesc, ZoneId, ZONE_ID_PRIVILEGED};
+
+use crate::mm::stack::{alloc_stack, Stack};
+
+/// Size of gate stacks in pages, including the guard page.
+const GATE_STACK_PAGES: usize = 64;
+
+fn descriptors() -> &'static [ZoneEntryDesc] {
+    extern "C" {
+        // Note: link-time label, see linker.ld
+        fn ZONE_ENTRY_DESC_START();
+        fn ZONE_ENTRY_DESC_END();
+    }
+    let start = ZONE_ENTRY_DESC_START as usize;
+    let end = ZONE_ENTRY_DESC_END as usize;
+    unsafe {
+        core::slice::from_raw_parts(
+            start as *const ZoneEntryDesc,
+            (end - start) / size_of::<ZoneEntryDesc>(),
+        )
+    }
+}
+
+/// Get the entry point registered at `entry` by `#[zone_entry]`.
+fn registered_entry(entry: usize) -> Option<&'static ZoneEntryDesc> {
+    descriptors()
+        .iter()
+        .find(|desc| desc.entry as usize == entry)
+}
+
+/// A gate stack mapped in a zone, cached by the thread calling into it.
+///
+/// It holds a reference to the zone, so its pages are never handed to another zone
+/// allocated with the same id.
+pub(crate) struct GateStack {
+    zone_id: ZoneId,
+    stack: Option<Stack>,
+}
+
+impl GateStack {
+    fn alloc(zone_id: ZoneId) -> Option<Self> {
+        let stack = alloc_stack(GATE_STACK_PAGES, zone_id)?;
+        zone::zone_get(zone_id);
+        Some(GateStack {
+            zone_id,
+            stack: Some(stack),
+        })
+    }
+
+    fn top(&self) -> usize {
+        let stack = self.stack.as_ref().unwrap();
+        (stack.start_address() + stack.size_in_bytes()).value()
+    }
+}
+
+impl Drop for GateStack {
+    fn drop(&mut self) {
+        drop(self.stack.take());
+        crate::libs::thread::zone_put(self.zone_id);
+    }
+}
+
+/// Take the gate stack cached by current thread for `zone_id`, or allocate one.
+/// It's not in the cache while in use, nested calls into the zone get their own.
+fn take_gate_stack(zone_id: ZoneId) -> Option<GateStack> {
+    if let Ok(t) = crate::libs::thread::current_thread() {
+        let mut stacks = t.gate_stacks().lock();
+        if let Some(i) = stacks.iter().position(|s| s.zone_id == zone_id) {
+            return Some(stacks.swap_remove(i));
+        }
+    }
+    GateStack::alloc(zone_id)
+}
+
+/// Give the gate stack back to current thread's cache, dropped if it has one for the zone.
+fn put_gate_stack(stack: GateStack) {
+    if let Ok(t) = crate::libs::thread::current_thread() {
+        let mut stacks = t.gate_stacks().lock();
+        if !stacks.iter().any(|s| s.zone_id == stack.zone_id) {
+            stacks.push(stack);
+        }
+    }
+}
+
+/// Call frame shared by the caller and the callee.
+struct CallFrame<A, R> {
+    zone_id: ZoneId,
+    entry: fn(A) -> R,
+    args: Option<A>,
+    ret: Option<Result<R, &'static str>>,
+}
+
+/// First code run on the gate stack, enters the target zone and calls the entry.
+extern "C" fn gate_entry<A, R>(frame: usize) {
+    let frame = unsafe { &mut *(frame as *mut CallFrame<A, R>) };
+    zone::switch_to_zone(frame.zone_id);
+
+    let entry = frame.entry;
+    let args = frame.args.take().unwrap();
+    #[cfg(feature = "unwind")]
+    let ret = {
+        #[cfg(not(feature = "std"))]
+        use crate::libs::unwind::catch::catch_unwind;
+        #[cfg(feature = "std")]
+        use std::panic::catch_unwind;
+        use core::panic::AssertUnwindSafe;
+
+        #[cfg(feature = "lock-poison")]
+        let lock_seq = crate::libs::synch::poison::last_seq();
+        // The payload belongs to the target zone, drop it before leaving.
+        catch_unwind(AssertUnwindSafe(move || entry(args))).map_err(|_| {
+            #[cfg(feature = "lock-poison")]
+            crate::libs::synch::poison::release_since(lock_seq, "unwound");
+            #[cfg(feature = "quota")]
+            if let Ok(t) = crate::libs::thread::current_thread() {
+                t.quota().set_unwinding(false);
+            }
+            "zone call: callee panicked"
+        })
+    };
+    #[cfg(not(feature = "unwind"))]
+    let ret = Ok(entry(args));
+    frame.ret = Some(ret);
+
+    // Returns on the caller's stack.
+    let _ = zone::switch_to_privilege();
+}
+
+/// Call `entry` with `args` in zone `zone_id`, see module doc.
+///
+/// Used by `#[zone_entry]`, fails if `entry` is not registered by it, the zone is not allocated,
+/// the gate stack can not be allocated or the callee panics.
+pub fn zone_call<A: Send + 'static, R: Send + 'static>(
+    zone_id: ZoneId,
+    args: A,
+    entry: fn(A) -> R,
+) -> Result<R, &'static str> {
+    if zone_id == ZONE_ID_PRIVILEGED || !zone::zone_allocated(zone_id) {
+        return Err("zone call: invalid target zone");
+    }
+
+    let ori_zone = zone::switch_to_privilege();
+    let ret = (|| {
+        let desc = registered_entry(entry as usize).ok_or("zone call: entry not registered")?;
+        let stack = take_gate_stack(zone_id).ok_or("zone call: fail to allocate gate stack")?;
+        let frame = (stack.top() - size_of::<CallFrame<A, R>>())
+            & !(align_of::<CallFrame<A, R>>().max(16) - 1);
+        let frame = frame as *mut CallFrame<A, R>;
+        unsafe {
+            frame.write(CallFrame {
+                zone_id,
+                entry,
+                args: Some(args),
+                ret: None,
+            });
+        }
+        trace!(
+            "zone call {} into zone {} on gate stack {:#x}",
+            desc.name,
+            zone_id,
+            frame as usize
+        );
+
+        unsafe {
+            crate::arch::call_on_stack(frame as usize, gate_entry::<A, R>, frame as usize);
+        }
+        let frame = unsafe { frame.read() };
+        put_gate_stack(stack);
+        frame.ret.unwrap()
+    })();
+    zone::switch_from_privilege(ori_zone);
+    ret
+}
@@ -66,6 +66,10 @@ impl VAddr {
         self.0 & (MapGranularity::Page2MB as usize - 1)
     }
 
+    pub const fn page_offset_1gb(&self) -> usize {
+        self.0 & (MapGranularity::Page1GB as usize - 1)
+    }
+
     // Todo: remove this method.
     pub fn to_physical_address(&self) -> PAddr {
         crate::mm::paging::virt_to_phys(&self)
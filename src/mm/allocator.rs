@@ -8,9 +8,9 @@ use crate::libs::traits::Address;
 use crate::mm::page_allocator;
 use crate::mm::frame_allocator;
 use crate::mm::frame_allocator::AllocatedFrames;
-use crate::mm::paging::{map_allocated_pages, EntryAttribute, MappedRegion};
+use crate::mm::paging::{map_allocated_pages, map_allocated_pages_to, EntryAttribute, MappedRegion};
 use crate::mm::address::VAddr;
-use crate::mm::interface::PageTableEntryAttrTrait;
+use crate::mm::interface::{MapGranularity, PageTableEntryAttrTrait};
 
 use zone::ZoneId;
 
@@ -59,6 +59,52 @@ pub fn kallocate(size: usize) -> Option<VAddr> {
     Some(kaddr)
 }
 
+/// Returns the alignment used for a huge allocation of `size` bytes.
+fn huge_alignment(size: usize) -> usize {
+    if crate::arch::page_table::huge_1gb_supported()
+        && size % MapGranularity::Page1GB as usize == 0
+    {
+        MapGranularity::Page1GB as usize
+    } else {
+        MapGranularity::Page2MB as usize
+    }
+}
+
+/// Allocate a region mapped by huge pages.
+///
+/// `size` is rounded up to 2MB, both the virtual and physical range are aligned
+/// to 2MB (or 1GB if `size` is a multiple of 1GB and the platform supports it),
+/// so the whole region is mapped by block entries.
+#[allow(unused_mut)]
+pub fn allocate_huge(size: usize, zone_id: Option<ZoneId>) -> Result<MappedRegion, &'static str> {
+    trace!("user allocate huge region size {:#x} zone {:?}", size, zone_id);
+    assert!(size > 0);
+    let size = crate::util::round_up(size, MapGranularity::Page2MB as usize);
+    let alignment = huge_alignment(size);
+    let size_in_pages = size / PAGE_SIZE;
+
+    let pages = match page_allocator::allocate_pages_alignment(size_in_pages, alignment) {
+        Some(pages) => pages,
+        None => {
+            return Err("allocate_huge(): Failed to allocate pages");
+        }
+    };
+    let frames = match frame_allocator::allocate_frames_alignment(size_in_pages, alignment) {
+        Some(frames) => frames,
+        None => {
+            return Err("allocate_huge(): Failed to allocate frames");
+        }
+    };
+    let mut attr = EntryAttribute::user_2mb();
+
+    #[cfg(feature = "zone")]
+    if zone_id.is_some() {
+        attr.set_zone(zone_id.unwrap());
+    }
+
+    map_allocated_pages_to(pages, frames, attr)
+}
+
 #[allow(unused_mut)]
 pub fn allocate_region(size: usize, zone_id: Option<ZoneId>) -> Result<MappedRegion, &'static str> {
     trace!("user allocate region size {:#x} zone {:?}", size, zone_id);
@@ -70,6 +116,15 @@ pub fn allocate_region(size: usize, zone_id: Option<ZoneId>) -> Result<MappedReg
         size,
         size % PAGE_SIZE,
     );
+
+    // Use huge pages if the size allows it, fall back to 4KB pages on fragmentation.
+    if size % MapGranularity::Page2MB as usize == 0 {
+        match allocate_huge(size, zone_id) {
+            Ok(region) => return Ok(region),
+            Err(e) => debug!("allocate_region(): {}, fall back to 4KB pages", e),
+        }
+    }
+
     let size_in_pages = size / PAGE_SIZE;
     let pages = match page_allocator::allocate_pages(size_in_pages) {
         Some(pages) => pages,
@@ -88,6 +143,22 @@ pub fn allocate_region(size: usize, zone_id: Option<ZoneId>) -> Result<MappedReg
 }
 
 pub fn allocate(size: usize, _protected: bool) -> Option<VAddr> {
+    inner_allocate(size, _protected, false)
+}
+
+/// Allocate memory for current thread which is guaranteed to be mapped by huge pages.
+///
+/// `size` is rounded up to 2MB, there is no fallback to 4KB pages.
+pub fn allocate_huge_pages(size: usize, _protected: bool) -> Option<VAddr> {
+    assert!(size > 0);
+    inner_allocate(
+        crate::util::round_up(size, MapGranularity::Page2MB as usize),
+        _protected,
+        true,
+    )
+}
+
+fn inner_allocate(size: usize, _protected: bool, huge: bool) -> Option<VAddr> {
     let t = match current_thread() {
         Ok(t) => t,
         Err(_) => {
@@ -105,35 +176,48 @@ pub fn allocate(size: usize, _protected: bool) -> Option<VAddr> {
         size,
         size % PAGE_SIZE,
     );
-    let size_in_pages = size / PAGE_SIZE;
-    let pages = match page_allocator::allocate_pages(size_in_pages) {
-        Some(pages) => pages,
-        None => {
-            warn!("allocate(): Failed to allocate mem size {:x}, OOM", size);
-            return None;
-        }
-    };
 
     #[cfg(not(feature = "zone"))]
-    let attr = EntryAttribute::user_default();
-
-    #[cfg(feature = "zone")]
-    let mut attr = EntryAttribute::user_default();
+    let zone_id = None;
 
     #[cfg(feature = "zone")]
-    if _protected {
-        attr.set_zone(t.zone_id());
+    let zone_id = if _protected {
+        Some(t.zone_id())
     } else {
-        attr.set_zone(zone::ZONE_ID_SHARED);
+        Some(zone::ZONE_ID_SHARED)
+    };
+
+    // Charge the memory quota of current thread and its zone first.
+    #[cfg(feature = "quota")]
+    if crate::mm::quota::charge_region(size).is_err() {
+        warn!(
+            "allocate(): thread {} exceeds memory quota on size {:#x}",
+            t.id(),
+            size
+        );
+        return None;
     }
 
-    let region = match map_allocated_pages(pages, attr) {
+    let result = if huge {
+        allocate_huge(size, zone_id)
+    } else {
+        allocate_region(size, zone_id)
+    };
+    let region = match result {
         Ok(region) => region,
         Err(e) => {
             warn!(
-                "allocate(): Couldn't map pages for the new region, error: {}",
-                e
+                "allocate(): Failed to allocate mem size {:x}, error: {}",
+                size, e
             );
+            #[cfg(feature = "quota")]
+            {
+                #[cfg(feature = "zone")]
+                let zone_id = t.zone_id();
+                #[cfg(not(feature = "zone"))]
+                let zone_id = 0;
+                crate::mm::quota::uncharge_region(t.quota(), zone_id, size);
+            }
             return None;
         }
     };
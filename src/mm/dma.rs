@@ -0,0 +1,435 @@
+//! Memory for DMA.
+//!
+//! Buffers shared with devices must be physically contiguous and their physical
+//! address must be known. All DMA memory here is made of contiguous frames accessed
+//! through the kernel linear mapping (`pa2kva`), so it is not restricted to any zone
+//! and can be touched by drivers running in any thread.
+//!
+//! * `DmaRegion`: raw contiguous memory, released on drop.
+//! * `DmaBuffer<T>`: a typed value living in DMA memory.
+//! * `DmaPool`: fixed size chunks carved from DMA regions, for small frequently used buffers.
+//! * `share`/`unshare`: hand an arbitrary buffer to a device, bounced through
+//!   DMA memory if it is not physically contiguous.
+//!
+//! On aarch64 the caches are maintained by `sync_for_device` and `sync_for_cpu`,
+//! on x86_64 and riscv64 DMA is cache coherent and they are no-ops.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use crate::arch::PAGE_SIZE;
+use crate::libs::string::memset;
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::traits::Address;
+use crate::mm::address::{PAddr, VAddr};
+use crate::mm::frame_allocator::{self, AllocatedFrames};
+use crate::mm::interface::MapGranularity;
+use crate::util::round_up;
+
+/// Direction of a DMA transfer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DmaDirection {
+    /// Device reads memory written by CPU.
+    ToDevice,
+    /// CPU reads memory written by device.
+    FromDevice,
+    Bidirectional,
+}
+
+/// Make CPU writes to [vaddr, vaddr + size) visible to device before a transfer.
+#[allow(unused_variables)]
+pub fn sync_for_device(vaddr: VAddr, size: usize, dir: DmaDirection) {
+    #[cfg(target_arch = "aarch64")]
+    match dir {
+        DmaDirection::ToDevice => crate::arch::cache::clean_dcache_range(vaddr.value(), size),
+        // Dirty lines must not be evicted on top of data written by device.
+        DmaDirection::FromDevice | DmaDirection::Bidirectional => {
+            crate::arch::cache::clean_invalidate_dcache_range(vaddr.value(), size)
+        }
+    }
+}
+
+/// Make device writes to [vaddr, vaddr + size) visible to CPU after a transfer.
+#[allow(unused_variables)]
+pub fn sync_for_cpu(vaddr: VAddr, size: usize, dir: DmaDirection) {
+    #[cfg(target_arch = "aarch64")]
+    match dir {
+        DmaDirection::ToDevice => {}
+        // Lines may be speculatively fetched during the transfer.
+        DmaDirection::FromDevice | DmaDirection::Bidirectional => {
+            crate::arch::cache::invalidate_dcache_range(vaddr.value(), size)
+        }
+    }
+}
+
+/// Physically contiguous memory accessed through the kernel linear mapping.
+pub struct DmaRegion {
+    frames: AllocatedFrames,
+    vaddr: VAddr,
+}
+
+impl DmaRegion {
+    fn from_frames(frames: AllocatedFrames) -> Self {
+        let vaddr = VAddr::new_canonical(frames.start_address().value().pa2kva());
+        // Zero allocated memory space.
+        unsafe {
+            memset(vaddr.value() as *mut u8, 0, frames.size_in_bytes());
+        }
+        DmaRegion { frames, vaddr }
+    }
+
+    /// Allocate a zeroed region of `size` bytes, rounded up to pages.
+    pub fn alloc(size: usize) -> Result<Self, &'static str> {
+        assert!(size > 0);
+        let frames = frame_allocator::allocate_frames(round_up(size, PAGE_SIZE) / PAGE_SIZE)
+            .ok_or("DmaRegion::alloc(): failed to allocate frames, OOM")?;
+        Ok(Self::from_frames(frames))
+    }
+
+    /// Allocate a zeroed region of `size` bytes, rounded up to 2MB and aligned to 2MB,
+    /// for devices which need large contiguous buffers.
+    pub fn alloc_huge(size: usize) -> Result<Self, &'static str> {
+        assert!(size > 0);
+        let size = round_up(size, MapGranularity::Page2MB as usize);
+        let frames = frame_allocator::allocate_frames_alignment(
+            size / PAGE_SIZE,
+            MapGranularity::Page2MB as usize,
+        )
+        .ok_or("DmaRegion::alloc_huge(): failed to allocate frames, OOM")?;
+        Ok(Self::from_frames(frames))
+    }
+
+    pub fn paddr(&self) -> PAddr {
+        self.frames.start_address()
+    }
+
+    pub fn vaddr(&self) -> VAddr {
+        self.vaddr
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.size_in_bytes()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr.as_ptr(), self.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr.as_mut_ptr(), self.size()) }
+    }
+}
+
+impl core::fmt::Debug for DmaRegion {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "DmaRegion [{} -> {}] size {:#x}", self.vaddr, self.paddr(), self.size())
+    }
+}
+
+/// A value of type `T` placed in its own DMA region.
+pub struct DmaBuffer<T: ?Sized> {
+    region: DmaRegion,
+    ptr: NonNull<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for DmaBuffer<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for DmaBuffer<T> {}
+
+impl<T> DmaBuffer<T> {
+    /// Move `value` into newly allocated DMA memory.
+    pub fn new(value: T) -> Result<Self, &'static str> {
+        assert!(core::mem::align_of::<T>() <= PAGE_SIZE);
+        let region = DmaRegion::alloc(core::cmp::max(core::mem::size_of::<T>(), 1))?;
+        let ptr = region.vaddr().as_mut_ptr::<T>();
+        unsafe { ptr.write(value) };
+        Ok(DmaBuffer {
+            region,
+            ptr: NonNull::new(ptr).unwrap(),
+        })
+    }
+}
+
+impl<T: Copy> DmaBuffer<[T]> {
+    /// Copy `src` into newly allocated DMA memory.
+    pub fn from_slice(src: &[T]) -> Result<Self, &'static str> {
+        assert!(core::mem::align_of::<T>() <= PAGE_SIZE);
+        let region = DmaRegion::alloc(core::cmp::max(core::mem::size_of_val(src), 1))?;
+        let ptr = region.vaddr().as_mut_ptr::<T>();
+        unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len()) };
+        Ok(DmaBuffer {
+            region,
+            ptr: NonNull::new(core::ptr::slice_from_raw_parts_mut(ptr, src.len())).unwrap(),
+        })
+    }
+}
+
+impl<T: ?Sized> DmaBuffer<T> {
+    /// Physical address of the value, to be handed to device.
+    pub fn paddr(&self) -> PAddr {
+        self.region.paddr()
+    }
+
+    pub fn vaddr(&self) -> VAddr {
+        self.region.vaddr()
+    }
+
+    pub fn sync_for_device(&self, dir: DmaDirection) {
+        sync_for_device(self.vaddr(), core::mem::size_of_val(&**self), dir);
+    }
+
+    pub fn sync_for_cpu(&self, dir: DmaDirection) {
+        sync_for_cpu(self.vaddr(), core::mem::size_of_val(&**self), dir);
+    }
+}
+
+impl<T: ?Sized> Deref for DmaBuffer<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for DmaBuffer<T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.ptr.as_ptr()) };
+    }
+}
+
+struct DmaPoolInner {
+    slabs: Vec<DmaRegion>,
+    /// Virtual addresses of free chunks.
+    free: Vec<VAddr>,
+}
+
+/// A pool of fixed size DMA chunks.
+///
+/// Chunks are carved from slabs of `slab_size` bytes, which are allocated on demand
+/// and kept for reuse. A chunk never crosses a slab, so it is physically contiguous.
+pub struct DmaPool {
+    chunk_size: usize,
+    slab_size: usize,
+    inner: SpinlockIrqSave<DmaPoolInner>,
+}
+
+impl DmaPool {
+    /// Create a pool of chunks of `chunk_size` bytes, aligned to `chunk_size`.
+    /// `chunk_size` must be a power of two not larger than a page.
+    pub const fn new(chunk_size: usize) -> Self {
+        assert!(chunk_size.is_power_of_two() && chunk_size <= PAGE_SIZE);
+        DmaPool {
+            chunk_size,
+            slab_size: PAGE_SIZE,
+            inner: SpinlockIrqSave::new(DmaPoolInner {
+                slabs: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Take a zeroed chunk from the pool, a new slab is allocated if the pool is empty.
+    pub fn alloc(&'static self) -> Result<DmaChunk, &'static str> {
+        let mut inner = self.inner.lock();
+        if inner.free.is_empty() {
+            let slab = DmaRegion::alloc(self.slab_size)?;
+            let base = slab.vaddr();
+            inner.slabs.push(slab);
+            for offset in (0..self.slab_size).step_by(self.chunk_size).rev() {
+                inner.free.push(base + offset);
+            }
+        }
+        let vaddr = inner.free.pop().unwrap();
+        drop(inner);
+        unsafe { memset(vaddr.value() as *mut u8, 0, self.chunk_size) };
+        Ok(DmaChunk { pool: self, vaddr })
+    }
+
+    fn free(&self, vaddr: VAddr) {
+        self.inner.lock().free.push(vaddr);
+    }
+
+    /// Bytes of DMA memory held by this pool.
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().slabs.len() * self.slab_size
+    }
+}
+
+/// A chunk taken from a `DmaPool`, returned to the pool on drop.
+pub struct DmaChunk {
+    pool: &'static DmaPool,
+    vaddr: VAddr,
+}
+
+impl DmaChunk {
+    pub fn paddr(&self) -> PAddr {
+        PAddr::new_canonical(self.vaddr.value().kva2pa())
+    }
+
+    pub fn vaddr(&self) -> VAddr {
+        self.vaddr
+    }
+
+    pub fn size(&self) -> usize {
+        self.pool.chunk_size
+    }
+}
+
+impl Deref for DmaChunk {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr.as_ptr(), self.size()) }
+    }
+}
+
+impl DerefMut for DmaChunk {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr.as_mut_ptr(), self.size()) }
+    }
+}
+
+impl Drop for DmaChunk {
+    fn drop(&mut self) {
+        self.pool.free(self.vaddr);
+    }
+}
+
+impl core::fmt::Debug for DmaChunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "DmaChunk [{} -> {}]", self.vaddr, self.paddr())
+    }
+}
+
+/// Regions handed out by `dma_alloc_pages`, indexed by virtual address.
+static REGIONS: SpinlockIrqSave<BTreeMap<VAddr, DmaRegion>> =
+    SpinlockIrqSave::new(BTreeMap::new());
+
+/// Allocate `pages` zeroed pages of DMA memory, for drivers managing raw pointers.
+/// Must be released by `dma_free_pages`.
+pub fn dma_alloc_pages(pages: usize) -> Option<(PAddr, VAddr)> {
+    let region = match DmaRegion::alloc(pages * PAGE_SIZE) {
+        Ok(region) => region,
+        Err(e) => {
+            warn!("dma_alloc_pages(): {}", e);
+            return None;
+        }
+    };
+    let (paddr, vaddr) = (region.paddr(), region.vaddr());
+    REGIONS.lock().insert(vaddr, region);
+    Some((paddr, vaddr))
+}
+
+/// Release pages allocated by `dma_alloc_pages`.
+pub fn dma_free_pages(vaddr: VAddr) {
+    // Drop the region out of the lock.
+    let region = REGIONS.lock().remove(&vaddr);
+    if region.is_none() {
+        warn!("dma_free_pages(): BUG, DMA region {} unexist", vaddr);
+    }
+}
+
+/// A DMA copy of a buffer which can not be handed to device directly.
+///
+/// Data is copied in on creation if device will read it,
+/// and copied back on `finish` if device wrote it.
+pub struct BounceBuffer {
+    region: DmaRegion,
+    orig: VAddr,
+    len: usize,
+    dir: DmaDirection,
+}
+
+impl BounceBuffer {
+    pub fn new(orig: VAddr, len: usize, dir: DmaDirection) -> Result<Self, &'static str> {
+        let region = DmaRegion::alloc(core::cmp::max(len, 1))?;
+        if dir != DmaDirection::FromDevice {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    orig.as_ptr::<u8>(),
+                    region.vaddr().as_mut_ptr::<u8>(),
+                    len,
+                )
+            };
+        }
+        sync_for_device(region.vaddr(), len, dir);
+        Ok(BounceBuffer {
+            region,
+            orig,
+            len,
+            dir,
+        })
+    }
+
+    pub fn paddr(&self) -> PAddr {
+        self.region.paddr()
+    }
+
+    /// Copy data written by device back to the original buffer and release the bounce buffer.
+    pub fn finish(self) {
+        sync_for_cpu(self.region.vaddr(), self.len, self.dir);
+        if self.dir != DmaDirection::ToDevice {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    self.region.vaddr().as_ptr::<u8>(),
+                    self.orig.as_mut_ptr::<u8>(),
+                    self.len,
+                )
+            };
+        }
+    }
+}
+
+/// Bounce buffers in use by `share`, indexed by physical address handed to device.
+static BOUNCES: SpinlockIrqSave<BTreeMap<PAddr, BounceBuffer>> =
+    SpinlockIrqSave::new(BTreeMap::new());
+
+/// Get the physical address of [vaddr, vaddr + len) if it is physically contiguous.
+fn contiguous_paddr(vaddr: VAddr, len: usize) -> Option<PAddr> {
+    if vaddr.is_kernel_address() {
+        return Some(PAddr::new_canonical(vaddr.value().kva2pa()));
+    }
+    let start = crate::mm::paging::virtual_to_physical(&vaddr)?;
+    let mut page = round_up(vaddr.value() + 1, PAGE_SIZE);
+    while page < vaddr.value() + len {
+        let paddr = crate::mm::paging::virtual_to_physical(&VAddr::new_canonical(page))?;
+        if paddr.value() != start.value() + (page - vaddr.value()) {
+            return None;
+        }
+        page += PAGE_SIZE;
+    }
+    Some(start)
+}
+
+/// Hand the buffer [vaddr, vaddr + len) to device, return the physical address to use.
+///
+/// The buffer is used in place if it is physically contiguous,
+/// otherwise it is bounced through DMA memory until `unshare`.
+pub fn share(vaddr: VAddr, len: usize, dir: DmaDirection) -> Result<PAddr, &'static str> {
+    if let Some(paddr) = contiguous_paddr(vaddr, len) {
+        sync_for_device(vaddr, len, dir);
+        return Ok(paddr);
+    }
+    let bounce = BounceBuffer::new(vaddr, len, dir)?;
+    let paddr = bounce.paddr();
+    BOUNCES.lock().insert(paddr, bounce);
+    Ok(paddr)
+}
+
+/// Take back the buffer shared by `share` at `paddr` after the device is done with it.
+pub fn unshare(paddr: PAddr, vaddr: VAddr, len: usize, dir: DmaDirection) {
+    let bounce = BOUNCES.lock().remove(&paddr);
+    match bounce {
+        Some(bounce) => bounce.finish(),
+        None => sync_for_cpu(vaddr, len, dir),
+    }
+}
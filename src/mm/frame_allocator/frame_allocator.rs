@@ -667,6 +667,11 @@ pub fn allocate_frames_by_bytes_deferred(
     allocate_frames_deferred(requested_paddr, None, num_frames)
 }
 
+/// Whether the frame allocator is busy, a caller holding its lock must not allocate frames.
+pub(crate) fn is_locked() -> bool {
+    FREE_GENERAL_FRAMES_LIST.is_locked()
+}
+
 /// Allocates the given number of frames with no constraints on the starting physical address.
 pub fn allocate_frames(num_frames: usize) -> Option<AllocatedFrames> {
     // trace!("allocate {} frames", num_frames);
@@ -4,8 +4,12 @@ use core::ptr::NonNull;
 // rCore buddy system allocator
 use buddy_system_allocator::Heap;
 
+use zone::ZoneId;
+
 use crate::libs::traits::*;
 use crate::libs::synch::spinlock::SpinlockIrqSave;
+#[cfg(feature = "quota")]
+use super::quota::HeapCharge;
 
 pub fn init() {
     // We dump the current memory layout here.
@@ -75,36 +79,161 @@ impl SpinlockIrqSaveHeapAllocator {
     }
 }
 
+impl SpinlockIrqSaveHeapAllocator {
+    /// Allocate from the buddy system, return null if failed.
+    fn buddy_alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .alloc(layout)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    /// Allocate from the heap arena of zone `arena`, or the shared heap if it's `None`.
+    ///
+    /// Zone allocations return null if the arena is out of memory,
+    /// only kernel bookkeeping goes to the shared heap instead.
+    fn arena_alloc(&self, layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+        #[cfg(feature = "zone")]
+        if let Some(zone_id) = arena {
+            if !super::zone_heap::is_bookkeeping() {
+                return super::zone_heap::alloc(zone_id, layout);
+            }
+        }
+        let _ = arena;
+        self.buddy_alloc(layout)
+    }
+
+    /// Release a block to the zone arena it belongs to, or the shared heap.
+    unsafe fn arena_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "zone")]
+        if let Some(zone_id) = super::zone_heap::owner_of(ptr) {
+            return super::zone_heap::dealloc(zone_id, ptr, layout);
+        }
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+
+    // Heap blocks pass through these layers, each one may wrap the block with its own header:
+    // alloc-track -> heap-debug -> quota -> zone arena or shared buddy system.
+
+    unsafe fn heap_alloc(&self, layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+        #[cfg(feature = "alloc-track")]
+        return super::heap_track::alloc(layout, |outer| self.debug_alloc(outer, arena));
+        #[cfg(not(feature = "alloc-track"))]
+        self.debug_alloc(layout, arena)
+    }
+
+    unsafe fn heap_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "alloc-track")]
+        return super::heap_track::dealloc(ptr, layout, |ptr, outer| {
+            self.debug_dealloc(ptr, outer)
+        });
+        #[cfg(not(feature = "alloc-track"))]
+        self.debug_dealloc(ptr, layout)
+    }
+
+    unsafe fn debug_alloc(&self, layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+        #[cfg(feature = "heap-debug")]
+        return super::heap_debug::alloc(layout, |outer| self.charged_alloc(outer, arena));
+        #[cfg(not(feature = "heap-debug"))]
+        self.charged_alloc(layout, arena)
+    }
+
+    unsafe fn debug_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-debug")]
+        return super::heap_debug::dealloc(ptr, layout, |ptr, outer| {
+            self.charged_dealloc(ptr, outer)
+        });
+        #[cfg(not(feature = "heap-debug"))]
+        self.charged_dealloc(ptr, layout)
+    }
+
+    #[cfg(not(feature = "quota"))]
+    unsafe fn charged_alloc(&self, layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+        self.arena_alloc(layout, arena)
+    }
+
+    #[cfg(not(feature = "quota"))]
+    unsafe fn charged_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.arena_dealloc(ptr, layout)
+    }
+
+    /// Charge the heap quota and allocate,
+    /// the charge record is stored right before the returned block.
+    #[cfg(feature = "quota")]
+    unsafe fn charged_alloc(&self, layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+        let (outer, offset) = match quota_layout(layout) {
+            Some(outer) => outer,
+            None => return core::ptr::null_mut(),
+        };
+        let charge = match HeapCharge::charge(layout.size()) {
+            Ok(charge) => charge,
+            Err(_) => return core::ptr::null_mut(),
+        };
+        let res = self.arena_alloc(outer, arena);
+        if res.is_null() {
+            charge.uncharge(layout.size());
+            return res;
+        }
+        let ptr = res.add(offset);
+        (ptr as *mut HeapCharge).sub(1).write(charge);
+        ptr
+    }
+
+    #[cfg(feature = "quota")]
+    unsafe fn charged_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (outer, offset) = quota_layout(layout).unwrap();
+        let charge = (ptr as *mut HeapCharge).sub(1).read();
+        self.arena_dealloc(ptr.sub(offset), outer);
+        // Uncharge after the heap lock is released, for it may drop the thread's quota.
+        charge.uncharge(layout.size());
+    }
+}
+
+/// Get the layout with a `HeapCharge` record ahead, and the offset of the user block.
+#[cfg(feature = "quota")]
+fn quota_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = core::cmp::max(core::mem::size_of::<HeapCharge>(), layout.align());
+    let align = core::cmp::max(core::mem::align_of::<HeapCharge>(), layout.align());
+    let outer = Layout::from_size_align(layout.size().checked_add(offset)?, align).ok()?;
+    Some((outer, offset))
+}
+
+/// Get the zone arena which heap allocations of current context go to.
+#[inline]
+fn current_arena() -> Option<ZoneId> {
+    #[cfg(feature = "zone")]
+    return super::zone_heap::current_arena();
+    #[cfg(not(feature = "zone"))]
+    None
+}
+
+/// Allocate from the heap arena of zone `arena`, or the shared heap if it's `None`.
+pub(super) unsafe fn alloc_in(layout: Layout, arena: Option<ZoneId>) -> *mut u8 {
+    HEAP_ALLOCATOR.heap_alloc(layout, arena)
+}
+
+/// Release a block allocated by `alloc_in`.
+pub(super) unsafe fn dealloc_in(ptr: *mut u8, layout: Layout) {
+    HEAP_ALLOCATOR.heap_dealloc(ptr, layout)
+}
+
 unsafe impl GlobalAlloc for SpinlockIrqSaveHeapAllocator {
+    #[cfg_attr(
+        feature = "fault-inject",
+        inject::fault_site("mm::alloc", alloc_failure, ret = "core::ptr::null_mut()")
+    )]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         // println!(
         //     "GlobalAlloc alloc {:?} with pkru {:#x}",
         //     layout,
         //     crate::arch::mpk::rdpkru()
         // );
-        let res = self
-            .0
-            .lock()
-            .alloc(layout)
-            .ok()
-            .map_or(0 as *mut u8, |allocation| allocation.as_ptr());
-        // println!(
-        //     "GlobalAlloc {} alloc success at {:#p} {:?}",
-        //     crate::libs::thread::current_thread_id(),
-        //     res,
-        //     layout,
-        // );
-        res
+        self.heap_alloc(layout, current_arena())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // println!(
-        //     "GlobalAlloc {} dealloc success at {:#p} {:?}",
-        //     crate::libs::thread::current_thread_id(),
-        //     ptr,
-        //     layout,
-        // );
-        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+        self.heap_dealloc(ptr, layout)
     }
 }
 
@@ -129,6 +258,17 @@ fn alloc_error_handler(layout: Layout) -> ! {
         stats_alloc_user,
         stats_alloc_actual
     );
+    // With unwind, only the thread which runs out of memory is unwound.
+    #[cfg(feature = "unwind")]
+    {
+        // Unwinding needs heap memory itself, let it bypass the quota.
+        #[cfg(feature = "quota")]
+        if let Ok(t) = crate::libs::thread::current_thread() {
+            t.quota().set_unwinding(true);
+        }
+        panic!("memory allocation of {} bytes failed", layout.size());
+    }
+    #[cfg(not(feature = "unwind"))]
     loop {}
 }
 
@@ -148,14 +288,7 @@ pub fn malloc(size: usize, align: usize) -> *mut u8 {
         return core::ptr::null::<*mut u8>() as *mut u8;
     }
     let layout = layout_res.unwrap();
-    let ptr = HEAP_ALLOCATOR
-        .0
-        .lock()
-        .alloc(layout)
-        .ok()
-        .map_or(core::ptr::null_mut() as *mut u8, |mut mem| unsafe {
-            mem.as_mut()
-        });
+    let ptr = unsafe { HEAP_ALLOCATOR.heap_alloc(layout, current_arena()) };
 
     trace!(
         "heap malloc: allocate memory at {:#x} (size {:#x}, align {:#x})",
@@ -195,10 +328,7 @@ pub fn free(ptr: *mut u8, size: usize, align: usize) {
         );
     }
     let layout = layout_res.unwrap();
-    HEAP_ALLOCATOR
-        .0
-        .lock()
-        .dealloc(unsafe { core::ptr::NonNull::new_unchecked(ptr) }, layout);
+    unsafe { HEAP_ALLOCATOR.heap_dealloc(ptr, layout) };
 }
 
 use core::ptr;
@@ -207,19 +337,16 @@ use core::alloc::{Allocator, AllocError};
 pub struct Global;
 
 unsafe impl Allocator for Global {
+    #[cfg_attr(
+        feature = "fault-inject",
+        inject::fault_site("mm::allocate", alloc_failure, ret = "Err(AllocError)")
+    )]
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         match layout.size() {
             0 => Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0)),
             // SAFETY: `layout` is non-zero in size,
             size => {
-                let raw_ptr = HEAP_ALLOCATOR
-                    .0
-                    .lock()
-                    .alloc(layout)
-                    .ok()
-                    .map_or(core::ptr::null_mut() as *mut u8, |mut mem| unsafe {
-                        mem.as_mut()
-                    });
+                let raw_ptr = unsafe { HEAP_ALLOCATOR.heap_alloc(layout, current_arena()) };
                 let ptr = NonNull::new(raw_ptr).ok_or(AllocError)?;
                 Ok(NonNull::slice_from_raw_parts(ptr, size))
             }
@@ -239,10 +366,7 @@ unsafe impl Allocator for Global {
         if layout.size() != 0 {
             // SAFETY: `layout` is non-zero in size,
             // other conditions must be upheld by the caller
-            HEAP_ALLOCATOR.0.lock().dealloc(
-                unsafe { core::ptr::NonNull::new_unchecked(ptr.as_ptr()) },
-                layout,
-            );
+            unsafe { HEAP_ALLOCATOR.heap_dealloc(ptr.as_ptr(), layout) };
         }
     }
     unsafe fn grow(
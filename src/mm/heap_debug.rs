@@ -0,0 +1,288 @@
+//! Heap debugging layer, enabled by feature "heap-debug".
+//!
+//! Every heap block is wrapped as:
+//!
+//! |-----------------------------------|
+//! |-  DebugHeader (owner, backtrace) -|
+//! |-        front red zone           -|
+//! |-----------------------------------| <- pointer returned to user
+//! |-           user block            -|
+//! |-----------------------------------|
+//! |-         rear red zone           -|
+//! |-----------------------------------|
+//!
+//! * Red zones are filled with guard bytes, which are checked on free and periodically.
+//! * Freed blocks are poisoned and kept in a quarantine, a write to a quarantined block
+//!   is reported when it is evicted. Their headers are marked freed to catch double frees.
+//! * Every report carries the allocating thread and its backtrace.
+
+use core::alloc::Layout;
+use core::mem::size_of;
+
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::thread::current_thread_id;
+use crate::util::round_up;
+
+/// Bytes of each red zone.
+const RED_ZONE: usize = 32;
+const GUARD_BYTE: u8 = 0xfd;
+/// Pattern of newly allocated memory, helps to surface reads of uninitialized memory.
+const ALLOC_POISON: u8 = 0xcd;
+/// Pattern of freed memory.
+const FREE_POISON: u8 = 0xdd;
+const HEADER_CANARY: usize = 0x5348_5950_4552_4844;
+/// Canary of a freed block's header, replacing `HEADER_CANARY` to catch double frees.
+const FREED_CANARY: usize = 0x5348_5950_4652_4545;
+/// Return addresses recorded for each allocation.
+const BACKTRACE_DEPTH: usize = 6;
+/// Number of freed blocks kept in quarantine.
+const QUARANTINE_SIZE: usize = 64;
+/// Blocks larger than this are not poisoned on free, to keep free() fast.
+const POISON_LIMIT: usize = 0x10000;
+
+#[repr(C)]
+struct DebugHeader {
+    prev: *mut DebugHeader,
+    next: *mut DebugHeader,
+    layout: Layout,
+    tid: usize,
+    backtrace: [usize; BACKTRACE_DEPTH],
+    canary: usize,
+}
+
+impl DebugHeader {
+    fn report(&self, ptr: *const u8, reason: &str) {
+        error!(
+            "heap-debug: {} on block {:p} size {:#x} align {:#x}",
+            reason,
+            ptr,
+            self.layout.size(),
+            self.layout.align()
+        );
+        error!(
+            "heap-debug: allocated by thread [{}], current thread [{}]",
+            self.tid,
+            current_thread_id()
+        );
+        for (i, addr) in self.backtrace.iter().take_while(|a| **a != 0).enumerate() {
+            error!("heap-debug:   #{} {:#x}", i, addr);
+        }
+    }
+}
+
+struct DebugState {
+    /// Live blocks, an intrusive list through `DebugHeader`.
+    head: *mut DebugHeader,
+    live: usize,
+    quarantine: [Option<(usize, Layout)>; QUARANTINE_SIZE],
+    quarantine_next: usize,
+}
+
+unsafe impl Send for DebugState {}
+
+static STATE: SpinlockIrqSave<DebugState> = SpinlockIrqSave::new(DebugState {
+    head: core::ptr::null_mut(),
+    live: 0,
+    quarantine: [None; QUARANTINE_SIZE],
+    quarantine_next: 0,
+});
+
+/// Offset of user block from the start of the outer block.
+fn user_offset(layout: Layout) -> usize {
+    round_up(
+        size_of::<DebugHeader>() + RED_ZONE,
+        core::cmp::max(layout.align(), core::mem::align_of::<DebugHeader>()),
+    )
+}
+
+/// Get the outer layout containing header and red zones of `layout`.
+fn outer_layout(layout: Layout) -> Option<Layout> {
+    let size = user_offset(layout)
+        .checked_add(layout.size())?
+        .checked_add(RED_ZONE)?;
+    let align = core::cmp::max(layout.align(), core::mem::align_of::<DebugHeader>());
+    Layout::from_size_align(size, align).ok()
+}
+
+unsafe fn fill(start: *mut u8, len: usize, byte: u8) {
+    core::ptr::write_bytes(start, byte, len);
+}
+
+unsafe fn check(start: *const u8, len: usize, byte: u8) -> bool {
+    core::slice::from_raw_parts(start, len)
+        .iter()
+        .all(|b| *b == byte)
+}
+
+/// Check header canary and red zones of the block at `header`.
+unsafe fn check_block(header: *mut DebugHeader) -> Result<(), &'static str> {
+    if (*header).canary == FREED_CANARY {
+        return Err("double free");
+    }
+    if (*header).canary != HEADER_CANARY {
+        return Err("header corrupted");
+    }
+    let layout = (*header).layout;
+    let ptr = (header as *mut u8).add(user_offset(layout));
+    if !check(ptr.sub(RED_ZONE), RED_ZONE, GUARD_BYTE) {
+        return Err("buffer underflow (front red zone corrupted)");
+    }
+    if !check(ptr.add(layout.size()), RED_ZONE, GUARD_BYTE) {
+        return Err("buffer overflow (rear red zone corrupted)");
+    }
+    Ok(())
+}
+
+/// Allocate `layout` through `inner` with header and red zones.
+pub(super) unsafe fn alloc<F: FnOnce(Layout) -> *mut u8>(layout: Layout, inner: F) -> *mut u8 {
+    let outer = match outer_layout(layout) {
+        Some(outer) => outer,
+        None => return core::ptr::null_mut(),
+    };
+    let base = inner(outer);
+    if base.is_null() {
+        return base;
+    }
+    let offset = user_offset(layout);
+    let ptr = base.add(offset);
+    let header = base as *mut DebugHeader;
+    let mut backtrace = [0; BACKTRACE_DEPTH];
+    crate::libs::backtrace::capture(1, &mut backtrace);
+    header.write(DebugHeader {
+        prev: core::ptr::null_mut(),
+        next: core::ptr::null_mut(),
+        layout,
+        tid: current_thread_id().as_u64() as usize,
+        backtrace,
+        canary: HEADER_CANARY,
+    });
+    let front = base.add(size_of::<DebugHeader>());
+    fill(front, ptr as usize - front as usize, GUARD_BYTE);
+    fill(ptr.add(layout.size()), RED_ZONE, GUARD_BYTE);
+    fill(ptr, layout.size(), ALLOC_POISON);
+
+    // Link to live list.
+    let mut state = STATE.lock();
+    (*header).next = state.head;
+    if !state.head.is_null() {
+        (*state.head).prev = header;
+    }
+    state.head = header;
+    state.live += 1;
+    ptr
+}
+
+/// Check and quarantine the block at `ptr`,
+/// the evicted oldest block in quarantine is released through `inner`.
+pub(super) unsafe fn dealloc<F: FnOnce(*mut u8, Layout)>(ptr: *mut u8, layout: Layout, inner: F) {
+    let header = ptr.sub(user_offset(layout)) as *mut DebugHeader;
+    if let Err(reason) = check_block(header) {
+        (*header).report(ptr, reason);
+        panic!("heap-debug: corrupted heap block {:p} freed", ptr);
+    }
+    if (*header).layout != layout {
+        (*header).report(ptr, "free with mismatched layout");
+        panic!("heap-debug: heap block {:p} freed with {:?}", ptr, layout);
+    }
+
+    let poisoned = layout.size() <= POISON_LIMIT;
+    if poisoned {
+        fill(ptr, layout.size(), FREE_POISON);
+    }
+
+    let mut state = STATE.lock();
+    // Checked again under the lock, the block may be freed concurrently.
+    if (*header).canary == FREED_CANARY {
+        drop(state);
+        (*header).report(ptr, "double free");
+        panic!("heap-debug: heap block {:p} freed twice", ptr);
+    }
+    (*header).canary = FREED_CANARY;
+    // Unlink from live list.
+    let (prev, next) = ((*header).prev, (*header).next);
+    if prev.is_null() {
+        state.head = next;
+    } else {
+        (*prev).next = next;
+    }
+    if !next.is_null() {
+        (*next).prev = prev;
+    }
+    state.live -= 1;
+
+    if !poisoned {
+        drop(state);
+        inner(header as *mut u8, outer_layout(layout).unwrap());
+        return;
+    }
+
+    // Put into quarantine, evict the oldest one.
+    let slot = state.quarantine_next;
+    state.quarantine_next = (slot + 1) % QUARANTINE_SIZE;
+    let evicted = state.quarantine[slot].replace((ptr as usize, layout));
+    drop(state);
+
+    if let Some((old_ptr, old_layout)) = evicted {
+        let old_ptr = old_ptr as *mut u8;
+        let old_header = old_ptr.sub(user_offset(old_layout)) as *mut DebugHeader;
+        if !check(old_ptr, old_layout.size(), FREE_POISON) {
+            (*old_header).report(old_ptr, "use after free (write to freed block)");
+        }
+        inner(old_header as *mut u8, outer_layout(old_layout).unwrap());
+    }
+}
+
+/// Check all live and quarantined blocks, return the number of corrupted blocks.
+pub fn check_all() -> usize {
+    let mut corrupted = 0;
+    let state = STATE.lock();
+    let mut header = state.head;
+    unsafe {
+        while !header.is_null() {
+            if let Err(reason) = check_block(header) {
+                let ptr = (header as *mut u8).add(user_offset((*header).layout));
+                (*header).report(ptr, reason);
+                corrupted += 1;
+                // The list may be broken as well.
+                if (*header).canary != HEADER_CANARY {
+                    break;
+                }
+            }
+            header = (*header).next;
+        }
+        for (ptr, layout) in state.quarantine.iter().flatten() {
+            let ptr = *ptr as *mut u8;
+            if !check(ptr, layout.size(), FREE_POISON) {
+                let header = ptr.sub(user_offset(*layout)) as *mut DebugHeader;
+                (*header).report(ptr, "use after free (write to freed block)");
+                corrupted += 1;
+            }
+        }
+    }
+    corrupted
+}
+
+/// Number of live heap blocks.
+#[allow(unused)]
+pub fn live_blocks() -> usize {
+    STATE.lock().live
+}
+
+/// Interval of the periodic heap check.
+const CHECK_INTERVAL_MS: usize = 1000;
+
+extern "C" fn heap_check_thread(_arg: usize) {
+    loop {
+        crate::libs::thread::thread_block_current_with_timeout(CHECK_INTERVAL_MS);
+        crate::libs::thread::thread_yield();
+        let corrupted = check_all();
+        if corrupted != 0 {
+            error!("heap-debug: periodic check found {} corrupted blocks", corrupted);
+        }
+    }
+}
+
+/// Spawn the thread checking heap canaries periodically.
+pub fn init() {
+    crate::libs::thread::thread_spawn_privilege(heap_check_thread, 0, "heap_check");
+}
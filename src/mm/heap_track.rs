@@ -0,0 +1,244 @@
+//! Heap allocation tracking, enabled by feature "alloc-track".
+//!
+//! Every live heap block carries a `TrackHeader` recording its size,
+//! allocating thread and call site (the return addresses of the allocation).
+//! Live blocks can be dumped grouped by call site, and snapshots of them can be diffed
+//! to find out which call sites are leaking.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::thread::current_thread_id;
+use crate::util::round_up;
+
+/// Return addresses identifying a call site.
+const CALL_SITE_DEPTH: usize = 4;
+/// Frames inside the allocator skipped when recording a call site.
+const CALL_SITE_SKIP: usize = 2;
+/// Snapshots kept for diff.
+const SNAPSHOT_MAX: usize = 8;
+
+pub type CallSite = [usize; CALL_SITE_DEPTH];
+
+#[repr(C)]
+struct TrackHeader {
+    prev: *mut TrackHeader,
+    next: *mut TrackHeader,
+    size: usize,
+    tid: usize,
+    call_site: CallSite,
+}
+
+struct TrackState {
+    head: *mut TrackHeader,
+    live: usize,
+}
+
+unsafe impl Send for TrackState {}
+
+static STATE: SpinlockIrqSave<TrackState> = SpinlockIrqSave::new(TrackState {
+    head: core::ptr::null_mut(),
+    live: 0,
+});
+
+fn header_offset(layout: Layout) -> usize {
+    round_up(
+        size_of::<TrackHeader>(),
+        core::cmp::max(layout.align(), core::mem::align_of::<TrackHeader>()),
+    )
+}
+
+fn outer_layout(layout: Layout) -> Option<Layout> {
+    let size = header_offset(layout).checked_add(layout.size())?;
+    let align = core::cmp::max(layout.align(), core::mem::align_of::<TrackHeader>());
+    Layout::from_size_align(size, align).ok()
+}
+
+/// Allocate `layout` through `inner` and record it.
+pub(super) unsafe fn alloc<F: FnOnce(Layout) -> *mut u8>(layout: Layout, inner: F) -> *mut u8 {
+    let outer = match outer_layout(layout) {
+        Some(outer) => outer,
+        None => return core::ptr::null_mut(),
+    };
+    let base = inner(outer);
+    if base.is_null() {
+        return base;
+    }
+    let header = base as *mut TrackHeader;
+    let mut call_site = [0; CALL_SITE_DEPTH];
+    crate::libs::backtrace::capture(CALL_SITE_SKIP, &mut call_site);
+    header.write(TrackHeader {
+        prev: core::ptr::null_mut(),
+        next: core::ptr::null_mut(),
+        size: layout.size(),
+        tid: current_thread_id().as_u64() as usize,
+        call_site,
+    });
+
+    let mut state = STATE.lock();
+    (*header).next = state.head;
+    if !state.head.is_null() {
+        (*state.head).prev = header;
+    }
+    state.head = header;
+    state.live += 1;
+    base.add(header_offset(layout))
+}
+
+/// Forget the record of `ptr` and release it through `inner`.
+pub(super) unsafe fn dealloc<F: FnOnce(*mut u8, Layout)>(ptr: *mut u8, layout: Layout, inner: F) {
+    let header = ptr.sub(header_offset(layout)) as *mut TrackHeader;
+    {
+        let mut state = STATE.lock();
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            state.head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        state.live -= 1;
+    }
+    inner(header as *mut u8, outer_layout(layout).unwrap());
+}
+
+/// Live allocations of one call site.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SiteStat {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Live allocations grouped by call site.
+pub type Snapshot = BTreeMap<CallSite, SiteStat>;
+
+/// Collect (call site, size, tid) of live allocations.
+///
+/// The heap can not be used while holding the tracking lock,
+/// so the buffer is reserved ahead and the records out of its capacity are dropped.
+fn collect_live() -> Vec<(CallSite, usize, usize)> {
+    const SLACK: usize = 64;
+    let live = STATE.lock().live;
+    let mut records = Vec::with_capacity(live + SLACK);
+    let state = STATE.lock();
+    let mut header = state.head;
+    while !header.is_null() && records.len() < records.capacity() {
+        unsafe {
+            records.push(((*header).call_site, (*header).size, (*header).tid));
+            header = (*header).next;
+        }
+    }
+    drop(state);
+    records
+}
+
+/// Group live allocations by call site.
+fn group_by_site(records: &[(CallSite, usize, usize)]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for (call_site, size, _) in records.iter() {
+        let stat = snapshot.entry(*call_site).or_default();
+        stat.count += 1;
+        stat.bytes += size;
+    }
+    snapshot
+}
+
+/// Take a snapshot of live allocations grouped by call site.
+pub fn snapshot() -> Snapshot {
+    group_by_site(&collect_live())
+}
+
+/// Saved snapshots by id, the oldest first.
+static SNAPSHOTS: Mutex<VecDeque<(usize, Snapshot)>> = Mutex::new(VecDeque::new());
+static NEXT_SNAPSHOT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Store a snapshot for later diff, return its id.
+/// The oldest one is evicted if there are too many, its id is not reused.
+pub fn save_snapshot() -> usize {
+    let snapshot = snapshot();
+    let mut snapshots = SNAPSHOTS.lock();
+    if snapshots.len() == SNAPSHOT_MAX {
+        snapshots.pop_front();
+    }
+    let id = NEXT_SNAPSHOT_ID.fetch_add(1, Ordering::Relaxed);
+    snapshots.push_back((id, snapshot));
+    id
+}
+
+fn print_call_site(call_site: &CallSite) {
+    for addr in call_site.iter().take_while(|a| **a != 0) {
+        print!(" <- {:#x}", addr);
+    }
+    println!();
+}
+
+/// Dump live allocations grouped by call site, the largest first.
+pub fn dump_live() {
+    let records = collect_live();
+    let mut threads = BTreeMap::new();
+    for (_, size, tid) in records.iter() {
+        *threads.entry(*tid).or_insert(0) += *size;
+    }
+    let mut sites: Vec<(CallSite, SiteStat)> = group_by_site(&records).into_iter().collect();
+    sites.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
+
+    println!(
+        "{} live allocations, {} bytes",
+        records.len(),
+        records.iter().map(|r| r.1).sum::<usize>()
+    );
+    println!("  COUNT       BYTES  CALL SITE");
+    for (call_site, stat) in sites.iter() {
+        print!("{:>7} {:>11} ", stat.count, stat.bytes);
+        print_call_site(call_site);
+    }
+    println!("  [ TID]       BYTES");
+    for (tid, bytes) in threads.iter() {
+        println!("  [{:>4}] {:>11}", tid, bytes);
+    }
+}
+
+/// Print the call sites which differ between snapshot `from` and `to`, by id.
+/// Default to diff the last two snapshots.
+pub fn dump_diff(from: Option<usize>, to: Option<usize>) -> Result<(), &'static str> {
+    let snapshots = SNAPSHOTS.lock();
+    let len = snapshots.len();
+    if len < 2 && (from.is_none() || to.is_none()) {
+        return Err("at least two snapshots are required");
+    }
+    let get = |id: Option<usize>, back: usize| match id {
+        Some(id) => snapshots
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, s)| s)
+            .ok_or("snapshot not exist or evicted"),
+        None => Ok(&snapshots[len - back].1),
+    };
+    let from = get(from, 2)?;
+    let to = get(to, 1)?;
+
+    println!("   COUNT        BYTES  CALL SITE");
+    let empty = SiteStat::default();
+    for call_site in from.keys().chain(to.keys().filter(|k| !from.contains_key(*k))) {
+        let old = from.get(call_site).unwrap_or(&empty);
+        let new = to.get(call_site).unwrap_or(&empty);
+        if old.count == new.count && old.bytes == new.bytes {
+            continue;
+        }
+        print!(
+            "{:>+8} {:>+12} ",
+            new.count as isize - old.count as isize,
+            new.bytes as isize - old.bytes as isize
+        );
+        print_call_site(call_site);
+    }
+    Ok(())
+}
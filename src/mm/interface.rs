@@ -43,8 +43,10 @@ pub trait PageTableTrait {
     fn base_pa(&self) -> usize;
     fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error>;
     fn map_2mb(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error>;
+    fn map_1gb(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error>;
     fn unmap(&mut self, va: usize);
     fn unmap_2mb(&mut self, va: usize);
+    fn unmap_1gb(&mut self, va: usize);
     // fn insert_page(
     //     &self,
     //     va: usize,
@@ -53,6 +55,9 @@ pub trait PageTableTrait {
     // ) -> Result<(), Error>;
     fn lookup_entry(&self, va: usize) -> Option<(Entry, MapGranularity)>;
     fn lookup_page(&self, va: usize) -> Option<Entry>;
+    /// Call `f` with each valid leaf entry of the whole address space and the virtual
+    /// address it maps, in ascending order.
+    fn for_each_entry(&self, f: &mut dyn FnMut(usize, Entry, MapGranularity));
     // fn remove_page(&self, va: usize) -> Result<(), Error>;
     // fn recursive_map(&self, va: usize);
 }
@@ -61,14 +66,13 @@ use crate::arch::PAGE_SHIFT;
 use crate::arch::page_table::PAGE_TABLE_L2_SHIFT;
 use crate::arch::page_table::PAGE_TABLE_L1_SHIFT;
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum MapGranularity {
     /// Mapped by 4KB page.
     Page4KB = 1 << PAGE_SHIFT,
     /// Mapped by 2MB page.
     Page2MB = 1 << PAGE_TABLE_L2_SHIFT,
-    /// Mapped by 1GB page, current unused.
-    #[allow(unused)]
+    /// Mapped by 1GB page, only used when the arch supports it.
     Page1GB = 1 << PAGE_TABLE_L1_SHIFT,
 }
 
@@ -82,3 +86,23 @@ impl core::convert::Into<usize> for MapGranularity {
         }
     }
 }
+
+impl MapGranularity {
+    /// Returns the largest granularity that `va`, `pa` and `size` are all aligned to.
+    ///
+    /// 1GB mappings are only considered if the current platform supports them,
+    /// see `crate::arch::page_table::huge_1gb_supported()`.
+    pub fn largest_fit(va: usize, pa: usize, size: usize) -> MapGranularity {
+        let fit = |granularity: MapGranularity| {
+            let align = granularity as usize;
+            va % align == 0 && pa % align == 0 && size % align == 0
+        };
+        if crate::arch::page_table::huge_1gb_supported() && fit(MapGranularity::Page1GB) {
+            MapGranularity::Page1GB
+        } else if fit(MapGranularity::Page2MB) {
+            MapGranularity::Page2MB
+        } else {
+            MapGranularity::Page4KB
+        }
+    }
+}
@@ -1,12 +1,27 @@
 pub mod address;
 pub mod allocator;
 pub mod config;
+pub mod dma;
 pub mod frame_allocator;
 pub mod heap;
+#[cfg(feature = "heap-debug")]
+pub mod heap_debug;
+#[cfg(feature = "alloc-track")]
+pub mod heap_track;
 pub mod interface;
 pub mod page_allocator;
 pub mod paging;
+#[cfg(feature = "quota")]
+pub mod quota;
 pub mod stack;
+pub mod vmmap;
+pub mod wx;
+#[cfg(feature = "zone")]
+pub mod zone_data;
+#[cfg(feature = "zone")]
+pub mod zone_heap;
+#[cfg(all(feature = "zone", not(target_arch = "x86_64")))]
+pub mod zone_view;
 
 pub use allocator::*;
 pub use self::page_allocator::Page;
@@ -37,6 +52,8 @@ fn allocator_init() {
 pub fn init() {
     heap::init();
     allocator_init();
+    #[cfg(feature = "zone")]
+    zone_heap::init();
 }
 
 #[cfg(feature = "terminal")]
@@ -47,4 +64,14 @@ pub fn dump_mm_usage() {
     page_allocator::dump_page_allocator_state();
     println!("------------ Physical Address -------------");
     frame_allocator::dump_frame_allocator_state();
+    #[cfg(feature = "zone")]
+    {
+        println!("---------------- Zone Heap ----------------");
+        zone_heap::dump_zone_heaps();
+    }
+    #[cfg(feature = "quota")]
+    {
+        println!("-------------- Memory Quota ---------------");
+        quota::dump_quota();
+    }
 }
@@ -114,7 +114,7 @@ impl PageTableEntryAttrTrait for EntryAttribute {
             user: true,
             device: false,
             k_executable: false,
-            u_executable: true,
+            u_executable: false,
             copy_on_write: false,
             shared: false,
             #[cfg(feature = "zone")]
@@ -129,7 +129,7 @@ impl PageTableEntryAttrTrait for EntryAttribute {
             user: true,
             device: false,
             k_executable: false,
-            u_executable: true,
+            u_executable: false,
             copy_on_write: false,
             shared: false,
             #[cfg(feature = "zone")]
@@ -2,7 +2,7 @@ use core::fmt;
 use core::ops::Deref;
 
 use crate::arch::PAGE_SHIFT;
-use crate::arch::page_table::{page_table, PAGE_TABLE_L2_SHIFT};
+use crate::arch::page_table::page_table;
 use crate::mm::interface::{PageTableEntryAttrTrait, PageTableTrait, MapGranularity};
 
 use crate::mm::page_allocator::{AllocatedPages, PageRange};
@@ -10,20 +10,22 @@ use crate::mm::page_allocator::{AllocatedPages, PageRange};
 use crate::mm::frame_allocator::AllocatedFrames;
 use crate::mm::paging::entry::EntryAttribute;
 use crate::mm::frame_allocator;
+use crate::mm::wx;
 use crate::mm::address::{PAddr, VAddr};
 
 pub struct MappedRegion {
     pages: AllocatedPages,
     frames: AllocatedFrames,
     attribute: EntryAttribute,
+    granularity: MapGranularity,
 }
 
 impl fmt::Debug for MappedRegion {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "MappedRegion\n\tpages ({:?})\n\tframes({:?})\n\tattributes {:?}",
-            self.pages, self.frames, self.attribute
+            "MappedRegion\n\tpages ({:?})\n\tframes({:?})\n\tattributes {:?}\n\tgranularity {:?}",
+            self.pages, self.frames, self.attribute, self.granularity
         )
     }
 }
@@ -43,6 +45,7 @@ impl MappedRegion {
             pages: AllocatedPages::empty(),
             frames: AllocatedFrames::empty(),
             attribute: EntryAttribute::user_default(),
+            granularity: MapGranularity::Page4KB,
         }
     }
 
@@ -51,6 +54,24 @@ impl MappedRegion {
         self.attribute
     }
 
+    /// Returns the page size this `MappedRegion` is mapped with.
+    pub fn granularity(&self) -> MapGranularity {
+        self.granularity
+    }
+
+    /// Returns the starting physical address of the frames backing this `MappedRegion`.
+    pub fn start_paddr(&self) -> PAddr {
+        self.frames.start_address()
+    }
+
+    /// Unmap this region and release its frames, but keep its virtual pages allocated,
+    /// so the address range can be mapped again later.
+    pub fn unmap_into_pages(mut self) -> AllocatedPages {
+        self.unmap();
+        // The drop handler sees an empty region and does nothing.
+        core::mem::replace(&mut self.pages, AllocatedPages::empty())
+    }
+
     /// Remove the virtual memory mapping represented by this `MappedRegion`.
     fn unmap(&mut self) {
         if self.size_in_pages() == 0 {
@@ -58,16 +79,12 @@ impl MappedRegion {
         }
         let mut page_table = crate::arch::page_table::page_table().lock();
 
-        if self.attribute().block() {
-            // Unmap by 2mb.
-            let step = 1 << (PAGE_TABLE_L2_SHIFT - PAGE_SHIFT);
-            for page in self.pages.deref().clone().into_iter().step_by(step) {
-                page_table.unmap_2mb(page.start_address().value());
-            }
-        } else {
-            // Unmap by 4kb.
-            for page in self.pages.deref().clone().into_iter() {
-                page_table.unmap(page.start_address().value());
+        let step = self.granularity as usize >> PAGE_SHIFT;
+        for page in self.pages.deref().clone().into_iter().step_by(step) {
+            match self.granularity {
+                MapGranularity::Page4KB => page_table.unmap(page.start_address().value()),
+                MapGranularity::Page2MB => page_table.unmap_2mb(page.start_address().value()),
+                MapGranularity::Page1GB => page_table.unmap_1gb(page.start_address().value()),
             }
         }
     }
@@ -84,6 +101,7 @@ pub fn map_allocated_pages(
     pages: AllocatedPages,
     attr: EntryAttribute,
 ) -> Result<MappedRegion, &'static str> {
+    wx::audit(&attr)?;
     let frames = match frame_allocator::allocate_frames(pages.size_in_pages()) {
         Some(allocated_frames) => allocated_frames,
         None => {
@@ -121,15 +139,21 @@ pub fn map_allocated_pages(
         pages,
         frames,
         attribute: attr,
+        granularity: MapGranularity::Page4KB,
     })
 }
 
 /// Mapped allocated pages to target allocated frames.
+///
+/// The largest page size that both `pages` and `frames` are aligned to is used,
+/// so a 2MB/1GB aligned pair of ranges is mapped by block entries automatically.
 pub fn map_allocated_pages_to(
     pages: AllocatedPages,
     frames: AllocatedFrames,
     attr: EntryAttribute,
 ) -> Result<MappedRegion, &'static str> {
+    wx::audit(&attr)?;
+
     // Judge if pages and frames and be mapped.
     let pages_count = pages.size_in_pages();
     let frames_count = frames.size_in_frames();
@@ -140,60 +164,53 @@ pub fn map_allocated_pages_to(
         );
         return Err("map_allocated_pages_to(): page count must equal frame count");
     }
+
+    // Judge if can be mapped as 1GB or 2MB.
+    let granularity = MapGranularity::largest_fit(
+        pages.start_address().value(),
+        frames.start_address().value(),
+        pages.size_in_bytes(),
+    );
+    let attr = match granularity {
+        MapGranularity::Page4KB => attr,
+        _ => attr.set_block(),
+    };
+    trace!(
+        "map_allocated_pages_to(): {} pages:{} frames:{} granularity {:?}",
+        pages_count,
+        pages.start_address(),
+        frames.start_address(),
+        granularity
+    );
+
     // Get global page table.
     let mut page_table = crate::arch::page_table::page_table().lock();
 
-    // Judge if can be map as 2MB.
-    if pages.size_in_bytes() % MapGranularity::Page2MB as usize == 0 {
-        let attr = attr.set_block();
-        let step = 1 << (PAGE_TABLE_L2_SHIFT - PAGE_SHIFT);
-        for (page, frame) in pages
-            .deref()
-            .clone()
-            .into_iter()
-            .zip(frames.deref().clone().into_iter())
-            .step_by(step)
-        {
-            match page_table.map_2mb(
-                page.start_address().value(),
-                frame.start_address().value(),
-                attr,
-            ) {
-                Ok(()) => continue,
-                Err(_) => {
-                    return Err("page table map_2mb error");
-                }
-            }
-        }
-        Ok(MappedRegion {
-            pages,
-            frames,
-            attribute: attr,
-        })
-    } else {
-        for (page, frame) in pages
-            .deref()
-            .clone()
-            .into_iter()
-            .zip(frames.deref().clone().into_iter())
-        {
-            match page_table.map(
-                page.start_address().value(),
-                frame.start_address().value(),
-                attr,
-            ) {
-                Ok(()) => continue,
-                Err(_) => {
-                    return Err("page table map error");
-                }
-            }
+    let step = granularity as usize >> PAGE_SHIFT;
+    for (page, frame) in pages
+        .deref()
+        .clone()
+        .into_iter()
+        .zip(frames.deref().clone().into_iter())
+        .step_by(step)
+    {
+        let va = page.start_address().value();
+        let pa = frame.start_address().value();
+        let result = match granularity {
+            MapGranularity::Page4KB => page_table.map(va, pa, attr),
+            MapGranularity::Page2MB => page_table.map_2mb(va, pa, attr),
+            MapGranularity::Page1GB => page_table.map_1gb(va, pa, attr),
+        };
+        if result.is_err() {
+            return Err("page table map error");
         }
-        Ok(MappedRegion {
-            pages,
-            frames,
-            attribute: attr,
-        })
     }
+    Ok(MappedRegion {
+        pages,
+        frames,
+        attribute: attr,
+        granularity,
+    })
 }
 
 use crate::libs::traits::Address;
@@ -225,7 +242,9 @@ pub fn virtual_to_physical(virtual_address: &VAddr) -> Option<PAddr> {
         MapGranularity::Page2MB => {
             PAddr::new_canonical(entry.pa() | virtual_address.page_offset_2mb())
         }
-        MapGranularity::Page1GB => unimplemented!("mapped by 1GB currently not supported"),
+        MapGranularity::Page1GB => {
+            PAddr::new_canonical(entry.pa() | virtual_address.page_offset_1gb())
+        }
     };
     Some(paddr)
 }
@@ -0,0 +1,263 @@
+//! Memory quotas of zones and threads.
+//!
+//! Each thread (and each zone, with feature "zone") owns a `MemQuota`,
+//! which limits the bytes of virtual pages, physical frames and heap memory it can hold.
+//!
+//! * Pages and frames are charged by `mm::allocate()` and released when the region is freed,
+//!   or when the owner thread exits.
+//! * Heap memory is charged by the global allocator, every heap block records the quota
+//!   it was charged to, so it is credited back correctly even if freed by another thread.
+//!
+//! An allocation exceeding its quota fails and the failure is returned to the caller.
+//! All limits are unlimited by default.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::libs::error::{Error, ERROR_INVARG, ERROR_OOM};
+use crate::libs::thread::{current_thread, thread_lookup, Tid};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QuotaKind {
+    /// Virtual address space in bytes.
+    Pages = 0,
+    /// Physical memory in bytes.
+    Frames = 1,
+    /// Heap memory in bytes.
+    Heap = 2,
+}
+
+const QUOTA_KIND_NUM: usize = 3;
+
+const UNLIMITED: usize = usize::MAX;
+
+pub struct MemQuota {
+    limit: [AtomicUsize; QUOTA_KIND_NUM],
+    used: [AtomicUsize; QUOTA_KIND_NUM],
+    /// Set when the owner is unwinding from an OOM, limits are not checked.
+    unwinding: AtomicBool,
+}
+
+impl MemQuota {
+    pub const fn new() -> Self {
+        MemQuota {
+            limit: [
+                AtomicUsize::new(UNLIMITED),
+                AtomicUsize::new(UNLIMITED),
+                AtomicUsize::new(UNLIMITED),
+            ],
+            used: [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)],
+            unwinding: AtomicBool::new(false),
+        }
+    }
+
+    /// Bypass the limits while the owner is unwinding from an OOM.
+    pub fn set_unwinding(&self, unwinding: bool) {
+        self.unwinding.store(unwinding, Ordering::Relaxed);
+    }
+
+    /// Set the limit of target kind, `None` means unlimited.
+    pub fn set_limit(&self, kind: QuotaKind, limit: Option<usize>) {
+        self.limit[kind as usize].store(limit.unwrap_or(UNLIMITED), Ordering::Relaxed);
+    }
+
+    /// Get the limit of target kind, `None` means unlimited.
+    pub fn limit(&self, kind: QuotaKind) -> Option<usize> {
+        match self.limit[kind as usize].load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Get the currently charged bytes of target kind.
+    pub fn used(&self, kind: QuotaKind) -> usize {
+        self.used[kind as usize].load(Ordering::Relaxed)
+    }
+
+    /// Try to charge `bytes` to target kind.
+    /// Return `ERROR_OOM` if the limit will be exceeded.
+    pub fn try_charge(&self, kind: QuotaKind, bytes: usize) -> Result<(), Error> {
+        let limit = if self.unwinding.load(Ordering::Relaxed) {
+            UNLIMITED
+        } else {
+            self.limit[kind as usize].load(Ordering::Relaxed)
+        };
+        self.used[kind as usize]
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|new| *new <= limit)
+            })
+            .map(|_| ())
+            .map_err(|_| ERROR_OOM)
+    }
+
+    /// Release `bytes` of target kind.
+    pub fn uncharge(&self, kind: QuotaKind, bytes: usize) {
+        let _ = self.used[kind as usize].fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            Some(used.saturating_sub(bytes))
+        });
+    }
+}
+
+impl core::fmt::Display for MemQuota {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (name, kind) in [
+            ("pages", QuotaKind::Pages),
+            ("frames", QuotaKind::Frames),
+            ("heap", QuotaKind::Heap),
+        ] {
+            match self.limit(kind) {
+                Some(limit) => write!(f, "{} {:#x}/{:#x} ", name, self.used(kind), limit)?,
+                None => write!(f, "{} {:#x}/unlimited ", name, self.used(kind))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "zone")]
+static ZONE_QUOTAS: [MemQuota; zone::ZONE_NUM] = {
+    const QUOTA: MemQuota = MemQuota::new();
+    [QUOTA; zone::ZONE_NUM]
+};
+
+/// Get quota of target zone.
+#[cfg(feature = "zone")]
+pub fn zone_quota(zone_id: zone::ZoneId) -> Option<&'static MemQuota> {
+    ZONE_QUOTAS.get(zone_id)
+}
+
+/// Set the limit of target zone, `None` means unlimited.
+#[cfg(feature = "zone")]
+pub fn set_zone_limit(
+    zone_id: zone::ZoneId,
+    kind: QuotaKind,
+    limit: Option<usize>,
+) -> Result<(), Error> {
+    zone_quota(zone_id).ok_or(ERROR_INVARG)?.set_limit(kind, limit);
+    Ok(())
+}
+
+/// Set the limit of target thread, `None` means unlimited.
+pub fn set_thread_limit(tid: Tid, kind: QuotaKind, limit: Option<usize>) -> Result<(), Error> {
+    thread_lookup(tid)
+        .ok_or(ERROR_INVARG)?
+        .quota()
+        .set_limit(kind, limit);
+    Ok(())
+}
+
+/// Charge `bytes` of both pages and frames, nothing is charged if it fails.
+fn try_charge_region(quota: &MemQuota, bytes: usize) -> Result<(), Error> {
+    quota.try_charge(QuotaKind::Pages, bytes)?;
+    quota.try_charge(QuotaKind::Frames, bytes).map_err(|e| {
+        quota.uncharge(QuotaKind::Pages, bytes);
+        e
+    })
+}
+
+/// Charge a newly allocated region of `bytes` to current thread and its zone.
+/// Both pages and frames are charged, nothing is charged if it fails.
+pub(super) fn charge_region(bytes: usize) -> Result<(), Error> {
+    let t = match current_thread() {
+        Ok(t) => t,
+        Err(_) => return Ok(()),
+    };
+    try_charge_region(t.quota(), bytes)?;
+
+    #[cfg(feature = "zone")]
+    if let Some(zone_quota) = zone_quota(t.zone_id()) {
+        if let Err(e) = try_charge_region(zone_quota, bytes) {
+            t.quota().uncharge(QuotaKind::Pages, bytes);
+            t.quota().uncharge(QuotaKind::Frames, bytes);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Release a region of `bytes` from target thread's quota and its zone.
+pub(crate) fn uncharge_region(quota: &MemQuota, _zone_id: usize, bytes: usize) {
+    quota.uncharge(QuotaKind::Pages, bytes);
+    quota.uncharge(QuotaKind::Frames, bytes);
+    #[cfg(feature = "zone")]
+    if let Some(zone_quota) = zone_quota(_zone_id) {
+        zone_quota.uncharge(QuotaKind::Pages, bytes);
+        zone_quota.uncharge(QuotaKind::Frames, bytes);
+    }
+}
+
+/// The record of a heap charge, stored ahead of each heap block.
+#[repr(C)]
+pub(super) struct HeapCharge {
+    /// Raw `Arc<MemQuota>` of the charged thread, may be null.
+    thread: *const MemQuota,
+    /// Charged zone, `usize::MAX` if no zone is charged.
+    zone_id: usize,
+}
+
+impl HeapCharge {
+    /// Charge `bytes` of heap to current thread and its zone.
+    ///
+    /// Called inside the global allocator, so it must not allocate.
+    pub(super) fn charge(bytes: usize) -> Result<HeapCharge, Error> {
+        let t = match current_thread() {
+            Ok(t) => t,
+            Err(_) => {
+                return Ok(HeapCharge {
+                    thread: core::ptr::null(),
+                    zone_id: usize::MAX,
+                })
+            }
+        };
+        t.quota().try_charge(QuotaKind::Heap, bytes)?;
+
+        #[cfg(not(feature = "zone"))]
+        let zone_id = usize::MAX;
+        #[cfg(feature = "zone")]
+        let zone_id = t.zone_id();
+        #[cfg(feature = "zone")]
+        if let Some(zone_quota) = zone_quota(zone_id) {
+            if let Err(e) = zone_quota.try_charge(QuotaKind::Heap, bytes) {
+                t.quota().uncharge(QuotaKind::Heap, bytes);
+                return Err(e);
+            }
+        }
+
+        Ok(HeapCharge {
+            thread: Arc::into_raw(t.quota_arc()),
+            zone_id,
+        })
+    }
+
+    /// Release `bytes` of heap from the charged thread and zone.
+    ///
+    /// The thread's quota may be dropped here, the caller must not hold the heap lock.
+    pub(super) fn uncharge(self, bytes: usize) {
+        if !self.thread.is_null() {
+            let quota = unsafe { Arc::from_raw(self.thread) };
+            quota.uncharge(QuotaKind::Heap, bytes);
+        }
+        #[cfg(feature = "zone")]
+        if let Some(zone_quota) = zone_quota(self.zone_id) {
+            zone_quota.uncharge(QuotaKind::Heap, bytes);
+        }
+    }
+}
+
+#[cfg(feature = "terminal")]
+pub fn dump_quota() {
+    #[cfg(feature = "zone")]
+    for (zone_id, quota) in ZONE_QUOTAS.iter().enumerate() {
+        if quota.used(QuotaKind::Pages) != 0
+            || quota.used(QuotaKind::Heap) != 0
+            || quota.limit(QuotaKind::Pages).is_some()
+            || quota.limit(QuotaKind::Frames).is_some()
+            || quota.limit(QuotaKind::Heap).is_some()
+        {
+            println!(" zone {:>2}: {}", zone_id, quota);
+        }
+    }
+    crate::libs::thread::for_each_thread(|t| {
+        println!(" [{:>4}]: {}", t.id(), t.quota());
+    });
+}
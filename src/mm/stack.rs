@@ -5,7 +5,7 @@ use crate::mm::frame_allocator;
 use crate::mm::frame_allocator::AllocatedFrames;
 use crate::mm::page_allocator::AllocatedPages;
 use crate::mm::paging::{MappedRegion, map_allocated_pages_to, EntryAttribute};
-use crate::mm::interface::PageTableEntryAttrTrait;
+use crate::mm::interface::{MapGranularity, PageTableEntryAttrTrait};
 #[cfg(feature = "zone")]
 use crate::mm::interface::PageTableEntryAttrZoneTrait;
 use zone::ZoneId;
@@ -67,21 +67,50 @@ pub fn alloc_stack(size_in_pages: usize, zone_id: ZoneId) -> Option<Stack> {
     let frames = frame_allocator::allocate_frames(size_in_pages - 1)?;
     trace!("alloc_stack pages {:?}", &pages);
     trace!("alloc_stack frames {:?}", &frames);
-    inner_alloc_stack(pages, frames, zone_id)
+    inner_alloc_stack(pages, frames, 1, zone_id)
+}
+
+/// Allocates a new stack mapped by huge pages.
+///
+/// The stack size is rounded up to 2MB, and a whole 2MB range of virtual address
+/// is reserved as guard beneath the stack to keep the stack range 2MB aligned,
+/// only virtual address space is consumed by the guard.
+///
+/// |-----------------------------------|
+/// |-                                 -|
+/// |-      stack range (2MB aligned)  -|
+/// |-       mapped by huge pages      -|
+/// |-                                 -|
+/// |-----------------------------------|
+/// |--------- guard range (2MB) -------|
+/// |-----------------------------------|
+pub fn alloc_stack_huge(size_in_pages: usize, zone_id: ZoneId) -> Option<Stack> {
+    let huge_pages = MapGranularity::Page2MB as usize / crate::arch::PAGE_SIZE;
+    let stack_pages = crate::util::round_up(size_in_pages, huge_pages);
+    let pages = page_allocator::allocate_pages_alignment(
+        stack_pages + huge_pages,
+        MapGranularity::Page2MB as usize,
+    )?;
+    let frames =
+        frame_allocator::allocate_frames_alignment(stack_pages, MapGranularity::Page2MB as usize)?;
+    trace!("alloc_stack_huge pages {:?}", &pages);
+    trace!("alloc_stack_huge frames {:?}", &frames);
+    inner_alloc_stack(pages, frames, huge_pages, zone_id)
 }
 
 /// The inner implementation of stack allocation.
 ///
 /// `pages` is the combined `AllocatedPages` object that holds
-///  the guard page followed by the actual stack pages to be mapped.
+///  the `guard_pages` guard pages followed by the actual stack pages to be mapped.
 #[allow(unused_mut)]
 fn inner_alloc_stack(
     pages: AllocatedPages,
     frames: AllocatedFrames,
+    guard_pages: usize,
     zone_id: ZoneId,
 ) -> Option<Stack> {
     // Split the guard page.
-    let start_of_stack_pages = *pages.start() + 1;
+    let start_of_stack_pages = *pages.start() + guard_pages;
     let (guard_page, stack_pages) = pages.split(start_of_stack_pages).ok()?;
 
     let mut attr = EntryAttribute::user_default();
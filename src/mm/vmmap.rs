@@ -0,0 +1,264 @@
+//! Inspect the mappings of the active page table.
+//!
+//! The whole page table hierarchy is walked with `PageTableTrait::for_each_entry`,
+//! adjacent entries with the same attributes and contiguous physical addresses are merged
+//! into one `VmRegion`. The stacks and memory regions recorded by threads and the kernel image
+//! only annotate what the regions belong to, mappings made otherwise are reported as well.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use crate::arch::page_table::page_table;
+use crate::arch::PAGE_SIZE;
+use crate::libs::thread::{Thread, Tid};
+use crate::mm::address::{PAddr, VAddr};
+use crate::mm::interface::{MapGranularity, PageTableEntryAttrTrait, PageTableTrait};
+#[cfg(feature = "zone")]
+use crate::mm::interface::PageTableEntryAttrZoneTrait;
+use crate::mm::paging::EntryAttribute;
+
+/// A range of virtual memory mapped with the same attributes.
+#[derive(Clone, Debug)]
+pub struct VmRegion {
+    pub start: VAddr,
+    pub size: usize,
+    /// Physical address mapped at `start`.
+    pub paddr: PAddr,
+    pub granularity: MapGranularity,
+    pub attribute: EntryAttribute,
+}
+
+impl VmRegion {
+    pub fn end(&self) -> VAddr {
+        self.start + self.size
+    }
+
+    /// Try to extend this region with the following `other`.
+    fn try_merge(&mut self, other: &VmRegion) -> bool {
+        if self.end() == other.start
+            && self.paddr.value() + self.size == other.paddr.value()
+            && self.granularity == other.granularity
+            && self.attribute == other.attribute
+        {
+            self.size += other.size;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl fmt::Display for VmRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let attr = &self.attribute;
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#012x} {} r{}{} {} {}",
+            self.start.value(),
+            self.end().value(),
+            self.paddr.value(),
+            match self.granularity {
+                MapGranularity::Page4KB => "4K",
+                MapGranularity::Page2MB => "2M",
+                MapGranularity::Page1GB => "1G",
+            },
+            if attr.writable() { "w" } else { "-" },
+            if attr.k_executable() || attr.u_executable() {
+                "x"
+            } else {
+                "-"
+            },
+            if attr.u_readable() { "user" } else { "kern" },
+            if attr.device() { "device" } else { "normal" },
+        )?;
+        #[cfg(feature = "zone")]
+        write!(f, " zone {:>2}", attr.get_zone_id())?;
+        Ok(())
+    }
+}
+
+/// Walk the active page table over `range`, return the mapped regions in it.
+pub fn walk(range: Range<usize>) -> Vec<VmRegion> {
+    let mut regions: Vec<VmRegion> = Vec::new();
+    let mut va = range.start & !(PAGE_SIZE - 1);
+    while va < range.end {
+        let entry = page_table().lock().lookup_entry(va);
+        let (entry, granularity) = match entry {
+            Some(entry) => entry,
+            None => {
+                va += PAGE_SIZE;
+                continue;
+            }
+        };
+        let size = granularity as usize;
+        let start = va & !(size - 1);
+        let region = VmRegion {
+            start: VAddr::new_canonical(start),
+            size,
+            paddr: PAddr::new_canonical(entry.pa()),
+            granularity,
+            attribute: entry.attribute(),
+        };
+        let merged = match regions.last_mut() {
+            Some(last) => last.try_merge(&region),
+            None => false,
+        };
+        if !merged {
+            regions.push(region);
+        }
+        va = start + size;
+    }
+    regions
+}
+
+/// A range of virtual memory known to belong to something.
+struct Owner {
+    range: Range<usize>,
+    name: String,
+    /// Thread the range belongs to, `None` for the kernel image.
+    thread: Option<Thread>,
+}
+
+/// Ranges of the kernel image and of the stacks and memory regions of all threads.
+fn owners() -> Vec<Owner> {
+    let mut owners = Vec::new();
+    owners.push(Owner {
+        range: crate::mm::wx::image_range(),
+        name: String::from("kernel image"),
+        thread: None,
+    });
+    let mut threads = Vec::new();
+    crate::libs::thread::for_each_thread(|t| threads.push(t.clone()));
+    for thread in threads {
+        thread.for_each_mem_region(|addr, region| {
+            owners.push(Owner {
+                range: addr.value()..addr.value() + region.size_in_bytes(),
+                name: format!("{} region {}", thread.id(), addr),
+                thread: Some(thread.clone()),
+            });
+        });
+        owners.push(Owner {
+            range: thread.stack_range(),
+            name: format!("{} stack", thread.id()),
+            thread: Some(thread),
+        });
+    }
+    owners
+}
+
+/// Walk the whole active page table, return the mapped regions with the index in `owners`
+/// of what each belongs to. Regions are not merged across owners.
+fn walk_all(owners: &[Owner]) -> Vec<(VmRegion, Option<usize>)> {
+    let mut regions: Vec<(VmRegion, Option<usize>)> = Vec::new();
+    page_table()
+        .lock()
+        .for_each_entry(&mut |va, entry, granularity| {
+            let region = VmRegion {
+                start: VAddr::new_canonical(va),
+                size: granularity as usize,
+                paddr: PAddr::new_canonical(entry.pa()),
+                granularity,
+                attribute: entry.attribute(),
+            };
+            let owner = owners.iter().position(|o| {
+                o.range.start < region.end().value() && region.start.value() < o.range.end
+            });
+            let merged = match regions.last_mut() {
+                Some((last, last_owner)) => *last_owner == owner && last.try_merge(&region),
+                None => false,
+            };
+            if !merged {
+                regions.push((region, owner));
+            }
+        });
+    regions
+}
+
+/// Mapped regions of the whole active page table, each tagged by what it belongs to if known.
+pub fn vmmap() -> Vec<(VmRegion, Option<String>)> {
+    let owners = owners();
+    walk_all(&owners)
+        .into_iter()
+        .map(|(region, owner)| (region, owner.map(|i| owners[i].name.clone())))
+        .collect()
+}
+
+/// Mapped regions of a thread, each tagged by what it belongs to.
+pub fn thread_vmmap(thread: &Thread) -> Vec<(String, VmRegion)> {
+    let mut ranges = Vec::new();
+    ranges.push((String::from("stack"), thread.stack_range()));
+    thread.for_each_mem_region(|addr, region| {
+        ranges.push((
+            format!("region {}", addr),
+            addr.value()..addr.value() + region.size_in_bytes(),
+        ));
+    });
+
+    let mut result = Vec::new();
+    for (owner, range) in ranges {
+        for region in walk(range) {
+            result.push((owner.clone(), region));
+        }
+    }
+    result
+}
+
+/// Whether `region` is mapped in another zone than of the thread it belongs to.
+#[allow(unused_variables)]
+fn foreign_zone(region: &VmRegion, thread: &Thread) -> bool {
+    #[cfg(feature = "zone")]
+    return region.attribute.get_zone_id() != thread.zone_id();
+    #[cfg(not(feature = "zone"))]
+    return false;
+}
+
+fn dump_thread_vmmap(thread: &Thread) {
+    #[cfg(feature = "zone")]
+    println!("{} zone {}", thread.id(), thread.zone_id());
+    #[cfg(not(feature = "zone"))]
+    println!("{}", thread.id());
+    for (owner, region) in thread_vmmap(thread) {
+        println!(
+            "  {} {}{}",
+            region,
+            owner,
+            if foreign_zone(&region, thread) {
+                " (foreign zone)"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Dump the whole page table, annotated by what the regions belong to.
+fn dump_page_table() {
+    let owners = owners();
+    for (region, owner) in walk_all(&owners) {
+        match owner.map(|i| &owners[i]) {
+            Some(owner) => println!(
+                "{} {}{}",
+                region,
+                owner.name,
+                match &owner.thread {
+                    Some(thread) if foreign_zone(&region, thread) => " (foreign zone)",
+                    _ => "",
+                }
+            ),
+            None => println!("{}", region),
+        }
+    }
+}
+
+/// Dump mapped regions of target thread, or the whole page table if `tid` is `None`.
+pub fn dump_vmmap(tid: Option<Tid>) {
+    match tid {
+        Some(tid) => match crate::libs::thread::thread_lookup(tid) {
+            Some(thread) => dump_thread_vmmap(&thread),
+            None => println!("vmmap: thread {} not exist", tid),
+        },
+        None => dump_page_table(),
+    }
+}
@@ -0,0 +1,126 @@
+//! W^X, no memory is both writable and executable.
+//!
+//! * The kernel image is remapped at page table init with the permissions of its sections,
+//!   bounded by linker symbols, see `cfg/*.ld`: `.text` read-execute, `.rodata` read-only,
+//!   everything else up to `IMAGE_END` read-write and non-executable.
+//! * Mappings made by `map_allocated_pages_to` and `map_allocated_pages` are audited,
+//!   writable and executable ones are refused.
+//! * Memory outside the image mapped at boot is made non-executable, aliases of the image
+//!   kept for booting, e.g. the identity gigapage on riscv64, get the same permissions.
+//! * With feature "wx-check", the terminal command `wxcheck` verifies the image
+//!   and walks the whole page table for mappings both writable and executable.
+
+use core::ops::Range;
+
+use crate::mm::interface::PageTableEntryAttrTrait;
+use crate::mm::paging::EntryAttribute;
+
+/// Permissions of a kernel image section.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SectionPerm {
+    ReadExecute,
+    ReadOnly,
+    ReadWrite,
+}
+
+impl SectionPerm {
+    pub fn writable(self) -> bool {
+        self == SectionPerm::ReadWrite
+    }
+
+    pub fn executable(self) -> bool {
+        self == SectionPerm::ReadExecute
+    }
+}
+
+/// Page-aligned sections of the kernel image with their permissions.
+pub fn image_sections() -> [(Range<usize>, SectionPerm); 3] {
+    extern "C" {
+        // Note: link-time label, see linker.ld
+        fn TEXT_START();
+        fn TEXT_END();
+        fn RODATA_END();
+        fn IMAGE_END();
+    }
+    let text_start = TEXT_START as usize;
+    let text_end = TEXT_END as usize;
+    let rodata_end = RODATA_END as usize;
+    let image_end = crate::util::round_up(IMAGE_END as usize, crate::arch::PAGE_SIZE);
+    [
+        (text_start..text_end, SectionPerm::ReadExecute),
+        (text_end..rodata_end, SectionPerm::ReadOnly),
+        (rodata_end..image_end, SectionPerm::ReadWrite),
+    ]
+}
+
+/// Range of the whole kernel image.
+pub fn image_range() -> Range<usize> {
+    let [(text, _), _, (data, _)] = image_sections();
+    text.start..data.end
+}
+
+/// Permissions of the kernel image page at `va`, `None` if it's outside the image.
+pub fn image_perm(va: usize) -> Option<SectionPerm> {
+    image_sections()
+        .into_iter()
+        .find(|(range, _)| range.contains(&va))
+        .map(|(_, perm)| perm)
+}
+
+fn writable_executable(attr: &EntryAttribute) -> bool {
+    attr.writable() && (attr.k_executable() || attr.u_executable())
+}
+
+/// Refuse mappings which are both writable and executable.
+pub fn audit(attr: &EntryAttribute) -> Result<(), &'static str> {
+    if writable_executable(attr) {
+        warn!("W^X: refuse to map writable and executable pages, {:?}", attr);
+        return Err("W^X: writable and executable mapping refused");
+    }
+    Ok(())
+}
+
+/// Check the kernel image and the whole page table, print the violations and return the number.
+#[cfg(feature = "wx-check")]
+pub fn self_check() -> usize {
+    let mut violations = 0;
+    for (range, perm) in image_sections() {
+        let mut va = range.start;
+        while va < range.end {
+            let (entry, granularity) = match crate::arch::page_table::kernel_entry(va) {
+                Some(entry) => entry,
+                None => {
+                    println!("wxcheck: image page {:#x} is not mapped", va);
+                    violations += 1;
+                    va += crate::arch::PAGE_SIZE;
+                    continue;
+                }
+            };
+            let attr = entry.attribute();
+            let executable = attr.k_executable() || attr.u_executable();
+            if attr.writable() != perm.writable() || executable != perm.executable() {
+                println!(
+                    "wxcheck: image page {:#x} expected {:?}, mapped {}{}",
+                    va,
+                    perm,
+                    if attr.writable() { "w" } else { "-" },
+                    if executable { "x" } else { "-" },
+                );
+                violations += 1;
+            }
+            va = (va & !(granularity as usize - 1)) + granularity as usize;
+        }
+    }
+
+    for (region, owner) in crate::mm::vmmap::vmmap() {
+        if writable_executable(&region.attribute) {
+            println!(
+                "wxcheck: {} {} is writable and executable",
+                region,
+                owner.as_deref().unwrap_or("unknown")
+            );
+            violations += 1;
+        }
+    }
+    violations
+}
@@ -0,0 +1,88 @@
+//! Data sections of named zones, enabled by feature "zone".
+//!
+//! Statics declared by `#[zone_protected::zone_data("name")]` are placed in section `.zone_data.<name>`,
+//! the linker scripts gather these page-aligned between `ZONE_DATA_START` and `ZONE_DATA_END`,
+//! and a `ZoneDataDesc` of each static in `.zone_data_desc`.
+//!
+//! At boot each name gets a zone of its own, which lives as long as the kernel,
+//! and pages of its statics are tagged with the zone. Threads run in a named zone
+//! when spawned by `ZoneSpec::Named`.
+//!
+//! On x86_64 the pages are tagged with the zone's protection key. Elsewhere they are removed
+//! from the page-table views of all zones but the owner, the privileged view still maps them.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use spin::Mutex;
+use zone::{ZoneDataDesc, ZoneId};
+
+use crate::arch::PAGE_SIZE;
+use crate::util::{round_down, round_up};
+
+/// Zones allocated for the names of zone data sections.
+static NAMED_ZONES: Mutex<BTreeMap<&'static str, ZoneId>> = Mutex::new(BTreeMap::new());
+
+fn descriptors() -> &'static [ZoneDataDesc] {
+    extern "C" {
+        // Note: link-time label, see linker.ld
+        fn ZONE_DATA_DESC_START();
+        fn ZONE_DATA_DESC_END();
+    }
+    let start = ZONE_DATA_DESC_START as usize;
+    let end = ZONE_DATA_DESC_END as usize;
+    unsafe {
+        core::slice::from_raw_parts(
+            start as *const ZoneDataDesc,
+            (end - start) / size_of::<ZoneDataDesc>(),
+        )
+    }
+}
+
+/// Allocate the named zones and tag their data sections, called after `zone::zone_init`.
+pub fn init() {
+    let mut named_zones = NAMED_ZONES.lock();
+    for desc in descriptors() {
+        let zone_id = match named_zones.get(desc.name) {
+            Some(zone_id) => *zone_id,
+            None => match zone::zone_alloc() {
+                Ok(zone_id) => {
+                    info!("zone data: \"{}\" owned by zone {}", desc.name, zone_id);
+                    named_zones.insert(desc.name, zone_id);
+                    zone_id
+                }
+                Err(e) => {
+                    warn!("zone data: \"{}\" is not isolated, {}", desc.name, e);
+                    continue;
+                }
+            },
+        };
+        if desc.size == 0 {
+            continue;
+        }
+        let start = round_down(desc.start as usize, PAGE_SIZE);
+        let end = round_up(desc.start as usize + desc.size, PAGE_SIZE);
+        debug!(
+            "zone data: [{:#x} to {:#x}] of \"{}\" tagged with zone {}",
+            start, end, desc.name, zone_id
+        );
+        crate::arch::page_table::page_table()
+            .lock()
+            .set_zone_range(start..end, zone_id);
+    }
+}
+
+/// Get the zone owning the data section `name`.
+pub fn named_zone(name: &str) -> Option<ZoneId> {
+    NAMED_ZONES.lock().get(name).copied()
+}
+
+/// Names of zone data sections with their zones.
+pub fn named_zones() -> Vec<(&'static str, ZoneId)> {
+    NAMED_ZONES
+        .lock()
+        .iter()
+        .map(|(name, zone_id)| (*name, *zone_id))
+        .collect()
+}
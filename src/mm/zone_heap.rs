@@ -0,0 +1,340 @@
+//! Per-zone heap arenas, enabled by feature "zone".
+//!
+//! Each zone owns a heap arena whose memory is mapped with the zone's protection key,
+//! so heap objects of a zone can not be touched by other zones.
+//!
+//! * A window of `ARENA_SPAN` bytes of virtual address space is reserved for every zone
+//!   at boot, the arena grows inside its window on demand by `ARENA_GROW` bytes.
+//! * Heap allocations are routed by the zone of current thread. Allocations made with
+//!   privileged zone keys (kernel paths) and by threads of the shared zone
+//!   go to the global shared heap.
+//! * Allocations of a zone fail if its arena is out of memory, they never fall back to
+//!   the shared heap. The only exception is kernel bookkeeping made inside memory management,
+//!   see `is_bookkeeping`.
+//! * A zone whose arena is still in use when it's put is retired, its id is freed
+//!   by `release_retired` once the arena is empty.
+//! * The owner arena of a block is derived from its address, so a block can be freed
+//!   by any thread which is able to access it.
+//! * `ZoneAllocator` allocates from the arena of an explicit zone, e.g. `Box::new_in(v, ZoneAllocator::shared())`
+//!   places an object in the shared zone so that it can be accessed across zones.
+
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::ptr::NonNull;
+
+use buddy_system_allocator::Heap;
+use spin::Once;
+use zone::{ZoneId, ZONE_ID_PRIVILEGED, ZONE_ID_SHARED, ZONE_NUM};
+
+use crate::arch::PAGE_SIZE;
+use crate::libs::synch::spinlock::SpinlockIrqSave;
+use crate::libs::thread::current_thread;
+use crate::mm::address::VAddr;
+use crate::mm::frame_allocator;
+use crate::mm::interface::{MapGranularity, PageTableEntryAttrTrait, PageTableEntryAttrZoneTrait};
+use crate::mm::page_allocator::{self, AllocatedPages, Page};
+use crate::mm::paging::{map_allocated_pages_to, EntryAttribute, MappedRegion};
+
+/// Virtual address space reserved for each arena.
+const ARENA_SPAN: usize = MapGranularity::Page1GB as usize;
+/// Minimum size an arena grows by.
+const ARENA_GROW: usize = MapGranularity::Page2MB as usize;
+
+struct ZoneArena {
+    heap: Heap<32>,
+    /// Regions mapped with the zone's key, in address order from the start of the window.
+    regions: Vec<MappedRegion>,
+    /// The rest of the window, not mapped yet.
+    unmapped: AllocatedPages,
+    /// Failed to be released for blocks still in use, the zone id is not freed yet.
+    retired: bool,
+}
+
+static ARENAS: [SpinlockIrqSave<ZoneArena>; ZONE_NUM] = {
+    const ARENA: SpinlockIrqSave<ZoneArena> = SpinlockIrqSave::new(ZoneArena {
+        heap: Heap::empty(),
+        regions: Vec::new(),
+        unmapped: AllocatedPages::empty(),
+        retired: false,
+    });
+    [ARENA; ZONE_NUM]
+};
+
+/// Start address of the windows of all arenas.
+static WINDOWS_BASE: Once<usize> = Once::new();
+
+/// Reserve the arena windows, called after the page allocator is ready.
+pub fn init() {
+    let pages = match page_allocator::allocate_pages_alignment(
+        ZONE_NUM * ARENA_SPAN / PAGE_SIZE,
+        ARENA_SPAN,
+    ) {
+        Some(pages) => pages,
+        None => {
+            warn!("zone heap: failed to reserve arena windows, zone heap arenas are disabled");
+            return;
+        }
+    };
+    let base = pages.start_address().value();
+    let mut rest = pages;
+    for (zone_id, arena) in ARENAS.iter().enumerate() {
+        let end = Page::containing_address(VAddr::new_canonical(base + (zone_id + 1) * ARENA_SPAN));
+        let (window, next) = rest.split(end).unwrap();
+        arena.lock().unmapped = window;
+        rest = next;
+    }
+    WINDOWS_BASE.call_once(|| base);
+    info!(
+        "zone heap: arena windows [{:#x} - {:#x}]",
+        base,
+        base + ZONE_NUM * ARENA_SPAN
+    );
+}
+
+/// Whether `zone_id` has its own arena, the privileged and shared zone use the shared heap.
+fn has_arena(zone_id: ZoneId) -> bool {
+    WINDOWS_BASE.get().is_some()
+        && zone_id < ZONE_NUM
+        && zone_id != ZONE_ID_PRIVILEGED
+        && zone_id != ZONE_ID_SHARED
+}
+
+/// Get the zone arena which a heap allocation from current context should go to,
+/// `None` means the shared heap.
+///
+/// This is the zone current context runs in, which differs from the thread's own zone
+/// inside zone calls, see `libs::zone_gate`.
+pub(super) fn current_arena() -> Option<ZoneId> {
+    if zone::is_privileged() || current_thread().is_err() {
+        return None;
+    }
+    let zone_id = zone::current_zone();
+    if has_arena(zone_id) {
+        Some(zone_id)
+    } else {
+        None
+    }
+}
+
+/// Get the arena of target zone, `None` means the shared heap.
+pub(super) fn arena_of(zone_id: ZoneId) -> Option<ZoneId> {
+    if has_arena(zone_id) {
+        Some(zone_id)
+    } else {
+        None
+    }
+}
+
+/// Get the zone whose arena contains `ptr`, `None` if it's not in any arena.
+pub(super) fn owner_of(ptr: *mut u8) -> Option<ZoneId> {
+    let base = *WINDOWS_BASE.get()?;
+    let addr = ptr as usize;
+    if addr < base || addr >= base + ZONE_NUM * ARENA_SPAN {
+        return None;
+    }
+    Some((addr - base) / ARENA_SPAN)
+}
+
+impl ZoneArena {
+    /// Map at least `size` bytes more to this arena.
+    ///
+    /// Runs with privileged zone keys, so the heap memory used by the
+    /// frame allocator and page table goes to the shared heap.
+    fn grow(&mut self, zone_id: ZoneId, size: usize) -> Result<(), &'static str> {
+        let size = crate::util::round_up(core::cmp::max(size, ARENA_GROW), ARENA_GROW);
+        if size > self.unmapped.size_in_bytes() {
+            return Err("arena window exhausted");
+        }
+        let frames = frame_allocator::allocate_frames_alignment(size / PAGE_SIZE, ARENA_GROW)
+            .or_else(|| frame_allocator::allocate_frames(size / PAGE_SIZE))
+            .ok_or("out of frames")?;
+
+        let unmapped = core::mem::replace(&mut self.unmapped, AllocatedPages::empty());
+        let at = Page::containing_address(unmapped.start_address() + size);
+        let (pages, rest) = unmapped.split(at).map_err(|_| "arena window exhausted")?;
+        self.unmapped = rest;
+
+        let mut attr = EntryAttribute::user_data();
+        attr.set_zone(zone_id);
+        let region = map_allocated_pages_to(pages, frames, attr)?;
+        let start = region.start_address().value();
+        unsafe { self.heap.add_to_heap(start, start + size) };
+        self.regions.push(region);
+        debug!(
+            "zone heap: zone {} arena grows to {:#x} bytes",
+            zone_id,
+            self.regions.iter().map(|r| r.size_in_bytes()).sum::<usize>()
+        );
+        Ok(())
+    }
+}
+
+/// Whether the allocation is kernel bookkeeping made inside the frame allocator
+/// or page table, e.g. page table frames recorded while mapping pages.
+///
+/// It's not owned by the zone, and growing an arena now would deadlock on their locks,
+/// so it goes to the shared heap. The locks are only checked as a hint.
+pub(super) fn is_bookkeeping() -> bool {
+    frame_allocator::is_locked() || crate::arch::page_table::page_table().is_locked()
+}
+
+/// Allocate from the arena of `zone_id`, growing it if needed, return null if failed.
+pub(super) fn alloc(zone_id: ZoneId, layout: Layout) -> *mut u8 {
+    let ori_pkru = zone::switch_to_privilege();
+    let mut arena = ARENAS[zone_id].lock();
+    let mut res = arena.heap.alloc(layout);
+    if res.is_err() {
+        // Blocks of the buddy system are aligned to their size.
+        let size = layout.size().max(layout.align()).next_power_of_two();
+        match arena.grow(zone_id, size) {
+            Ok(_) => res = arena.heap.alloc(layout),
+            Err(e) => warn!(
+                "zone heap: zone {} failed to grow for {:?}, {}",
+                zone_id, layout, e
+            ),
+        }
+    }
+    drop(arena);
+    zone::switch_from_privilege(ori_pkru);
+    res.map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+}
+
+/// Release a block to the arena of `zone_id`.
+pub(super) unsafe fn dealloc(zone_id: ZoneId, ptr: *mut u8, layout: Layout) {
+    let ori_pkru = zone::switch_to_privilege();
+    ARENAS[zone_id]
+        .lock()
+        .heap
+        .dealloc(NonNull::new_unchecked(ptr), layout);
+    zone::switch_from_privilege(ori_pkru);
+}
+
+/// Unmap the whole arena of `zone_id` before the zone is freed,
+/// so its memory is not leaked to the next owner of the zone id.
+///
+/// Fails if blocks in the arena are still in use, the zone must not be freed then.
+/// The arena is retired, and the zone is freed by `release_retired` once it's empty.
+pub fn release(zone_id: ZoneId) -> Result<(), &'static str> {
+    if !has_arena(zone_id) {
+        return Ok(());
+    }
+    let ori_pkru = zone::switch_to_privilege();
+    let mut arena = ARENAS[zone_id].lock();
+    if arena.heap.stats_alloc_user() != 0 {
+        arena.retired = true;
+        drop(arena);
+        zone::switch_from_privilege(ori_pkru);
+        return Err("zone heap arena still in use");
+    }
+    arena.retired = false;
+    arena.heap = Heap::empty();
+    let regions = core::mem::take(&mut arena.regions);
+    let mut window = AllocatedPages::empty();
+    for region in regions {
+        let pages = region.unmap_into_pages();
+        if window.size_in_pages() == 0 {
+            window = pages;
+        } else if window.merge(pages).is_err() {
+            error!("zone heap: BUG, zone {} arena regions are not contiguous", zone_id);
+        }
+    }
+    let unmapped = core::mem::replace(&mut arena.unmapped, AllocatedPages::empty());
+    arena.unmapped = if window.size_in_pages() == 0 {
+        unmapped
+    } else {
+        if unmapped.size_in_pages() != 0 && window.merge(unmapped).is_err() {
+            error!("zone heap: BUG, zone {} arena window is broken", zone_id);
+        }
+        window
+    };
+    drop(arena);
+    zone::switch_from_privilege(ori_pkru);
+    Ok(())
+}
+
+/// Release the retired arenas which are empty now, and free their zones by `free`.
+pub fn release_retired(mut free: impl FnMut(ZoneId)) {
+    for (zone_id, users) in zone::zone_users() {
+        if !ARENAS[zone_id].lock().retired {
+            continue;
+        }
+        // Taken again, e.g. by spawning a thread into it.
+        if users != 0 {
+            ARENAS[zone_id].lock().retired = false;
+            continue;
+        }
+        match release(zone_id) {
+            Ok(()) => {
+                info!("zone heap: retired zone {} is empty, freed", zone_id);
+                free(zone_id);
+            }
+            Err(_) => continue,
+        }
+    }
+}
+
+#[cfg(feature = "terminal")]
+pub fn dump_zone_heaps() {
+    for (zone_id, arena) in ARENAS.iter().enumerate() {
+        let arena = arena.lock();
+        if arena.regions.is_empty() {
+            continue;
+        }
+        println!(
+            " zone {:>2}: mapped {:#x} bytes, allocated user {:#x} actual {:#x}",
+            zone_id,
+            arena.heap.stats_total_bytes(),
+            arena.heap.stats_alloc_user(),
+            arena.heap.stats_alloc_actual()
+        );
+    }
+}
+
+/// Allocator of an explicit zone's heap arena.
+///
+/// Blocks of the shared zone are accessible from all zones,
+/// and blocks of other zones are only accessible with their keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ZoneAllocator(ZoneId);
+
+impl ZoneAllocator {
+    pub const fn new(zone_id: ZoneId) -> Self {
+        ZoneAllocator(zone_id)
+    }
+
+    /// Allocator of the shared zone, for objects accessed across zones.
+    pub const fn shared() -> Self {
+        ZoneAllocator(ZONE_ID_SHARED)
+    }
+
+    /// Allocator of current thread's zone.
+    pub fn current() -> Self {
+        ZoneAllocator(current_thread().map_or(ZONE_ID_SHARED, |t| t.zone_id()))
+    }
+
+    pub fn zone_id(&self) -> ZoneId {
+        self.0
+    }
+}
+
+unsafe impl Allocator for ZoneAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0));
+        }
+        // The block may belong to a zone current thread can not access.
+        let ori_pkru = zone::switch_to_privilege();
+        let ptr = unsafe { super::heap::alloc_in(layout, arena_of(self.0)) };
+        zone::switch_from_privilege(ori_pkru);
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            let ori_pkru = zone::switch_to_privilege();
+            super::heap::dealloc_in(ptr.as_ptr(), layout);
+            zone::switch_from_privilege(ori_pkru);
+        }
+    }
+}
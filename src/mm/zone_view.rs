@@ -0,0 +1,56 @@
+//! Zone isolation by per-zone page-table views, for architectures without protection keys.
+//!
+//! Besides the full page table, which is the view of the privileged zone,
+//! each zone owns a directory which only maps the pages its zone keys are allowed to access,
+//! pages readable but not writable with the keys are mapped read-only.
+//! Every mapping of the page table is mirrored into the views that can see it.
+//!
+//! Views are switched by writing the translation table base register (TTBR0_EL1 or satp)
+//! with the view's directory, using the zone id as the ASID,
+//! on thread switches and when entering or leaving privileged functions.
+
+use zone::{ZoneId, ZoneKeys, ZONE_ID_PRIVILEGED};
+
+use crate::mm::interface::{PageTableEntryAttrTrait, PageTableEntryAttrZoneTrait};
+use crate::mm::paging::EntryAttribute;
+
+/// Number of views, zone id is 4-bit.
+pub const VIEW_NUM: usize = 16;
+
+/// Zones which own a view directory besides the full page table.
+pub fn view_zones() -> impl Iterator<Item = ZoneId> {
+    (0..VIEW_NUM).filter(|zone_id| *zone_id != ZONE_ID_PRIVILEGED)
+}
+
+/// Get the attribute a mapping of `attr` has in the view of `view`, `None` if it's invisible.
+pub fn view_attr(view: ZoneId, attr: EntryAttribute) -> Option<EntryAttribute> {
+    let keys = ZoneKeys::from(view);
+    let zone_id = attr.get_zone_id();
+    if !keys.readable(zone_id) {
+        return None;
+    }
+    if !attr.writable() || keys.writable(zone_id) {
+        return Some(attr);
+    }
+    let mut readonly = EntryAttribute::new(
+        false,
+        attr.u_readable(),
+        attr.device(),
+        attr.k_executable(),
+        attr.u_executable(),
+        attr.copy_on_write(),
+        attr.u_shared(),
+        attr.block(),
+    );
+    readonly.set_zone(zone_id);
+    Some(readonly)
+}
+
+/// Register the views to zone crate, called after page table is ready.
+pub fn init() {
+    zone::register_view_ops(zone::ViewOps {
+        current: crate::arch::page_table::current_view,
+        switch: crate::arch::page_table::switch_view,
+    });
+    info!("zone: {} page table views ready", VIEW_NUM);
+}
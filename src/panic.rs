@@ -1,3 +1,242 @@
+//! Panic handling, with hooks and a policy deciding what a panic does to the image.
+//!
+//! A panic prints its message and backtrace, then calls the panicking thread's hook
+//! and the global hook, see `set_panic_hook`, then follows the thread's `PanicPolicy`,
+//! or the default one set by `set_default_panic_policy` if the thread has none.
+//!
+//! Without feature "unwind", panics can't be unwound, the panicking thread hangs
+//! under `Unwind` and `UnwindRestart`, and under `Rollback` if it can't roll back.
+//!
+//! With feature "std", std prints the panic and unwinds it, `init` installs a std hook
+//! calling the same hooks and following the same policies in between.
+//! `Rollback` unwinds instead, since std counts the panic until it's caught.
+//! Replacing the std hook with `std::panic::set_hook` bypasses hooks and policies.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use spin::Mutex;
+
+/// What a panic does to the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Halt all cores, for threads the system can't run without.
+    Abort = 1,
+    /// Unwind the thread, which exits once its panic is caught.
+    Unwind,
+    /// Unwind the thread and run its entry again.
+    ///
+    /// Only threads spawned from an entry function or a restartable closure can restart,
+    /// others exit as under `Unwind`.
+    UnwindRestart,
+    /// Roll the thread back to its last checkpoint, see `libs::checkpoint`,
+    /// threads without one unwind as under `Unwind`.
+    #[cfg(feature = "checkpoint")]
+    Rollback,
+}
+
+impl PanicPolicy {
+    fn from_u8(value: u8) -> Option<PanicPolicy> {
+        match value {
+            1 => Some(PanicPolicy::Abort),
+            2 => Some(PanicPolicy::Unwind),
+            3 => Some(PanicPolicy::UnwindRestart),
+            #[cfg(feature = "checkpoint")]
+            4 => Some(PanicPolicy::Rollback),
+            _ => None,
+        }
+    }
+}
+
+pub type PanicHook = dyn Fn(&PanicInfo<'_>) + Send + Sync + 'static;
+
+static PANIC_HOOK: Mutex<Option<Arc<PanicHook>>> = Mutex::new(None);
+
+static DEFAULT_POLICY: AtomicU8 = AtomicU8::new(PanicPolicy::UnwindRestart as u8);
+
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+/// Register a hook called on every panic, after the panicking thread's own hook,
+/// replacing the previous one.
+pub fn set_panic_hook(hook: Box<PanicHook>) {
+    *PANIC_HOOK.lock() = Some(Arc::from(hook));
+}
+
+/// Unregister the global hook, returning it.
+pub fn take_panic_hook() -> Option<Arc<PanicHook>> {
+    PANIC_HOOK.lock().take()
+}
+
+/// Set the policy of threads without their own one, `UnwindRestart` by default.
+pub fn set_default_panic_policy(policy: PanicPolicy) {
+    DEFAULT_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn default_panic_policy() -> PanicPolicy {
+    PanicPolicy::from_u8(DEFAULT_POLICY.load(Ordering::Relaxed)).unwrap()
+}
+
+/// Panic policy and hook of a thread, kept in its control block.
+pub(crate) struct ThreadPanic {
+    /// Zero for the default policy.
+    policy: AtomicU8,
+    hook: Mutex<Option<Arc<PanicHook>>>,
+    /// Set while the hooks run, a panic raised by them aborts.
+    in_hook: AtomicBool,
+    /// `STOP_REQUESTED` or `STOP_UNWINDING` once asked to stop, see `thread_stop`.
+    stop: AtomicU8,
+}
+
+const STOP_REQUESTED: u8 = 1;
+const STOP_UNWINDING: u8 = 2;
+
+impl ThreadPanic {
+    pub const fn new() -> Self {
+        ThreadPanic {
+            policy: AtomicU8::new(0),
+            hook: Mutex::new(None),
+            in_hook: AtomicBool::new(false),
+            stop: AtomicU8::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> PanicPolicy {
+        PanicPolicy::from_u8(self.policy.load(Ordering::Relaxed))
+            .unwrap_or_else(default_panic_policy)
+    }
+
+    pub fn set_policy(&self, policy: Option<PanicPolicy>) {
+        self.policy
+            .store(policy.map_or(0, |p| p as u8), Ordering::Relaxed);
+    }
+
+    pub fn set_hook(&self, hook: Option<Box<PanicHook>>) {
+        *self.hook.lock() = hook.map(Arc::from);
+    }
+
+    /// Ask the thread to unwind when it next yields.
+    #[cfg(feature = "unwind")]
+    pub fn request_stop(&self) {
+        let _ = self
+            .stop
+            .compare_exchange(0, STOP_REQUESTED, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Start unwinding if the thread is asked to stop, returns whether it is.
+    #[cfg(feature = "unwind")]
+    pub fn begin_stop(&self) -> bool {
+        self.stop
+            .compare_exchange(
+                STOP_REQUESTED,
+                STOP_UNWINDING,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Whether the thread is unwinding since it's asked to stop,
+    /// then it unwinds regardless of its hooks and policy.
+    pub fn stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) == STOP_UNWINDING
+    }
+
+    /// Take the policy and hook of `other`, for a thread restarting it.
+    pub fn inherit(&self, other: &ThreadPanic) {
+        self.policy
+            .store(other.policy.load(Ordering::Relaxed), Ordering::Relaxed);
+        *self.hook.lock() = other.hook.lock().clone();
+    }
+}
+
+/// Call the hooks of current thread's panic, returns the policy to follow.
+fn call_hooks(info: &PanicInfo) -> PanicPolicy {
+    let thread = crate::libs::thread::current_thread().ok();
+    let state = thread.as_ref().map(|t| t.panic_state());
+    let policy = state.map_or_else(default_panic_policy, |s| s.policy());
+
+    if let Some(state) = state {
+        if state.in_hook.swap(true, Ordering::Acquire) {
+            println!("PANIC in panic hook, abort");
+            return PanicPolicy::Abort;
+        }
+    }
+    // Hooks are cloned out, so they may panic or set hooks without deadlocking.
+    let thread_hook = state.and_then(|s| s.hook.lock().clone());
+    if let Some(hook) = thread_hook {
+        hook(info);
+    }
+    let global_hook = PANIC_HOOK.lock().clone();
+    if let Some(hook) = global_hook {
+        hook(info);
+    }
+    if let Some(state) = state {
+        state.in_hook.store(false, Ordering::Release);
+    }
+    policy
+}
+
+/// Call the hooks and follow the policy of current thread's panic,
+/// returns if the thread is to be unwound.
+fn dispatch(info: &PanicInfo) {
+    // A stopped thread just unwinds, see `thread_stop`.
+    let stopping =
+        crate::libs::thread::current_thread().map_or(false, |t| t.panic_state().stopping());
+    let policy = if stopping {
+        PanicPolicy::Unwind
+    } else {
+        call_hooks(info)
+    };
+    if policy == PanicPolicy::Abort {
+        abort();
+    }
+    #[cfg(all(feature = "checkpoint", not(feature = "std")))]
+    if policy == PanicPolicy::Rollback {
+        crate::libs::checkpoint::rollback_from_fault();
+    }
+}
+
+/// Install the std hook dispatching panics, see the module doc.
+#[cfg(feature = "std")]
+pub(crate) fn init() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        dispatch(info);
+    }));
+}
+
+/// Whether the image is aborted, cores halt when they next schedule.
+#[inline]
+pub(crate) fn aborted() -> bool {
+    ABORTED.load(Ordering::Relaxed)
+}
+
+/// Halt current core.
+pub(crate) fn halt() -> ! {
+    use crate::libs::traits::ArchTrait;
+    crate::arch::irq::disable();
+    loop {
+        crate::arch::Arch::wait_for_interrupt();
+    }
+}
+
+/// Abort the image: halt current core now, and other cores when they next schedule.
+#[inline(always)]
+pub fn abort() -> ! {
+    crate::arch::irq::disable();
+    ABORTED.store(true, Ordering::Relaxed);
+    println!(
+        "ABORT on Thread [{}]",
+        crate::libs::thread::current_thread_id()
+    );
+    #[cfg(feature = "coredump")]
+    crate::libs::coredump::dump_current(crate::libs::coredump::SIGNAL_PANIC);
+    halt()
+}
+
 #[allow(non_snake_case)]
 #[no_mangle]
 extern "C" fn _Unwind_Resume(_arg: usize) -> ! {
@@ -33,9 +272,13 @@ pub fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     if let Some(location) = info.location() {
         println!("Location: {}:{}", location.file(), location.line());
     }
+    crate::libs::backtrace::print_backtrace(0);
 
+    dispatch(info);
     #[cfg(feature = "unwind")]
     crate::libs::unwind::unwind_from_panic(3);
+    #[cfg(all(feature = "coredump", not(feature = "unwind")))]
+    crate::libs::coredump::dump_current(crate::libs::coredump::SIGNAL_PANIC);
     #[cfg(not(feature = "unwind"))]
     loop {}
 }